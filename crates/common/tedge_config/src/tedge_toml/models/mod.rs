@@ -119,6 +119,73 @@ impl FromStr for AutoLogUpload {
     }
 }
 
+/// How the built-in bridge chooses the next cloud endpoint after repeated connection failures
+#[derive(
+    Debug, Display, Clone, Copy, Eq, PartialEq, doku::Document, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum FailoverStrategy {
+    /// Endpoints are tried in order, going back to the first one once a connection is lost
+    Primary,
+    /// Endpoints are tried in order, staying on the current one as long as it can be reached
+    RoundRobin,
+    /// Another endpoint is picked at random
+    Random,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to parse failover strategy: {input}. Supported values are: 'primary', 'round-robin' or 'random'")]
+pub struct InvalidFailoverStrategy {
+    input: String,
+}
+
+impl FromStr for FailoverStrategy {
+    type Err = InvalidFailoverStrategy;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "primary" => Ok(FailoverStrategy::Primary),
+            "round-robin" => Ok(FailoverStrategy::RoundRobin),
+            "random" => Ok(FailoverStrategy::Random),
+            _ => Err(InvalidFailoverStrategy {
+                input: input.to_string(),
+            }),
+        }
+    }
+}
+
+/// The algorithm used by a mapper to compress the batches of outbound telemetry
+#[derive(
+    Debug, Display, Clone, Copy, Eq, PartialEq, doku::Document, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum CompressionAlgorithm {
+    Gzip,
+    Zstd,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to parse compression algorithm: {input}. Supported values are: 'gzip' or 'zstd'")]
+pub struct InvalidCompressionAlgorithm {
+    input: String,
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = InvalidCompressionAlgorithm;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "gzip" => Ok(CompressionAlgorithm::Gzip),
+            "zstd" => Ok(CompressionAlgorithm::Zstd),
+            _ => Err(InvalidCompressionAlgorithm {
+                input: input.to_string(),
+            }),
+        }
+    }
+}
+
 pub const MQTT_MAX_PAYLOAD_SIZE: u32 = 268435455;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Document)]
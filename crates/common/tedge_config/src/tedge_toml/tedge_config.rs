@@ -16,8 +16,10 @@ use super::models::AptConfig;
 use super::models::AutoFlag;
 use super::models::AutoLogUpload;
 use super::models::CloudType;
+use super::models::CompressionAlgorithm;
 use super::models::ConnectUrl;
 use super::models::Cryptoki;
+use super::models::FailoverStrategy;
 use super::models::HostPort;
 use super::models::MqttPayloadLimit;
 use super::models::SecondsOrHumanTime;
@@ -576,6 +578,33 @@ define_tedge_config! {
         organization_unit: Arc<str>,
     },
 
+    mapper: {
+        // Compression of the outbound telemetry, shared by the Cumulocity, Azure and AWS mappers.
+        // The telemetry topics are specific to each cloud, see `<cloud>.mapper.compression`.
+        compression: {
+            /// Batch the outbound telemetry and send each batch compressed to the cloud
+            #[tedge_config(example = "true", default(value = false))]
+            enable: bool,
+
+            /// The algorithm used to compress the batches of telemetry
+            #[tedge_config(note = "zstd is only available when tedge is built with the `zstd` feature")]
+            #[tedge_config(example = "gzip", example = "zstd", default(variable = "CompressionAlgorithm::Gzip"))]
+            algorithm: CompressionAlgorithm,
+
+            /// How long the outbound telemetry messages are batched before being sent
+            #[tedge_config(example = "30s", default(from_str = "10s"))]
+            interval: SecondsOrHumanTime,
+
+            /// The content type of the uncompressed batches, sent as a `content-type` MQTT 5 user property or HTTP header
+            #[tedge_config(example = "application/json", default(value = "application/json"))]
+            content_type: String,
+
+            /// The URL to which the compressed batches are posted, instead of being published over MQTT
+            #[tedge_config(example = "https://telemetry.example.com/ingest")]
+            http_url: Arc<str>,
+        },
+    },
+
     #[tedge_config(multi, reader(private))]
     c8y: {
         #[tedge_config(reader(skip))]
@@ -723,6 +752,16 @@ define_tedge_config! {
                 #[tedge_config(example = "16184", default(function = "c8y_mqtt_payload_limit"))]
                 max_payload_size: MqttPayloadLimit,
             }
+
+            compression: {
+                /// The topics, relative to the bridge topic prefix, of the outbound telemetry messages to be batched when `mapper.compression.enable` is set
+                #[tedge_config(example = "measurement/measurements/create", default(value = "measurement/measurements/create"))]
+                input_topics: TemplatesSet,
+
+                /// The topic, relative to the bridge topic prefix, on which the compressed batches are published
+                #[tedge_config(example = "measurement/compressed", default(value = "measurement/compressed"))]
+                topic: String,
+            },
         },
 
         proxy: {
@@ -782,6 +821,47 @@ define_tedge_config! {
             #[tedge_config(example = "60s", default(from_str = "60s"))]
             keepalive_interval: SecondsOrHumanTime,
 
+            websocket: {
+                /// Tunnel the bridge MQTT connection to the cloud over a secure WebSocket
+                #[tedge_config(note = "This is useful when only outbound HTTPS is allowed. The default MQTT port 8883 is then replaced by 443")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The HTTP path of the cloud WebSocket endpoint
+                #[tedge_config(example = "/mqtt", default(value = "/mqtt"))]
+                path: String,
+
+                /// Extra HTTP headers sent when opening the WebSocket, as `name: value` pairs
+                #[tedge_config(example = "X-Site: plant-1", default(function = "TemplatesSet::default"))]
+                headers: TemplatesSet,
+            },
+
+            failover: {
+                /// Fallback cloud endpoints the bridge connects to when the main endpoint cannot be reached, in order of preference
+                #[tedge_config(example = "eu-2.cumulocity.com:8883", default(function = "TemplatesSet::default"))]
+                urls: TemplatesSet,
+
+                /// How the bridge chooses the next endpoint: primary, round-robin or random
+                #[tedge_config(note = "With 'primary', the bridge goes back to the main endpoint each time the connection is lost")]
+                #[tedge_config(example = "round-robin", default(variable = "FailoverStrategy::Primary"))]
+                strategy: FailoverStrategy,
+
+                /// The number of consecutive connection failures after which the bridge tries the next endpoint
+                #[tedge_config(example = "3", default(value = 3u32))]
+                max_failures: u32,
+            },
+
+            mqtt5: {
+                /// Connect the bridge to the cloud broker using MQTT 5 rather than MQTT 3.1.1
+                #[tedge_config(note = "This enables the message_expiry and user_properties settings of the bridge rules")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The maximum number of topic aliases used by the bridge to shorten the published topics, 0 to disable topic aliases
+                #[tedge_config(note = "The number of aliases is also bounded by the maximum accepted by the cloud broker")]
+                #[tedge_config(example = "16", default(value = 16u16))]
+                topic_alias_max: u16,
+            },
         },
 
         entity_store: {
@@ -899,6 +979,22 @@ define_tedge_config! {
             key_pin: Arc<str>,
         },
 
+        dps: {
+            /// The ID scope of the Azure Device Provisioning Service used to assign the device to an IoT Hub
+            #[tedge_config(note = "The device is enrolled on `tedge connect az` only when `az.url` is not set, i.e. not yet assigned to an IoT Hub")]
+            #[tedge_config(example = "0ne00000A0A")]
+            id_scope: String,
+
+            /// The registration id of the device enrolment, defaulting to the device id
+            #[tedge_config(example = "Raspberrypi-4d18303a-6d3a-11eb-b1a6-175f6bb72665")]
+            registration_id: String,
+
+            /// Endpoint URL of the Azure Device Provisioning Service
+            #[tedge_config(example = "global.azure-devices-provisioning.net")]
+            #[tedge_config(default(from_str = "global.azure-devices-provisioning.net"))]
+            url: ConnectUrl,
+        },
+
         mapper: {
             /// Whether the Azure IoT mapper should add a timestamp or not
             #[tedge_config(example = "true")]
@@ -911,11 +1007,47 @@ define_tedge_config! {
             #[tedge_config(default(variable = "TimeFormat::Unix"))]
             timestamp_format: TimeFormat,
 
+            twin: {
+                /// Synchronise the twin data of the main device with the Azure IoT Hub device twin
+                #[tedge_config(example = "false", default(value = true))]
+                enable: bool,
+
+                /// The command created on desired-property updates, instead of updating the local twin data
+                #[tedge_config(note = "The command payload holds the desired properties and their version")]
+                #[tedge_config(example = "set_desired_properties")]
+                desired_command: String,
+            },
+
+            methods: {
+                /// Map Azure IoT Hub direct methods to thin-edge commands
+                #[tedge_config(example = "false", default(value = true))]
+                enable: bool,
+
+                /// The direct methods mapped to thin-edge operations
+                #[tedge_config(note = "Expect a list of <method-name>:<operation> pairs, a method name alone being mapped to the operation with the same name")]
+                #[tedge_config(example = "restart, reboot:restart", default(from_str = "restart"))]
+                operations: TemplatesSet,
+
+                /// How long to wait for a command to reach a final state before responding to the direct method
+                #[tedge_config(example = "60s", default(from_str = "60s"))]
+                timeout: SecondsOrHumanTime,
+            },
+
             mqtt: {
                 /// The maximum message payload size that can be mapped to the cloud via MQTT
                 #[tedge_config(example = "262144", default(function = "az_mqtt_payload_limit"))]
                 max_payload_size: MqttPayloadLimit,
             }
+
+            compression: {
+                /// The topics, relative to the bridge topic prefix, of the outbound telemetry messages to be batched when `mapper.compression.enable` is set
+                #[tedge_config(example = "messages/events/#", default(value = "messages/events/#"))]
+                input_topics: TemplatesSet,
+
+                /// The topic, relative to the bridge topic prefix, on which the compressed batches are published
+                #[tedge_config(example = "messages/events/compressed=true", default(value = "messages/events/compressed=true"))]
+                topic: String,
+            },
         },
 
         bridge: {
@@ -928,6 +1060,48 @@ define_tedge_config! {
             /// The amount of time after which the bridge should send a ping if no other traffic has occurred
             #[tedge_config(example = "60s", default(from_str = "60s"))]
             keepalive_interval: SecondsOrHumanTime,
+
+            websocket: {
+                /// Tunnel the bridge MQTT connection to the cloud over a secure WebSocket
+                #[tedge_config(note = "This is useful when only outbound HTTPS is allowed. The default MQTT port 8883 is then replaced by 443")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The HTTP path of the cloud WebSocket endpoint
+                #[tedge_config(example = "/$iothub/websocket", default(value = "/$iothub/websocket"))]
+                path: String,
+
+                /// Extra HTTP headers sent when opening the WebSocket, as `name: value` pairs
+                #[tedge_config(example = "X-Site: plant-1", default(function = "TemplatesSet::default"))]
+                headers: TemplatesSet,
+            },
+
+            failover: {
+                /// Fallback cloud endpoints the bridge connects to when the main endpoint cannot be reached, in order of preference
+                #[tedge_config(example = "backup-hub.azure-devices.net", default(function = "TemplatesSet::default"))]
+                urls: TemplatesSet,
+
+                /// How the bridge chooses the next endpoint: primary, round-robin or random
+                #[tedge_config(note = "With 'primary', the bridge goes back to the main endpoint each time the connection is lost")]
+                #[tedge_config(example = "round-robin", default(variable = "FailoverStrategy::Primary"))]
+                strategy: FailoverStrategy,
+
+                /// The number of consecutive connection failures after which the bridge tries the next endpoint
+                #[tedge_config(example = "3", default(value = 3u32))]
+                max_failures: u32,
+            },
+
+            mqtt5: {
+                /// Connect the bridge to the cloud broker using MQTT 5 rather than MQTT 3.1.1
+                #[tedge_config(note = "This enables the message_expiry and user_properties settings of the bridge rules")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The maximum number of topic aliases used by the bridge to shorten the published topics, 0 to disable topic aliases
+                #[tedge_config(note = "The number of aliases is also bounded by the maximum accepted by the cloud broker")]
+                #[tedge_config(example = "16", default(value = 16u16))]
+                topic_alias_max: u16,
+            },
         },
 
         /// Set of MQTT topics the Azure IoT mapper should subscribe to
@@ -999,6 +1173,30 @@ define_tedge_config! {
             key_pin: Arc<str>,
         },
 
+        provisioning: {
+            /// The name of the AWS IoT fleet provisioning template used to register the device on `tedge connect aws`
+            #[tedge_config(note = "The device is only provisioned when there is no device certificate yet")]
+            #[tedge_config(example = "tedge-fleet-template")]
+            template: String,
+
+            /// The template parameters sent on registration, as `name=value` pairs
+            #[tedge_config(note = "The device id is always sent as the `SerialNumber` parameter")]
+            #[tedge_config(example = "Site=plant-1", default(function = "TemplatesSet::default"))]
+            parameters: TemplatesSet,
+
+            claim: {
+                /// Path where the claim certificate shared by the fleet is stored
+                #[tedge_config(example = "/etc/tedge/device-certs/aws-claim-certificate.pem")]
+                #[doku(as = "PathBuf")]
+                cert_path: AbsolutePath,
+
+                /// Path where the private key of the claim certificate is stored
+                #[tedge_config(example = "/etc/tedge/device-certs/aws-claim-private-key.pem")]
+                #[doku(as = "PathBuf")]
+                key_path: AbsolutePath,
+            },
+        },
+
         mapper: {
             /// Whether the AWS IoT mapper should add a timestamp or not
             #[tedge_config(example = "true")]
@@ -1011,11 +1209,50 @@ define_tedge_config! {
             #[tedge_config(default(variable = "TimeFormat::Unix"))]
             timestamp_format: TimeFormat,
 
+            shadow: {
+                /// Synchronise the twin data of the entities with AWS IoT device shadows
+                #[tedge_config(example = "false", default(value = true))]
+                enable: bool,
+
+                /// Synchronise the child devices and services with named shadows of the device thing
+                #[tedge_config(note = "The shadow name is derived from the entity topic identifier, e.g. `device:child1`")]
+                #[tedge_config(example = "false", default(value = true))]
+                named: bool,
+
+                /// The command created on shadow delta updates, instead of updating the local twin data
+                #[tedge_config(note = "The command payload holds the desired state and its version")]
+                #[tedge_config(example = "set_desired_state")]
+                desired_command: String,
+            },
+
+            jobs: {
+                /// Execute the AWS IoT Jobs of the device and its child devices as thin-edge commands
+                #[tedge_config(note = "The jobs of a child device are those of the thing named after the main device and the child, e.g. `<device-id>:device:child1`")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The job operations mapped to thin-edge commands
+                #[tedge_config(note = "Expect a list of <job-operation>:<command> pairs, a job operation alone being mapped to the command with the same name")]
+                #[tedge_config(example = "software_update, reboot:restart")]
+                #[tedge_config(default(from_str = "restart, software_update, config_update, firmware_update"))]
+                operations: TemplatesSet,
+            },
+
             mqtt: {
                 /// The maximum message payload size that can be mapped to the cloud via MQTT
                 #[tedge_config(example = "131072", default(function = "aws_mqtt_payload_limit"))]
                 max_payload_size: MqttPayloadLimit,
             }
+
+            compression: {
+                /// The topics, relative to the bridge topic prefix, of the outbound telemetry messages to be batched when `mapper.compression.enable` is set
+                #[tedge_config(example = "td/#", default(value = "td/#"))]
+                input_topics: TemplatesSet,
+
+                /// The topic, relative to the bridge topic prefix, on which the compressed batches are published
+                #[tedge_config(example = "td/compressed", default(value = "td/compressed"))]
+                topic: String,
+            },
         },
 
         bridge: {
@@ -1029,6 +1266,48 @@ define_tedge_config! {
             /// The amount of time after which the bridge should send a ping if no other traffic has occurred
             #[tedge_config(example = "60s", default(from_str = "60s"))]
             keepalive_interval: SecondsOrHumanTime,
+
+            websocket: {
+                /// Tunnel the bridge MQTT connection to the cloud over a secure WebSocket
+                #[tedge_config(note = "This is useful when only outbound HTTPS is allowed. The default MQTT port 8883 is then replaced by 443")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The HTTP path of the cloud WebSocket endpoint
+                #[tedge_config(example = "/mqtt", default(value = "/mqtt"))]
+                path: String,
+
+                /// Extra HTTP headers sent when opening the WebSocket, as `name: value` pairs
+                #[tedge_config(example = "X-Site: plant-1", default(function = "TemplatesSet::default"))]
+                headers: TemplatesSet,
+            },
+
+            failover: {
+                /// Fallback cloud endpoints the bridge connects to when the main endpoint cannot be reached, in order of preference
+                #[tedge_config(example = "backup.iot.eu-west-1.amazonaws.com", default(function = "TemplatesSet::default"))]
+                urls: TemplatesSet,
+
+                /// How the bridge chooses the next endpoint: primary, round-robin or random
+                #[tedge_config(note = "With 'primary', the bridge goes back to the main endpoint each time the connection is lost")]
+                #[tedge_config(example = "round-robin", default(variable = "FailoverStrategy::Primary"))]
+                strategy: FailoverStrategy,
+
+                /// The number of consecutive connection failures after which the bridge tries the next endpoint
+                #[tedge_config(example = "3", default(value = 3u32))]
+                max_failures: u32,
+            },
+
+            mqtt5: {
+                /// Connect the bridge to the cloud broker using MQTT 5 rather than MQTT 3.1.1
+                #[tedge_config(note = "This enables the message_expiry and user_properties settings of the bridge rules")]
+                #[tedge_config(example = "true", default(value = false))]
+                enable: bool,
+
+                /// The maximum number of topic aliases used by the bridge to shorten the published topics, 0 to disable topic aliases
+                #[tedge_config(note = "The number of aliases is also bounded by the maximum accepted by the cloud broker")]
+                #[tedge_config(example = "16", default(value = 16u16))]
+                topic_alias_max: u16,
+            },
         },
 
         /// Set of MQTT topics the AWS IoT mapper should subscribe to
@@ -1165,6 +1444,49 @@ define_tedge_config! {
                 #[tedge_config(example = "5m", default(from_str = "5m"))]
                 reset_window: SecondsOrHumanTime,
             },
+
+            queue: {
+                /// Persist the messages forwarded by the built-in bridge to the cloud until acknowledged by the cloud
+                #[tedge_config(example = "true", default(value = false))]
+                #[tedge_config(note = "The queue is stored under `data.path`")]
+                outbound: bool,
+
+                /// Persist the messages forwarded by the built-in bridge from the cloud until acknowledged by the local broker
+                #[tedge_config(example = "true", default(value = false))]
+                inbound: bool,
+
+                /// The maximum size in bytes of the messages persisted by each bridge queue
+                #[tedge_config(example = "104857600", default(value = 104857600u64))]
+                max_size: u64,
+
+                /// The age after which the messages that can be dropped are removed from the bridge queues
+                #[tedge_config(example = "1d", default(from_str = "1d"))]
+                max_age: SecondsOrHumanTime,
+
+                /// Filters of the topics on which are received the messages dropped oldest first when a bridge queue is full or too old
+                ///
+                /// The messages received on other topics are never dropped.
+                #[tedge_config(example = "te/+/+/+/+/m/+,c8y/measurement/measurements/create", default(value = "te/+/+/+/+/m/+,c8y/measurement/measurements/create,c8y/measurement/measurements/createBulk,aws/td/+/m/+"))]
+                #[tedge_config(note = "By default, only the measurements are dropped, be they sent to the local broker or to the cloud")]
+                drop_oldest: TemplatesSet,
+
+                /// The maximum number of queued messages forwarded per second, 0 for no limit
+                #[tedge_config(example = "100", default(value = 100u32))]
+                drain_rate: u32,
+            },
+
+            priority: {
+                /// The number of messages of a priority class published in a row by the built-in bridge while messages of a lower class are waiting, 0 for strict priority
+                #[tedge_config(example = "10", default(value = 10u32))]
+                quota: u32,
+            },
+
+            stats: {
+                /// How often the traffic counters of the built-in bridge are published as measurements of the bridge service, 0 to disable
+                #[tedge_config(example = "5m", default(from_str = "60s"))]
+                #[tedge_config(note = "The last published counters are also shown by `tedge bridge inspect --stats`")]
+                interval: SecondsOrHumanTime,
+            },
         },
     },
 
@@ -1247,6 +1569,37 @@ define_tedge_config! {
             /// When set to `true`, the entity store is re-created from the retained entity registration MQTT messages.
             #[tedge_config(example = "true", default(value = false), deprecated_key = "c8y.entity_store.clean_start")]
             clean_start: bool,
+
+            expiry: {
+                /// Time since the last message, health status or twin update after which a child device is marked as inactive
+                #[tedge_config(note = "Child devices never expire when this setting is not set.")]
+                #[tedge_config(example = "30d", example = "12h")]
+                child_device_ttl: SecondsOrHumanTime,
+
+                /// Time since the last message, health status or twin update after which a service is marked as inactive
+                #[tedge_config(note = "Services never expire when this setting is not set.")]
+                #[tedge_config(example = "7d", example = "1h")]
+                service_ttl: SecondsOrHumanTime,
+
+                /// Time an inactive entity is kept in the entity store before being deregistered
+                #[tedge_config(example = "7d", default(from_str = "1d"))]
+                grace_period: SecondsOrHumanTime,
+
+                /// How often the agent checks for inactive and expired entities
+                #[tedge_config(example = "5m", default(from_str = "1m"))]
+                check_interval: SecondsOrHumanTime,
+            },
+
+            twin_schemas: {
+                /// Directory of the JSON schemas twin fragments are checked against, one `<fragment>.json` file per fragment,
+                /// optionally in a `device`, `child-device` or `service` sub-directory to only apply to that type of entity
+                #[tedge_config(example = "/etc/tedge/twin-schemas", default(function = "default_twin_schemas_path"))]
+                path: AbsolutePath,
+
+                /// Reject the twin updates that don't conform to their schema, instead of only logging a warning
+                #[tedge_config(example = "true", default(value = false))]
+                enforce: bool,
+            },
         },
 
 
@@ -1620,6 +1973,9 @@ fn az_device_id(
     }
 }
 
+/// The common name of the certificates issued by AWS IoT, which doesn't identify the device
+const AWS_ISSUED_CERTIFICATE_CN: &str = "AWS IoT Certificate";
+
 fn aws_device_id(
     aws_device: &TEdgeConfigReaderAwsDevice,
     dto_value: &OptionalConfig<String>,
@@ -1628,6 +1984,10 @@ fn aws_device_id(
         device_id_from_cert(&aws_device.cert_path),
         dto_value.or_none(),
     ) {
+        // A certificate issued on fleet provisioning doesn't hold the thing name
+        (Ok(common_name), Some(dto_value)) if common_name == AWS_ISSUED_CERTIFICATE_CN => {
+            Ok(dto_value.to_string())
+        }
         (Ok(common_name), _) => Ok(common_name),
         (Err(_), Some(dto_value)) => Ok(dto_value.to_string()),
         (Err(err), None) => Err(err),
@@ -1695,6 +2055,14 @@ fn default_credentials_path(location: &TEdgeConfigLocation) -> AbsolutePath {
         .unwrap()
 }
 
+fn default_twin_schemas_path(location: &TEdgeConfigLocation) -> AbsolutePath {
+    location
+        .tedge_config_root_path()
+        .join("twin-schemas")
+        .try_into()
+        .unwrap()
+}
+
 fn default_mqtt_port() -> NonZeroU16 {
     NonZeroU16::try_from(1883).unwrap()
 }
@@ -50,6 +50,8 @@ impl_append_remove_for_single_value!(
     TopicPrefix,
     SoftwareManagementApiFlag,
     AutoLogUpload,
+    FailoverStrategy,
+    CompressionAlgorithm,
     TimeFormat,
     NonZeroU16,
     SecondsOrHumanTime,
@@ -176,6 +176,15 @@ impl FromCloudConfig for AzMapperSpecificConfig {
             mapper: AzCloudMapperConfig {
                 timestamp: az.mapper.timestamp,
                 timestamp_format: az.mapper.timestamp_format,
+                twin: AzTwinConfig {
+                    enable: az.mapper.twin.enable,
+                    desired_command: az.mapper.twin.desired_command.or_none().cloned(),
+                },
+                methods: AzMethodsConfig {
+                    enable: az.mapper.methods.enable,
+                    operations: operation_pairs(&az.mapper.methods.operations),
+                    timeout: az.mapper.methods.timeout.duration(),
+                },
             },
         }
     }
@@ -213,11 +222,32 @@ impl FromCloudConfig for AwsMapperSpecificConfig {
             mapper: AwsCloudMapperConfig {
                 timestamp: aws.mapper.timestamp,
                 timestamp_format: aws.mapper.timestamp_format,
+                shadow: AwsShadowConfig {
+                    enable: aws.mapper.shadow.enable,
+                    named: aws.mapper.shadow.named,
+                    desired_command: aws.mapper.shadow.desired_command.or_none().cloned(),
+                },
+                jobs: AwsJobsConfig {
+                    enable: aws.mapper.jobs.enable,
+                    operations: operation_pairs(&aws.mapper.jobs.operations),
+                },
             },
         }
     }
 }
 
+/// Parse a list of `<name>:<operation>` pairs, a name alone being mapped to the operation with the same name
+fn operation_pairs(operations: &TemplatesSet) -> Vec<(String, String)> {
+    operations
+        .0
+        .iter()
+        .map(|name| match name.split_once(':') {
+            Some((name, operation)) => (name.trim().to_owned(), operation.trim().to_owned()),
+            None => (name.trim().to_owned(), name.trim().to_owned()),
+        })
+        .collect()
+}
+
 /// Generic helper to build MapperConfig from any cloud config reader
 pub fn build_mapper_config<T>(
     cloud_config: T::CloudConfigReader,
@@ -244,6 +274,9 @@ where
     let bridge = BridgeConfig {
         topic_prefix: cloud_config.bridge_topic_prefix(profile),
         keepalive_interval: cloud_config.bridge_keepalive_interval().clone(),
+        websocket: cloud_config.bridge_websocket(),
+        failover: cloud_config.bridge_failover(),
+        mqtt5: cloud_config.bridge_mqtt5(),
     };
 
     let topics = cloud_config.topics().clone();
@@ -252,6 +285,8 @@ where
 
     let max_payload_size = cloud_config.max_payload_size();
 
+    let compression = cloud_config.mapper_compression();
+
     let cloud_specific = T::from_cloud_config(&cloud_config, profile);
 
     Ok(MapperConfig {
@@ -263,6 +298,7 @@ where
         bridge,
         mapper: CommonMapperConfig {
             mqtt: MqttConfig { max_payload_size },
+            compression,
         },
         cloud_specific,
     })
@@ -283,9 +319,13 @@ pub trait CloudConfigAccessor {
     fn device_key_pin(&self) -> Option<Arc<str>>;
     fn bridge_topic_prefix(&self, profile: Option<&str>) -> Keyed<TopicPrefix>;
     fn bridge_keepalive_interval(&self) -> &SecondsOrHumanTime;
+    fn bridge_websocket(&self) -> BridgeWebSocketConfig;
+    fn bridge_failover(&self) -> BridgeFailoverConfig;
+    fn bridge_mqtt5(&self) -> BridgeMqtt5Config;
     fn topics(&self) -> &TemplatesSet;
     fn root_cert_path(&self, profile: Option<&str>) -> Keyed<AbsolutePath>;
     fn max_payload_size(&self) -> MqttPayloadLimit;
+    fn mapper_compression(&self) -> CompressionTopics;
 }
 
 impl CloudConfigAccessor for TEdgeConfigReaderC8y {
@@ -332,6 +372,29 @@ impl CloudConfigAccessor for TEdgeConfigReaderC8y {
         &self.bridge.keepalive_interval
     }
 
+    fn bridge_websocket(&self) -> BridgeWebSocketConfig {
+        BridgeWebSocketConfig {
+            enable: self.bridge.websocket.enable,
+            path: self.bridge.websocket.path.clone(),
+            headers: self.bridge.websocket.headers.clone(),
+        }
+    }
+
+    fn bridge_failover(&self) -> BridgeFailoverConfig {
+        BridgeFailoverConfig {
+            urls: self.bridge.failover.urls.clone(),
+            strategy: self.bridge.failover.strategy,
+            max_failures: self.bridge.failover.max_failures,
+        }
+    }
+
+    fn bridge_mqtt5(&self) -> BridgeMqtt5Config {
+        BridgeMqtt5Config {
+            enable: self.bridge.mqtt5.enable,
+            topic_alias_max: self.bridge.mqtt5.topic_alias_max,
+        }
+    }
+
     fn topics(&self) -> &TemplatesSet {
         &self.topics
     }
@@ -346,6 +409,13 @@ impl CloudConfigAccessor for TEdgeConfigReaderC8y {
     fn max_payload_size(&self) -> MqttPayloadLimit {
         self.mapper.mqtt.max_payload_size
     }
+
+    fn mapper_compression(&self) -> CompressionTopics {
+        CompressionTopics {
+            input_topics: self.mapper.compression.input_topics.clone(),
+            topic: self.mapper.compression.topic.clone(),
+        }
+    }
 }
 
 impl CloudConfigAccessor for TEdgeConfigReaderAz {
@@ -392,6 +462,29 @@ impl CloudConfigAccessor for TEdgeConfigReaderAz {
         &self.bridge.keepalive_interval
     }
 
+    fn bridge_websocket(&self) -> BridgeWebSocketConfig {
+        BridgeWebSocketConfig {
+            enable: self.bridge.websocket.enable,
+            path: self.bridge.websocket.path.clone(),
+            headers: self.bridge.websocket.headers.clone(),
+        }
+    }
+
+    fn bridge_failover(&self) -> BridgeFailoverConfig {
+        BridgeFailoverConfig {
+            urls: self.bridge.failover.urls.clone(),
+            strategy: self.bridge.failover.strategy,
+            max_failures: self.bridge.failover.max_failures,
+        }
+    }
+
+    fn bridge_mqtt5(&self) -> BridgeMqtt5Config {
+        BridgeMqtt5Config {
+            enable: self.bridge.mqtt5.enable,
+            topic_alias_max: self.bridge.mqtt5.topic_alias_max,
+        }
+    }
+
     fn topics(&self) -> &TemplatesSet {
         &self.topics
     }
@@ -406,6 +499,13 @@ impl CloudConfigAccessor for TEdgeConfigReaderAz {
     fn max_payload_size(&self) -> MqttPayloadLimit {
         self.mapper.mqtt.max_payload_size
     }
+
+    fn mapper_compression(&self) -> CompressionTopics {
+        CompressionTopics {
+            input_topics: self.mapper.compression.input_topics.clone(),
+            topic: self.mapper.compression.topic.clone(),
+        }
+    }
 }
 
 impl CloudConfigAccessor for TEdgeConfigReaderAws {
@@ -452,6 +552,29 @@ impl CloudConfigAccessor for TEdgeConfigReaderAws {
         &self.bridge.keepalive_interval
     }
 
+    fn bridge_websocket(&self) -> BridgeWebSocketConfig {
+        BridgeWebSocketConfig {
+            enable: self.bridge.websocket.enable,
+            path: self.bridge.websocket.path.clone(),
+            headers: self.bridge.websocket.headers.clone(),
+        }
+    }
+
+    fn bridge_failover(&self) -> BridgeFailoverConfig {
+        BridgeFailoverConfig {
+            urls: self.bridge.failover.urls.clone(),
+            strategy: self.bridge.failover.strategy,
+            max_failures: self.bridge.failover.max_failures,
+        }
+    }
+
+    fn bridge_mqtt5(&self) -> BridgeMqtt5Config {
+        BridgeMqtt5Config {
+            enable: self.bridge.mqtt5.enable,
+            topic_alias_max: self.bridge.mqtt5.topic_alias_max,
+        }
+    }
+
     fn topics(&self) -> &TemplatesSet {
         &self.topics
     }
@@ -466,6 +589,13 @@ impl CloudConfigAccessor for TEdgeConfigReaderAws {
     fn max_payload_size(&self) -> MqttPayloadLimit {
         self.mapper.mqtt.max_payload_size
     }
+
+    fn mapper_compression(&self) -> CompressionTopics {
+        CompressionTopics {
+            input_topics: self.mapper.compression.input_topics.clone(),
+            topic: self.mapper.compression.topic.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
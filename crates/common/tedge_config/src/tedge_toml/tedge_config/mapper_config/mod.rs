@@ -15,6 +15,7 @@ use super::super::models::AbsolutePath;
 use super::super::models::AutoFlag;
 use super::super::models::AutoLogUpload;
 use super::super::models::ConnectUrl;
+use super::super::models::FailoverStrategy;
 use super::super::models::HostPort;
 use super::super::models::MqttPayloadLimit;
 use super::super::models::SecondsOrHumanTime;
@@ -36,6 +37,7 @@ use std::fmt::Display;
 use std::net::IpAddr;
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::Duration;
 use tedge_config_macros::MultiDto;
 use tedge_config_macros::ProfileName;
 
@@ -89,6 +91,48 @@ pub struct BridgeConfig {
 
     /// The amount of time after which the bridge should send a ping
     pub keepalive_interval: SecondsOrHumanTime,
+
+    /// Settings to tunnel the bridge connection over a WebSocket
+    pub websocket: BridgeWebSocketConfig,
+
+    /// Fallback endpoints of the bridge connection
+    pub failover: BridgeFailoverConfig,
+
+    /// MQTT 5 settings of the bridge connection
+    pub mqtt5: BridgeMqtt5Config,
+}
+
+/// MQTT 5 settings of the bridge connection
+pub struct BridgeMqtt5Config {
+    /// Whether the bridge connects to the cloud using MQTT 5
+    pub enable: bool,
+
+    /// The maximum number of topic aliases used on the connection
+    pub topic_alias_max: u16,
+}
+
+/// Failover settings of the bridge connection
+pub struct BridgeFailoverConfig {
+    /// Fallback cloud endpoints, in order of preference
+    pub urls: TemplatesSet,
+
+    /// How the next endpoint is chosen
+    pub strategy: FailoverStrategy,
+
+    /// The number of consecutive connection failures before trying the next endpoint
+    pub max_failures: u32,
+}
+
+/// WebSocket transport settings of the bridge connection
+pub struct BridgeWebSocketConfig {
+    /// Whether the bridge connection is tunneled over a WebSocket
+    pub enable: bool,
+
+    /// The HTTP path of the cloud WebSocket endpoint
+    pub path: String,
+
+    /// Extra HTTP headers, as `name: value` pairs
+    pub headers: TemplatesSet,
 }
 
 pub struct C8yBridgeConfig {
@@ -251,6 +295,35 @@ pub struct AwsCloudMapperConfig {
 
     /// The timestamp format to use
     pub timestamp_format: TimeFormat,
+
+    /// Device shadow synchronisation
+    pub shadow: AwsShadowConfig,
+
+    /// AWS IoT Jobs execution
+    pub jobs: AwsJobsConfig,
+}
+
+/// AWS IoT device shadow synchronisation settings
+#[derive(Clone, Debug, Default)]
+pub struct AwsShadowConfig {
+    /// Whether the twin data of the entities is synchronised with device shadows
+    pub enable: bool,
+
+    /// Whether child devices and services are synchronised with named shadows
+    pub named: bool,
+
+    /// The command created on delta updates, if any
+    pub desired_command: Option<String>,
+}
+
+/// AWS IoT Jobs settings
+#[derive(Clone, Debug, Default)]
+pub struct AwsJobsConfig {
+    /// Whether AWS IoT Jobs are executed as thin-edge commands
+    pub enable: bool,
+
+    /// The thin-edge command associated to each supported job operation
+    pub operations: Vec<(String, String)>,
 }
 
 /// Azure cloud-specific mapper configuration
@@ -260,10 +333,42 @@ pub struct AzCloudMapperConfig {
 
     /// The timestamp format to use
     pub timestamp_format: TimeFormat,
+
+    /// Device twin synchronisation
+    pub twin: AzTwinConfig,
+
+    /// Direct methods mapping
+    pub methods: AzMethodsConfig,
+}
+
+/// Azure IoT Hub device twin synchronisation settings
+#[derive(Clone, Debug, Default)]
+pub struct AzTwinConfig {
+    /// Whether the twin data of the main device is synchronised with the device twin
+    pub enable: bool,
+
+    /// The command created on desired-property updates, if any
+    pub desired_command: Option<String>,
+}
+
+/// Azure IoT Hub direct methods settings
+#[derive(Clone, Debug, Default)]
+pub struct AzMethodsConfig {
+    /// Whether direct methods are mapped to thin-edge commands
+    pub enable: bool,
+
+    /// The thin-edge operation associated to each supported method
+    pub operations: Vec<(String, String)>,
+
+    /// How long to wait for a command to reach a final state
+    pub timeout: Duration,
 }
 
 pub struct CommonMapperConfig {
     pub mqtt: MqttConfig,
+
+    /// The telemetry topics compressed when `mapper.compression.enable` is set
+    pub compression: CompressionTopics,
 }
 
 pub struct MqttConfig {
@@ -271,6 +376,17 @@ pub struct MqttConfig {
     pub max_payload_size: MqttPayloadLimit,
 }
 
+/// The cloud specific topics of the compressed telemetry
+///
+/// The other compression settings are shared by all the cloud mappers (see `mapper.compression`).
+pub struct CompressionTopics {
+    /// The topics of the telemetry messages to batch, relative to the bridge topic prefix
+    pub input_topics: TemplatesSet,
+
+    /// The topic of the compressed batches, relative to the bridge topic prefix
+    pub topic: String,
+}
+
 /// SmartREST configuration for Cumulocity
 pub struct SmartrestConfig {
     /// Set of SmartREST template IDs the device should subscribe to
@@ -3,6 +3,7 @@ pub mod file;
 pub mod fs;
 pub mod http;
 pub mod paths;
+pub mod protobuf;
 pub mod signals;
 pub mod timers;
 
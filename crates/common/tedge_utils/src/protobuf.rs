@@ -0,0 +1,68 @@
+//! A minimal protobuf encoder, for the few messages thin-edge builds without generated code
+//!
+//! Each function appends a field to a buffer using the protobuf wire format.
+//! A nested message is encoded into its own buffer, then appended with [encode_bytes_field].
+
+// Protobuf wire types
+pub const VARINT: u64 = 0;
+pub const FIXED64: u64 = 1;
+pub const LENGTH_DELIMITED: u64 = 2;
+pub const FIXED32: u64 = 5;
+
+/// Append the key of a field, i.e. its number and wire type
+pub fn encode_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    encode_varint(buf, (field << 3) | wire_type)
+}
+
+pub fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Append an integer or boolean field
+pub fn encode_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    encode_key(buf, field, VARINT);
+    encode_varint(buf, value);
+}
+
+/// Append a double field
+pub fn encode_fixed64_field(buf: &mut Vec<u8>, field: u64, value: f64) {
+    encode_key(buf, field, FIXED64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append a float field
+pub fn encode_fixed32_field(buf: &mut Vec<u8>, field: u64, value: f32) {
+    encode_key(buf, field, FIXED32);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append a length-delimited field (a string, bytes or a nested message)
+pub fn encode_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_key(buf, field, LENGTH_DELIMITED);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_fields() {
+        let mut buf = vec![];
+        encode_varint_field(&mut buf, 1, 150);
+        assert_eq!(buf, [0x08, 0x96, 0x01]);
+
+        let mut buf = vec![];
+        encode_bytes_field(&mut buf, 2, b"testing");
+        assert_eq!(buf, b"\x12\x07testing");
+
+        let mut buf = vec![];
+        encode_fixed64_field(&mut buf, 1, 1.0);
+        assert_eq!(buf, [0x09, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+    }
+}
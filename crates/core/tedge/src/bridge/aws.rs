@@ -27,6 +27,7 @@ pub struct BridgeConfigAwsParams {
     pub mqtt_schema: MqttSchema,
     pub keepalive_interval: Duration,
     pub proxy: Option<rumqttc::Proxy>,
+    pub jobs: bool,
 }
 
 impl From<BridgeConfigAwsParams> for BridgeConfig {
@@ -44,6 +45,7 @@ impl From<BridgeConfigAwsParams> for BridgeConfig {
             mqtt_schema,
             keepalive_interval,
             proxy,
+            jobs,
         } = params;
 
         let user_name = remote_clientid.to_string();
@@ -56,6 +58,9 @@ impl From<BridgeConfigAwsParams> for BridgeConfig {
         let shadow_topic =
             format!("shadow/# both 1 {topic_prefix}/ $aws/things/{remote_clientid}/");
 
+        // topics to execute the jobs of the device thing and of its child things
+        let jobs_topic = format!("+/jobs/# both 1 {topic_prefix}/things/ $aws/things/");
+
         // echo topic mapping to check the connection
         let connection_check_pub_msg_topic = format!(
             r#""" out 1 {topic_prefix}/test-connection thinedge/devices/{remote_clientid}/test-connection"#
@@ -64,6 +69,17 @@ impl From<BridgeConfigAwsParams> for BridgeConfig {
             r#""" in 1 {topic_prefix}/connection-success thinedge/devices/{remote_clientid}/test-connection"#
         );
 
+        let mut topics = vec![
+            pub_msg_topic,
+            sub_msg_topic,
+            shadow_topic,
+            connection_check_pub_msg_topic,
+            connection_check_sub_msg_topic,
+        ];
+        if jobs {
+            topics.push(jobs_topic);
+        }
+
         let service_name = format!("mosquitto-{topic_prefix}-bridge");
         let health = mqtt_schema.topic_for(
             &EntityTopicId::default_main_service(&service_name).unwrap(),
@@ -100,13 +116,7 @@ impl From<BridgeConfigAwsParams> for BridgeConfig {
             notifications_local_only: true,
             notification_topic: health.name,
             bridge_attempt_unsubscribe: false,
-            topics: vec![
-                pub_msg_topic,
-                sub_msg_topic,
-                shadow_topic,
-                connection_check_pub_msg_topic,
-                connection_check_sub_msg_topic,
-            ],
+            topics,
             bridge_location,
             // AWS IoT Just In Time Provisioning (JITP) uses the first connection
             // to create the "Thing", so the first connection attempt can fail, but retrying
@@ -135,6 +145,7 @@ fn test_bridge_config_from_aws_params() -> anyhow::Result<()> {
         mqtt_schema: MqttSchema::with_root("te".into()),
         keepalive_interval: Duration::from_secs(60),
         proxy: None,
+        jobs: false,
     };
 
     let bridge = BridgeConfig::from(params);
@@ -197,6 +208,7 @@ fn test_bridge_config_aws_custom_topic_prefix() -> anyhow::Result<()> {
         mqtt_schema: MqttSchema::with_root("te".into()),
         keepalive_interval: Duration::from_secs(60),
         proxy: None,
+        jobs: false,
     };
 
     let bridge = BridgeConfig::from(params);
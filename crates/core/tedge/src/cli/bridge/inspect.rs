@@ -4,6 +4,8 @@ use pad::PadStr;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_mqtt_bridge::config_toml::Direction;
 use tedge_mqtt_bridge::config_toml::ExpandedBridgeRule;
+use tedge_mqtt_bridge::BridgeStats;
+use tedge_mqtt_bridge::DirectionStats;
 use yansi::Paint as _;
 
 use super::common::load_bridge_rules;
@@ -34,6 +36,10 @@ pub struct BridgeInspectCmd {
     /// Show skipped rules (e.g. due to unmet conditions or empty template loops)
     #[clap(long)]
     show_all: bool,
+
+    /// Show the traffic counters last published by the running bridge, rather than its rules
+    #[clap(long)]
+    stats: bool,
 }
 
 #[async_trait::async_trait]
@@ -44,6 +50,10 @@ impl Command for BridgeInspectCmd {
 
     #[mutants::skip]
     async fn execute(&self, config: TEdgeConfig) -> Result<(), MaybeFancy<anyhow::Error>> {
+        if self.stats {
+            run_inspect_stats(&mut std::io::stdout(), self, &config)?;
+            return Ok(());
+        }
         tedge_mapper::warn_misconfigured_mapper_dirs(&config.root_dir().join("mappers")).await;
         let detail = if self.show_all {
             DetailLevel::Debug
@@ -124,6 +134,132 @@ async fn run_inspect(
     Ok(())
 }
 
+fn run_inspect_stats(
+    w: &mut impl Write,
+    cmd: &BridgeInspectCmd,
+    config: &TEdgeConfig,
+) -> anyhow::Result<()> {
+    let service_name = bridge_service_name(cmd, config)?;
+    let path = tedge_mqtt_bridge::stats_path(config, &service_name);
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            writeln!(
+                w,
+                "{}",
+                format!("No bridge stats found for {service_name}").yellow()
+            )?;
+            writeln!(
+                w,
+                "The stats are saved by the running bridge every `mqtt.bridge.stats.interval`, at: {}",
+                path.bright_blue()
+            )?;
+            return Ok(());
+        }
+        Err(err) => return Err(anyhow::anyhow!("Failed to read {path}: {err}")),
+    };
+    let stats: BridgeStats = serde_json::from_slice(&content)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {path}: {err}"))?;
+
+    writeln!(w, "{} {}", "Bridge traffic for".bold(), service_name.bold())?;
+    writeln!(w, "Reading from: {}", path.bright_blue())?;
+    writeln!(w)?;
+    print_stats(w, &stats);
+    Ok(())
+}
+
+/// The name of the bridge service of a cloud or custom mapper
+fn bridge_service_name(cmd: &BridgeInspectCmd, config: &TEdgeConfig) -> anyhow::Result<String> {
+    let prefix = match resolve_cloud(&cmd.cloud, cmd.profile.clone()) {
+        Some(cloud) => match &cloud {
+            #[cfg(feature = "c8y")]
+            Cloud::C8y(_) => {
+                use tedge_config::tedge_toml::mapper_config::C8yMapperSpecificConfig;
+                let c8y = config.mapper_config::<C8yMapperSpecificConfig>(&cloud.profile_name())?;
+                c8y.bridge.topic_prefix.to_string()
+            }
+            #[cfg(feature = "aws")]
+            Cloud::Aws(_) => {
+                use tedge_config::tedge_toml::mapper_config::AwsMapperSpecificConfig;
+                let aws = config.mapper_config::<AwsMapperSpecificConfig>(&cloud.profile_name())?;
+                aws.bridge.topic_prefix.to_string()
+            }
+            #[cfg(feature = "azure")]
+            Cloud::Azure(_) => {
+                use tedge_config::tedge_toml::mapper_config::AzMapperSpecificConfig;
+                let az = config.mapper_config::<AzMapperSpecificConfig>(&cloud.profile_name())?;
+                az.bridge.topic_prefix.to_string()
+            }
+            Cloud::Custom(_) => unreachable!("resolve_cloud never returns Custom"),
+        },
+        None => cmd.cloud.clone(),
+    };
+    Ok(format!("tedge-mapper-bridge-{prefix}"))
+}
+
+fn print_stats(w: &mut impl Write, stats: &BridgeStats) {
+    let _ = writeln!(
+        w,
+        "{} {} {}",
+        "Local".bold().bright_blue(),
+        "->".bold(),
+        "Remote".bold().green()
+    );
+    print_direction_stats(w, &stats.outbound);
+    let _ = writeln!(
+        w,
+        "{} {} {}",
+        "Remote".bold().green(),
+        "->".bold(),
+        "Local".bold().bright_blue()
+    );
+    print_direction_stats(w, &stats.inbound);
+}
+
+fn print_direction_stats(w: &mut impl Write, stats: &DirectionStats) {
+    let counters = [
+        (
+            "forwarded",
+            format!(
+                "{} messages, {} bytes",
+                stats.forwarded, stats.forwarded_bytes
+            ),
+        ),
+        ("dropped", stats.dropped.to_string()),
+        ("duplicated", stats.duplicated.to_string()),
+        ("acknowledged", stats.acknowledged.to_string()),
+        ("in flight", stats.in_flight.to_string()),
+    ];
+    for (name, value) in counters {
+        let _ = writeln!(w, "  {}  {value}", name.pad_to_width(12).dim());
+    }
+    if let (Some(avg), Some(max)) = (stats.ack_latency_avg_ms, stats.ack_latency_max_ms) {
+        let _ = writeln!(
+            w,
+            "  {}  avg {avg:.1} ms, max {max:.1} ms",
+            "ack latency".pad_to_width(12).dim()
+        );
+    }
+
+    let rules: Vec<_> = stats
+        .rules
+        .iter()
+        .filter(|rule| rule.forwarded > 0 || rule.dropped > 0)
+        .collect();
+    let max_width = rules.iter().map(|r| r.topic.len()).max().unwrap_or(0);
+    for rule in rules {
+        let _ = writeln!(
+            w,
+            "  {}  {} messages, {} bytes, {} dropped",
+            rule.topic.pad_to_width(max_width),
+            rule.forwarded,
+            rule.forwarded_bytes,
+            rule.dropped
+        );
+    }
+    let _ = writeln!(w);
+}
+
 fn print_rules(w: &mut impl Write, rules: Vec<ExpandedBridgeRule>) {
     let (bidir, dir): (Vec<_>, Vec<_>) = rules
         .into_iter()
@@ -160,12 +296,13 @@ fn print_outbound_rules(w: &mut impl Write, rules: &[ExpandedBridgeRule]) {
             let remote = format!("{}{}", rule.remote_prefix, rule.topic);
             let _ = writeln!(
                 w,
-                "  {}  {}  {}",
+                "  {}  {}  {}{}",
                 local
                     .pad_to_width_with_alignment(max_width, pad::Alignment::Left)
                     .bright_blue(),
                 "->".bold(),
-                remote.green()
+                remote.green(),
+                display_options(rule)
             );
         }
     }
@@ -195,12 +332,13 @@ fn print_inbound_rules(w: &mut impl Write, rules: &[ExpandedBridgeRule]) {
             let local = format!("{}{}", rule.local_prefix, rule.topic);
             let _ = writeln!(
                 w,
-                "  {}  {}  {}",
+                "  {}  {}  {}{}",
                 remote
                     .pad_to_width_with_alignment(max_width, pad::Alignment::Left)
                     .green(),
                 "->".bold(),
-                local.bright_blue()
+                local.bright_blue(),
+                display_options(rule)
             );
         }
     }
@@ -224,21 +362,35 @@ fn print_bidirectional_rules(w: &mut impl Write, rules: &[ExpandedBridgeRule]) {
             let remote = format!("{}{}", rule.remote_prefix, rule.topic);
             let _ = writeln!(
                 w,
-                "  {}  {}  {}",
+                "  {}  {}  {}{}",
                 local
                     .pad_to_width_with_alignment(max_width, pad::Alignment::Left)
                     .bright_blue(),
                 "<->".bold().yellow(),
-                remote.green()
+                remote.green(),
+                display_options(rule)
             );
         }
     }
 }
 
+/// The publish settings overridden by a rule, if any
+fn display_options(rule: &ExpandedBridgeRule) -> String {
+    if rule.options.is_default() {
+        String::new()
+    } else {
+        format!("  [{}]", rule.options).dim().to_string()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cli::bridge::common::render;
     use crate::cli::bridge::common::strip_ansi;
+    use tedge_mqtt_bridge::config_toml::RetainPolicy;
+    use tedge_mqtt_bridge::QoS;
+    use tedge_mqtt_bridge::RateLimit;
+    use tedge_mqtt_bridge::RuleOptions;
 
     use super::*;
 
@@ -315,6 +467,29 @@ mod tests {
         assert!(output.contains("health"));
     }
 
+    #[test]
+    fn rules_show_their_overridden_settings() {
+        let mut debug = rule(Direction::Outbound, "c8y/", "", "debug/#");
+        debug.options = RuleOptions {
+            qos: Some(QoS::AtMostOnce),
+            retain: RetainPolicy::Strip,
+            rate_limit: Some(RateLimit::new(10.0)),
+            ..RuleOptions::default()
+        };
+        let rules = vec![debug, rule(Direction::Outbound, "c8y/", "", "s/us")];
+
+        let output = render(|w| print_outbound_rules(w, &rules));
+
+        pretty_assertions::assert_eq!(
+            output,
+            "\
+Local -> Remote
+  c8y/debug/#  ->  debug/#  [qos=0 retain=strip max_rate=10/s burst=10]
+  c8y/s/us     ->  s/us
+\n"
+        );
+    }
+
     #[test]
     fn outbound_rules_aligns_columns() {
         let rules = vec![
@@ -706,12 +881,107 @@ Bidirectional
         );
     }
 
+    #[test]
+    fn stats_show_counters_per_direction_and_active_rule() {
+        let stats = BridgeStats {
+            outbound: DirectionStats {
+                forwarded: 12,
+                forwarded_bytes: 3400,
+                dropped: 1,
+                duplicated: 0,
+                acknowledged: 11,
+                in_flight: 1,
+                ack_latency_avg_ms: Some(12.5),
+                ack_latency_max_ms: Some(40.0),
+                rules: vec![
+                    rule_stats("c8y/s/us", 4, 400, 0),
+                    rule_stats("te/+/+/+/+/m/+", 8, 3000, 1),
+                    rule_stats("c8y/inventory/managedObjects/update/#", 0, 0, 0),
+                ],
+            },
+            inbound: DirectionStats::default(),
+        };
+
+        let output = render(|w| print_stats(w, &stats));
+
+        pretty_assertions::assert_eq!(
+            output,
+            "\
+Local -> Remote
+  forwarded     12 messages, 3400 bytes
+  dropped       1
+  duplicated    0
+  acknowledged  11
+  in flight     1
+  ack latency   avg 12.5 ms, max 40.0 ms
+  c8y/s/us        4 messages, 400 bytes, 0 dropped
+  te/+/+/+/+/m/+  8 messages, 3000 bytes, 1 dropped
+
+Remote -> Local
+  forwarded     0 messages, 0 bytes
+  dropped       0
+  duplicated    0
+  acknowledged  0
+  in flight     0
+
+"
+        );
+    }
+
+    #[test]
+    fn stats_are_read_from_the_file_of_the_bridge_service() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = config_with_root(
+            tmp.path(),
+            &c8y_toml(&format!(
+                "c8y.bridge.topic_prefix = \"c8y-eu\"\ndata.path = \"{}\"",
+                tmp.path().join("data").display()
+            )),
+        );
+        let path = tmp.path().join("data/bridge/tedge-mapper-bridge-c8y-eu");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(
+            path.join("stats.json"),
+            r#"{"outbound":{"forwarded":3,"forwarded_bytes":42,"dropped":0,"duplicated":0,"acknowledged":3,"in_flight":0},
+                "inbound":{"forwarded":1,"forwarded_bytes":7,"dropped":0,"duplicated":0,"acknowledged":1,"in_flight":0}}"#,
+        )
+        .unwrap();
+
+        let output = render_inspect_stats("c8y", &config);
+
+        assert!(
+            output.contains("Bridge traffic for tedge-mapper-bridge-c8y-eu"),
+            "output was: {output}"
+        );
+        assert!(
+            output.contains("forwarded     3 messages, 42 bytes"),
+            "output was: {output}"
+        );
+    }
+
+    #[test]
+    fn missing_stats_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = config_with_root(
+            tmp.path(),
+            &format!("data.path = \"{}\"", tmp.path().join("data").display()),
+        );
+
+        let output = render_inspect_stats("my-mapper", &config);
+
+        assert!(
+            output.contains("No bridge stats found for tedge-mapper-bridge-my-mapper"),
+            "output was: {output}"
+        );
+    }
+
     #[test]
     fn description_includes_cloud_name() {
         let cmd = BridgeInspectCmd {
             cloud: "c8y".to_string(),
             profile: None,
             show_all: false,
+            stats: false,
         };
         assert_eq!(
             cmd.description(),
@@ -813,6 +1083,7 @@ topic = "data"
             local_prefix: local_prefix.into(),
             remote_prefix: remote_prefix.into(),
             topic: topic.into(),
+            options: RuleOptions::default(),
         }
     }
 
@@ -869,6 +1140,32 @@ topic = "data"
         )
     }
 
+    fn render_inspect_stats(cloud: &str, config: &TEdgeConfig) -> String {
+        let cmd = BridgeInspectCmd {
+            cloud: cloud.to_string(),
+            profile: None,
+            show_all: false,
+            stats: true,
+        };
+        let mut buf = Vec::new();
+        run_inspect_stats(&mut buf, &cmd, config).unwrap();
+        strip_ansi(&String::from_utf8(buf).unwrap())
+    }
+
+    fn rule_stats(
+        topic: &str,
+        forwarded: u64,
+        forwarded_bytes: u64,
+        dropped: u64,
+    ) -> tedge_mqtt_bridge::RuleStats {
+        tedge_mqtt_bridge::RuleStats {
+            topic: topic.to_owned(),
+            forwarded,
+            forwarded_bytes,
+            dropped,
+        }
+    }
+
     /// Create the mosquitto config file that signals the cloud is connected
     fn mark_connected(root: &std::path::Path, cloud: &Cloud) {
         let dir = root.join("mosquitto-conf");
@@ -897,6 +1194,7 @@ topic = "data"
             cloud: cloud.to_string(),
             profile,
             show_all: detail == DetailLevel::Debug,
+            stats: false,
         };
         let rt = tokio::runtime::Runtime::new().unwrap();
         let mut buf = Vec::new();
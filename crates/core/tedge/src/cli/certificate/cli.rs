@@ -228,25 +228,8 @@ pub enum CA {
 #[async_trait::async_trait]
 impl BuildCommand for TEdgeCertCli {
     async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
-        let (user, group) = if config.mqtt.bridge.built_in {
-            let system_config = config.read_system_config();
-            (system_config.user, system_config.group)
-        } else {
-            (crate::BROKER_USER.to_owned(), crate::BROKER_USER.to_owned())
-        };
-
-        let csr_template = CsrTemplate {
-            max_cn_size: 64,
-            validity_period_days: config
-                .certificate
-                .validity
-                .requested_duration
-                .duration()
-                .as_secs() as u32
-                / (24 * 3600),
-            organization_name: config.certificate.organization.to_string(),
-            organizational_unit_name: config.certificate.organization_unit.to_string(),
-        };
+        let (user, group) = certificate_owner(config);
+        let csr_template = csr_template(config);
 
         let cmd = match self {
             TEdgeCertCli::Create { id, cloud } => {
@@ -548,6 +531,32 @@ impl BuildCommand for TEdgeCertCli {
     }
 }
 
+/// The user and group owning the device private key and certificate
+pub(crate) fn certificate_owner(config: &TEdgeConfig) -> (String, String) {
+    if config.mqtt.bridge.built_in {
+        let system_config = config.read_system_config();
+        (system_config.user, system_config.group)
+    } else {
+        (crate::BROKER_USER.to_owned(), crate::BROKER_USER.to_owned())
+    }
+}
+
+/// The template used to create the device certificate signing requests
+pub(crate) fn csr_template(config: &TEdgeConfig) -> CsrTemplate {
+    CsrTemplate {
+        max_cn_size: 64,
+        validity_period_days: config
+            .certificate
+            .validity
+            .requested_duration
+            .duration()
+            .as_secs() as u32
+            / (24 * 3600),
+        organization_name: config.certificate.organization.to_string(),
+        organizational_unit_name: config.certificate.organization_unit.to_string(),
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 pub enum UploadCertCli {
     /// Upload root certificate to Cumulocity
@@ -5,7 +5,7 @@ use tokio::io::AsyncReadExt;
 mod c8y;
 mod cli;
 mod create;
-mod create_csr;
+pub(crate) mod create_csr;
 mod create_key;
 mod error;
 mod remove;
@@ -1,17 +1,42 @@
 use super::command::bridge_health_topic;
 use super::command::is_bridge_health_up_message;
+use crate::cli::certificate::certificate_owner;
+use crate::cli::certificate::create_csr::CreateCsrCmd;
+use crate::cli::certificate::create_csr::Key;
+use crate::cli::certificate::csr_template;
+use crate::cli::certificate::read_cert_to_string;
+use crate::cli::connect::update_cloud_profile;
+use crate::cli::connect::CONNECTION_TIMEOUT;
+use crate::cli::connect::MQTT_TLS_PORT;
 use crate::cli::RESPONSE_TIMEOUT;
+use crate::override_public_key;
 use crate::ConnectError;
 use crate::DeviceStatus;
 use anyhow::anyhow;
+use anyhow::Context as _;
+use camino::Utf8PathBuf;
+use rumqttc::AsyncClient;
 use rumqttc::Event;
 use rumqttc::Incoming;
+use rumqttc::MqttOptions;
 use rumqttc::Outgoing;
 use rumqttc::Packet;
 use rumqttc::QoS::AtLeastOnce;
+use rumqttc::SubscribeFilter;
+use rumqttc::Transport;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use tedge_config::models::HostPort;
 use tedge_config::tedge_toml::mapper_config::AwsMapperSpecificConfig;
+use tedge_config::tedge_toml::MqttAuthClientConfigCloudBroker;
+use tedge_config::tedge_toml::MqttAuthConfigCloudBroker;
+use tedge_config::tedge_toml::PrivateKeyType;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
+use tedge_utils::file::path_exists;
+
+const CREATE_FROM_CSR_TOPIC: &str = "$aws/certificates/create-from-csr/json";
 
 pub async fn check_device_status_aws(
     tedge_config: &TEdgeConfig,
@@ -127,3 +152,454 @@ pub async fn check_device_status_aws(
             .into()),
     }
 }
+
+/// Return true when the device has to be provisioned on AWS IoT
+///
+/// This is the case when a fleet provisioning template is configured and the device has no certificate yet.
+pub async fn fleet_provisioning_required(
+    tedge_config: &TEdgeConfig,
+    profile: Option<&ProfileName>,
+) -> anyhow::Result<bool> {
+    let aws_reader = tedge_config.aws_reader(profile.map(|p| p.as_ref()))?;
+    if aws_reader.provisioning.template.or_none().is_none() {
+        return Ok(false);
+    }
+    let aws_config = tedge_config.mapper_config::<AwsMapperSpecificConfig>(&profile)?;
+    Ok(!path_exists(&aws_config.device.cert_path).await)
+}
+
+/// Provision the device on AWS IoT using fleet provisioning by claim
+///
+/// The device private key never leaves the device (or the PKCS#11 token):
+/// - a CSR is created for the device key, as with `tedge cert create-csr aws`
+/// - the device connects to AWS IoT using the claim certificate shared by the fleet
+/// - a device certificate is requested for the CSR on `$aws/certificates/create-from-csr/json`
+/// - the thing is registered on `$aws/provisioning-templates/{template}/provision/json`
+/// - the issued certificate and the thing name are persisted into the `aws` profile
+///
+/// Return the name of the provisioned thing.
+pub async fn provision_device_aws(
+    tedge_config: &TEdgeConfig,
+    profile: Option<&ProfileName>,
+) -> anyhow::Result<String> {
+    let aws_reader = tedge_config.aws_reader(profile.map(|p| p.as_ref()))?;
+    let provisioning = &aws_reader.provisioning;
+    let template = provisioning.template.or_config_not_set()?;
+    let aws_config = tedge_config.mapper_config::<AwsMapperSpecificConfig>(&profile)?;
+    let cert_path: Utf8PathBuf = aws_config.device.cert_path.clone().into();
+
+    let device_id = aws_config.device.id().context(
+        "The device id must be set with `tedge config set device.id <id>` to provision the device",
+    )?;
+    let claim = MqttAuthConfigCloudBroker {
+        ca_path: aws_config.root_cert_path.clone().into(),
+        client: Some(MqttAuthClientConfigCloudBroker {
+            cert_file: provisioning
+                .claim
+                .cert_path
+                .or_config_not_set()?
+                .clone()
+                .into(),
+            private_key: PrivateKeyType::File(
+                provisioning
+                    .claim
+                    .key_path
+                    .or_config_not_set()?
+                    .clone()
+                    .into(),
+            ),
+        }),
+    };
+
+    let key = match tedge_config.device.cryptoki_config(Some(&aws_config))? {
+        Some(cryptoki) => Key::Cryptoki(cryptoki),
+        None => Key::Local(aws_config.device.key_path.clone().into()),
+    };
+    let (user, group) = certificate_owner(tedge_config);
+    let csr_path: Utf8PathBuf = aws_config.device.csr_path.clone().into();
+    let create_csr = CreateCsrCmd {
+        id: device_id.clone(),
+        key,
+        csr_path: csr_path.clone(),
+        current_cert: None,
+        user,
+        group,
+        csr_template: csr_template(tedge_config),
+    };
+    create_csr.create_certificate_signing_request().await?;
+    let csr = read_cert_to_string(&csr_path).await?;
+
+    let address =
+        HostPort::<MQTT_TLS_PORT>::try_from(aws_config.url().or_config_not_set()?.as_str())?;
+    let mut mqtt_options = MqttOptions::new(
+        device_id.clone(),
+        address.host().to_string(),
+        address.port().into(),
+    );
+    mqtt_options.set_keep_alive(RESPONSE_TIMEOUT);
+    mqtt_options.set_transport(Transport::tls_with_config(
+        claim.to_rustls_client_config()?.into(),
+    ));
+
+    let parameters = template_parameters(device_id, &provisioning.parameters.0)?;
+    let (certificate_pem, thing_name) =
+        register_thing(mqtt_options, template, &csr, parameters).await?;
+
+    // The thing name is persisted first,
+    // as the device id cannot be derived from a certificate issued by AWS
+    update_cloud_profile(tedge_config, "aws", profile, &[("device.id", &thing_name)]).await?;
+    override_public_key(&cert_path, certificate_pem).await?;
+
+    Ok(thing_name)
+}
+
+/// Request a device certificate and register the thing, using the claim certificate of the MQTT options
+///
+/// Return the issued certificate and the name of the registered thing.
+async fn register_thing(
+    mqtt_options: MqttOptions,
+    template: &str,
+    csr: &str,
+    parameters: Map<String, Value>,
+) -> anyhow::Result<(String, String)> {
+    let provision_topic = format!("$aws/provisioning-templates/{template}/provision/json");
+    let create_accepted = format!("{CREATE_FROM_CSR_TOPIC}/accepted");
+    let provision_accepted = format!("{provision_topic}/accepted");
+
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
+    event_loop
+        .network_options
+        .set_connection_timeout(CONNECTION_TIMEOUT.as_secs());
+
+    let mut certificate_pem = None;
+    let res = loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                client
+                    .subscribe_many([
+                        SubscribeFilter::new(format!("{CREATE_FROM_CSR_TOPIC}/+"), AtLeastOnce),
+                        SubscribeFilter::new(format!("{provision_topic}/+"), AtLeastOnce),
+                    ])
+                    .await?;
+            }
+            Ok(Event::Incoming(Packet::SubAck(_))) => {
+                // We are ready to get the response, hence send the request
+                let request = serde_json::json!({ "certificateSigningRequest": csr });
+                client
+                    .publish(
+                        CREATE_FROM_CSR_TOPIC,
+                        AtLeastOnce,
+                        false,
+                        request.to_string(),
+                    )
+                    .await?;
+            }
+            Ok(Event::Incoming(Packet::Publish(response))) => {
+                if response.topic == create_accepted {
+                    let certificate: CreateCertificateResponse =
+                        serde_json::from_slice(&response.payload)
+                            .context("Invalid response from AWS IoT to the certificate request")?;
+                    let request = serde_json::json!({
+                        "certificateOwnershipToken": certificate.certificate_ownership_token,
+                        "parameters": parameters,
+                    });
+                    client
+                        .publish(&provision_topic, AtLeastOnce, false, request.to_string())
+                        .await?;
+                    certificate_pem = Some(certificate.certificate_pem);
+                } else if response.topic == provision_accepted {
+                    let registration: ProvisionResponse = serde_json::from_slice(&response.payload)
+                        .context("Invalid response from AWS IoT to the thing registration")?;
+                    match certificate_pem.take() {
+                        Some(certificate_pem) => {
+                            break Ok((certificate_pem, registration.thing_name))
+                        }
+                        None => {
+                            break Err(anyhow!(
+                            "Thing registered by AWS IoT before the certificate has been issued"
+                        ))
+                        }
+                    }
+                } else if response.topic.ends_with("/rejected") {
+                    break Err(rejection_error(&response.topic, &response.payload));
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::PingReq)) => {
+                // No messages have been received for a while
+                break Err(anyhow!(
+                    "Timed-out waiting for a response from AWS IoT fleet provisioning"
+                ));
+            }
+            Ok(Event::Incoming(Incoming::Disconnect)) => {
+                break Err(anyhow!(
+                    "Disconnected by AWS IoT. Does the claim certificate policy allow fleet provisioning?"
+                ));
+            }
+            Err(e) => {
+                break Err(anyhow::Error::from(e)
+                    .context("Failed to connect to AWS IoT with the claim certificate"));
+            }
+            _ => {}
+        }
+    };
+
+    // Cleanly disconnect client
+    client.disconnect().await?;
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    res
+}
+
+/// The parameters sent to the provisioning template
+///
+/// The device id is sent as the `SerialNumber`, unless overridden by the configured `name=value` pairs.
+fn template_parameters(
+    device_id: &str,
+    parameters: &[String],
+) -> anyhow::Result<Map<String, Value>> {
+    let mut template_parameters = Map::new();
+    template_parameters.insert("SerialNumber".to_string(), device_id.into());
+    for parameter in parameters {
+        let Some((name, value)) = parameter.split_once('=') else {
+            anyhow::bail!(
+                "Invalid provisioning template parameter: expected name=value, found: {parameter}"
+            );
+        };
+        template_parameters.insert(name.trim().to_string(), value.trim().into());
+    }
+    Ok(template_parameters)
+}
+
+fn rejection_error(topic: &str, payload: &[u8]) -> anyhow::Error {
+    let request = if topic.starts_with(CREATE_FROM_CSR_TOPIC) {
+        "Certificate request"
+    } else {
+        "Thing registration"
+    };
+    match serde_json::from_slice::<ErrorResponse>(payload) {
+        Ok(error) => anyhow!(
+            "{request} rejected by AWS IoT: {} ({}: {})",
+            error.error_message,
+            error.status_code,
+            error.error_code
+        ),
+        Err(_) => anyhow!(
+            "{request} rejected by AWS IoT: {}",
+            String::from_utf8_lossy(payload)
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateCertificateResponse {
+    certificate_pem: String,
+    certificate_ownership_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProvisionResponse {
+    thing_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorResponse {
+    status_code: u16,
+    error_code: String,
+    error_message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::mqttbytes;
+    use rumqttc::mqttbytes::v4;
+    use rumqttc::QoS;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    #[test]
+    fn the_device_id_is_sent_as_serial_number() {
+        let parameters = template_parameters("device-001", &[]).unwrap();
+        assert_eq!(
+            Value::Object(parameters),
+            json!({"SerialNumber": "device-001"})
+        );
+    }
+
+    #[test]
+    fn configured_parameters_are_sent_to_the_template() {
+        let parameters = template_parameters(
+            "device-001",
+            &[
+                "Site=plant-1".to_string(),
+                "SerialNumber = SN-42".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            Value::Object(parameters),
+            json!({"SerialNumber": "SN-42", "Site": "plant-1"})
+        );
+
+        assert!(template_parameters("device-001", &["Site".to_string()]).is_err());
+    }
+
+    #[test]
+    fn rejections_are_reported_with_the_aws_error() {
+        let error = rejection_error(
+            "$aws/provisioning-templates/fleet/provision/json/rejected",
+            br#"{"statusCode":400,"errorCode":"InvalidParametersException","errorMessage":"Missing parameter: SerialNumber"}"#,
+        );
+        assert_eq!(
+            error.to_string(),
+            "Thing registration rejected by AWS IoT: Missing parameter: SerialNumber (400: InvalidParametersException)"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_thing_is_registered_with_the_certificate_issued_by_aws() {
+        let (mqtt_options, aws) =
+            fake_aws_iot_endpoint("accepted", json!({"thingName": "device-001"})).await;
+        let parameters = template_parameters("device-001", &[]).unwrap();
+
+        let (certificate_pem, thing_name) = tokio::time::timeout(
+            Duration::from_secs(5),
+            register_thing(mqtt_options, "fleet", "CSR", parameters),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(certificate_pem, "ISSUED CERTIFICATE");
+        assert_eq!(thing_name, "device-001");
+        assert_eq!(
+            aws.await.unwrap(),
+            vec![
+                (
+                    "$aws/certificates/create-from-csr/json".to_string(),
+                    json!({"certificateSigningRequest": "CSR"})
+                ),
+                (
+                    "$aws/provisioning-templates/fleet/provision/json".to_string(),
+                    json!({
+                        "certificateOwnershipToken": "OWNERSHIP TOKEN",
+                        "parameters": {"SerialNumber": "device-001"}
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_registration_rejected_by_aws_is_reported_as_an_error() {
+        let (mqtt_options, aws) = fake_aws_iot_endpoint(
+            "rejected",
+            json!({
+                "statusCode": 400,
+                "errorCode": "InvalidParametersException",
+                "errorMessage": "Missing parameter: Site"
+            }),
+        )
+        .await;
+        let parameters = template_parameters("device-001", &[]).unwrap();
+
+        let error = tokio::time::timeout(
+            Duration::from_secs(5),
+            register_thing(mqtt_options, "fleet", "CSR", parameters),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Thing registration rejected by AWS IoT: Missing parameter: Site (400: InvalidParametersException)"
+        );
+        assert_eq!(aws.await.unwrap().len(), 2);
+    }
+
+    /// Launch a plain-TCP MQTT endpoint playing the AWS IoT fleet provisioning API
+    ///
+    /// The certificate request is always accepted,
+    /// while the thing registration is answered on the given `outcome` topic with the given `response`.
+    /// The returned task completes with the requests received by the endpoint, once the client disconnects.
+    async fn fake_aws_iot_endpoint(
+        outcome: &'static str,
+        response: Value,
+    ) -> (MqttOptions, JoinHandle<Vec<(String, Value)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let endpoint = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut incoming = BytesMut::new();
+            let mut requests = Vec::new();
+            loop {
+                let packet = match v4::read(&mut incoming, 1024 * 1024) {
+                    Ok(packet) => packet,
+                    Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                        if stream.read_buf(&mut incoming).await.unwrap() == 0 {
+                            return requests;
+                        }
+                        continue;
+                    }
+                    Err(err) => panic!("Invalid MQTT packet: {err}"),
+                };
+
+                let mut outgoing = BytesMut::new();
+                match packet {
+                    v4::Packet::Connect(_) => {
+                        v4::ConnAck::new(v4::ConnectReturnCode::Success, false)
+                            .write(&mut outgoing)
+                            .unwrap();
+                    }
+                    v4::Packet::Subscribe(subscribe) => {
+                        let codes = subscribe
+                            .filters
+                            .iter()
+                            .map(|_| v4::SubscribeReasonCode::Success(QoS::AtLeastOnce))
+                            .collect();
+                        v4::SubAck::new(subscribe.pkid, codes)
+                            .write(&mut outgoing)
+                            .unwrap();
+                    }
+                    v4::Packet::Publish(request) => {
+                        v4::PubAck::new(request.pkid).write(&mut outgoing).unwrap();
+                        let (topic, payload) = if request.topic == CREATE_FROM_CSR_TOPIC {
+                            let certificate = json!({
+                                "certificatePem": "ISSUED CERTIFICATE",
+                                "certificateOwnershipToken": "OWNERSHIP TOKEN",
+                            });
+                            (format!("{}/accepted", request.topic), certificate)
+                        } else {
+                            (format!("{}/{outcome}", request.topic), response.clone())
+                        };
+                        v4::Publish::new(topic, QoS::AtMostOnce, payload.to_string())
+                            .write(&mut outgoing)
+                            .unwrap();
+                        let payload = serde_json::from_slice(&request.payload).unwrap();
+                        requests.push((request.topic, payload));
+                    }
+                    v4::Packet::Disconnect => return requests,
+                    _ => continue,
+                }
+                stream.write_all(&outgoing).await.unwrap();
+            }
+        });
+
+        (MqttOptions::new("claim", "127.0.0.1", port), endpoint)
+    }
+}
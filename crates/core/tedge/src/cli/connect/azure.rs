@@ -1,17 +1,31 @@
 use crate::cli::bridge_health_topic;
+use crate::cli::connect::update_cloud_profile;
+use crate::cli::connect::CONNECTION_TIMEOUT;
+use crate::cli::connect::MQTT_TLS_PORT;
 use crate::cli::RESPONSE_TIMEOUT;
 use crate::ConnectError;
 use crate::DeviceStatus;
 use anyhow::anyhow;
+use anyhow::Context as _;
+use rumqttc::AsyncClient;
 use rumqttc::Event;
 use rumqttc::Incoming;
+use rumqttc::MqttOptions;
 use rumqttc::Outgoing;
 use rumqttc::Packet;
 use rumqttc::QoS::AtLeastOnce;
+use rumqttc::Transport;
+use serde::Deserialize;
+use std::time::Duration;
+use tedge_config::models::HostPort;
 use tedge_config::tedge_toml::mapper_config::AzMapperSpecificConfig;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
 
+const DPS_API_VERSION: &str = "2019-03-31";
+const DPS_RESPONSE_TOPIC: &str = "$dps/registrations/res/";
+const DPS_DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(3);
+
 // Here We check the az device twin properties over mqtt to check if connection has been open.
 // First the mqtt client will subscribe to a topic az/$iothub/twin/res/#, listen to the
 // device twin property output.
@@ -132,3 +146,349 @@ pub(crate) async fn check_device_status_azure(
             .into()),
     }
 }
+
+/// Return true when the device has to be enrolled with the Azure Device Provisioning Service
+///
+/// This is the case when a DPS ID scope is configured and no IoT Hub has been assigned yet.
+/// The IoT Hub assigned by a previous enrolment is kept, unless `reprovision` is requested.
+pub(crate) fn dps_provisioning_required(
+    tedge_config: &TEdgeConfig,
+    profile: Option<&ProfileName>,
+    reprovision: bool,
+) -> anyhow::Result<bool> {
+    let az_reader = tedge_config.az_reader(profile.map(|p| p.as_ref()))?;
+    if az_reader.dps.id_scope.or_none().is_none() {
+        if reprovision {
+            anyhow::bail!("The device cannot be re-provisioned: az.dps.id_scope is not set");
+        }
+        return Ok(false);
+    }
+    Ok(reprovision || az_reader.url.or_none().is_none())
+}
+
+/// The IoT Hub assignment returned by the Azure Device Provisioning Service
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct DpsRegistration {
+    pub assigned_hub: String,
+    pub device_id: String,
+}
+
+/// Enrol the device with the Azure Device Provisioning Service, using X.509 attestation
+///
+/// - the device connects to DPS using its certificate,
+///   the registration id being the DPS registration id, if set, or the device id
+/// - the registration is requested on `$dps/registrations/PUT/iotdps-register`
+/// - the operation status is polled on `$dps/registrations/GET/iotdps-get-operationstatus`
+///   till the device is assigned to an IoT Hub
+/// - the assigned hub URL and device id are persisted into the `az` profile
+pub(crate) async fn provision_device_azure(
+    tedge_config: &TEdgeConfig,
+    profile: Option<&ProfileName>,
+) -> anyhow::Result<DpsRegistration> {
+    let az_reader = tedge_config.az_reader(profile.map(|p| p.as_ref()))?;
+    let dps = &az_reader.dps;
+    let id_scope = dps.id_scope.or_config_not_set()?;
+    let az_config = tedge_config.mapper_config::<AzMapperSpecificConfig>(&profile)?;
+    let registration_id = match dps.registration_id.or_none() {
+        Some(registration_id) => registration_id.clone(),
+        None => az_config
+            .device
+            .id()
+            .context("The DPS registration id is derived from the device id, which is not set")?
+            .clone(),
+    };
+
+    let address = HostPort::<MQTT_TLS_PORT>::try_from(dps.url.as_str())?;
+    let mut mqtt_options = MqttOptions::new(
+        registration_id.clone(),
+        address.host().to_string(),
+        address.port().into(),
+    );
+    mqtt_options.set_keep_alive(RESPONSE_TIMEOUT);
+    mqtt_options.set_credentials(
+        format!("{id_scope}/registrations/{registration_id}/api-version={DPS_API_VERSION}"),
+        "",
+    );
+    let tls_config = tedge_config
+        .mqtt_auth_config_cloud_broker(&az_config)?
+        .to_rustls_client_config()?;
+    mqtt_options.set_transport(Transport::tls_with_config(tls_config.into()));
+
+    let registration = tokio::time::timeout(
+        CONNECTION_TIMEOUT,
+        register_device(mqtt_options, &registration_id),
+    )
+    .await
+    .map_err(|_| anyhow!("Timed-out waiting for the device to be assigned by Azure DPS"))??;
+
+    update_cloud_profile(
+        tedge_config,
+        "az",
+        profile,
+        &[
+            ("url", &registration.assigned_hub),
+            ("device.id", &registration.device_id),
+        ],
+    )
+    .await?;
+
+    Ok(registration)
+}
+
+async fn register_device(
+    mqtt_options: MqttOptions,
+    registration_id: &str,
+) -> anyhow::Result<DpsRegistration> {
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
+    event_loop
+        .network_options
+        .set_connection_timeout(CONNECTION_TIMEOUT.as_secs());
+
+    let mut request_id = 0;
+    let res = loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                client
+                    .subscribe(format!("{DPS_RESPONSE_TOPIC}#"), AtLeastOnce)
+                    .await?;
+            }
+            Ok(Event::Incoming(Packet::SubAck(_))) => {
+                // We are ready to get the response, hence send the request
+                request_id += 1;
+                let request = serde_json::json!({ "registrationId": registration_id });
+                client
+                    .publish(
+                        format!("$dps/registrations/PUT/iotdps-register/?$rid={request_id}"),
+                        AtLeastOnce,
+                        false,
+                        request.to_string(),
+                    )
+                    .await?;
+            }
+            Ok(Event::Incoming(Packet::Publish(response))) => {
+                match parse_dps_response(&response.topic, &response.payload) {
+                    Ok(DpsStatus::Assigning {
+                        operation_id,
+                        retry_after,
+                    }) => {
+                        // Poll the operation status, without blocking the event loop
+                        request_id += 1;
+                        let client = client.clone();
+                        let topic = format!("$dps/registrations/GET/iotdps-get-operationstatus/?$rid={request_id}&operationId={operation_id}");
+                        tokio::spawn(async move {
+                            tokio::time::sleep(retry_after).await;
+                            client.publish(topic, AtLeastOnce, false, "").await
+                        });
+                    }
+                    Ok(DpsStatus::Assigned(registration)) => break Ok(registration),
+                    Err(err) => break Err(err),
+                }
+            }
+            Ok(Event::Incoming(Incoming::Disconnect)) => {
+                break Err(anyhow!(
+                    "Disconnected by Azure DPS. Is the device enrolled with its certificate?"
+                ));
+            }
+            Err(e) => {
+                break Err(anyhow::Error::from(e).context("Failed to connect to Azure DPS"));
+            }
+            _ => {}
+        }
+    };
+
+    // Cleanly disconnect client
+    client.disconnect().await?;
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    res
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum DpsStatus {
+    Assigning {
+        operation_id: String,
+        retry_after: Duration,
+    },
+    Assigned(DpsRegistration),
+}
+
+/// Parse a response received from DPS on `$dps/registrations/res/{status}/?$rid={rid}&retry-after={seconds}`
+fn parse_dps_response(topic: &str, payload: &[u8]) -> anyhow::Result<DpsStatus> {
+    let Some((status, query)) = topic
+        .strip_prefix(DPS_RESPONSE_TOPIC)
+        .and_then(|response| response.split_once("/?"))
+    else {
+        return Err(anyhow!("Unexpected response from Azure DPS on {topic}"));
+    };
+
+    if status != "200" && status != "202" {
+        let error: DpsError = serde_json::from_slice(payload).unwrap_or_default();
+        return Err(anyhow!(
+            "Registration rejected by Azure DPS: {} ({status}: {})",
+            error.message,
+            error.error_code
+        ));
+    }
+
+    let operation: DpsOperation =
+        serde_json::from_slice(payload).context("Invalid response from Azure DPS")?;
+    match operation.status.as_str() {
+        "assigning" | "unassigned" => {
+            let retry_after = query
+                .split('&')
+                .find_map(|param| param.strip_prefix("retry-after="))
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DPS_DEFAULT_RETRY_AFTER);
+            Ok(DpsStatus::Assigning {
+                operation_id: operation.operation_id,
+                retry_after,
+            })
+        }
+        "assigned" => match operation.registration_state {
+            Some(DpsRegistrationState {
+                assigned_hub: Some(assigned_hub),
+                device_id: Some(device_id),
+                ..
+            }) => Ok(DpsStatus::Assigned(DpsRegistration {
+                assigned_hub,
+                device_id,
+            })),
+            _ => Err(anyhow!(
+                "Azure DPS assigned the device without providing an IoT Hub"
+            )),
+        },
+        status => {
+            let reason = operation
+                .registration_state
+                .and_then(|state| state.error_message)
+                .unwrap_or_default();
+            Err(anyhow!("Azure DPS registration is {status}: {reason}"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DpsOperation {
+    operation_id: String,
+    status: String,
+    registration_state: Option<DpsRegistrationState>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DpsRegistrationState {
+    assigned_hub: Option<String>,
+    device_id: Option<String>,
+    error_message: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DpsError {
+    #[serde(default)]
+    error_code: u32,
+    #[serde(default)]
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provisioning_is_only_required_till_a_hub_is_assigned() {
+        let config = TEdgeConfig::load_toml_str("");
+        assert!(!dps_provisioning_required(&config, None, false).unwrap());
+
+        let config = TEdgeConfig::load_toml_str("az.dps.id_scope = \"0ne00000A0A\"");
+        assert!(dps_provisioning_required(&config, None, false).unwrap());
+
+        let config = TEdgeConfig::load_toml_str(
+            "az.dps.id_scope = \"0ne00000A0A\"\naz.url = \"assigned-hub.azure-devices.net\"",
+        );
+        assert!(!dps_provisioning_required(&config, None, false).unwrap());
+    }
+
+    #[test]
+    fn reprovisioning_ignores_the_assigned_hub() {
+        let config = TEdgeConfig::load_toml_str(
+            "az.dps.id_scope = \"0ne00000A0A\"\naz.url = \"assigned-hub.azure-devices.net\"",
+        );
+        assert!(dps_provisioning_required(&config, None, true).unwrap());
+
+        // Without DPS, there is nothing to re-provision with
+        let config = TEdgeConfig::load_toml_str("az.url = \"my-hub.azure-devices.net\"");
+        assert!(dps_provisioning_required(&config, None, true).is_err());
+    }
+
+    #[test]
+    fn pending_registrations_are_polled_after_the_retry_delay() {
+        let status = parse_dps_response(
+            "$dps/registrations/res/202/?$rid=1&retry-after=5",
+            br#"{"operationId":"4.d0a671905ea5b2c8.42","status":"assigning"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            DpsStatus::Assigning {
+                operation_id: "4.d0a671905ea5b2c8.42".to_string(),
+                retry_after: Duration::from_secs(5)
+            }
+        );
+    }
+
+    #[test]
+    fn the_assigned_hub_and_device_id_are_returned() {
+        let status = parse_dps_response(
+            "$dps/registrations/res/200/?$rid=2",
+            br#"{
+                "operationId":"4.d0a671905ea5b2c8.42",
+                "status":"assigned",
+                "registrationState":{
+                    "registrationId":"device-001",
+                    "assignedHub":"my-hub.azure-devices.net",
+                    "deviceId":"device-001",
+                    "status":"assigned"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            DpsStatus::Assigned(DpsRegistration {
+                assigned_hub: "my-hub.azure-devices.net".to_string(),
+                device_id: "device-001".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn registration_errors_are_reported() {
+        let err = parse_dps_response(
+            "$dps/registrations/res/401/?$rid=1",
+            br#"{"errorCode":401002,"message":"Unauthorized"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Registration rejected by Azure DPS: Unauthorized (401: 401002)"
+        );
+
+        let err = parse_dps_response(
+            "$dps/registrations/res/200/?$rid=2",
+            br#"{"operationId":"42","status":"disabled","registrationState":{"errorMessage":"Enrollment disabled"}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Azure DPS registration is disabled: Enrollment disabled"
+        );
+    }
+}
@@ -19,6 +19,11 @@ pub struct TEdgeConnectOpt {
     #[clap(long = "offline")]
     offline_mode: bool,
 
+    /// Enrol the device again with the Azure Device Provisioning Service,
+    /// rather than keeping the IoT Hub assigned by a previous enrolment
+    #[clap(long)]
+    reprovision: bool,
+
     /// The cloud or custom mapper to connect to (e.g. c8y, aws, az, or a custom mapper name)
     #[arg(add(ArgValueCandidates::new(mapper_name_completions)))]
     cloud: String,
@@ -40,6 +45,7 @@ impl BuildCommand for TEdgeConnectOpt {
         let Self {
             is_test_connection,
             offline_mode,
+            reprovision,
             cloud,
             profile,
         } = self;
@@ -53,6 +59,7 @@ impl BuildCommand for TEdgeConnectOpt {
             cloud,
             is_test_connection,
             offline_mode,
+            reprovision,
             is_reconnect: false,
         }))
     }
@@ -23,6 +23,8 @@ use crate::warning;
 use crate::ConfigError;
 use anyhow::anyhow;
 use anyhow::bail;
+#[cfg(any(feature = "aws", feature = "azure"))]
+use anyhow::Context as _;
 #[cfg(feature = "c8y")]
 use c8y_api::http_proxy::read_c8y_credentials;
 use camino::Utf8PathBuf;
@@ -55,9 +57,11 @@ use tedge_config::tedge_toml::mapper_config::C8yMapperSpecificConfig;
 use tedge_config::tedge_toml::mapper_config::HasUrl;
 use tedge_config::tedge_toml::mapper_config::MapperConfig;
 use tedge_config::tedge_toml::mapper_config::SpecialisedCloudConfig;
-#[cfg(feature = "c8y")]
+#[cfg(any(feature = "aws", feature = "azure", feature = "c8y"))]
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::tedge_toml::TEdgeConfigReaderMqtt;
+#[cfg(any(feature = "aws", feature = "azure"))]
+use tedge_config::tedge_toml::WritableKey;
 use tedge_config::TEdgeConfig;
 #[cfg(any(feature = "aws", feature = "azure"))]
 use tedge_config::TEdgeConfigError;
@@ -68,16 +72,18 @@ use tracing::warn;
 use yansi::Paint as _;
 
 pub(crate) const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
-#[cfg(feature = "c8y")]
+#[cfg(any(feature = "aws", feature = "azure", feature = "c8y"))]
 pub(crate) const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
 const MOSQUITTO_RESTART_TIMEOUT_SECONDS: u64 = 20;
 #[cfg(any(feature = "aws", feature = "azure"))]
-const MQTT_TLS_PORT: u16 = 8883;
+pub(crate) const MQTT_TLS_PORT: u16 = 8883;
 
 pub struct ConnectCommand {
     pub cloud: Cloud,
     pub is_test_connection: bool,
     pub offline_mode: bool,
+    /// Enrol the device again with the provisioning service, even if already provisioned
+    pub reprovision: bool,
     pub service_manager: Arc<dyn SystemServiceManager>,
     pub is_reconnect: bool,
 }
@@ -113,6 +119,8 @@ impl Command for ConnectCommand {
             }
         }
 
+        let tedge_config = self.provision_device(tedge_config).await?;
+
         let bridge_config = bridge_config(&tedge_config, &self.cloud)
             .await
             .map_err(anyhow::Error::new)?;
@@ -191,6 +199,57 @@ impl Command for ConnectCommand {
 }
 
 impl ConnectCommand {
+    /// Provision the device on the cloud, when configured to use a provisioning service
+    ///
+    /// Return the updated configuration, as the provisioning service assigns some of the cloud settings.
+    async fn provision_device(
+        &self,
+        tedge_config: TEdgeConfig,
+    ) -> Result<TEdgeConfig, MaybeFancy<anyhow::Error>> {
+        if self.is_test_connection || self.offline_mode {
+            return Ok(tedge_config);
+        }
+
+        match &self.cloud {
+            #[cfg(feature = "aws")]
+            Cloud::Aws(profile) => {
+                let profile = profile.as_deref();
+                if self.reprovision {
+                    return Err(anyhow!("--reprovision is only supported with Azure DPS").into());
+                }
+                if !aws::fleet_provisioning_required(&tedge_config, profile).await? {
+                    return Ok(tedge_config);
+                }
+                let spinner = Spinner::start("Provisioning device on AWS IoT");
+                let res = aws::provision_device_aws(&tedge_config, profile).await;
+                let thing_name = spinner.finish(res)?;
+                eprintln!("The device has been provisioned as the thing {thing_name}");
+            }
+            #[cfg(feature = "azure")]
+            Cloud::Azure(profile) => {
+                let profile = profile.as_deref();
+                if !azure::dps_provisioning_required(&tedge_config, profile, self.reprovision)? {
+                    return Ok(tedge_config);
+                }
+                let spinner = Spinner::start("Provisioning device with Azure DPS");
+                let res = azure::provision_device_azure(&tedge_config, profile).await;
+                let registration = spinner.finish(res)?;
+                eprintln!(
+                    "The device has been assigned to {} as {}",
+                    registration.assigned_hub, registration.device_id
+                );
+            }
+            _ if self.reprovision => {
+                return Err(anyhow!("--reprovision is only supported with Azure DPS").into());
+            }
+            _ => return Ok(tedge_config),
+        }
+
+        Ok(TEdgeConfig::load(tedge_config.root_dir())
+            .await
+            .map_err(anyhow::Error::new)?)
+    }
+
     async fn check_bridge(
         &self,
         tedge_config: &TEdgeConfig,
@@ -565,12 +624,17 @@ async fn wait_for_custom_mapper_health(
     let mapper_health = service_health_topic(&mqtt_schema, &device_topic_id, &mapper_service).name;
     let bridge_health = service_health_topic(&mqtt_schema, &device_topic_id, &bridge_service).name;
 
-    let bridge_dir = tedge_config
-        .root_dir()
-        .join("mappers")
-        .join(mapper_name)
-        .join("bridge");
-    let has_bridge = tokio::fs::try_exists(&bridge_dir).await.unwrap_or(false);
+    let mapper_dir = tedge_config.root_dir().join("mappers").join(mapper_name);
+    // The HTTP upstream reports its status as the bridge does
+    let has_http_upstream = tedge_mapper::custom_mapper_config::load_mapper_config(&mapper_dir)
+        .await
+        .ok()
+        .flatten()
+        .is_some_and(|config| config.http.is_some());
+    let has_bridge = has_http_upstream
+        || tokio::fs::try_exists(mapper_dir.join("bridge"))
+            .await
+            .unwrap_or(false);
 
     const CLIENT_ID: &str = "check_connection_custom_mapper";
 
@@ -953,6 +1017,7 @@ pub async fn bridge_config(
                 mqtt_schema,
                 keepalive_interval: aws_config.bridge.keepalive_interval.duration(),
                 proxy,
+                jobs: aws_config.cloud_specific.mapper.jobs.enable,
             };
 
             Ok(BridgeConfig::from(params))
@@ -1020,6 +1085,38 @@ pub async fn bridge_config(
     }
 }
 
+/// Persist the settings assigned by a provisioning service into the table of a cloud profile
+#[cfg(any(feature = "aws", feature = "azure"))]
+pub(crate) async fn update_cloud_profile(
+    tedge_config: &TEdgeConfig,
+    cloud: &str,
+    profile: Option<&ProfileName>,
+    settings: &[(&str, &str)],
+) -> anyhow::Result<()> {
+    let mut updates = Vec::with_capacity(settings.len());
+    for (key, value) in settings {
+        let key = match profile {
+            Some(profile) => format!("{cloud}.profiles.{profile}.{key}"),
+            None => format!("{cloud}.{key}"),
+        };
+        let key = key
+            .parse::<WritableKey>()
+            .with_context(|| format!("failed to parse '{key}' as a WritableKey"))?;
+        updates.push((key, *value));
+    }
+
+    TEdgeConfig::load(tedge_config.root_dir())
+        .await?
+        .update_toml(&|dto, _reader| {
+            for (key, value) in &updates {
+                dto.try_update_str(key, value)?;
+            }
+            Ok(())
+        })
+        .await?;
+    Ok(())
+}
+
 pub(crate) fn bridge_health_topic(prefix: &TopicPrefix, tedge_config: &TEdgeConfig) -> Topic {
     let bridge_name = if tedge_config.mqtt.bridge.built_in {
         format!("tedge-mapper-bridge-{prefix}")
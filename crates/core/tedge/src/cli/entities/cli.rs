@@ -0,0 +1,137 @@
+use crate::cli::entities::client::EntitiesClient;
+use crate::cli::entities::export::ExportCommand;
+use crate::cli::entities::export::ExportSource;
+use crate::cli::entities::import::ImportCommand;
+use crate::cli::entities::validate::ValidateCommand;
+use crate::cli::http::http_client;
+use crate::cli::http::https_if_some;
+use crate::command::BuildCommand;
+use crate::command::Command;
+use crate::ConfigError;
+use camino::Utf8PathBuf;
+use clap::ValueHint;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_config::TEdgeConfig;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TEdgeEntitiesCli {
+    /// Export all the registered entities with their twin data
+    ///
+    /// Examples:
+    ///   # Export the entities registered on a running agent
+    ///   tedge entities export --output entities.json
+    ///
+    ///   # Export the entities from the persisted entity store, while the agent is stopped
+    ///   tedge entities export --offline --output entities.json
+    #[clap(verbatim_doc_comment)]
+    Export {
+        /// File where the export is written
+        ///
+        /// If none is provided, the export is written on stdout
+        #[clap(long, short, value_hint = ValueHint::FilePath)]
+        output: Option<Utf8PathBuf>,
+
+        /// Read the entity store file of the agent instead of using the agent HTTP API
+        ///
+        /// Only the twin data sent along registration messages are exported in that case.
+        #[clap(long)]
+        offline: bool,
+    },
+
+    /// Import entities from an export file
+    ///
+    /// The entities are registered parents first, then their twin data is restored.
+    /// Entities that are already registered with a different type or parent are reported and skipped.
+    ///
+    /// Examples:
+    ///   # Check what would be done
+    ///   tedge entities import entities.json --dry-run
+    ///
+    ///   # Register the entities on the running agent
+    ///   tedge entities import entities.json
+    #[clap(verbatim_doc_comment)]
+    Import {
+        /// Path to the export file
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: Utf8PathBuf,
+
+        /// Only display the changes that would be applied
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Check the twin data of all the entities against the twin schemas
+    ///
+    /// The schemas are read from the `agent.entity_store.twin_schemas.path` directory.
+    /// The command fails if any twin fragment doesn't conform to its schema.
+    ///
+    /// Examples:
+    ///   # Check the twin data of the entities registered on a running agent
+    ///   tedge entities validate
+    ///
+    ///   # Check the twin data persisted by the agent, while the agent is stopped
+    ///   tedge entities validate --offline
+    #[clap(verbatim_doc_comment)]
+    Validate {
+        /// Read the entity store file of the agent instead of using the agent HTTP API
+        #[clap(long)]
+        offline: bool,
+    },
+}
+
+#[async_trait::async_trait]
+impl BuildCommand for TEdgeEntitiesCli {
+    async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
+        match self {
+            TEdgeEntitiesCli::Export { output, offline } => {
+                let source = export_source(config, offline).await?;
+                Ok(ExportCommand { source, output }.into_boxed())
+            }
+
+            TEdgeEntitiesCli::Import { file, dry_run } => Ok(ImportCommand {
+                client: agent_client(config).await?,
+                file,
+                dry_run,
+            }
+            .into_boxed()),
+
+            TEdgeEntitiesCli::Validate { offline } => Ok(ValidateCommand {
+                source: export_source(config, offline).await?,
+                schemas_dir: config.agent.entity_store.twin_schemas.path.to_path_buf(),
+            }
+            .into_boxed()),
+        }
+    }
+}
+
+async fn export_source(config: &TEdgeConfig, offline: bool) -> Result<ExportSource, ConfigError> {
+    if offline {
+        let mqtt_schema = MqttSchema::with_root(config.mqtt.topic_root.clone());
+        Ok(ExportSource::EntityStoreLog {
+            log_dir: agent_state_dir(config),
+            mqtt_schema,
+        })
+    } else {
+        Ok(ExportSource::Agent(agent_client(config).await?))
+    }
+}
+
+async fn agent_client(config: &TEdgeConfig) -> Result<EntitiesClient, ConfigError> {
+    let client = &config.http.client;
+    let protocol = https_if_some(&config.http.cert_path);
+    let base_url = format!(
+        "{protocol}://{}:{}/te/v1/entities",
+        client.host, client.port
+    );
+    let identity = config.http.client.auth.identity()?;
+    let client = http_client(config.cloud_root_certs().await?, identity.as_ref())?;
+    Ok(EntitiesClient::new(client, base_url))
+}
+
+fn agent_state_dir(config: &TEdgeConfig) -> Utf8PathBuf {
+    if config.agent.state.path.exists() {
+        config.agent.state.path.to_path_buf()
+    } else {
+        config.root_dir().join(".agent")
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use reqwest::Client;
+use reqwest::Response;
+use serde_json::Map;
+use serde_json::Value;
+use tedge_api::entity::EntityMetadata;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::store::export::ExportedEntity;
+
+/// A client of the entity store HTTP API of the agent
+pub struct EntitiesClient {
+    client: Client,
+    base_url: String,
+}
+
+impl EntitiesClient {
+    pub fn new(client: Client, base_url: String) -> Self {
+        EntitiesClient { client, base_url }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// List all the registered entities, parents first
+    pub async fn list(&self) -> Result<Vec<EntityMetadata>, Error> {
+        let response = self.client.get(&self.base_url).send().await;
+        let response = Self::check(response, "list the entities").await?;
+        Ok(response.json().await?)
+    }
+
+    /// Get all the twin fragments of an entity
+    pub async fn twin(&self, topic_id: &EntityTopicId) -> Result<Map<String, Value>, Error> {
+        let url = format!("{}/{topic_id}/twin", self.base_url);
+        let response = self.client.get(url).send().await;
+        let response = Self::check(response, &format!("get the twin data of {topic_id}")).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Register an entity, excluding its twin data
+    pub async fn register(&self, entity: &ExportedEntity) -> Result<(), Error> {
+        let registration = ExportedEntity {
+            twin: Map::new(),
+            ..entity.clone()
+        };
+        let response = self
+            .client
+            .post(&self.base_url)
+            .json(&registration)
+            .send()
+            .await;
+        Self::check(response, &format!("register {}", entity.topic_id)).await?;
+        Ok(())
+    }
+
+    /// Set a twin fragment of an entity, leaving untouched the other fragments
+    pub async fn set_twin_fragment(
+        &self,
+        topic_id: &EntityTopicId,
+        key: &str,
+        value: &Value,
+    ) -> Result<(), Error> {
+        let url = format!("{}/{topic_id}/twin/{key}", self.base_url);
+        let response = self.client.put(url).json(value).send().await;
+        Self::check(
+            response,
+            &format!("set the twin fragment {key} of {topic_id}"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn check(
+        response: Result<Response, reqwest::Error>,
+        action: &str,
+    ) -> Result<Response, Error> {
+        let response =
+            response.with_context(|| format!("Failed to {action}: is the tedge-agent running?"))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        Err(anyhow!(
+            "Failed to {action}: {} {}\n{body}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        ))
+    }
+}
@@ -0,0 +1,89 @@
+use crate::cli::entities::client::EntitiesClient;
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::Context;
+use anyhow::Error;
+use camino::Utf8PathBuf;
+use tedge_api::entity::EntityType;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::store::export::EntityStoreExport;
+use tedge_api::store::export::ExportedEntity;
+use tedge_config::TEdgeConfig;
+
+pub enum ExportSource {
+    /// Export the entities registered on a running agent
+    Agent(EntitiesClient),
+
+    /// Export the entities persisted by the agent
+    EntityStoreLog {
+        log_dir: Utf8PathBuf,
+        mqtt_schema: MqttSchema,
+    },
+}
+
+pub struct ExportCommand {
+    pub source: ExportSource,
+    pub output: Option<Utf8PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl Command for ExportCommand {
+    fn description(&self) -> String {
+        format!("export the entities {}", self.source)
+    }
+
+    async fn execute(&self, _: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        let export = self.source.export().await?;
+        let content = serde_json::to_string_pretty(&export).map_err(Error::from)?;
+        match &self.output {
+            Some(path) => tokio::fs::write(path, content)
+                .await
+                .with_context(|| format!("writing {path}"))?,
+            None => println!("{content}"),
+        }
+        Ok(())
+    }
+}
+
+impl ExportSource {
+    /// Read all the entities with their twin data
+    pub async fn export(&self) -> Result<EntityStoreExport, Error> {
+        match self {
+            ExportSource::Agent(client) => Self::export_from_agent(client).await,
+            ExportSource::EntityStoreLog {
+                log_dir,
+                mqtt_schema,
+            } => EntityStoreExport::from_message_log(
+                log_dir,
+                mqtt_schema,
+                &EntityTopicId::default_main_device(),
+            )
+            .with_context(|| format!("reading the entity store from {log_dir}")),
+        }
+    }
+
+    async fn export_from_agent(client: &EntitiesClient) -> Result<EntityStoreExport, Error> {
+        let mut entities = vec![];
+        let mut main_device = EntityTopicId::default_main_device();
+        for metadata in client.list().await? {
+            if metadata.r#type == EntityType::MainDevice {
+                main_device = metadata.topic_id.clone();
+            }
+            let mut entity = ExportedEntity::from(&metadata);
+            entity.twin = client.twin(&metadata.topic_id).await?;
+            entities.push(entity);
+        }
+
+        Ok(EntityStoreExport::new(entities).in_topological_order(&main_device)?)
+    }
+}
+
+impl std::fmt::Display for ExportSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportSource::Agent(client) => write!(f, "from {}", client.url()),
+            ExportSource::EntityStoreLog { log_dir, .. } => write!(f, "persisted in {log_dir}"),
+        }
+    }
+}
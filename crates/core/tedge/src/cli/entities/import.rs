@@ -0,0 +1,254 @@
+use crate::cli::entities::client::EntitiesClient;
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use camino::Utf8PathBuf;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use tedge_api::entity::EntityMetadata;
+use tedge_api::entity::EntityType;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::store::export::EntityStoreExport;
+use tedge_api::store::export::ExportedEntity;
+use tedge_config::TEdgeConfig;
+
+pub struct ImportCommand {
+    pub client: EntitiesClient,
+    pub file: Utf8PathBuf,
+    pub dry_run: bool,
+}
+
+/// What has to be done to import an entity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportAction {
+    /// Register a new entity along with its twin data
+    Register(ExportedEntity),
+
+    /// Add the twin data of an entity that is already registered
+    UpdateTwin(ExportedEntity),
+
+    /// Nothing to do, the entity is already registered
+    Skip(EntityTopicId),
+
+    /// The entity is already registered, but with a different definition
+    Conflict {
+        topic_id: EntityTopicId,
+        reason: String,
+    },
+}
+
+#[async_trait::async_trait]
+impl Command for ImportCommand {
+    fn description(&self) -> String {
+        format!(
+            "import the entities from {} to {}",
+            self.file,
+            self.client.url()
+        )
+    }
+
+    async fn execute(&self, _: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        let content = tokio::fs::read_to_string(&self.file)
+            .await
+            .with_context(|| format!("reading {}", self.file))?;
+        let export =
+            EntityStoreExport::parse(&content).with_context(|| format!("parsing {}", self.file))?;
+        let existing = self.client.list().await?;
+        let plan = plan_import(export, &existing)?;
+
+        let mut conflicts = 0;
+        for action in plan {
+            println!("{action}");
+            match action {
+                ImportAction::Register(entity) if !self.dry_run => {
+                    self.client.register(&entity).await?;
+                    self.update_twin(&entity).await?;
+                }
+                ImportAction::UpdateTwin(entity) if !self.dry_run => {
+                    self.update_twin(&entity).await?;
+                }
+                ImportAction::Conflict { .. } => conflicts += 1,
+                _ => (),
+            }
+        }
+
+        if conflicts > 0 {
+            Err(anyhow!(
+                "{conflicts} conflicting entities have not been imported"
+            ))?
+        }
+        Ok(())
+    }
+}
+
+impl ImportCommand {
+    async fn update_twin(&self, entity: &ExportedEntity) -> Result<(), Error> {
+        for (key, value) in entity.twin.iter() {
+            self.client
+                .set_twin_fragment(&entity.topic_id, key, value)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the actions required to import the given entities on top of the existing ones
+///
+/// The actions are ordered so parents are registered before their children.
+/// The descendants of a conflicting entity are reported as conflicts too,
+/// so none of them is registered under the existing entity.
+pub fn plan_import(
+    export: EntityStoreExport,
+    existing: &[EntityMetadata],
+) -> Result<Vec<ImportAction>, Error> {
+    let main_device = existing
+        .iter()
+        .find(|entity| entity.r#type == EntityType::MainDevice)
+        .map(|entity| entity.topic_id.clone())
+        .unwrap_or_else(EntityTopicId::default_main_device);
+    let existing: HashMap<&EntityTopicId, &EntityMetadata> = existing
+        .iter()
+        .map(|entity| (&entity.topic_id, entity))
+        .collect();
+
+    let export = export.in_topological_order(&main_device)?;
+    let mut actions = Vec::with_capacity(export.entities.len());
+    let mut not_imported = HashSet::new();
+    for entity in export.entities {
+        let topic_id = entity.topic_id.clone();
+        let parent = entity.parent_or_default(&main_device);
+        let action = match existing.get(&topic_id) {
+            _ if parent
+                .as_ref()
+                .is_some_and(|parent| not_imported.contains(parent)) =>
+            {
+                ImportAction::Conflict {
+                    topic_id,
+                    reason: format!(
+                        "its parent {} has not been imported",
+                        display_parent(parent.as_ref())
+                    ),
+                }
+            }
+            None if entity.r#type == EntityType::MainDevice => ImportAction::Conflict {
+                topic_id,
+                reason: format!("the main device of the target is {main_device}"),
+            },
+            None => ImportAction::Register(entity),
+            Some(current) if current.r#type != entity.r#type => ImportAction::Conflict {
+                topic_id,
+                reason: format!(
+                    "registered as a {} and not a {}",
+                    current.r#type, entity.r#type
+                ),
+            },
+            Some(current) if current.parent != parent => ImportAction::Conflict {
+                topic_id,
+                reason: format!(
+                    "registered with parent {} and not {}",
+                    display_parent(current.parent.as_ref()),
+                    display_parent(parent.as_ref())
+                ),
+            },
+            Some(_) if entity.twin.is_empty() => ImportAction::Skip(topic_id),
+            Some(_) => ImportAction::UpdateTwin(entity),
+        };
+        if let ImportAction::Conflict { topic_id, .. } = &action {
+            not_imported.insert(topic_id.clone());
+        }
+        actions.push(action);
+    }
+
+    Ok(actions)
+}
+
+fn display_parent(parent: Option<&EntityTopicId>) -> &str {
+    parent.map_or("none", |parent| parent.as_str())
+}
+
+impl std::fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportAction::Register(entity) => write!(f, "register  {}", entity.topic_id),
+            ImportAction::UpdateTwin(entity) => write!(f, "twin      {}", entity.topic_id),
+            ImportAction::Skip(topic_id) => write!(f, "skip      {topic_id}"),
+            ImportAction::Conflict { topic_id, reason } => {
+                write!(f, "conflict  {topic_id}: {reason}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn plan_import_on_a_fresh_agent() {
+        let existing = vec![EntityMetadata::main_device(None)];
+        let export = EntityStoreExport::parse(
+            &json!({
+                "version": 1,
+                "entities": [
+                    {"@topic-id": "device/child1/service/app", "@type": "service"},
+                    {"@topic-id": "device/child1//", "@type": "child-device", "twin": {"name": "child1"}},
+                    {"@topic-id": "device/main//", "@type": "device"},
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let plan = plan_import(export, &existing).unwrap();
+        let plan: Vec<_> = plan.iter().map(|action| action.to_string()).collect();
+        assert_eq!(
+            plan,
+            vec![
+                "skip      device/main//",
+                "register  device/child1//",
+                "register  device/child1/service/app",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_import_reports_conflicts() {
+        let existing = vec![
+            EntityMetadata::main_device(None),
+            EntityMetadata::child_device("child1".to_string()).unwrap(),
+            EntityMetadata::child_device("child2".to_string()).unwrap(),
+        ];
+        let export = EntityStoreExport::parse(
+            &json!({
+                "version": 1,
+                "entities": [
+                    {"@topic-id": "device/main//", "@type": "device", "twin": {"name": "gateway"}},
+                    {"@topic-id": "device/child1//", "@type": "service"},
+                    {"@topic-id": "device/child2//", "@type": "child-device", "@parent": "device/main//"},
+                    {"@topic-id": "device/child3//", "@type": "child-device", "@parent": "device/child2//"},
+                    {"@topic-id": "device/child4//", "@type": "child-device", "@parent": "device/child1//"},
+                    {"@topic-id": "device/child4/service/app", "@type": "service", "@parent": "device/child4//"},
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let plan = plan_import(export, &existing).unwrap();
+        let plan: Vec<_> = plan.iter().map(|action| action.to_string()).collect();
+        assert_eq!(
+            plan,
+            vec![
+                "twin      device/main//",
+                "conflict  device/child1//: registered as a child-device and not a service",
+                "skip      device/child2//",
+                "register  device/child3//",
+                "conflict  device/child4//: its parent device/child1// has not been imported",
+                "conflict  device/child4/service/app: its parent device/child4// has not been imported",
+            ]
+        );
+    }
+}
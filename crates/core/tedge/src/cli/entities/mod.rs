@@ -0,0 +1,7 @@
+mod cli;
+mod client;
+mod export;
+mod import;
+mod validate;
+
+pub use cli::TEdgeEntitiesCli;
@@ -0,0 +1,133 @@
+use crate::cli::entities::export::ExportSource;
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::anyhow;
+use anyhow::Error;
+use camino::Utf8PathBuf;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::store::export::EntityStoreExport;
+use tedge_api::twin_schema::SchemaViolation;
+use tedge_api::twin_schema::TwinSchemas;
+use tedge_config::TEdgeConfig;
+
+pub struct ValidateCommand {
+    pub source: ExportSource,
+    pub schemas_dir: Utf8PathBuf,
+}
+
+/// A twin fragment that doesn't conform to its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFragment {
+    pub topic_id: EntityTopicId,
+    pub fragment_key: String,
+    pub violation: SchemaViolation,
+}
+
+#[async_trait::async_trait]
+impl Command for ValidateCommand {
+    fn description(&self) -> String {
+        format!(
+            "validate the twin data of the entities {} against the schemas of {}",
+            self.source, self.schemas_dir
+        )
+    }
+
+    async fn execute(&self, _: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        let schemas = TwinSchemas::load(&self.schemas_dir).map_err(Error::from)?;
+        if schemas.is_empty() {
+            eprintln!("No twin schemas found in {}", self.schemas_dir);
+            return Ok(());
+        }
+
+        let export = self.source.export().await?;
+        let invalid_fragments = validate_twin_data(&export, &schemas);
+        for invalid in invalid_fragments.iter() {
+            println!("{invalid}");
+        }
+
+        if !invalid_fragments.is_empty() {
+            Err(anyhow!(
+                "{} twin fragments don't conform to their schema",
+                invalid_fragments.len()
+            ))?
+        }
+        Ok(())
+    }
+}
+
+/// Check the twin data of all the entities against the schemas
+pub fn validate_twin_data(
+    export: &EntityStoreExport,
+    schemas: &TwinSchemas,
+) -> Vec<InvalidFragment> {
+    let mut invalid_fragments = vec![];
+    for entity in export.entities.iter() {
+        for (fragment_key, fragment_value) in entity.twin.iter() {
+            if let Err(violation) = schemas.validate(entity.r#type, fragment_key, fragment_value) {
+                invalid_fragments.push(InvalidFragment {
+                    topic_id: entity.topic_id.clone(),
+                    fragment_key: fragment_key.clone(),
+                    violation,
+                })
+            }
+        }
+    }
+    invalid_fragments
+}
+
+impl std::fmt::Display for InvalidFragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.topic_id, self.fragment_key, self.violation
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_api::entity::EntityType;
+    use tedge_api::twin_schema::TwinSchema;
+
+    #[test]
+    fn report_invalid_twin_fragments() {
+        let schemas = TwinSchemas::default()
+            .with_schema(
+                None,
+                "name",
+                TwinSchema::new(json!({"type": "string"})).unwrap(),
+            )
+            .with_schema(
+                Some(EntityType::ChildDevice),
+                "firmware",
+                TwinSchema::new(json!({"type": "object", "required": ["version"]})).unwrap(),
+            );
+        let export = EntityStoreExport::parse(
+            &json!({
+                "version": 1,
+                "entities": [
+                    {"@topic-id": "device/main//", "@type": "device", "twin": {"name": "gateway", "firmware": "1.0"}},
+                    {"@topic-id": "device/child1//", "@type": "child-device", "twin": {"name": 1, "firmware": {"version": "1.0"}}},
+                    {"@topic-id": "device/child2//", "@type": "child-device", "twin": {"firmware": {"name": "fw"}}},
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let report: Vec<_> = validate_twin_data(&export, &schemas)
+            .iter()
+            .map(|invalid| invalid.to_string())
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                "device/child1// name: expected string, found number",
+                "device/child2// firmware: missing required property version",
+            ]
+        );
+    }
+}
@@ -288,11 +288,14 @@ impl TEdgeHttpCli {
     }
 }
 
-fn https_if_some<T>(cert_path: &OptionalConfig<T>) -> &'static str {
+pub(crate) fn https_if_some<T>(cert_path: &OptionalConfig<T>) -> &'static str {
     cert_path.or_none().map_or("http", |_| "https")
 }
 
-fn http_client(http_config: CloudHttpConfig, identity: Option<&Identity>) -> Result<Client, Error> {
+pub(crate) fn http_client(
+    http_config: CloudHttpConfig,
+    identity: Option<&Identity>,
+) -> Result<Client, Error> {
     let builder = http_config.client_builder();
     let builder = if let Some(identity) = identity {
         builder.identity(identity.clone())
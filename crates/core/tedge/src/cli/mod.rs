@@ -26,6 +26,7 @@ pub mod config;
 mod connect;
 mod diag;
 mod disconnect;
+mod entities;
 mod flows;
 mod http;
 mod init;
@@ -157,6 +158,10 @@ pub enum TEdgeOpt {
     #[clap(subcommand)]
     Http(http::TEdgeHttpCli),
 
+    /// Export and import the entities registered on the device
+    #[clap(subcommand)]
+    Entities(entities::TEdgeEntitiesCli),
+
     /// Monitor and test flows
     #[clap(subcommand)]
     Flows(flows::TEdgeFlowsCli),
@@ -243,6 +248,7 @@ impl BuildCommand for TEdgeOpt {
             TEdgeOpt::Mqtt(opt) => opt.build_command(config).await,
             TEdgeOpt::Http(opt) => opt.build_command(config).await,
             TEdgeOpt::Reconnect(opt) => opt.build_command(config).await,
+            TEdgeOpt::Entities(opt) => opt.build_command(config).await,
             TEdgeOpt::Flows(opt) => opt.build_command(config).await,
             TEdgeOpt::Mapper(opt) => opt.build_command(config).await,
             TEdgeOpt::Bridge(opt) => opt.build_command(config).await,
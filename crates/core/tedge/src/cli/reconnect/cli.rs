@@ -10,6 +10,11 @@ pub struct TEdgeReconnectCli {
     #[clap(long = "offline", global = true)]
     offline_mode: bool,
 
+    /// Enrol the device again with the Azure Device Provisioning Service,
+    /// rather than keeping the IoT Hub assigned by a previous enrolment
+    #[clap(long, global = true)]
+    reprovision: bool,
+
     #[clap(subcommand)]
     cloud: CloudArg,
 }
@@ -25,6 +30,7 @@ impl BuildCommand for TEdgeReconnectCli {
             service_manager: service_manager(config.root_dir())?,
             cloud: self.cloud.try_into()?,
             offline_mode: self.offline_mode,
+            reprovision: self.reprovision,
             use_mapper: true,
         }
         .into_boxed())
@@ -13,6 +13,7 @@ pub struct ReconnectBridgeCommand {
     pub config_dir: Utf8PathBuf,
     pub cloud: Cloud,
     pub offline_mode: bool,
+    pub reprovision: bool,
     pub use_mapper: bool,
     pub service_manager: Arc<dyn SystemServiceManager>,
 }
@@ -52,6 +53,7 @@ impl From<&ReconnectBridgeCommand> for ConnectCommand {
             cloud: reconnect_cmd.cloud.clone(),
             is_test_connection: false,
             offline_mode: reconnect_cmd.offline_mode,
+            reprovision: reconnect_cmd.reprovision,
             service_manager: reconnect_cmd.service_manager.clone(),
             is_reconnect: true,
         }
@@ -1,6 +1,7 @@
 use crate::device_profile_manager::DeviceProfileManagerBuilder;
 use crate::entity_manager;
 use crate::entity_manager::server::EntityStoreRequest;
+use crate::entity_manager::server::EntityStoreResponse;
 use crate::entity_manager::server::EntityStoreServer;
 use crate::entity_manager::server::EntityStoreServerConfig;
 use crate::http_server::actor::HttpServerBuilder;
@@ -25,15 +26,19 @@ use reqwest::Identity;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tedge_actors::Concurrent;
+use tedge_actors::DynSender;
 use tedge_actors::MessageSource;
 use tedge_actors::NullSender;
 use tedge_actors::RequestEnvelope;
 use tedge_actors::Runtime;
+use tedge_actors::Sender;
 use tedge_actors::Sequential;
 use tedge_actors::ServerActorBuilder;
 use tedge_actors::ServerConfig;
 use tedge_api::entity_store::EntityRegistrationMessage;
+use tedge_api::entity_store::ExpiryPolicy;
 use tedge_api::mqtt_topics::DeviceTopicId;
 use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
@@ -41,6 +46,8 @@ use tedge_api::mqtt_topics::OperationType;
 use tedge_api::mqtt_topics::Service;
 use tedge_api::mqtt_topics::ServiceTopicId;
 use tedge_api::path::DataDir;
+use tedge_api::twin_schema::SchemaEnforcement;
+use tedge_api::twin_schema::TwinSchemas;
 use tedge_api::EntityStore;
 use tedge_config::tedge_toml::TEdgeConfigReaderService;
 use tedge_config_manager::ConfigManagerBuilder;
@@ -95,6 +102,10 @@ pub(crate) struct AgentConfig {
     pub config_plugin_dirs: Vec<Utf8PathBuf>,
     entity_auto_register: bool,
     entity_store_clean_start: bool,
+    entity_expiry_policy: ExpiryPolicy,
+    entity_expiry_check_interval: Duration,
+    twin_schemas: TwinSchemas,
+    twin_schema_enforcement: SchemaEnforcement,
 }
 
 impl AgentConfig {
@@ -190,6 +201,26 @@ impl AgentConfig {
 
         let entity_auto_register = tedge_config.agent.entity_store.auto_register;
         let entity_store_clean_start = tedge_config.agent.entity_store.clean_start;
+        let entity_expiry = &tedge_config.agent.entity_store.expiry;
+        let entity_expiry_policy = ExpiryPolicy {
+            child_device_ttl: entity_expiry
+                .child_device_ttl
+                .or_none()
+                .map(|ttl| ttl.duration()),
+            service_ttl: entity_expiry
+                .service_ttl
+                .or_none()
+                .map(|ttl| ttl.duration()),
+            grace_period: entity_expiry.grace_period.duration(),
+        };
+        let entity_expiry_check_interval = entity_expiry.check_interval.duration();
+        let twin_schemas_config = &tedge_config.agent.entity_store.twin_schemas;
+        let twin_schemas = TwinSchemas::load_or_skip(&twin_schemas_config.path);
+        let twin_schema_enforcement = if twin_schemas_config.enforce {
+            SchemaEnforcement::Reject
+        } else {
+            SchemaEnforcement::Warn
+        };
         let log_plugin_dirs = tedge_config
             .log
             .plugin_paths
@@ -233,6 +264,10 @@ impl AgentConfig {
             config_plugin_dirs,
             entity_auto_register,
             entity_store_clean_start,
+            entity_expiry_policy,
+            entity_expiry_check_interval,
+            twin_schemas,
+            twin_schema_enforcement,
         })
     }
 }
@@ -444,9 +479,15 @@ impl Agent {
                 telemetry_cache_size,
                 state_dir,
                 clean_start,
-            )?;
+            )?
+            .with_twin_schemas(
+                self.config.twin_schemas.clone(),
+                self.config.twin_schema_enforcement,
+            );
+            let expiry_policy = self.config.entity_expiry_policy.clone();
             let entity_store_server_config =
-                EntityStoreServerConfig::new(mqtt_schema.clone(), self.config.entity_auto_register);
+                EntityStoreServerConfig::new(mqtt_schema.clone(), self.config.entity_auto_register)
+                    .with_expiry_policy(expiry_policy);
             let entity_store_server = EntityStoreServer::new(
                 entity_store_server_config,
                 entity_store,
@@ -465,6 +506,12 @@ impl Agent {
                 },
             );
 
+            // Expiry checks are always run, as any entity can be registered with its own TTL
+            spawn_expiry_checks(
+                entity_store_actor_builder.request_sender(),
+                self.config.entity_expiry_check_interval,
+            );
+
             let file_transfer_server_builder = HttpServerBuilder::try_bind(
                 self.config.http_config,
                 &mut entity_store_actor_builder,
@@ -507,3 +554,24 @@ impl Agent {
         Ok(runtime)
     }
 }
+
+/// Periodically triggers the expiry of the entities that are silent for too long
+fn spawn_expiry_checks(
+    mut entity_store: DynSender<RequestEnvelope<EntityStoreRequest, EntityStoreResponse>>,
+    check_interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            let request = RequestEnvelope {
+                request: EntityStoreRequest::CheckExpiry,
+                reply_to: Box::new(NullSender),
+            };
+            if entity_store.send(request).await.is_err() {
+                // The entity store is no more running
+                break;
+            }
+        }
+    });
+}
@@ -1,19 +1,24 @@
 use async_trait::async_trait;
 use futures::channel::mpsc;
 use futures::StreamExt as _;
+use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use std::time::Instant;
 use tedge_actors::LoggingSender;
 use tedge_actors::MappingSender;
 use tedge_actors::MessageSink;
 use tedge_actors::Sender;
 use tedge_actors::Server;
 use tedge_api::entity::EntityMetadata;
+use tedge_api::entity_query::EntityPage;
+use tedge_api::entity_query::EntityQuery;
 use tedge_api::entity_store;
 use tedge_api::entity_store::EntityRegistrationMessage;
 use tedge_api::entity_store::EntityTwinMessage;
 use tedge_api::entity_store::EntityUpdateMessage;
-use tedge_api::entity_store::ListFilters;
+use tedge_api::entity_store::ExpiryPolicy;
+use tedge_api::health::HealthStatus;
 use tedge_api::mqtt_topics::Channel;
 use tedge_api::mqtt_topics::ChannelFilter;
 use tedge_api::mqtt_topics::EntityFilter;
@@ -23,8 +28,17 @@ use tedge_api::pending_entity_store::RegisteredEntityData;
 use tedge_api::EntityStore;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::MqttRequest;
+use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::TopicFilter;
 use tracing::error;
+use tracing::info;
+
+/// The status component used to notify that an entity is active or inactive
+///
+/// A retained message is published on `te/<entity>/status/activity`
+/// with a `{"status": "inactive"}` payload when the entity is silent for longer than its TTL,
+/// and a `{"status": "active"}` payload when it sends messages again.
+pub const ACTIVITY_STATUS: &str = "activity";
 
 #[derive(Debug)]
 pub enum EntityStoreRequest {
@@ -32,12 +46,13 @@ pub enum EntityStoreRequest {
     Create(EntityRegistrationMessage),
     Update(EntityTopicId, EntityUpdateMessage),
     Delete(EntityTopicId),
-    List(ListFilters),
+    List(EntityQuery),
     MqttMessage(MqttMessage),
     GetTwinFragment(EntityTopicId, String),
     SetTwinFragment(EntityTwinMessage),
     GetTwinFragments(EntityTopicId),
     SetTwinFragments(EntityTopicId, Map<String, Value>),
+    CheckExpiry,
 }
 
 #[derive(Debug)]
@@ -46,7 +61,7 @@ pub enum EntityStoreResponse {
     Create(Result<Vec<RegisteredEntityData>, entity_store::Error>),
     Update(Result<EntityMetadata, entity_store::Error>),
     Delete(Vec<EntityMetadata>),
-    List(Vec<EntityMetadata>),
+    List(EntityPage),
     Ok,
     GetTwinFragment(Option<Value>),
     SetTwinFragment(Result<bool, entity_store::Error>),
@@ -64,6 +79,7 @@ pub struct EntityStoreServer {
 pub struct EntityStoreServerConfig {
     pub mqtt_schema: MqttSchema,
     pub entity_auto_register: bool,
+    pub expiry_policy: ExpiryPolicy,
 }
 
 impl EntityStoreServerConfig {
@@ -71,6 +87,14 @@ impl EntityStoreServerConfig {
         Self {
             mqtt_schema,
             entity_auto_register,
+            expiry_policy: ExpiryPolicy::default(),
+        }
+    }
+
+    pub fn with_expiry_policy(self, expiry_policy: ExpiryPolicy) -> Self {
+        Self {
+            expiry_policy,
+            ..self
         }
     }
 }
@@ -140,9 +164,9 @@ impl Server for EntityStoreServer {
                 let deleted_entities = self.deregister_entity(&topic_id).await;
                 EntityStoreResponse::Delete(deleted_entities)
             }
-            EntityStoreRequest::List(filters) => {
-                let entities = self.entity_store.list_entity_tree(filters);
-                EntityStoreResponse::List(entities.into_iter().cloned().collect())
+            EntityStoreRequest::List(query) => {
+                let page = self.entity_store.query(&query, Instant::now());
+                EntityStoreResponse::List(page)
             }
             EntityStoreRequest::GetTwinFragment(topic_id, fragment_key) => {
                 let twin = self
@@ -166,6 +190,10 @@ impl Server for EntityStoreServer {
                 self.process_mqtt_message(mqtt_message).await;
                 EntityStoreResponse::Ok
             }
+            EntityStoreRequest::CheckExpiry => {
+                self.check_expiry(Instant::now()).await;
+                EntityStoreResponse::Ok
+            }
         }
     }
 }
@@ -173,6 +201,10 @@ impl Server for EntityStoreServer {
 impl EntityStoreServer {
     pub(crate) async fn process_mqtt_message(&mut self, message: MqttMessage) {
         if let Ok((topic_id, channel)) = self.config.mqtt_schema.entity_channel_of(&message.topic) {
+            if is_activity(&channel, &message) {
+                self.record_activity(&topic_id, Instant::now()).await;
+            }
+
             if let Channel::EntityMetadata = channel {
                 self.process_entity_registration(topic_id, message.payload_bytes())
                     .await;
@@ -246,8 +278,33 @@ impl EntityStoreServer {
             } else {
                 serde_json::from_slice(message.payload_bytes())?
             };
-            let twin_message = EntityTwinMessage::new(topic_id, fragment_key, fragment_value);
-            self.entity_store.update_twin_fragment(twin_message)?;
+            let previous_value = self
+                .entity_store
+                .get_twin_fragment(&topic_id, &fragment_key)
+                .cloned();
+            let twin_message =
+                EntityTwinMessage::new(topic_id.clone(), fragment_key.clone(), fragment_value);
+            if let Err(err) = self.entity_store.update_twin_fragment(twin_message) {
+                if let entity_store::Error::TwinSchemaViolation(..) = err {
+                    // Overwrite the rejected retained message, so it is not forwarded to the cloud
+                    self.publish_twin_data(
+                        &topic_id,
+                        fragment_key,
+                        previous_value.unwrap_or(Value::Null),
+                    )
+                    .await;
+                }
+                return Err(err);
+            }
+        } else if let Channel::Health = channel {
+            let status = if message.payload().is_empty() {
+                None
+            } else {
+                HealthStatus::try_from_health_status_message(&message, &self.config.mqtt_schema)
+                    .ok()
+                    .map(|health| health.status.to_string())
+            };
+            self.entity_store.set_health_status(&topic_id, status);
         }
 
         Ok(())
@@ -257,6 +314,10 @@ impl EntityStoreServer {
         &mut self,
         twin_message: EntityTwinMessage,
     ) -> Result<bool, entity_store::Error> {
+        if !twin_message.fragment_value.is_null() {
+            self.record_activity(&twin_message.topic_id, Instant::now())
+                .await;
+        }
         let updated = self
             .entity_store
             .update_twin_fragment(twin_message.clone())?;
@@ -354,6 +415,7 @@ impl EntityStoreServer {
                 ChannelFilter::AnyCommand,
                 ChannelFilter::AnyCommandMetadata,
                 ChannelFilter::Health,
+                ChannelFilter::Status(ACTIVITY_STATUS.to_string()),
             ] {
                 let topic = self
                     .config
@@ -392,6 +454,7 @@ impl EntityStoreServer {
         topic_id: &EntityTopicId,
         fragments: Map<String, Value>,
     ) -> Result<(), entity_store::Error> {
+        self.record_activity(topic_id, Instant::now()).await;
         let mut old_fragments = self
             .entity_store
             .set_twin_fragments(topic_id, fragments.clone())?;
@@ -424,6 +487,62 @@ impl EntityStoreServer {
 
         Ok(())
     }
+
+    /// Marks the entity and its ancestors as active,
+    /// notifying those which were previously inactive
+    async fn record_activity(&mut self, topic_id: &EntityTopicId, now: Instant) {
+        for topic_id in self.entity_store.record_activity(topic_id, now) {
+            info!("Entity {topic_id} is active again");
+            self.publish_activity_status(&topic_id, "active").await;
+        }
+    }
+
+    /// Marks as inactive the entities that have been silent for too long
+    /// and deregisters those which have been inactive for longer than the grace period
+    pub(crate) async fn check_expiry(&mut self, now: Instant) {
+        let outcome = self
+            .entity_store
+            .check_expiry(&self.config.expiry_policy, now);
+        for topic_id in outcome.inactive {
+            info!("Entity {topic_id} is inactive");
+            self.publish_activity_status(&topic_id, "inactive").await;
+        }
+        for topic_id in outcome.expired {
+            // Already removed if one of its ancestors expired too
+            if self.entity_store.get(&topic_id).is_some() {
+                info!("Entity {topic_id} expired and is deregistered");
+                self.deregister_entity(&topic_id).await;
+            }
+        }
+    }
+
+    async fn publish_activity_status(&mut self, topic_id: &EntityTopicId, status: &str) {
+        let channel = Channel::Status {
+            component: ACTIVITY_STATUS.to_string(),
+        };
+        let topic = self.config.mqtt_schema.topic_for(topic_id, &channel);
+        let payload = json!({ "status": status }).to_string();
+        let message = MqttMessage::new(&topic, payload)
+            .with_retain()
+            .with_qos(QoS::AtLeastOnce);
+        self.publish_message(message).await;
+    }
+}
+
+/// Returns `true` if the message has been sent by the entity or on its behalf.
+///
+/// Deregistration messages and the activity notifications of the agent itself are not activities.
+/// Checks if a message is a sign of life of its entity
+///
+/// Clearing a retained message, e.g. when an entity is deregistered, is not.
+fn is_activity(channel: &Channel, message: &MqttMessage) -> bool {
+    if message.payload().is_empty() {
+        return false;
+    }
+    match channel {
+        Channel::Status { component } => component != ACTIVITY_STATUS,
+        _ => true,
+    }
 }
 
 pub fn subscriptions(mqtt_schema: &MqttSchema) -> TopicFilter {
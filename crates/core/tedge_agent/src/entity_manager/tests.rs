@@ -9,12 +9,18 @@ use crate::entity_manager::tests::model::Protocol::MQTT;
 use proptest::proptest;
 use serde_json::json;
 use std::collections::HashSet;
+use std::time::Duration;
+use std::time::Instant;
 use tedge_actors::test_helpers::MessageReceiverExt;
+use tedge_actors::MessageReceiver;
 use tedge_actors::Sender;
 use tedge_actors::Server;
 use tedge_api::entity::EntityMetadata;
 use tedge_api::entity::EntityType;
+use tedge_api::entity_store::ExpiryPolicy;
 use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::twin_schema::TwinSchema;
+use tedge_api::twin_schema::TwinSchemas;
 use tedge_mqtt_ext::test_helpers::assert_received_contains_str;
 use tedge_mqtt_ext::MqttMessage;
 
@@ -222,6 +228,153 @@ async fn clear_entity_twin_data() {
     assert_eq!(entity.twin_data.get("x"), None);
 }
 
+#[tokio::test]
+async fn twin_updates_rejected_by_their_schema_are_reverted() {
+    let schemas = TwinSchemas::default().with_schema(
+        None,
+        "firmware",
+        TwinSchema::new(json!({"type": "object", "required": ["version"]})).unwrap(),
+    );
+    let handle = entity::server_with_twin_schemas("device-under-test", schemas);
+    let (mut entity_store, mut mqtt_output) = (handle.entity_store, handle.mqtt_output);
+
+    // Without a previous valid value, the rejected fragment is cleared
+    entity_store
+        .process_mqtt_message(
+            MqttMessage::from(("te/device/main///twin/firmware", r#"{"name":"fw"}"#)).with_retain(),
+        )
+        .await;
+    mqtt_output
+        .assert_received([MqttMessage::from(("te/device/main///twin/firmware", "")).with_retain()])
+        .await;
+
+    // Otherwise, the previous valid value is restored
+    let valid =
+        MqttMessage::from(("te/device/main///twin/firmware", r#"{"version":"1.0"}"#)).with_retain();
+    entity_store.process_mqtt_message(valid.clone()).await;
+    entity_store
+        .process_mqtt_message(
+            MqttMessage::from(("te/device/main///twin/firmware", r#"{"name":"fw"}"#)).with_retain(),
+        )
+        .await;
+    mqtt_output.assert_received([valid]).await;
+
+    let entity = entity_store
+        .get(&"device/main//".parse::<EntityTopicId>().unwrap())
+        .unwrap();
+    assert_eq!(
+        entity.twin_data.get("firmware"),
+        Some(&json!({"version": "1.0"}))
+    );
+}
+
+#[tokio::test]
+async fn silent_child_devices_are_marked_inactive_then_deregistered() {
+    let policy = ExpiryPolicy {
+        child_device_ttl: Some(Duration::from_secs(60)),
+        service_ttl: None,
+        grace_period: Duration::from_secs(30),
+    };
+    let handle = entity::server_with_expiry_policy("device-under-test", policy);
+    let (mut entity_store, mut mqtt_box) = (handle.entity_store, handle.mqtt_output);
+
+    entity::create_entity(
+        &mut entity_store,
+        "device/child0//",
+        EntityType::ChildDevice,
+        None,
+    )
+    .await
+    .unwrap();
+    mqtt_box.skip(1).await; // Skip the registration message
+
+    let start = Instant::now();
+    entity_store
+        .check_expiry(start + Duration::from_secs(120))
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_box,
+        [(
+            "te/device/child0///status/activity",
+            r#"{"status":"inactive"}"#,
+        )],
+    )
+    .await;
+
+    // Any message from the device makes it active again
+    entity_store
+        .process_mqtt_message(MqttMessage::from((
+            "te/device/child0///m/temp",
+            r#"{"temp": 21}"#,
+        )))
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_box,
+        [(
+            "te/device/child0///status/activity",
+            r#"{"status":"active"}"#,
+        )],
+    )
+    .await;
+
+    let now = Instant::now();
+    entity_store
+        .check_expiry(now + Duration::from_secs(60))
+        .await;
+    mqtt_box.skip(1).await; // Skip the inactive status
+    entity_store
+        .check_expiry(now + Duration::from_secs(90))
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_box,
+        [
+            ("te/device/child0///status/activity", ""),
+            ("te/device/child0//", ""),
+        ],
+    )
+    .await;
+    assert!(entity::get(&mut entity_store, "device/child0//")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn clearing_retained_messages_does_not_reactivate_an_entity() {
+    let policy = ExpiryPolicy {
+        child_device_ttl: Some(Duration::from_secs(60)),
+        service_ttl: None,
+        grace_period: Duration::from_secs(30),
+    };
+    let handle = entity::server_with_expiry_policy("device-under-test", policy);
+    let mut entity_store = handle.entity_store;
+    let mut mqtt_box = handle.mqtt_output.with_timeout(Duration::from_millis(100));
+
+    entity::create_entity(
+        &mut entity_store,
+        "device/child0//",
+        EntityType::ChildDevice,
+        None,
+    )
+    .await
+    .unwrap();
+    mqtt_box.skip(1).await; // Skip the registration message
+
+    entity_store
+        .check_expiry(Instant::now() + Duration::from_secs(120))
+        .await;
+    mqtt_box.skip(1).await; // Skip the inactive status
+
+    for topic in [
+        "te/device/child0///m/temp",
+        "te/device/child0///status/health",
+    ] {
+        entity_store
+            .process_mqtt_message(MqttMessage::from((topic, "")).with_retain())
+            .await;
+    }
+    assert_eq!(mqtt_box.recv().await, None);
+}
+
 proptest! {
     //#![proptest_config(proptest::prelude::ProptestConfig::with_cases(1000))]
     #[test]
@@ -350,8 +503,11 @@ mod entity {
     use tedge_api::entity::EntityMetadata;
     use tedge_api::entity::EntityType;
     use tedge_api::entity_store::EntityRegistrationMessage;
+    use tedge_api::entity_store::ExpiryPolicy;
     use tedge_api::mqtt_topics::EntityTopicId;
     use tedge_api::mqtt_topics::MqttSchema;
+    use tedge_api::twin_schema::SchemaEnforcement;
+    use tedge_api::twin_schema::TwinSchemas;
     use tedge_api::EntityStore;
     use tedge_mqtt_ext::DynSubscriptions;
     use tedge_mqtt_ext::MqttMessage;
@@ -423,6 +579,22 @@ mod entity {
     }
 
     pub fn server(device_id: &str) -> TestHandle {
+        server_with_expiry_policy(device_id, ExpiryPolicy::default())
+    }
+
+    pub fn server_with_expiry_policy(device_id: &str, expiry_policy: ExpiryPolicy) -> TestHandle {
+        new_server(device_id, expiry_policy, TwinSchemas::default())
+    }
+
+    pub fn server_with_twin_schemas(device_id: &str, twin_schemas: TwinSchemas) -> TestHandle {
+        new_server(device_id, ExpiryPolicy::default(), twin_schemas)
+    }
+
+    fn new_server(
+        device_id: &str,
+        expiry_policy: ExpiryPolicy,
+        twin_schemas: TwinSchemas,
+    ) -> TestHandle {
         let mqtt_schema = MqttSchema::default();
         let main_device = EntityRegistrationMessage::main_device(Some(device_id.to_string()));
         let telemetry_cache_size = 0;
@@ -437,9 +609,11 @@ mod entity {
             log_dir,
             clean_start,
         )
-        .unwrap();
+        .unwrap()
+        .with_twin_schemas(twin_schemas, SchemaEnforcement::Reject);
 
-        let config = EntityStoreServerConfig::new(mqtt_schema.clone(), entity_auto_register);
+        let config = EntityStoreServerConfig::new(mqtt_schema.clone(), entity_auto_register)
+            .with_expiry_policy(expiry_policy);
 
         let mqtt_actor = SimpleMessageBoxBuilder::new("MQTT", 64);
         let mut actor_builder = TestMqttActorBuilder {
@@ -734,6 +908,7 @@ mod model {
                 r#type: action.target_type(),
                 parent: action.parent_topic_id(),
                 health_endpoint: None,
+                ttl: None,
                 twin_data: action.properties(),
             }
         }
@@ -27,8 +27,9 @@ use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
 use std::str::FromStr;
-use tedge_api::entity::EntityMetadata;
 use tedge_api::entity::InvalidEntityType;
+use tedge_api::entity_query::EntityQuery;
+use tedge_api::entity_query::QueryError;
 use tedge_api::entity_store;
 use tedge_api::entity_store::EntityRegistrationMessage;
 use tedge_api::entity_store::EntityRegistrationPayload;
@@ -49,8 +50,23 @@ pub struct ListParams {
     parent: Option<String>,
     #[serde(default)]
     r#type: Option<String>,
+    #[serde(default)]
+    filter: Option<String>,
+    #[serde(default)]
+    sort: Option<String>,
+    #[serde(default)]
+    after: Option<String>,
+    #[serde(default)]
+    limit: Option<String>,
+    #[serde(default)]
+    fields: Option<String>,
 }
 
+/// Response header holding the cursor to be used as `after` parameter to get the next page
+///
+/// The cursor is an opaque URL-safe string.
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
 #[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
 pub enum InputValidationError {
     #[error(transparent)]
@@ -59,6 +75,44 @@ pub enum InputValidationError {
     InvalidEntityTopic(#[from] TopicIdError),
     #[error("The provided parameters: {0} and {1} are mutually exclusive. Use either one.")]
     IncompatibleParams(String, String),
+    #[error(transparent)]
+    InvalidQuery(#[from] QueryError),
+}
+
+impl TryFrom<ListParams> for EntityQuery {
+    type Error = InputValidationError;
+
+    fn try_from(params: ListParams) -> Result<Self, Self::Error> {
+        let after = non_empty(params.after.as_ref())
+            .map(|val| val.parse())
+            .transpose()?;
+        let limit = non_empty(params.limit.as_ref())
+            .map(EntityQuery::parse_limit)
+            .transpose()?;
+        let sort = non_empty(params.sort.as_ref())
+            .map(|val| val.parse())
+            .transpose()?;
+        let fields = non_empty(params.fields.as_ref())
+            .map(EntityQuery::parse_fields)
+            .transpose()?;
+        let conditions = non_empty(params.filter.as_ref())
+            .map(EntityQuery::parse_conditions)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(EntityQuery {
+            scope: ListFilters::try_from(params)?,
+            conditions,
+            sort,
+            after,
+            limit,
+            fields,
+        })
+    }
+}
+
+fn non_empty(value: Option<&String>) -> Option<&str> {
+    value.map(|v| v.as_str()).filter(|v| !v.is_empty())
 }
 
 impl TryFrom<ListParams> for ListFilters {
@@ -192,6 +246,7 @@ impl From<EntityRegistrationHttpPayload> for EntityRegistrationMessage {
             r#type: value.others.r#type,
             parent: value.others.parent,
             health_endpoint: value.others.health_endpoint,
+            ttl: value.others.ttl,
             twin_data: value.others.twin_data,
         }
     }
@@ -421,19 +476,26 @@ async fn deregister_entity(state: AgentState, topic_id: EntityTopicId) -> Result
 async fn list_entities(
     State(state): State<AgentState>,
     Query(params): Query<ListParams>,
-) -> Result<Json<Vec<EntityMetadata>>, Error> {
-    let filters = params.try_into()?;
+) -> Result<Response, Error> {
+    let query: EntityQuery = params.try_into()?;
     let response = state
         .entity_store_handle
         .clone()
-        .await_response(EntityStoreRequest::List(filters))
+        .await_response(EntityStoreRequest::List(query))
         .await?;
 
-    let EntityStoreResponse::List(entities) = response else {
+    let EntityStoreResponse::List(page) = response else {
         return Err(Error::InvalidEntityStoreResponse);
     };
 
-    Ok(Json(entities))
+    match page.next {
+        Some(next) => Ok((
+            [(NEXT_CURSOR_HEADER, next.to_string())],
+            Json(page.entities),
+        )
+            .into_response()),
+        None => Ok(Json(page.entities).into_response()),
+    }
 }
 
 async fn get_entity_twin_fragment(
@@ -580,6 +642,10 @@ mod tests {
     use tedge_actors::ServerMessageBoxBuilder;
     use tedge_api::entity::EntityMetadata;
     use tedge_api::entity::EntityType;
+    use tedge_api::entity_query::Cursor;
+    use tedge_api::entity_query::EntityPage;
+    use tedge_api::entity_query::Field;
+    use tedge_api::entity_query::SortOrder;
     use tedge_api::entity_store;
     use tedge_api::mqtt_topics::EntityTopicId;
     use tedge_api::path::DataDir;
@@ -933,7 +999,7 @@ mod tests {
             if let Some(mut req) = entity_store_box.recv().await {
                 if let EntityStoreRequest::List(_) = req.request {
                     req.reply_to
-                        .send(EntityStoreResponse::List(vec![
+                        .send(list_response(vec![
                             EntityMetadata::main_device(None),
                             EntityMetadata::child_device("child0".to_string()).unwrap(),
                             EntityMetadata::child_device("child1".to_string()).unwrap(),
@@ -976,10 +1042,7 @@ mod tests {
         tokio::spawn(async move {
             if let Some(mut req) = entity_store_box.recv().await {
                 if let EntityStoreRequest::List(_) = req.request {
-                    req.reply_to
-                        .send(EntityStoreResponse::List(vec![]))
-                        .await
-                        .unwrap();
+                    req.reply_to.send(list_response(vec![])).await.unwrap();
                 }
             }
         });
@@ -1010,7 +1073,7 @@ mod tests {
             if let Some(mut req) = entity_store_box.recv().await {
                 if let EntityStoreRequest::List(_) = req.request {
                     req.reply_to
-                        .send(EntityStoreResponse::List(vec![
+                        .send(list_response(vec![
                             EntityMetadata::child_device("child00".to_string()).unwrap(),
                             EntityMetadata::child_device("child01".to_string()).unwrap(),
                         ]))
@@ -1050,10 +1113,7 @@ mod tests {
         tokio::spawn(async move {
             while let Some(mut req) = entity_store_box.recv().await {
                 if let EntityStoreRequest::List(_) = req.request {
-                    req.reply_to
-                        .send(EntityStoreResponse::List(vec![]))
-                        .await
-                        .unwrap();
+                    req.reply_to.send(list_response(vec![])).await.unwrap();
                 }
             }
         });
@@ -1683,6 +1743,109 @@ mod tests {
         assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
     }
 
+    #[tokio::test]
+    async fn entity_list_with_filter_sort_and_pagination() {
+        let TestHandle {
+            mut app,
+            mut entity_store_box,
+        } = setup();
+
+        let after = Cursor {
+            key: json!(300),
+            topic_id: EntityTopicId::default_child_device("child0").unwrap(),
+        };
+        let next = Cursor {
+            key: json!(200),
+            topic_id: EntityTopicId::default_child_device("child1").unwrap(),
+        };
+
+        // Mock entity store actor response
+        let expected_after = after.clone();
+        let returned_next = next.clone();
+        tokio::spawn(async move {
+            if let Some(mut req) = entity_store_box.recv().await {
+                if let EntityStoreRequest::List(query) = req.request {
+                    assert_eq!(query.conditions.len(), 3);
+                    assert_eq!(query.conditions[2].value, "a,b");
+                    assert_eq!(
+                        query.sort,
+                        Some(SortOrder {
+                            field: Field::LastSeen,
+                            descending: true
+                        })
+                    );
+                    assert_eq!(query.after, Some(expected_after));
+                    assert_eq!(query.limit, Some(1));
+                    req.reply_to
+                        .send(EntityStoreResponse::List(EntityPage {
+                            entities: vec![json!({"@topic-id": "device/child1//", "twin.firmware.version": "1.0"})],
+                            next: Some(returned_next),
+                        }))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("/v1/entities?type=child-device&filter=twin.firmware.version!%3D1.2,health%3Dup,twin.tags%3Da%5C,b&sort=-last-seen&after={after}&limit=1&fields=twin.firmware.version"))
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(NEXT_CURSOR_HEADER).unwrap(),
+            next.to_string().as_str()
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let entities: Value = serde_json::from_slice(&body).unwrap();
+        assert_json_eq!(
+            entities,
+            json!([{"@topic-id": "device/child1//", "twin.firmware.version": "1.0"}])
+        );
+    }
+
+    #[test_case("filter=firmware%3D1.2", "Unknown entity field: 'firmware'")]
+    #[test_case("filter=last-seen>soon", "Invalid duration in condition")]
+    #[test_case("limit=0", "Invalid limit: '0'")]
+    #[test_case("sort=name", "Unknown entity field: 'name'")]
+    #[test_case("after=device/child0//", "Invalid cursor: 'device/child0//'")]
+    #[tokio::test]
+    async fn entity_list_invalid_query(query: &str, error: &str) {
+        let TestHandle {
+            mut app,
+            entity_store_box: _,
+        } = setup();
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("/v1/entities?{query}"))
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let message = body["error"].as_str().unwrap();
+        assert!(message.starts_with(error), "unexpected error: {message}");
+    }
+
+    fn list_response(entities: Vec<EntityMetadata>) -> EntityStoreResponse {
+        let entities = entities
+            .iter()
+            .map(|entity| serde_json::to_value(entity).unwrap())
+            .collect();
+        EntityStoreResponse::List(EntityPage {
+            entities,
+            next: None,
+        })
+    }
+
     async fn assert_non_existent_entity_response(response: Response<Body>) {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
@@ -6,6 +6,7 @@ use serde_json::Map;
 use serde_json::Value as JsonValue;
 use std::fmt::Display;
 use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Represents externally provided unique ID of an entity.
@@ -69,6 +70,15 @@ pub struct EntityMetadata {
     pub external_id: Option<EntityExternalId>,
     #[serde(rename = "@health", skip_serializing_if = "Option::is_none")]
     pub health_endpoint: Option<EntityTopicId>,
+    /// Time since the last activity after which the entity is marked as inactive,
+    /// overriding the TTL configured for its type
+    #[serde(
+        rename = "@ttl",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ttl_format"
+    )]
+    pub ttl: Option<Duration>,
 
     #[serde(skip)]
     pub twin_data: Map<String, JsonValue>,
@@ -82,6 +92,7 @@ impl EntityMetadata {
             external_id: None,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         }
     }
@@ -101,6 +112,11 @@ impl EntityMetadata {
         self
     }
 
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
     /// Creates a entity metadata for the main device.
     pub fn main_device(device_id: Option<EntityExternalId>) -> Self {
         Self {
@@ -109,6 +125,7 @@ impl EntityMetadata {
             r#type: EntityType::MainDevice,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         }
     }
@@ -121,6 +138,7 @@ impl EntityMetadata {
             r#type: EntityType::ChildDevice,
             parent: Some(EntityTopicId::default_main_device()),
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         })
     }
@@ -141,7 +159,7 @@ impl EntityMetadata {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EntityType {
     #[serde(rename = "device")]
     MainDevice,
@@ -190,3 +208,52 @@ pub enum InsertOutcome {
     Updated,
     Inserted,
 }
+
+/// (De)serialization of an entity TTL,
+/// given either as a human readable duration (e.g. `"30m"` or `"7d"`) or as a number of seconds
+///
+/// A zero TTL is rejected, as it would make the entity inactive right after its registration.
+pub(crate) mod ttl_format {
+    use serde::de::Error;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serializer;
+    use serde_json::Value as JsonValue;
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        ttl: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match ttl {
+            Some(ttl) => serializer.serialize_str(&humantime::format_duration(*ttl).to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        let ttl = match JsonValue::deserialize(deserializer)? {
+            JsonValue::Null => return Ok(None),
+            JsonValue::Number(secs) => secs.as_u64().map(Duration::from_secs).ok_or_else(|| {
+                D::Error::custom(format!(
+                    "invalid TTL: {secs}. Expecting a positive number of seconds"
+                ))
+            })?,
+            JsonValue::String(ttl) => humantime::parse_duration(&ttl)
+                .map_err(|err| D::Error::custom(format!("invalid TTL: '{ttl}': {err}")))?,
+            ttl => {
+                return Err(D::Error::custom(format!(
+                "invalid TTL: {ttl}. Expecting a duration such as \"30m\" or a number of seconds"
+            )))
+            }
+        };
+        if ttl.is_zero() {
+            return Err(D::Error::custom(
+                "invalid TTL: 0. Expecting a positive duration",
+            ));
+        }
+        Ok(Some(ttl))
+    }
+}
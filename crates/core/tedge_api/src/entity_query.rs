@@ -0,0 +1,808 @@
+//! Queries over the entity store.
+//!
+//! On top of the tree scope given by [ListFilters] (`root`, `parent` and `type`),
+//! a query can filter entities on their registration fields, twin data, health status and activity,
+//! sort them, paginate the results with a cursor and only return a subset of the fields.
+//!
+//! Conditions are expressed as `<field><operator><value>`, e.g. `twin.firmware.version!=1.2`:
+//! - fields: `@topic-id`, `@id`, `@type`, `@parent`, `@health`,
+//!   `health` (the health status), `last-seen` (the time elapsed since the last activity)
+//!   and `twin.<fragment>[.<key>]*` (a twin fragment or a nested value of a twin fragment)
+//! - operators: `=` (or `==`), `!=`, `<`, `<=`, `>`, `>=`
+//! - a field without any operator and value only checks that the field is set.
+//!
+//! Values are compared as numbers when both sides are numbers, and as strings otherwise.
+//! `last-seen` values are durations, e.g. `last-seen>1h`.
+//!
+//! Conditions and fields are given as comma separated lists,
+//! a comma or a backslash in a value being escaped with a backslash, e.g. `twin.tags=a\,b`.
+use crate::entity::EntityMetadata;
+use crate::entity_store::ListFilters;
+use crate::mqtt_topics::EntityTopicId;
+use base64::prelude::*;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value as JsonValue;
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A query over the entity store
+#[derive(Debug, Default, Clone)]
+pub struct EntityQuery {
+    /// The sub-tree of entities to be considered
+    pub scope: ListFilters,
+
+    /// Conditions that must all be satisfied by the returned entities
+    pub conditions: Vec<Condition>,
+
+    /// The order of the returned entities, parents first if none
+    ///
+    /// Entities with the same sort value are ordered by topic id.
+    /// Unsorted and unpaginated entities are returned in tree order.
+    pub sort: Option<SortOrder>,
+
+    /// Return only the entities listed after this position
+    pub after: Option<Cursor>,
+
+    /// The maximum number of entities to return
+    pub limit: Option<usize>,
+
+    /// The fields to return; the whole entity definition if none
+    pub fields: Option<Vec<Field>>,
+}
+
+/// A page of query results
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EntityPage {
+    pub entities: Vec<JsonValue>,
+
+    /// The cursor to be used to get the next page, if there are more results
+    pub next: Option<Cursor>,
+}
+
+/// The position of an entity in the results of a query
+///
+/// A cursor holds the sort value and the topic id of the last entity of a page,
+/// so the next page can be computed even if that entity has been updated or deregistered since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub key: JsonValue,
+    pub topic_id: EntityTopicId,
+}
+
+/// A field of an entity, that can be used to filter, sort and project entities
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    TopicId,
+    ExternalId,
+    Type,
+    Parent,
+    HealthEndpoint,
+    HealthStatus,
+    LastSeen,
+    Twin(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Exists,
+    Eq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A condition on the value of an entity field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub field: Field,
+    pub operator: Operator,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortOrder {
+    pub field: Field,
+    pub descending: bool,
+}
+
+/// The state of an entity against which a query is evaluated
+pub struct EntityView<'a> {
+    pub metadata: &'a EntityMetadata,
+    /// The number of ancestors of the entity, used to list parents first
+    pub depth: usize,
+    pub health_status: Option<&'a str>,
+    pub last_seen: Duration,
+}
+
+/// The value of a field for a given entity
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue<'a> {
+    Str(&'a str),
+    Json(&'a JsonValue),
+    Elapsed(Duration),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
+pub enum QueryError {
+    #[error("Unknown entity field: '{0}'. Expecting one of @topic-id, @id, @type, @parent, @health, health, last-seen or twin.<fragment>")]
+    UnknownField(String),
+
+    #[error("Invalid condition: '{0}'")]
+    InvalidCondition(String),
+
+    #[error("Invalid duration in condition: '{0}'. Expecting a duration such as 30s, 5m or 1h")]
+    InvalidDuration(String),
+
+    #[error("Invalid limit: '{0}'. Expecting a positive number")]
+    InvalidLimit(String),
+
+    #[error("Invalid cursor: '{0}'. Expecting a cursor returned by a previous query")]
+    InvalidCursor(String),
+}
+
+impl EntityQuery {
+    pub fn new(scope: ListFilters) -> Self {
+        EntityQuery {
+            scope,
+            ..EntityQuery::default()
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn with_sort(mut self, sort: SortOrder) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    pub fn with_after(mut self, after: Cursor) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Vec<Field>) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+
+    /// Parses a comma separated list of conditions
+    pub fn parse_conditions(conditions: &str) -> Result<Vec<Condition>, QueryError> {
+        split_list(conditions)
+            .iter()
+            .map(|condition| condition.trim())
+            .filter(|condition| !condition.is_empty())
+            .map(Condition::from_str)
+            .collect()
+    }
+
+    /// Parses a comma separated list of fields
+    pub fn parse_fields(fields: &str) -> Result<Vec<Field>, QueryError> {
+        split_list(fields)
+            .iter()
+            .map(|field| field.trim())
+            .filter(|field| !field.is_empty())
+            .map(Field::from_str)
+            .collect()
+    }
+
+    pub fn parse_limit(limit: &str) -> Result<usize, QueryError> {
+        match limit.parse() {
+            Ok(limit) if limit > 0 => Ok(limit),
+            _ => Err(QueryError::InvalidLimit(limit.to_string())),
+        }
+    }
+
+    /// Checks if an entity satisfies all the conditions of this query
+    ///
+    /// The tree scope of the query is not checked by this method.
+    pub fn matches(&self, entity: &EntityView) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(entity))
+    }
+
+    /// Filters, sorts, paginates and projects the given entities
+    ///
+    /// The entities are ordered by sort value and then by topic id,
+    /// so a page starts right after the cursor position,
+    /// even if entities have been updated, registered or deregistered since the previous page.
+    ///
+    /// With no sort order nor pagination, the entities are kept in the given order,
+    /// i.e. the tree order of the entity store.
+    pub fn page(&self, entities: Vec<EntityView>) -> EntityPage {
+        if self.sort.is_none() && self.after.is_none() && self.limit.is_none() {
+            let entities = entities
+                .iter()
+                .filter(|entity| self.matches(entity))
+                .map(|entity| self.project(entity))
+                .collect();
+            return EntityPage {
+                entities,
+                next: None,
+            };
+        }
+
+        let mut matching: Vec<(Cursor, &EntityView)> = entities
+            .iter()
+            .filter(|entity| self.matches(entity))
+            .map(|entity| (self.cursor(entity), entity))
+            .collect();
+        matching.sort_by(|(a, _), (b, _)| self.compare(a, b));
+
+        let start = match &self.after {
+            None => 0,
+            Some(after) => matching
+                .partition_point(|(cursor, _)| self.compare(cursor, after) != Ordering::Greater),
+        };
+        let remaining = &matching[start..];
+        let page = &remaining[..remaining.len().min(self.limit.unwrap_or(usize::MAX))];
+        let next = if remaining.len() > page.len() {
+            page.last().map(|(cursor, _)| cursor.clone())
+        } else {
+            None
+        };
+        let entities = page
+            .iter()
+            .map(|(_, entity)| self.project(entity))
+            .collect();
+
+        EntityPage { entities, next }
+    }
+
+    /// The position of an entity in the results of this query
+    fn cursor(&self, entity: &EntityView) -> Cursor {
+        let key = match &self.sort {
+            Some(sort) => sort.key(entity),
+            None => JsonValue::from(entity.depth),
+        };
+        Cursor {
+            key,
+            topic_id: entity.metadata.topic_id.clone(),
+        }
+    }
+
+    fn compare(&self, a: &Cursor, b: &Cursor) -> Ordering {
+        let ordering = match &self.sort {
+            Some(sort) => sort.compare_keys(&a.key, &b.key),
+            None => compare_json(&a.key, &b.key),
+        };
+        ordering.then_with(|| a.topic_id.as_str().cmp(b.topic_id.as_str()))
+    }
+
+    fn project(&self, entity: &EntityView) -> JsonValue {
+        let Some(fields) = &self.fields else {
+            return serde_json::to_value(entity.metadata).unwrap_or(JsonValue::Null);
+        };
+
+        let mut projection = Map::new();
+        projection.insert(
+            Field::TopicId.to_string(),
+            entity.metadata.topic_id.as_str().into(),
+        );
+        for field in fields {
+            if let Some(value) = field.value(entity) {
+                projection.insert(field.to_string(), value.to_json());
+            }
+        }
+        JsonValue::Object(projection)
+    }
+}
+
+impl Field {
+    /// Returns the value of this field for the given entity, if any
+    pub fn value<'a>(&self, entity: &EntityView<'a>) -> Option<FieldValue<'a>> {
+        let metadata = entity.metadata;
+        match self {
+            Field::TopicId => Some(FieldValue::Str(metadata.topic_id.as_str())),
+            Field::ExternalId => metadata
+                .external_id
+                .as_ref()
+                .map(|id| FieldValue::Str(id.as_ref())),
+            Field::Type => Some(FieldValue::Str(metadata.r#type.as_str())),
+            Field::Parent => metadata
+                .parent
+                .as_ref()
+                .map(|parent| FieldValue::Str(parent.as_str())),
+            Field::HealthEndpoint => metadata
+                .health_endpoint
+                .as_ref()
+                .map(|endpoint| FieldValue::Str(endpoint.as_str())),
+            Field::HealthStatus => entity.health_status.map(FieldValue::Str),
+            Field::LastSeen => Some(FieldValue::Elapsed(entity.last_seen)),
+            Field::Twin(path) => {
+                let (fragment, keys) = path.split_first()?;
+                let mut value = metadata.twin_data.get(fragment)?;
+                for key in keys {
+                    value = value.get(key)?;
+                }
+                Some(FieldValue::Json(value))
+            }
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = QueryError;
+
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        match field {
+            "@topic-id" => Ok(Field::TopicId),
+            "@id" => Ok(Field::ExternalId),
+            "@type" => Ok(Field::Type),
+            "@parent" => Ok(Field::Parent),
+            "@health" => Ok(Field::HealthEndpoint),
+            "health" => Ok(Field::HealthStatus),
+            "last-seen" => Ok(Field::LastSeen),
+            _ => {
+                let path = field
+                    .strip_prefix("twin.")
+                    .ok_or_else(|| QueryError::UnknownField(field.to_string()))?;
+                let path: Vec<String> = path.split('.').map(str::to_string).collect();
+                if path.iter().any(|key| key.is_empty()) {
+                    return Err(QueryError::UnknownField(field.to_string()));
+                }
+                Ok(Field::Twin(path))
+            }
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::TopicId => f.write_str("@topic-id"),
+            Field::ExternalId => f.write_str("@id"),
+            Field::Type => f.write_str("@type"),
+            Field::Parent => f.write_str("@parent"),
+            Field::HealthEndpoint => f.write_str("@health"),
+            Field::HealthStatus => f.write_str("health"),
+            Field::LastSeen => f.write_str("last-seen"),
+            Field::Twin(path) => write!(f, "twin.{}", path.join(".")),
+        }
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, entity: &EntityView) -> bool {
+        let Some(value) = self.field.value(entity) else {
+            // A missing value is different from any given value
+            return self.operator == Operator::NotEq;
+        };
+        let ordering = match value.compare_to(&self.value) {
+            Some(ordering) => ordering,
+            None => return self.operator == Operator::Exists || self.operator == Operator::NotEq,
+        };
+        match self.operator {
+            Operator::Exists => true,
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::NotEq => ordering != Ordering::Equal,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = QueryError;
+
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        let Some(index) = condition.find(['=', '!', '<', '>']) else {
+            return Ok(Condition {
+                field: condition.trim().parse()?,
+                operator: Operator::Exists,
+                value: String::new(),
+            });
+        };
+
+        let (field, rest) = condition.split_at(index);
+        let (operator, value) = [
+            ("==", Operator::Eq),
+            ("!=", Operator::NotEq),
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("=", Operator::Eq),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ]
+        .into_iter()
+        .find_map(|(symbol, operator)| rest.strip_prefix(symbol).map(|value| (operator, value)))
+        .ok_or_else(|| QueryError::InvalidCondition(condition.to_string()))?;
+
+        let field: Field = field.trim().parse()?;
+        let value = value.trim().to_string();
+        if field == Field::LastSeen && humantime::parse_duration(&value).is_err() {
+            return Err(QueryError::InvalidDuration(condition.to_string()));
+        }
+
+        Ok(Condition {
+            field,
+            operator,
+            value,
+        })
+    }
+}
+
+impl SortOrder {
+    /// The sort value of an entity, `null` if the entity has no value for the sort field
+    pub fn key(&self, entity: &EntityView) -> JsonValue {
+        self.field
+            .value(entity)
+            .map_or(JsonValue::Null, |value| value.to_json())
+    }
+
+    pub fn compare_keys(&self, a: &JsonValue, b: &JsonValue) -> Ordering {
+        // Entities with no value for the sort field are listed last
+        match (a, b) {
+            (JsonValue::Null, JsonValue::Null) => Ordering::Equal,
+            (JsonValue::Null, _) => Ordering::Greater,
+            (_, JsonValue::Null) => Ordering::Less,
+            (a, b) if self.descending => compare_json(a, b).reverse(),
+            (a, b) => compare_json(a, b),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = QueryError;
+
+    /// Parses a sort order given as a field name, prefixed by `-` for a descending order
+    fn from_str(sort: &str) -> Result<Self, Self::Err> {
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort.strip_prefix('+').unwrap_or(sort), false),
+        };
+        Ok(SortOrder {
+            field: field.parse()?,
+            descending,
+        })
+    }
+}
+
+impl FieldValue<'_> {
+    /// Compares this value to a value given as a string in a condition
+    ///
+    /// Returns `None` if the values cannot be compared
+    fn compare_to(&self, other: &str) -> Option<Ordering> {
+        match self {
+            FieldValue::Str(value) => Some((*value).cmp(other)),
+            FieldValue::Elapsed(elapsed) => {
+                let other = humantime::parse_duration(other).ok()?;
+                Some(elapsed.cmp(&other))
+            }
+            FieldValue::Json(JsonValue::Number(value)) => {
+                let value = value.as_f64()?;
+                let other = other.parse::<f64>().ok()?;
+                value.partial_cmp(&other)
+            }
+            FieldValue::Json(JsonValue::String(value)) => Some(value.as_str().cmp(other)),
+            FieldValue::Json(JsonValue::Null) if other.is_empty() => Some(Ordering::Equal),
+            FieldValue::Json(value) => Some(value.to_string().as_str().cmp(other)),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        match self {
+            FieldValue::Str(value) => JsonValue::from(*value),
+            FieldValue::Json(value) => (*value).clone(),
+            FieldValue::Elapsed(elapsed) => JsonValue::from(elapsed.as_secs()),
+        }
+    }
+}
+
+impl Display for Cursor {
+    /// Encodes the cursor as an opaque string that can be used in a URL or an HTTP header
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cursor = json!([self.key, self.topic_id.as_str()]);
+        f.write_str(&BASE64_URL_SAFE_NO_PAD.encode(cursor.to_string()))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = QueryError;
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let invalid = || QueryError::InvalidCursor(cursor.to_string());
+        let bytes = BASE64_URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| invalid())?;
+        let (key, topic_id): (JsonValue, String) =
+            serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        Ok(Cursor {
+            key,
+            topic_id: topic_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Compares two field values, as numbers when both are numbers and as strings otherwise
+fn compare_json(a: &JsonValue, b: &JsonValue) -> Ordering {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (JsonValue::String(a), JsonValue::String(b)) => a.cmp(b),
+        (a, b) => json_string(a).cmp(&json_string(b)),
+    }
+}
+
+fn json_string(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Splits a comma separated list, unescaping the commas and backslashes escaped with a backslash
+fn split_list(list: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut chars = list.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ (',' | '\\')) => item.push(escaped),
+                Some(other) => {
+                    item.push(c);
+                    item.push(other);
+                }
+                None => item.push(c),
+            },
+            ',' => items.push(std::mem::take(&mut item)),
+            _ => item.push(c),
+        }
+    }
+    items.push(item);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::EntityType;
+    use serde_json::json;
+    use test_case::test_case;
+
+    #[test_case("twin.firmware.version!=1.2", true; "string not equal")]
+    #[test_case("twin.firmware.version=1.0", true; "string equal")]
+    #[test_case("twin.firmware.version==1.2", false; "string double equal")]
+    #[test_case("twin.battery<20", true; "number less than")]
+    #[test_case("twin.battery>=20", false; "number greater or equal")]
+    #[test_case("twin.battery>9", true; "numbers are not compared as strings")]
+    #[test_case("twin.location", true; "existing fragment")]
+    #[test_case("twin.unknown", false; "missing fragment")]
+    #[test_case("twin.unknown!=foo", true; "missing fragment is different")]
+    #[test_case("@type=child-device", true; "entity type")]
+    #[test_case("@parent=device/hub//", true; "entity parent")]
+    #[test_case("@id", false; "missing external id")]
+    #[test_case("health=up", true; "health status")]
+    #[test_case("last-seen>1m", true; "inactive entity")]
+    #[test_case("last-seen<1m", false; "active entity")]
+    fn condition_matches(condition: &str, expected: bool) {
+        let metadata = sensor(
+            "device/sensor//",
+            json!({
+                "firmware": {"version": "1.0"},
+                "battery": 12,
+                "location": {"lat": 42.0, "lon": 3.1}
+            }),
+        );
+        let entity = EntityView {
+            metadata: &metadata,
+            depth: 1,
+            health_status: Some("up"),
+            last_seen: Duration::from_secs(300),
+        };
+
+        let condition: Condition = condition.parse().unwrap();
+        assert_eq!(condition.matches(&entity), expected);
+    }
+
+    #[test_case("unknown=12", QueryError::UnknownField("unknown".to_string()))]
+    #[test_case("twin..x=12", QueryError::UnknownField("twin..x".to_string()))]
+    #[test_case("@type!foo", QueryError::InvalidCondition("@type!foo".to_string()))]
+    #[test_case("last-seen>yesterday", QueryError::InvalidDuration("last-seen>yesterday".to_string()))]
+    fn invalid_conditions(condition: &str, expected: QueryError) {
+        assert_eq!(condition.parse::<Condition>().unwrap_err(), expected);
+    }
+
+    #[test]
+    fn sort_and_paginate() {
+        let entities: Vec<_> = [
+            ("device/sensor1//", 30),
+            ("device/sensor2//", 10),
+            ("device/sensor3//", 20),
+            ("device/sensor4//", 10),
+        ]
+        .into_iter()
+        .map(|(topic_id, battery)| sensor(topic_id, json!({"battery": battery})))
+        .collect();
+        let views = || {
+            entities
+                .iter()
+                .map(|metadata| EntityView {
+                    metadata,
+                    depth: 1,
+                    health_status: None,
+                    last_seen: Duration::ZERO,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let query = EntityQuery::default()
+            .with_sort("-twin.battery".parse().unwrap())
+            .with_limit(2)
+            .with_fields(EntityQuery::parse_fields("twin.battery").unwrap());
+        let page = query.page(views());
+        assert_eq!(
+            page.entities,
+            vec![
+                json!({"@topic-id": "device/sensor1//", "twin.battery": 30}),
+                json!({"@topic-id": "device/sensor3//", "twin.battery": 20}),
+            ]
+        );
+        let next = page.next.unwrap();
+        assert_eq!(
+            next,
+            Cursor {
+                key: json!(20),
+                topic_id: "device/sensor3//".parse().unwrap()
+            }
+        );
+
+        // The cursor is given back as an opaque string
+        let query = query.with_after(next.to_string().parse().unwrap());
+        let page = query.page(views());
+        assert_eq!(
+            page.entities,
+            vec![
+                json!({"@topic-id": "device/sensor2//", "twin.battery": 10}),
+                json!({"@topic-id": "device/sensor4//", "twin.battery": 10}),
+            ]
+        );
+        assert_eq!(page.next, None);
+
+        // The next page doesn't depend on the entity of the cursor being still registered
+        let query = query.with_after(Cursor {
+            key: json!(15),
+            topic_id: "device/unknown//".parse().unwrap(),
+        });
+        let page = query.page(views());
+        assert_eq!(page.entities.len(), 2);
+    }
+
+    #[test]
+    fn entities_with_the_same_sort_value_are_paginated_by_topic_id() {
+        let entities: Vec<_> = ["device/sensor2//", "device/sensor3//", "device/sensor1//"]
+            .into_iter()
+            .map(|topic_id| sensor(topic_id, json!({"battery": 10})))
+            .collect();
+        let views = || {
+            entities
+                .iter()
+                .map(|metadata| EntityView {
+                    metadata,
+                    depth: 1,
+                    health_status: None,
+                    last_seen: Duration::ZERO,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut query = EntityQuery::default()
+            .with_sort("twin.battery".parse().unwrap())
+            .with_limit(1);
+        let mut topic_ids = vec![];
+        loop {
+            let page = query.page(views());
+            topic_ids.extend(page.entities.iter().map(|e| e["@topic-id"].clone()));
+            match page.next {
+                Some(next) => query = query.with_after(next),
+                None => break,
+            }
+        }
+        assert_eq!(
+            topic_ids,
+            vec![
+                json!("device/sensor1//"),
+                json!("device/sensor2//"),
+                json!("device/sensor3//")
+            ]
+        );
+    }
+
+    #[test]
+    fn unsorted_entities_are_kept_in_tree_order_unless_paginated() {
+        // As listed by the entity store: parents first, then children in tree order
+        let entities: Vec<_> = [
+            ("device/main//", 0),
+            ("device/zeta//", 1),
+            ("device/alpha//", 1),
+            ("device/zeta/service/a", 2),
+        ]
+        .into_iter()
+        .map(|(topic_id, depth)| (sensor(topic_id, json!({})), depth))
+        .collect();
+        let views = || {
+            entities
+                .iter()
+                .map(|(metadata, depth)| EntityView {
+                    metadata,
+                    depth: *depth,
+                    health_status: None,
+                    last_seen: Duration::ZERO,
+                })
+                .collect::<Vec<_>>()
+        };
+        let topic_ids = |page: EntityPage| {
+            page.entities
+                .iter()
+                .map(|e| e["@topic-id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let query = EntityQuery::default().with_fields(vec![]);
+        assert_eq!(
+            topic_ids(query.page(views())),
+            vec![
+                "device/main//",
+                "device/zeta//",
+                "device/alpha//",
+                "device/zeta/service/a"
+            ]
+        );
+
+        let query = query.with_limit(3);
+        assert_eq!(
+            topic_ids(query.page(views())),
+            vec!["device/main//", "device/alpha//", "device/zeta//"]
+        );
+    }
+
+    #[test_case("", QueryError::InvalidCursor("".to_string()))]
+    #[test_case("device/sensor1//", QueryError::InvalidCursor("device/sensor1//".to_string()))]
+    #[test_case("WzIwXQ", QueryError::InvalidCursor("WzIwXQ".to_string()); "missing topic id")]
+    fn invalid_cursors(cursor: &str, expected: QueryError) {
+        assert_eq!(cursor.parse::<Cursor>().unwrap_err(), expected);
+    }
+
+    #[test]
+    fn commas_can_be_escaped_in_conditions() {
+        let conditions =
+            EntityQuery::parse_conditions(r"twin.tags=a\,b, @type=child-device,twin.path=c:\\d")
+                .unwrap();
+        assert_eq!(
+            conditions
+                .iter()
+                .map(|condition| condition.value.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a,b", "child-device", r"c:\d"]
+        );
+    }
+
+    fn sensor(topic_id: &str, twin: JsonValue) -> EntityMetadata {
+        let JsonValue::Object(twin_data) = twin else {
+            panic!("Expecting a JSON object");
+        };
+        EntityMetadata {
+            twin_data,
+            ..EntityMetadata::new(topic_id.parse().unwrap(), EntityType::ChildDevice)
+                .with_parent("device/hub//".parse().unwrap())
+        }
+    }
+}
@@ -11,6 +11,9 @@ use crate::entity::EntityExternalId;
 use crate::entity::EntityMetadata;
 use crate::entity::EntityType;
 use crate::entity::InsertOutcome;
+use crate::entity_query::EntityPage;
+use crate::entity_query::EntityQuery;
+use crate::entity_query::EntityView;
 use crate::entity_store;
 use crate::mqtt_topics::default_topic_schema;
 use crate::mqtt_topics::Channel;
@@ -19,6 +22,9 @@ use crate::mqtt_topics::MqttSchema;
 use crate::store::message_log::MessageLog;
 use crate::store::pending_entity_store::PendingEntityStore;
 use crate::store::pending_entity_store::RegisteredEntityData;
+use crate::twin_schema::SchemaEnforcement;
+use crate::twin_schema::SchemaViolation;
+use crate::twin_schema::TwinSchemas;
 use log::debug;
 use log::error;
 use log::info;
@@ -35,6 +41,8 @@ use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::mem;
 use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
 
 /// A store for topic-based entity metadata lookup.
 ///
@@ -78,6 +86,9 @@ pub struct EntityStore {
     pending_entity_store: PendingEntityStore,
     // The persistent message log to persist entity registrations and twin data messages
     message_log: MessageLog,
+    // The optional schemas twin fragments are checked against
+    twin_schemas: TwinSchemas,
+    schema_enforcement: SchemaEnforcement,
 }
 
 impl EntityStore {
@@ -104,6 +115,7 @@ impl EntityStore {
             r#type: main_device.r#type,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: main_device.twin_data,
         };
 
@@ -127,6 +139,8 @@ impl EntityStore {
             entities: EntityTree::new(main_device.topic_id, metadata),
             pending_entity_store: PendingEntityStore::new(mqtt_schema, telemetry_cache_size),
             message_log,
+            twin_schemas: TwinSchemas::default(),
+            schema_enforcement: SchemaEnforcement::default(),
         };
 
         entity_store.load_from_message_log();
@@ -134,9 +148,24 @@ impl EntityStore {
         Ok(entity_store)
     }
 
+    /// Checks the twin fragments of the subsequent updates against the given schemas
+    ///
+    /// The twin data restored from the persistent entity store is not checked.
+    pub fn with_twin_schemas(
+        mut self,
+        twin_schemas: TwinSchemas,
+        enforcement: SchemaEnforcement,
+    ) -> Self {
+        self.twin_schemas = twin_schemas;
+        self.schema_enforcement = enforcement;
+        self
+    }
+
     pub fn load_from_message_log(&mut self) {
         info!("Loading the entity store from the log");
         let messages: Vec<_> = self.message_log.messages().collect();
+        // Entities re-parented after registration might be listed before their new parent
+        let mut orphans = vec![];
         for message in messages {
             if let Ok((source, channel)) = self.mqtt_schema.entity_channel_of(&message.topic) {
                 match channel {
@@ -145,9 +174,13 @@ impl EntityStore {
                             source.clone(),
                             message.payload_bytes(),
                         ) {
-                            if let Err(err) = self.register_entity(register_message) {
-                                error!("Failed to re-register {source} from the persistent entity store due to {err}");
-                                continue;
+                            match self.register_entity(register_message.clone()) {
+                                Ok(_) => (),
+                                Err(Error::NoParent(_)) => orphans.push(register_message),
+                                Err(err) => {
+                                    error!("Failed to re-register {source} from the persistent entity store due to {err}");
+                                    continue;
+                                }
                             }
                         }
                     }
@@ -168,9 +201,34 @@ impl EntityStore {
                 );
             }
         }
+        self.register_orphans(orphans);
         info!("Finished loading the entity store from the log");
     }
 
+    /// Registers entities loaded before their parent, until no more progress is made
+    fn register_orphans(&mut self, mut orphans: Vec<EntityRegistrationMessage>) {
+        loop {
+            let count = orphans.len();
+            let mut remaining = vec![];
+            for register_message in orphans {
+                if let Err(err) = self.register_entity(register_message.clone()) {
+                    remaining.push((register_message, err));
+                }
+            }
+            if remaining.is_empty() {
+                return;
+            }
+            if remaining.len() == count {
+                for (register_message, err) in remaining {
+                    let source = register_message.topic_id;
+                    error!("Failed to re-register {source} from the persistent entity store due to {err}");
+                }
+                return;
+            }
+            orphans = remaining.into_iter().map(|(message, _)| message).collect();
+        }
+    }
+
     /// Iterates over the entity topic ids
     pub fn entity_topic_ids(&self) -> impl Iterator<Item = &EntityTopicId> {
         self.entities.entity_topic_ids()
@@ -241,6 +299,15 @@ impl EntityStore {
         &mut self,
         message: EntityRegistrationMessage,
     ) -> Result<Vec<RegisteredEntityData>, Error> {
+        for (fragment_key, fragment_value) in message.twin_data.iter() {
+            self.check_twin_fragment(
+                &message.topic_id,
+                message.r#type,
+                fragment_key,
+                fragment_value,
+            )?;
+        }
+
         match self.register_and_persist_entity(message.clone()) {
             Ok(affected_entities) => {
                 if affected_entities.is_empty() {
@@ -283,18 +350,7 @@ impl EntityStore {
 
         let mut affected_entities = vec![];
 
-        let parent = match message.r#type {
-            EntityType::MainDevice => None,
-            EntityType::ChildDevice => message
-                .parent
-                .clone()
-                .or_else(|| Some(self.main_device.clone())),
-            EntityType::Service => message
-                .parent
-                .clone()
-                .or_else(|| topic_id.default_service_parent_identifier())
-                .or_else(|| Some(self.main_device.clone())),
-        };
+        let parent = message.parent_or_default(&self.main_device);
 
         // parent device is affected if new device is its child
         if let Some(parent) = &parent {
@@ -305,19 +361,28 @@ impl EntityStore {
             affected_entities.push(parent.clone());
         }
 
+        // An entity re-registered with a new parent is moved along with its children
+        let reparented = match (&parent, self.entities.get(&topic_id)) {
+            (Some(parent), Some(current)) if current.parent.as_ref() != Some(parent) => {
+                self.entities.set_parent(&topic_id, parent)?
+            }
+            _ => false,
+        };
+
         let entity_metadata = EntityMetadata {
             topic_id: topic_id.clone(),
             r#type: message.r#type,
             external_id: message.external_id,
             parent,
             health_endpoint: message.health_endpoint,
+            ttl: message.ttl,
             twin_data: message.twin_data,
         };
 
         match self.entities.insert(topic_id.clone(), entity_metadata) {
-            InsertOutcome::Unchanged => Ok(vec![]),
+            InsertOutcome::Unchanged if !reparented => Ok(vec![]),
             InsertOutcome::Inserted => Ok(affected_entities),
-            InsertOutcome::Updated => {
+            InsertOutcome::Unchanged | InsertOutcome::Updated => {
                 affected_entities.push(topic_id);
                 Ok(affected_entities)
             }
@@ -342,7 +407,50 @@ impl EntityStore {
         topic_id: &EntityTopicId,
         update_message: EntityUpdateMessage,
     ) -> Result<&EntityMetadata, Error> {
-        self.entities.update_entity(topic_id, update_message)
+        let previous = self.entities.try_get(topic_id)?.clone();
+        let updated = self.entities.update_entity(topic_id, update_message)?;
+        if updated != &previous {
+            self.persist_registration_update(topic_id)?;
+        }
+        self.entities.try_get(topic_id)
+    }
+
+    /// Moves an entity, along with its child devices and services, under a new parent
+    ///
+    /// The twin data of the moved entities is preserved.
+    /// Returns `true` if the parent has actually been changed and persisted.
+    pub fn set_parent(
+        &mut self,
+        topic_id: &EntityTopicId,
+        new_parent: &EntityTopicId,
+    ) -> Result<bool, Error> {
+        let updated = self.entities.set_parent(topic_id, new_parent)?;
+        if updated {
+            self.persist_registration_update(topic_id)?;
+        }
+        Ok(updated)
+    }
+
+    /// Persists the current registration of an entity,
+    /// keeping the twin data that was part of the previously persisted registration message.
+    fn persist_registration_update(&mut self, topic_id: &EntityTopicId) -> Result<(), Error> {
+        let mut registration = EntityRegistrationMessage::from(self.entities.try_get(topic_id)?);
+        let topic = self
+            .mqtt_schema
+            .topic_for(topic_id, &Channel::EntityMetadata);
+        if let Some(persisted) = self
+            .message_log
+            .latest_payload(&topic.name)
+            .and_then(|payload| {
+                EntityRegistrationMessage::try_from(topic_id.clone(), payload.as_bytes()).ok()
+            })
+        {
+            registration.twin_data = persisted.twin_data;
+        }
+
+        self.message_log
+            .append_message(&registration.to_mqtt_message(&self.mqtt_schema))?;
+        Ok(())
     }
 
     pub fn ancestors(&self, topic_id: &EntityTopicId) -> Result<Vec<&EntityTopicId>, Error> {
@@ -441,6 +549,14 @@ impl EntityStore {
             return Err(Error::InvalidTwinData(fragment_key));
         }
 
+        let entity_type = self.try_get(&twin_message.topic_id)?.r#type;
+        self.check_twin_fragment(
+            &twin_message.topic_id,
+            entity_type,
+            &fragment_key,
+            &fragment_value,
+        )?;
+
         let entity = self.try_get_mut(&twin_message.topic_id)?;
         if fragment_value.is_null() {
             let existing = entity.twin_data.remove(&fragment_key);
@@ -472,11 +588,47 @@ impl EntityStore {
         topic_id: &EntityTopicId,
         fragments: Map<String, JsonValue>,
     ) -> Result<Map<String, JsonValue>, entity_store::Error> {
+        let entity_type = self.try_get(topic_id)?.r#type;
+        for (fragment_key, fragment_value) in fragments.iter() {
+            self.check_twin_fragment(topic_id, entity_type, fragment_key, fragment_value)?;
+        }
+
         let entity = self.try_get_mut(topic_id)?;
         let old = mem::replace(&mut entity.twin_data, fragments);
         Ok(old)
     }
 
+    /// Checks a twin fragment against its schema, if any
+    ///
+    /// Depending on the schema enforcement, a non-conforming fragment is either rejected
+    /// or accepted with a warning.
+    fn check_twin_fragment(
+        &self,
+        topic_id: &EntityTopicId,
+        entity_type: EntityType,
+        fragment_key: &str,
+        fragment_value: &JsonValue,
+    ) -> Result<(), Error> {
+        let Err(violation) = self
+            .twin_schemas
+            .validate(entity_type, fragment_key, fragment_value)
+        else {
+            return Ok(());
+        };
+
+        match self.schema_enforcement {
+            SchemaEnforcement::Reject => Err(Error::TwinSchemaViolation(
+                topic_id.clone(),
+                fragment_key.to_string(),
+                violation,
+            )),
+            SchemaEnforcement::Warn => {
+                warn!("The twin fragment '{fragment_key}' of {topic_id} doesn't conform to its schema: {violation}");
+                Ok(())
+            }
+        }
+    }
+
     pub fn cache_early_data_message(&mut self, message: MqttMessage) {
         self.pending_entity_store.cache_early_data_message(message)
     }
@@ -484,9 +636,121 @@ impl EntityStore {
     pub fn list_entity_tree(&self, filters: ListFilters) -> Vec<&EntityMetadata> {
         self.entities.list_entity_tree(filters)
     }
+
+    /// Evaluates a query against the entities, cloning only the entities of the returned page
+    pub fn query(&self, query: &EntityQuery, now: Instant) -> EntityPage {
+        let entities = self
+            .entities
+            .list_entity_nodes(&query.scope)
+            .into_iter()
+            .map(|node| EntityView {
+                metadata: node.metadata(),
+                depth: self
+                    .entities
+                    .ancestors(&node.metadata.topic_id)
+                    .map_or(0, |ancestors| ancestors.len()),
+                health_status: self.entities.health_status(node),
+                last_seen: now.saturating_duration_since(node.last_seen),
+            })
+            .collect();
+        query.page(entities)
+    }
+
+    /// Records the latest health status published by an entity
+    pub fn set_health_status(&mut self, topic_id: &EntityTopicId, status: Option<String>) {
+        if let Some(node) = self.entities.entities.get_mut(topic_id) {
+            node.health_status = status;
+        }
+    }
+
+    /// Records some activity of an entity: a message, a health status or a twin update.
+    ///
+    /// The ancestors of the entity are considered active too,
+    /// as a device is alive as long as one of its services or child devices is.
+    ///
+    /// Returns the entities that were inactive and are now active again.
+    pub fn record_activity(
+        &mut self,
+        topic_id: &EntityTopicId,
+        now: Instant,
+    ) -> Vec<EntityTopicId> {
+        self.entities.record_activity(topic_id, now)
+    }
+
+    /// Returns the last time some activity has been recorded for the given entity.
+    pub fn last_seen(&self, topic_id: &EntityTopicId) -> Option<Instant> {
+        self.entities.node(topic_id).map(|node| node.last_seen)
+    }
+
+    /// Returns `true` if the given entity has been marked as inactive.
+    pub fn is_inactive(&self, topic_id: &EntityTopicId) -> bool {
+        self.entities
+            .node(topic_id)
+            .is_some_and(|node| node.inactive_since.is_some())
+    }
+
+    /// Marks as inactive the entities that have been silent for longer than their TTL
+    /// and returns those which have been inactive for longer than the grace period.
+    ///
+    /// The expired entities are not deregistered by this method,
+    /// as this has to be done along the cleaning of their retained MQTT messages.
+    pub fn check_expiry(&mut self, policy: &ExpiryPolicy, now: Instant) -> ExpiredEntities {
+        self.entities.check_expiry(policy, now)
+    }
+}
+
+/// Policy applied to the entities that stop sending any message
+///
+/// The main device never expires.
+/// The TTL of a child device or a service can be overridden by its registration message (see [EntityMetadata::ttl]).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+    /// Time since the last activity after which a child device is marked as inactive
+    pub child_device_ttl: Option<Duration>,
+
+    /// Time since the last activity after which a service is marked as inactive
+    pub service_ttl: Option<Duration>,
+
+    /// Time an inactive entity is kept before being deregistered
+    pub grace_period: Duration,
+}
+
+impl ExpiryPolicy {
+    /// The TTL for entities of the given type, if any
+    pub fn ttl(&self, entity_type: EntityType) -> Option<Duration> {
+        match entity_type {
+            EntityType::MainDevice => None,
+            EntityType::ChildDevice => self.child_device_ttl,
+            EntityType::Service => self.service_ttl,
+        }
+    }
+
+    /// The TTL of the given entity: its own TTL if any, or the TTL for its type
+    pub fn entity_ttl(&self, entity: &EntityMetadata) -> Option<Duration> {
+        match entity.r#type {
+            EntityType::MainDevice => None,
+            _ => entity.ttl.or_else(|| self.ttl(entity.r#type)),
+        }
+    }
+}
+
+/// The entities whose activity state has been changed by an expiry check
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExpiredEntities {
+    /// The entities that have just been marked as inactive
+    pub inactive: Vec<EntityTopicId>,
+
+    /// The inactive entities whose grace period is over and that have to be deregistered
+    pub expired: Vec<EntityTopicId>,
+}
+
+impl ExpiredEntities {
+    pub fn is_empty(&self) -> bool {
+        self.inactive.is_empty() && self.expired.is_empty()
+    }
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct ListFilters {
     pub root: Option<EntityTopicId>,
     pub parent: Option<EntityTopicId>,
@@ -534,6 +798,13 @@ struct EntityTree {
 struct EntityNode {
     metadata: EntityMetadata,
     children: BTreeSet<EntityTopicId>,
+
+    // The activity state is not persisted: on restart, all the entities are considered active
+    last_seen: Instant,
+    inactive_since: Option<Instant>,
+
+    // The latest health status published by the entity, if any
+    health_status: Option<String>,
 }
 
 impl EntityNode {
@@ -541,6 +812,9 @@ impl EntityNode {
         EntityNode {
             metadata,
             children: BTreeSet::new(),
+            last_seen: Instant::now(),
+            inactive_since: None,
+            health_status: None,
         }
     }
 
@@ -589,6 +863,10 @@ impl EntityTree {
             .ok_or_else(|| Error::UnknownEntity(topic_id.to_string()))
     }
 
+    fn node(&self, topic_id: &EntityTopicId) -> Option<&EntityNode> {
+        self.entities.get(topic_id)
+    }
+
     fn try_get_entity_node_mut(
         &mut self,
         topic_id: &EntityTopicId,
@@ -628,6 +906,9 @@ impl EntityTree {
                 // if there is no change, no entities were affected
                 let existing_entity = occupied.get().metadata.clone();
                 let existing_children = occupied.get().children.clone();
+                let last_seen = occupied.get().last_seen;
+                let inactive_since = occupied.get().inactive_since;
+                let health_status = occupied.get().health_status.clone();
 
                 let mut merged_other = existing_entity.twin_data.clone();
                 merged_other.extend(entity_metadata.twin_data.clone());
@@ -642,6 +923,9 @@ impl EntityTree {
                     let updated_entity = EntityNode {
                         metadata: merged_entity,
                         children: existing_children,
+                        last_seen,
+                        inactive_since,
+                        health_status,
                     };
                     occupied.insert(updated_entity);
                     InsertOutcome::Updated
@@ -677,6 +961,14 @@ impl EntityTree {
     }
 
     pub fn list_entity_tree(&self, filters: ListFilters) -> Vec<&EntityMetadata> {
+        self.list_entity_nodes(&filters)
+            .into_iter()
+            .map(|node| node.metadata())
+            .collect()
+    }
+
+    /// Lists the nodes of the sub-tree selected by the filters, parents first
+    fn list_entity_nodes(&self, filters: &ListFilters) -> Vec<&EntityNode> {
         let start_root = filters
             .root
             .as_ref()
@@ -687,13 +979,9 @@ impl EntityTree {
             let mut entities = vec![];
 
             while let Some(topic_id) = topic_ids.pop_front() {
-                let metadata = self
-                    .entities
-                    .get(topic_id)
-                    .map(|node| node.metadata())
-                    .unwrap();
-                if filters.matches(metadata) {
-                    entities.push(metadata);
+                let node = self.entities.get(topic_id).unwrap();
+                if filters.matches(node.metadata()) {
+                    entities.push(node);
                 }
 
                 let (child_topics, _): (Vec<_>, Vec<_>) =
@@ -714,6 +1002,14 @@ impl EntityTree {
         }
     }
 
+    /// The health status of an entity, using the status of its health endpoint if it has no own status
+    fn health_status<'a>(&'a self, node: &'a EntityNode) -> Option<&'a str> {
+        node.health_status.as_deref().or_else(|| {
+            let endpoint = node.metadata.health_endpoint.as_ref()?;
+            self.entities.get(endpoint)?.health_status.as_deref()
+        })
+    }
+
     fn get_parent(&self, topic_id: &EntityTopicId) -> Result<&EntityTopicId, Error> {
         let parent = self
             .try_get(topic_id)?
@@ -729,46 +1025,7 @@ impl EntityTree {
         update_message: EntityUpdateMessage,
     ) -> Result<&EntityMetadata, Error> {
         if let Some(new_parent) = update_message.parent {
-            if new_parent == topic_id {
-                return Err(Error::InvalidSelfParent(new_parent.clone()));
-            }
-
-            if topic_id == &self.main_device {
-                // The main device can not have a parent
-                return Err(Error::InvalidMainDeviceParent);
-            }
-
-            let entity = self
-                .try_get(&new_parent)
-                .map_err(|_| Error::NoParent(new_parent.to_string().into_boxed_str()))?;
-            if entity.r#type == EntityType::Service {
-                return Err(Error::InvalidServiceParent(
-                    new_parent.clone(),
-                    topic_id.clone(),
-                ));
-            }
-
-            if self.ancestors(&new_parent)?.contains(&topic_id) {
-                return Err(Error::InvalidDescendentParent(
-                    new_parent.clone(),
-                    topic_id.clone(),
-                ));
-            }
-
-            let current_parent = self.get_parent(topic_id)?.clone();
-            if current_parent != new_parent {
-                let current_node = self.try_get_entity_node_mut(topic_id)?;
-                current_node.metadata.parent = Some(new_parent.clone());
-
-                let new_parent_node = self.try_get_entity_node_mut(&new_parent)?;
-                new_parent_node.children.insert(topic_id.clone());
-
-                self.entities
-                    .get_mut(&current_parent)
-                    .expect("Parent entity should exist")
-                    .children
-                    .remove(topic_id);
-            }
+            self.set_parent(topic_id, &new_parent)?;
         }
 
         if let Some(health_endpoint) = update_message.health_endpoint {
@@ -788,6 +1045,61 @@ impl EntityTree {
         self.try_get(topic_id)
     }
 
+    /// Moves an entity, along with its child devices and services, under a new parent
+    ///
+    /// The twin data of the moved entities is left unchanged.
+    /// Returns `true` if the parent has actually been changed.
+    pub fn set_parent(
+        &mut self,
+        topic_id: &EntityTopicId,
+        new_parent: &EntityTopicId,
+    ) -> Result<bool, Error> {
+        if new_parent == topic_id {
+            return Err(Error::InvalidSelfParent(new_parent.clone()));
+        }
+
+        if topic_id == &self.main_device {
+            // The main device can not have a parent
+            return Err(Error::InvalidMainDeviceParent);
+        }
+
+        let entity = self
+            .try_get(new_parent)
+            .map_err(|_| Error::NoParent(new_parent.to_string().into_boxed_str()))?;
+        if entity.r#type == EntityType::Service {
+            return Err(Error::InvalidServiceParent(
+                new_parent.clone(),
+                topic_id.clone(),
+            ));
+        }
+
+        if self.ancestors(new_parent)?.contains(&topic_id) {
+            return Err(Error::InvalidDescendentParent(
+                new_parent.clone(),
+                topic_id.clone(),
+            ));
+        }
+
+        let current_parent = self.get_parent(topic_id)?.clone();
+        if &current_parent == new_parent {
+            return Ok(false);
+        }
+
+        let current_node = self.try_get_entity_node_mut(topic_id)?;
+        current_node.metadata.parent = Some(new_parent.clone());
+
+        let new_parent_node = self.try_get_entity_node_mut(new_parent)?;
+        new_parent_node.children.insert(topic_id.clone());
+
+        self.entities
+            .get_mut(&current_parent)
+            .expect("Parent entity should exist")
+            .children
+            .remove(topic_id);
+
+        Ok(true)
+    }
+
     pub fn ancestors(&self, topic_id: &EntityTopicId) -> Result<Vec<&EntityTopicId>, Error> {
         let mut ancestors = vec![];
         let mut current = topic_id;
@@ -797,6 +1109,49 @@ impl EntityTree {
         }
         Ok(ancestors)
     }
+
+    pub fn record_activity(
+        &mut self,
+        topic_id: &EntityTopicId,
+        now: Instant,
+    ) -> Vec<EntityTopicId> {
+        let mut reactivated = vec![];
+        let mut current = Some(topic_id.clone());
+        while let Some(topic_id) = current {
+            let Some(node) = self.entities.get_mut(&topic_id) else {
+                break;
+            };
+            node.last_seen = now;
+            if node.inactive_since.take().is_some() {
+                reactivated.push(topic_id);
+            }
+            current = node.metadata.parent.clone();
+        }
+        reactivated
+    }
+
+    pub fn check_expiry(&mut self, policy: &ExpiryPolicy, now: Instant) -> ExpiredEntities {
+        let mut outcome = ExpiredEntities::default();
+        for (topic_id, node) in self.entities.iter_mut() {
+            let Some(ttl) = policy.entity_ttl(&node.metadata) else {
+                continue;
+            };
+            match node.inactive_since {
+                None if now.saturating_duration_since(node.last_seen) >= ttl => {
+                    node.inactive_since = Some(now);
+                    outcome.inactive.push(topic_id.clone());
+                }
+                Some(since) if now.saturating_duration_since(since) >= policy.grace_period => {
+                    outcome.expired.push(topic_id.clone());
+                }
+                _ => {}
+            }
+        }
+
+        outcome.inactive.sort();
+        outcome.expired.sort();
+        outcome
+    }
 }
 
 /// Represents an error encountered while updating the store.
@@ -834,6 +1189,9 @@ pub enum Error {
     #[error("Invalid twin key: '{0}'. Keys that are empty, containing '/' or starting with '@' are not allowed")]
     InvalidTwinData(String),
 
+    #[error("Invalid twin fragment '{1}' for {0}: {2}")]
+    TwinSchemaViolation(EntityTopicId, String, SchemaViolation),
+
     #[error("Entity: '{0}' can not be its own parent")]
     InvalidSelfParent(EntityTopicId),
 
@@ -885,6 +1243,13 @@ pub struct EntityRegistrationPayload {
     pub parent: Option<EntityTopicId>,
     #[serde(rename = "@health", skip_serializing_if = "Option::is_none")]
     pub health_endpoint: Option<EntityTopicId>,
+    #[serde(
+        rename = "@ttl",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::entity::ttl_format"
+    )]
+    pub ttl: Option<Duration>,
 
     #[serde(flatten)]
     pub twin_data: Map<String, JsonValue>,
@@ -902,6 +1267,7 @@ pub struct EntityRegistrationMessage {
     pub r#type: EntityType,
     pub parent: Option<EntityTopicId>,
     pub health_endpoint: Option<EntityTopicId>,
+    pub ttl: Option<Duration>,
 
     pub twin_data: Map<String, JsonValue>,
 }
@@ -916,6 +1282,7 @@ impl EntityRegistrationMessage {
             r#type: payload.r#type,
             parent: payload.parent,
             health_endpoint: payload.health_endpoint,
+            ttl: payload.ttl,
             twin_data: payload.twin_data,
         })
     }
@@ -927,6 +1294,7 @@ impl EntityRegistrationMessage {
             external_id: None,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         }
     }
@@ -936,6 +1304,21 @@ impl EntityRegistrationMessage {
         self
     }
 
+    /// Returns the parent of the entity, using the default parent if none is explicitly given:
+    /// - the main device for a child device
+    /// - the device of a service that follows the default topic scheme, or the main device.
+    pub fn parent_or_default(&self, main_device: &EntityTopicId) -> Option<EntityTopicId> {
+        match self.r#type {
+            EntityType::MainDevice => None,
+            EntityType::ChildDevice => self.parent.clone().or_else(|| Some(main_device.clone())),
+            EntityType::Service => self
+                .parent
+                .clone()
+                .or_else(|| self.topic_id.default_service_parent_identifier())
+                .or_else(|| Some(main_device.clone())),
+        }
+    }
+
     pub fn with_external_id(mut self, external_id: EntityExternalId) -> Self {
         let _ = self.external_id.insert(external_id);
         self
@@ -951,6 +1334,11 @@ impl EntityRegistrationMessage {
         self
     }
 
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        let _ = self.ttl.insert(ttl);
+        self
+    }
+
     /// Creates a entity registration message for a main device.
     pub fn main_device(main_device_id: Option<String>) -> Self {
         Self {
@@ -959,6 +1347,7 @@ impl EntityRegistrationMessage {
             r#type: EntityType::MainDevice,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         }
     }
@@ -981,6 +1370,13 @@ impl EntityRegistrationMessage {
             props.insert("@health".to_string(), health_endpoint.to_string().into());
         }
 
+        if let Some(ttl) = self.ttl {
+            props.insert(
+                "@ttl".to_string(),
+                humantime::format_duration(ttl).to_string().into(),
+            );
+        }
+
         props.append(&mut self.twin_data);
 
         let message = serde_json::to_string(&props).unwrap();
@@ -998,6 +1394,7 @@ impl From<&EntityMetadata> for EntityRegistrationMessage {
             external_id: value.external_id.clone(),
             parent: value.parent.clone(),
             health_endpoint: value.health_endpoint.clone(),
+            ttl: value.ttl,
             twin_data: Map::new(),
         }
     }
@@ -1060,6 +1457,7 @@ impl EntityTwinMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::twin_schema::TwinSchema;
     use assert_matches::assert_matches;
     use serde_json::json;
     use std::collections::BTreeSet;
@@ -1478,6 +1876,7 @@ mod tests {
                     external_id: None,
                     parent,
                     health_endpoint: None,
+                    ttl: None,
                     twin_data: Map::new(),
                 })
                 .unwrap();
@@ -1497,6 +1896,7 @@ mod tests {
                 topic_id: EntityTopicId::default_main_service("service1").unwrap(),
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             })
             .unwrap();
@@ -1517,6 +1917,7 @@ mod tests {
                 topic_id: EntityTopicId::default_main_service("service2").unwrap(),
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             })
             .unwrap();
@@ -1550,6 +1951,7 @@ mod tests {
                     external_id: None,
                     parent: Some(EntityTopicId::from_str("device/main//").unwrap()),
                     health_endpoint: None,
+                    ttl: None,
                     twin_data: json!({ "name": "child1" }).as_object().unwrap().to_owned(),
                 },
                 EntityRegistrationMessage {
@@ -1558,6 +1960,7 @@ mod tests {
                     external_id: None,
                     parent: Some(EntityTopicId::from_str("device/child1//").unwrap()),
                     health_endpoint: None,
+                    ttl: None,
                     twin_data: json!({ "name": "service1" })
                         .as_object()
                         .unwrap()
@@ -1583,6 +1986,7 @@ mod tests {
                 external_id: None,
                 parent: Some(EntityTopicId::from_str("device/main//").unwrap()),
                 health_endpoint: None,
+                ttl: None,
                 twin_data: json!({ "name": "child2" }).as_object().unwrap().to_owned(),
             },]
         );
@@ -1612,6 +2016,7 @@ mod tests {
                 external_id: None,
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: json!({}).as_object().unwrap().to_owned(),
             })
             .unwrap();
@@ -1622,6 +2027,7 @@ mod tests {
             r#type: EntityType::MainDevice,
             external_id: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         };
         // Assert main device registered with custom topic scheme
@@ -1639,6 +2045,7 @@ mod tests {
                 external_id: None,
                 parent: Some(main_topic_id.clone()),
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             })
             .unwrap();
@@ -1649,6 +2056,7 @@ mod tests {
             r#type: EntityType::Service,
             external_id: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         };
         // Assert service registered under main device with custom topic scheme
@@ -1760,6 +2168,7 @@ mod tests {
                 r#type: EntityType::MainDevice,
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: json!({ "name" : "test-name", "type": "test-type" })
                     .as_object()
                     .unwrap()
@@ -1787,6 +2196,7 @@ mod tests {
             r#type: EntityType::MainDevice,
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: json!({ "name" : "new-test-device" })
                 .as_object()
                 .unwrap()
@@ -1824,6 +2234,7 @@ mod tests {
             external_id: Some("child1".into()),
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         };
 
@@ -1853,6 +2264,7 @@ mod tests {
             external_id: Some("child1".into()),
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         };
 
@@ -2225,6 +2637,235 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reparented_entity_keeps_its_services_and_twin_data_across_restarts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = new_entity_store(&temp_dir, false);
+            register(
+                &mut store,
+                "device/hub0//",
+                json!({"@type": "child-device"}),
+            );
+            register(
+                &mut store,
+                "device/sensor//",
+                json!({"@type": "child-device", "@parent": "device/hub0//", "name": "sensor"}),
+            );
+            register(
+                &mut store,
+                "device/sensor/service/app",
+                json!({"@type": "service"}),
+            );
+            register(
+                &mut store,
+                "device/hub1//",
+                json!({"@type": "child-device"}),
+            );
+            store
+                .update_twin_fragment(EntityTwinMessage::new(
+                    entity("device/sensor//"),
+                    "firmware".to_string(),
+                    json!("1.0"),
+                ))
+                .unwrap();
+
+            store
+                .update_entity(
+                    &entity("device/sensor//"),
+                    EntityUpdateMessage::default().with_parent(entity("device/hub1//")),
+                )
+                .unwrap();
+
+            assert_eq!(
+                list_entity_tree_topics::<Vec<&str>>(
+                    &mut store,
+                    ListFilters::default().root(entity("device/hub1//")),
+                ),
+                [
+                    "device/hub1//",
+                    "device/sensor//",
+                    "device/sensor/service/app"
+                ]
+            );
+            assert_eq!(
+                list_entity_tree_topics::<Vec<&str>>(
+                    &mut store,
+                    ListFilters::default().root(entity("device/hub0//")),
+                ),
+                ["device/hub0//"]
+            );
+            assert_eq!(
+                store.get_twin_fragment(&entity("device/sensor//"), "firmware"),
+                Some(&json!("1.0"))
+            );
+        }
+
+        // The new parent has been registered after the moved entity, but is properly restored
+        let mut store = new_entity_store(&temp_dir, false);
+        assert_eq!(
+            list_entity_tree_topics::<Vec<&str>>(
+                &mut store,
+                ListFilters::default().root(entity("device/hub1//")),
+            ),
+            [
+                "device/hub1//",
+                "device/sensor//",
+                "device/sensor/service/app"
+            ]
+        );
+        assert_eq!(
+            store.get_twin_fragment(&entity("device/sensor//"), "name"),
+            Some(&json!("sensor"))
+        );
+    }
+
+    #[test]
+    fn reregistration_with_a_new_parent_moves_the_entity() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        register(
+            &mut store,
+            "device/hub0//",
+            json!({"@type": "child-device"}),
+        );
+        register(
+            &mut store,
+            "device/hub1//",
+            json!({"@type": "child-device"}),
+        );
+        register(
+            &mut store,
+            "device/sensor//",
+            json!({"@type": "child-device", "@parent": "device/hub0//"}),
+        );
+
+        register(
+            &mut store,
+            "device/sensor//",
+            json!({"@type": "child-device", "@parent": "device/hub1//"}),
+        );
+        assert_eq!(
+            store.child_devices(&entity("device/hub0//")),
+            Vec::<&EntityTopicId>::new()
+        );
+        assert_eq!(
+            store.child_devices(&entity("device/hub1//")),
+            vec![&entity("device/sensor//")]
+        );
+
+        // Cycles are rejected
+        let registration = EntityRegistrationMessage::try_from(
+            entity("device/hub1//"),
+            json!({"@type": "child-device", "@parent": "device/sensor//"})
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_matches!(
+            store.update(registration),
+            Err(Error::InvalidDescendentParent(_, _))
+        );
+    }
+
+    #[test]
+    fn query_entities_on_twin_data_and_health_status() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        register(
+            &mut store,
+            "device/child0//",
+            json!({"@type": "child-device", "@id": "child0", "firmware": {"version": "1.2"}}),
+        );
+        register(
+            &mut store,
+            "device/child1//",
+            json!({"@type": "child-device", "@id": "child1", "firmware": {"version": "1.0"}, "@health": "device/child1/service/agent"}),
+        );
+        register(
+            &mut store,
+            "device/child1/service/agent",
+            json!({"@type": "service"}),
+        );
+        register(
+            &mut store,
+            "device/child2//",
+            json!({"@type": "child-device"}),
+        );
+        store.set_health_status(&entity("device/child1/service/agent"), Some("up".into()));
+
+        let query = EntityQuery::new(ListFilters {
+            r#type: Some(EntityType::ChildDevice),
+            ..ListFilters::default()
+        })
+        .with_condition("twin.firmware.version!=1.2".parse().unwrap())
+        .with_fields(EntityQuery::parse_fields("@id,health").unwrap());
+        let page = store.query(&query, Instant::now());
+        assert_eq!(
+            page.entities,
+            vec![
+                json!({"@topic-id": "device/child1//", "@id": "child1", "health": "up"}),
+                json!({"@topic-id": "device/child2//", "@id": "child2"}),
+            ]
+        );
+        assert_eq!(page.next, None);
+
+        let query = query.with_condition("health=up".parse().unwrap());
+        let page = store.query(&query, Instant::now());
+        assert_eq!(page.entities.len(), 1);
+    }
+
+    #[test]
+    fn twin_fragments_not_conforming_to_their_schema_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let schemas = TwinSchemas::default().with_schema(
+            Some(EntityType::ChildDevice),
+            "firmware",
+            TwinSchema::new(json!({"type": "object", "required": ["version"]})).unwrap(),
+        );
+        let mut store =
+            new_entity_store(&temp_dir, true).with_twin_schemas(schemas, SchemaEnforcement::Reject);
+        register(
+            &mut store,
+            "device/child0//",
+            json!({"@type": "child-device"}),
+        );
+
+        let invalid = json!({"name": "fw"});
+        let twin_update = EntityTwinMessage::new(
+            entity("device/child0//"),
+            "firmware".into(),
+            invalid.clone(),
+        );
+        assert_matches!(
+            store.update_twin_fragment(twin_update),
+            Err(Error::TwinSchemaViolation(_, _, _))
+        );
+
+        let registration = EntityRegistrationMessage::try_from(
+            entity("device/child1//"),
+            json!({"@type": "child-device", "firmware": invalid})
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_matches!(
+            store.update(registration),
+            Err(Error::TwinSchemaViolation(_, _, _))
+        );
+        assert!(store.get(&entity("device/child1//")).is_none());
+
+        // The schema only applies to child devices
+        let twin_update =
+            EntityTwinMessage::new(entity("device/main//"), "firmware".into(), invalid);
+        assert!(store.update_twin_fragment(twin_update).unwrap());
+
+        let valid = json!({"name": "fw", "version": "1.2"});
+        let twin_update =
+            EntityTwinMessage::new(entity("device/child0//"), "firmware".into(), valid);
+        assert!(store.update_twin_fragment(twin_update).unwrap());
+    }
+
     #[test]
     fn update_health_endpoint() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -2330,6 +2971,160 @@ mod tests {
         assert_eq!(ancestors, expected);
     }
 
+    #[test]
+    fn silent_entities_are_marked_inactive_then_expired() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        register_child(&mut store, "device/main//", "device/child1//");
+        register_service(&mut store, "device/child1//", "device/child1/service/app");
+
+        let policy = ExpiryPolicy {
+            child_device_ttl: Some(Duration::from_secs(60)),
+            service_ttl: None,
+            grace_period: Duration::from_secs(30),
+        };
+        let start = Instant::now();
+        store.record_activity(&entity("device/child1//"), start);
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(59));
+        assert!(outcome.is_empty());
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(60));
+        assert_eq!(outcome.inactive, vec![entity("device/child1//")]);
+        assert!(outcome.expired.is_empty());
+        assert!(store.is_inactive(&entity("device/child1//")));
+
+        // The main device and the services have no TTL
+        assert!(!store.is_inactive(&entity("device/main//")));
+        assert!(!store.is_inactive(&entity("device/child1/service/app")));
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(89));
+        assert!(outcome.is_empty());
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(90));
+        assert!(outcome.inactive.is_empty());
+        assert_eq!(outcome.expired, vec![entity("device/child1//")]);
+    }
+
+    #[test]
+    fn the_ttl_of_an_entity_overrides_the_ttl_of_its_type() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        register_child(&mut store, "device/main//", "device/child1//");
+        register(
+            &mut store,
+            "device/child2//",
+            json!({"@type": "child-device", "@ttl": "30s"}),
+        );
+        register(
+            &mut store,
+            "device/child1/service/app",
+            json!({"@type": "service", "@parent": "device/child1//", "@ttl": 120}),
+        );
+        assert_eq!(
+            store.get(&entity("device/child2//")).unwrap().ttl,
+            Some(Duration::from_secs(30))
+        );
+
+        let policy = ExpiryPolicy {
+            child_device_ttl: Some(Duration::from_secs(60)),
+            service_ttl: None,
+            grace_period: Duration::from_secs(30),
+        };
+        let start = Instant::now();
+        store.record_activity(&entity("device/child1/service/app"), start);
+        store.record_activity(&entity("device/child2//"), start);
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(30));
+        assert_eq!(outcome.inactive, vec![entity("device/child2//")]);
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(120));
+        assert_eq!(
+            outcome.inactive,
+            vec![
+                entity("device/child1//"),
+                entity("device/child1/service/app")
+            ]
+        );
+    }
+
+    #[test]
+    fn the_ttl_of_an_entity_is_persisted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = new_entity_store(&temp_dir, true);
+            register(
+                &mut store,
+                "device/child1//",
+                json!({"@type": "child-device", "@ttl": "1h 30m"}),
+            );
+        }
+
+        let store = new_entity_store(&temp_dir, false);
+        assert_eq!(
+            store.get(&entity("device/child1//")).unwrap().ttl,
+            Some(Duration::from_secs(5400))
+        );
+    }
+
+    #[test]
+    fn invalid_ttls_are_rejected() {
+        for ttl in [json!("soon"), json!(-1), json!(true), json!(0), json!("0s")] {
+            let payload = json!({"@type": "child-device", "@ttl": ttl}).to_string();
+            assert!(
+                EntityRegistrationMessage::try_from(entity("device/child1//"), payload.as_bytes())
+                    .is_err(),
+                "{ttl}"
+            );
+        }
+    }
+
+    #[test]
+    fn activity_of_a_service_keeps_its_parent_devices_active() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        register_child(&mut store, "device/main//", "device/child1//");
+        register_child(&mut store, "device/child1//", "device/child2//");
+        register_service(&mut store, "device/child2//", "device/child2/service/app");
+
+        let policy = ExpiryPolicy {
+            child_device_ttl: Some(Duration::from_secs(60)),
+            service_ttl: Some(Duration::from_secs(60)),
+            grace_period: Duration::from_secs(30),
+        };
+        let start = Instant::now();
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(120));
+        assert_eq!(
+            outcome.inactive,
+            vec![
+                entity("device/child1//"),
+                entity("device/child2//"),
+                entity("device/child2/service/app"),
+            ]
+        );
+
+        let reactivated = store.record_activity(
+            &entity("device/child2/service/app"),
+            start + Duration::from_secs(130),
+        );
+        assert_eq!(
+            reactivated,
+            vec![
+                entity("device/child2/service/app"),
+                entity("device/child2//"),
+                entity("device/child1//"),
+            ]
+        );
+        assert!(!store.is_inactive(&entity("device/child1//")));
+        assert_eq!(
+            store.last_seen(&entity("device/child1//")),
+            Some(start + Duration::from_secs(130))
+        );
+
+        let outcome = store.check_expiry(&policy, start + Duration::from_secs(180));
+        assert!(outcome.is_empty());
+    }
+
     fn new_entity_store(temp_dir: &TempDir, clean_start: bool) -> EntityStore {
         EntityStore::with_main_device(
             MqttSchema::default(),
@@ -2339,6 +3134,7 @@ mod tests {
                 r#type: EntityType::MainDevice,
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             },
             0,
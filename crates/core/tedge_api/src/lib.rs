@@ -2,6 +2,7 @@ pub mod alarm;
 pub mod commands;
 pub mod device_profile;
 pub mod entity;
+pub mod entity_query;
 pub mod entity_store;
 pub mod error;
 pub mod event;
@@ -13,6 +14,7 @@ pub mod script;
 mod software;
 pub mod store;
 pub mod substitution;
+pub mod twin_schema;
 pub mod workflow;
 
 pub use commands::CommandStatus;
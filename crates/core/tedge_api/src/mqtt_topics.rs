@@ -473,6 +473,7 @@ pub mod default_topic_schema {
                 r#type: EntityType::MainDevice,
                 parent: None,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Default::default(),
             }],
             ["device", child, "", ""] if !child.is_empty() => vec![EntityRegistrationMessage {
@@ -481,6 +482,7 @@ pub mod default_topic_schema {
                 r#type: EntityType::ChildDevice,
                 parent: Some(EntityTopicId::default_main_device()),
                 health_endpoint: None,
+                ttl: None,
                 twin_data: json!({ "name": child }).as_object().unwrap().to_owned(),
             }],
             ["device", device, "service", service] if !device.is_empty() && !service.is_empty() => {
@@ -495,6 +497,7 @@ pub mod default_topic_schema {
                     r#type: EntityType::Service,
                     parent: Some(device_topic_id),
                     health_endpoint: None,
+                    ttl: None,
                     twin_data: json!({ "name": service }).as_object().unwrap().to_owned(),
                 });
                 registrations
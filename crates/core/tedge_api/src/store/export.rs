@@ -0,0 +1,391 @@
+//! A versioned file format to export the entities of an entity store and to import them into another one.
+//!
+//! An export file is a JSON object listing all the entities with their registration payload,
+//! twin fragments and parent. The entities are listed in topological order, parents first,
+//! so they can be replayed in that order on a fresh agent.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "entities": [
+//!     { "@topic-id": "device/main//", "@type": "device", "twin": { "name": "gateway" } },
+//!     { "@topic-id": "device/child1//", "@type": "child-device", "@parent": "device/main//" }
+//!   ]
+//! }
+//! ```
+use crate::entity::EntityExternalId;
+use crate::entity::EntityMetadata;
+use crate::entity::EntityType;
+use crate::entity_store::EntityRegistrationMessage;
+use crate::mqtt_topics::Channel;
+use crate::mqtt_topics::EntityTopicId;
+use crate::mqtt_topics::MqttSchema;
+use crate::store::message_log::MessageLog;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// The version of the export format produced by this crate
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// The content of an entity store export file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityStoreExport {
+    pub version: u32,
+    pub entities: Vec<ExportedEntity>,
+}
+
+/// An exported entity: its registration payload along with its twin fragments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedEntity {
+    #[serde(rename = "@topic-id")]
+    pub topic_id: EntityTopicId,
+    #[serde(rename = "@type")]
+    pub r#type: EntityType,
+    #[serde(rename = "@id", default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<EntityExternalId>,
+    #[serde(rename = "@parent", default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<EntityTopicId>,
+    #[serde(rename = "@health", default, skip_serializing_if = "Option::is_none")]
+    pub health_endpoint: Option<EntityTopicId>,
+    #[serde(
+        rename = "@ttl",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::entity::ttl_format"
+    )]
+    pub ttl: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub twin: Map<String, JsonValue>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    #[error("Unsupported entity export format version: {0}. The supported version is {EXPORT_FORMAT_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error("The entity {0} is listed more than once")]
+    DuplicateEntity(EntityTopicId),
+
+    #[error("The entity {0} is one of its own ancestors")]
+    CyclicParent(EntityTopicId),
+
+    #[error(transparent)]
+    FromStdIoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    FromSerdeJson(#[from] serde_json::Error),
+}
+
+impl EntityStoreExport {
+    pub fn new(entities: Vec<ExportedEntity>) -> Self {
+        EntityStoreExport {
+            version: EXPORT_FORMAT_VERSION,
+            entities,
+        }
+    }
+
+    /// Parses an export file, checking that its format is supported
+    pub fn parse(content: &str) -> Result<Self, ExportError> {
+        let export: EntityStoreExport = serde_json::from_str(content)?;
+        if export.version != EXPORT_FORMAT_VERSION {
+            return Err(ExportError::UnsupportedVersion(export.version));
+        }
+        Ok(export)
+    }
+
+    /// Builds an export from the persistent entity store log of an agent
+    ///
+    /// The log is only read, hence this can be done while the agent is not running.
+    /// Only the twin fragments that are part of the registration messages are exported,
+    /// as twin updates are not persisted in that log.
+    pub fn from_message_log<P>(
+        log_dir: P,
+        mqtt_schema: &MqttSchema,
+        main_device: &EntityTopicId,
+    ) -> Result<Self, ExportError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut entities = vec![];
+        for message in MessageLog::read_latest_messages(log_dir)? {
+            let Ok((topic_id, Channel::EntityMetadata)) =
+                mqtt_schema.entity_channel_of(&message.topic)
+            else {
+                continue;
+            };
+            let registration =
+                EntityRegistrationMessage::try_from(topic_id, message.payload_bytes())?;
+            entities.push(ExportedEntity::from(registration));
+        }
+
+        // The main device is not persisted in the log, but is always present
+        if !entities
+            .iter()
+            .any(|entity| &entity.topic_id == main_device)
+        {
+            let mut main = EntityRegistrationMessage::main_device(None);
+            main.topic_id = main_device.clone();
+            entities.insert(0, ExportedEntity::from(main));
+        }
+
+        EntityStoreExport::new(entities).in_topological_order(main_device)
+    }
+
+    /// Sorts the entities so any entity is listed after its parent
+    ///
+    /// Entities whose parent is not part of the export are kept in place,
+    /// their parent being expected to be registered on the target.
+    pub fn in_topological_order(self, main_device: &EntityTopicId) -> Result<Self, ExportError> {
+        let mut entities: HashMap<EntityTopicId, ExportedEntity> = HashMap::new();
+        let mut order = vec![];
+        for entity in self.entities {
+            let topic_id = entity.topic_id.clone();
+            if entities.insert(topic_id.clone(), entity).is_some() {
+                return Err(ExportError::DuplicateEntity(topic_id));
+            }
+            order.push(topic_id);
+        }
+
+        let mut sorted = Vec::with_capacity(order.len());
+        let mut visited = HashSet::new();
+        for topic_id in order {
+            let mut path = vec![];
+            let mut current = Some(topic_id);
+            while let Some(topic_id) = current.take() {
+                if visited.contains(&topic_id) {
+                    break;
+                }
+                if path.contains(&topic_id) {
+                    return Err(ExportError::CyclicParent(topic_id));
+                }
+                current = entities
+                    .get(&topic_id)
+                    .and_then(|entity| entity.parent_or_default(main_device))
+                    .filter(|parent| entities.contains_key(parent));
+                path.push(topic_id);
+            }
+
+            // Register the ancestors first
+            for topic_id in path.into_iter().rev() {
+                visited.insert(topic_id.clone());
+                if let Some(entity) = entities.remove(&topic_id) {
+                    sorted.push(entity);
+                }
+            }
+        }
+
+        Ok(EntityStoreExport {
+            version: self.version,
+            entities: sorted,
+        })
+    }
+}
+
+impl ExportedEntity {
+    /// The registration message of this entity, excluding its twin fragments
+    pub fn registration_message(&self) -> EntityRegistrationMessage {
+        EntityRegistrationMessage {
+            topic_id: self.topic_id.clone(),
+            external_id: self.external_id.clone(),
+            r#type: self.r#type,
+            parent: self.parent.clone(),
+            health_endpoint: self.health_endpoint.clone(),
+            ttl: self.ttl,
+            twin_data: Map::new(),
+        }
+    }
+
+    /// The parent of this entity, using the default parent if none is explicitly given
+    pub fn parent_or_default(&self, main_device: &EntityTopicId) -> Option<EntityTopicId> {
+        self.registration_message().parent_or_default(main_device)
+    }
+}
+
+impl From<&EntityMetadata> for ExportedEntity {
+    fn from(entity: &EntityMetadata) -> Self {
+        ExportedEntity {
+            topic_id: entity.topic_id.clone(),
+            r#type: entity.r#type,
+            external_id: entity.external_id.clone(),
+            parent: entity.parent.clone(),
+            health_endpoint: entity.health_endpoint.clone(),
+            ttl: entity.ttl,
+            twin: entity.twin_data.clone(),
+        }
+    }
+}
+
+impl From<EntityRegistrationMessage> for ExportedEntity {
+    fn from(registration: EntityRegistrationMessage) -> Self {
+        ExportedEntity {
+            topic_id: registration.topic_id,
+            r#type: registration.r#type,
+            external_id: registration.external_id,
+            parent: registration.parent,
+            health_endpoint: registration.health_endpoint,
+            ttl: registration.ttl,
+            twin: registration.twin_data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntityStore;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    #[test]
+    fn parse_export_file() {
+        let export = EntityStoreExport::parse(
+            &json!({
+                "version": 1,
+                "entities": [
+                    {"@topic-id": "device/main//", "@type": "device", "twin": {"name": "gateway"}},
+                    {"@topic-id": "device/child1//", "@type": "child-device", "@id": "child-001"},
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(export.entities.len(), 2);
+        assert_eq!(export.entities[0].twin.get("name"), Some(&json!("gateway")));
+        assert_eq!(
+            export.entities[1].external_id,
+            Some(EntityExternalId::from("child-001"))
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_version() {
+        let result = EntityStoreExport::parse(&json!({"version": 42, "entities": []}).to_string());
+        assert_matches!(result, Err(ExportError::UnsupportedVersion(42)));
+    }
+
+    #[test]
+    fn entities_are_sorted_parents_first() {
+        let main = EntityTopicId::default_main_device();
+        let export = EntityStoreExport::new(vec![
+            entity("device/child2/service/app", "service", None),
+            entity("device/child2//", "child-device", Some("device/child1//")),
+            entity("device/child1//", "child-device", None),
+            entity("device/main//", "device", None),
+            entity("device/other//", "child-device", Some("device/unknown//")),
+        ])
+        .in_topological_order(&main)
+        .unwrap();
+
+        let topic_ids: Vec<_> = export
+            .entities
+            .iter()
+            .map(|entity| entity.topic_id.as_str())
+            .collect();
+        assert_eq!(
+            topic_ids,
+            vec![
+                "device/main//",
+                "device/child1//",
+                "device/child2//",
+                "device/child2/service/app",
+                "device/other//",
+            ]
+        );
+    }
+
+    #[test]
+    fn cyclic_parents_are_rejected() {
+        let main = EntityTopicId::default_main_device();
+        let result = EntityStoreExport::new(vec![
+            entity("device/child1//", "child-device", Some("device/child2//")),
+            entity("device/child2//", "child-device", Some("device/child1//")),
+        ])
+        .in_topological_order(&main);
+
+        assert_matches!(result, Err(ExportError::CyclicParent(_)));
+    }
+
+    #[test]
+    fn duplicate_entities_are_rejected() {
+        let main = EntityTopicId::default_main_device();
+        let result = EntityStoreExport::new(vec![
+            entity("device/child1//", "child-device", None),
+            entity("device/child1//", "child-device", None),
+        ])
+        .in_topological_order(&main);
+
+        assert_matches!(result, Err(ExportError::DuplicateEntity(_)));
+    }
+
+    #[test]
+    fn export_from_entity_store_log() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mqtt_schema = MqttSchema::default();
+        let main = EntityTopicId::default_main_device();
+        {
+            let mut store = EntityStore::with_main_device(
+                mqtt_schema.clone(),
+                EntityRegistrationMessage::main_device(None),
+                0,
+                &temp_dir,
+                true,
+            )
+            .unwrap();
+            for (topic_id, payload) in [
+                (
+                    "device/child1//",
+                    json!({"@type": "child-device", "name": "child1"}),
+                ),
+                ("device/child1/service/app", json!({"@type": "service"})),
+                ("device/child2//", json!({"@type": "child-device"})),
+            ] {
+                let registration = EntityRegistrationMessage::try_from(
+                    topic_id.parse().unwrap(),
+                    payload.to_string().as_bytes(),
+                )
+                .unwrap();
+                store.update(registration).unwrap();
+            }
+            store
+                .deregister_and_persist_entity(&"device/child2//".parse().unwrap())
+                .unwrap();
+        }
+
+        let export = EntityStoreExport::from_message_log(&temp_dir, &mqtt_schema, &main).unwrap();
+
+        let topic_ids: Vec<_> = export
+            .entities
+            .iter()
+            .map(|entity| entity.topic_id.as_str())
+            .collect();
+        assert_eq!(
+            topic_ids,
+            vec![
+                "device/main//",
+                "device/child1//",
+                "device/child1/service/app"
+            ]
+        );
+        assert_eq!(export.entities[1].twin.get("name"), Some(&json!("child1")));
+    }
+
+    fn entity(topic_id: &str, r#type: &str, parent: Option<&str>) -> ExportedEntity {
+        ExportedEntity {
+            topic_id: topic_id.parse().unwrap(),
+            r#type: r#type.parse().unwrap(),
+            external_id: None,
+            parent: parent.map(|parent| parent.parse().unwrap()),
+            health_endpoint: None,
+            ttl: None,
+            twin: Map::new(),
+        }
+    }
+}
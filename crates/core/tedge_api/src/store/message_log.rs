@@ -149,6 +149,29 @@ impl MessageLog {
         Ok(entries)
     }
 
+    /// Reads the latest message per topic from the log stored in the given directory,
+    /// without opening the log for writes.
+    pub fn read_latest_messages<P>(log_dir: P) -> Result<Vec<MqttMessage>, std::io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut messages = IndexMap::new();
+        for (topic, payload) in Self::read_file(log_dir.as_ref())? {
+            if payload.is_empty() {
+                messages.shift_remove(&topic);
+            } else {
+                messages.insert(topic, payload);
+            }
+        }
+
+        Ok(messages
+            .iter()
+            .map(|(topic, payload)| {
+                MqttMessage::new(&Topic::new_unchecked(topic), payload.as_str())
+            })
+            .collect())
+    }
+
     /// Iterates over the latest message per topic, in the order topics were first seen
     pub fn messages(&self) -> impl Iterator<Item = MqttMessage> + '_ {
         self.messages.iter().map(|(topic, payload)| {
@@ -156,6 +179,11 @@ impl MessageLog {
         })
     }
 
+    /// Returns the latest payload persisted for the given topic, if any
+    pub fn latest_payload(&self, topic: &str) -> Option<&str> {
+        self.messages.get(topic).map(|payload| payload.as_str())
+    }
+
     /// Persists the message to the log
     pub fn append_message(&mut self, message: &MqttMessage) -> Result<(), std::io::Error> {
         if is_entity_twin_topic(&message.topic.name) {
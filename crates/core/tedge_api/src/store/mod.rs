@@ -1,3 +1,4 @@
+pub mod export;
 pub mod message_log;
 pub mod pending_entity_store;
 mod ring_buffer;
@@ -0,0 +1,661 @@
+//! Optional JSON schemas for entity twin fragments.
+//!
+//! The schemas are loaded from a directory, one file per twin fragment:
+//! - `<dir>/<fragment>.json` applies to the fragment of any entity,
+//! - `<dir>/<entity-type>/<fragment>.json` applies to the entities of that type
+//!   (`device`, `child-device` or `service`), and takes precedence over the former.
+//!
+//! Twin fragments with no schema are not checked.
+//!
+//! Only the following subset of JSON Schema is supported:
+//! `type`, `enum`, `const`, `properties`, `required`, `additionalProperties`,
+//! `items`, `minItems`, `maxItems`, `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`,
+//! `minLength`, `maxLength` and `pattern`.
+//! Schemas using other validation keywords, such as `$ref`, `oneOf` or `format`, are rejected,
+//! while annotations, such as `title` or `description`, are ignored.
+use crate::entity::EntityType;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use log::warn;
+use regex::Regex;
+use serde_json::Map;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The JSON Schema keywords that are not supported, and would be silently ignored if accepted
+const UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "$ref",
+    "$dynamicRef",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "dependencies",
+    "dependentRequired",
+    "dependentSchemas",
+    "patternProperties",
+    "propertyNames",
+    "minProperties",
+    "maxProperties",
+    "unevaluatedProperties",
+    "prefixItems",
+    "additionalItems",
+    "unevaluatedItems",
+    "contains",
+    "minContains",
+    "maxContains",
+    "uniqueItems",
+    "multipleOf",
+    "format",
+];
+
+/// The JSON schemas of the twin fragments, per entity type and fragment name
+#[derive(Debug, Clone, Default)]
+pub struct TwinSchemas {
+    any_type: HashMap<String, TwinSchema>,
+    per_type: HashMap<(EntityType, String), TwinSchema>,
+}
+
+/// What to do with twin updates that don't conform to their schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaEnforcement {
+    /// Accept the update, logging a warning
+    #[default]
+    Warn,
+
+    /// Reject the update
+    Reject,
+}
+
+/// The JSON schema of a twin fragment
+#[derive(Debug, Clone)]
+pub struct TwinSchema {
+    schema: JsonValue,
+    patterns: HashMap<String, Regex>,
+}
+
+/// A twin fragment value that doesn't conform to its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// JSON pointer to the non-conforming value, empty for the fragment value itself
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TwinSchemaError {
+    #[error("Failed to read the twin schemas from {path}: {error}")]
+    Io {
+        path: Utf8PathBuf,
+        error: std::io::Error,
+    },
+
+    #[error("Invalid twin schema {path}: {error}")]
+    InvalidJson {
+        path: Utf8PathBuf,
+        error: serde_json::Error,
+    },
+
+    #[error("Invalid twin schema {path}: {reason}")]
+    InvalidSchema { path: Utf8PathBuf, reason: String },
+
+    #[error("Invalid twin schema directory {0}: expecting `device`, `child-device` or `service`")]
+    UnknownEntityType(Utf8PathBuf),
+}
+
+impl TwinSchemas {
+    /// Loads all the schemas of a directory, failing on the first one that cannot be loaded
+    ///
+    /// No schemas are loaded if the directory doesn't exist.
+    pub fn load(dir: &Utf8Path) -> Result<Self, TwinSchemaError> {
+        Self::load_with(dir, Err)
+    }
+
+    /// Loads the schemas of a directory, skipping with a warning those that cannot be loaded
+    ///
+    /// A schema that cannot be read or is invalid is ignored, the twin fragment not being checked.
+    pub fn load_or_skip(dir: &Utf8Path) -> Self {
+        Self::load_with(dir, |err| {
+            warn!("Ignoring twin schema: {err}");
+            Ok(())
+        })
+        .unwrap_or_default()
+    }
+
+    fn load_with(
+        dir: &Utf8Path,
+        mut on_error: impl FnMut(TwinSchemaError) -> Result<(), TwinSchemaError>,
+    ) -> Result<Self, TwinSchemaError> {
+        let mut schemas = TwinSchemas::default();
+        if !dir.exists() {
+            return Ok(schemas);
+        }
+
+        let listing = schema_files(dir).and_then(|files| Ok((files, read_dir(dir)?)));
+        let (files, entries) = match listing {
+            Ok(listing) => listing,
+            Err(err) => {
+                on_error(err)?;
+                return Ok(schemas);
+            }
+        };
+        for (path, fragment) in files {
+            match TwinSchema::load(&path) {
+                Ok(schema) => {
+                    schemas.any_type.insert(fragment, schema);
+                }
+                Err(err) => on_error(err)?,
+            }
+        }
+        for entry in entries {
+            if !entry.is_dir() {
+                continue;
+            }
+            let Some(entity_type) = entry
+                .file_name()
+                .and_then(|name| EntityType::from_str(name).ok())
+            else {
+                on_error(TwinSchemaError::UnknownEntityType(entry.clone()))?;
+                continue;
+            };
+            let files = match schema_files(&entry) {
+                Ok(files) => files,
+                Err(err) => {
+                    on_error(err)?;
+                    continue;
+                }
+            };
+            for (path, fragment) in files {
+                match TwinSchema::load(&path) {
+                    Ok(schema) => {
+                        schemas.per_type.insert((entity_type, fragment), schema);
+                    }
+                    Err(err) => on_error(err)?,
+                }
+            }
+        }
+
+        Ok(schemas)
+    }
+
+    pub fn with_schema(
+        mut self,
+        entity_type: Option<EntityType>,
+        fragment: &str,
+        schema: TwinSchema,
+    ) -> Self {
+        match entity_type {
+            None => self.any_type.insert(fragment.to_string(), schema),
+            Some(entity_type) => self
+                .per_type
+                .insert((entity_type, fragment.to_string()), schema),
+        };
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.any_type.is_empty() && self.per_type.is_empty()
+    }
+
+    /// The schema of a twin fragment for the entities of the given type, if any
+    pub fn get(&self, entity_type: EntityType, fragment: &str) -> Option<&TwinSchema> {
+        self.per_type
+            .get(&(entity_type, fragment.to_string()))
+            .or_else(|| self.any_type.get(fragment))
+    }
+
+    /// Checks a twin fragment value against its schema, if any
+    ///
+    /// A null value, which clears the fragment, is always valid.
+    pub fn validate(
+        &self,
+        entity_type: EntityType,
+        fragment: &str,
+        value: &JsonValue,
+    ) -> Result<(), SchemaViolation> {
+        match self.get(entity_type, fragment) {
+            Some(schema) if !value.is_null() => schema.validate(value),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TwinSchema {
+    pub fn load(path: &Utf8Path) -> Result<Self, TwinSchemaError> {
+        let content = std::fs::read_to_string(path).map_err(|error| TwinSchemaError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let schema =
+            serde_json::from_str(&content).map_err(|error| TwinSchemaError::InvalidJson {
+                path: path.to_path_buf(),
+                error,
+            })?;
+        TwinSchema::new(schema).map_err(|reason| TwinSchemaError::InvalidSchema {
+            path: path.to_path_buf(),
+            reason,
+        })
+    }
+
+    /// Builds a schema, checking that it only uses supported keywords and valid regular expressions
+    pub fn new(schema: JsonValue) -> Result<Self, String> {
+        let mut patterns = HashMap::new();
+        check_schema(&schema, &mut patterns)?;
+        Ok(TwinSchema { schema, patterns })
+    }
+
+    pub fn validate(&self, value: &JsonValue) -> Result<(), SchemaViolation> {
+        self.validate_at(&self.schema, value, "")
+    }
+
+    fn validate_at(
+        &self,
+        schema: &JsonValue,
+        value: &JsonValue,
+        path: &str,
+    ) -> Result<(), SchemaViolation> {
+        let Some(schema) = schema.as_object() else {
+            // `true` and `{}` accept anything, while `false` rejects everything
+            return match schema {
+                JsonValue::Bool(false) => Err(SchemaViolation::new(path, "no value is allowed")),
+                _ => Ok(()),
+            };
+        };
+
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                JsonValue::String(t) => vec![t.as_str()],
+                JsonValue::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+                _ => vec![],
+            };
+            if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+                return Err(SchemaViolation::new(
+                    path,
+                    format!("expected {}, found {}", types.join(" or "), type_of(value)),
+                ));
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return Err(SchemaViolation::new(path, format!("expected {expected}")));
+            }
+        }
+
+        if let Some(JsonValue::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(|v| v.to_string()).collect();
+                return Err(SchemaViolation::new(
+                    path,
+                    format!("expected one of {}", allowed.join(", ")),
+                ));
+            }
+        }
+
+        match value {
+            JsonValue::Number(number) => {
+                let number = number.as_f64().unwrap_or_default();
+                check_bound(schema, "minimum", path, |min| number >= min, ">=")?;
+                check_bound(schema, "maximum", path, |max| number <= max, "<=")?;
+                check_bound(schema, "exclusiveMinimum", path, |min| number > min, ">")?;
+                check_bound(schema, "exclusiveMaximum", path, |max| number < max, "<")?;
+            }
+            JsonValue::String(string) => {
+                let length = string.chars().count();
+                check_length(
+                    schema,
+                    "minLength",
+                    path,
+                    |min| length >= min,
+                    "at least",
+                    "characters",
+                )?;
+                check_length(
+                    schema,
+                    "maxLength",
+                    path,
+                    |max| length <= max,
+                    "at most",
+                    "characters",
+                )?;
+                if let Some(pattern) = schema.get("pattern").and_then(|p| p.as_str()) {
+                    if self
+                        .patterns
+                        .get(pattern)
+                        .is_some_and(|regex| !regex.is_match(string))
+                    {
+                        return Err(SchemaViolation::new(
+                            path,
+                            format!("expected a string matching {pattern}"),
+                        ));
+                    }
+                }
+            }
+            JsonValue::Array(items) => {
+                let length = items.len();
+                check_length(
+                    schema,
+                    "minItems",
+                    path,
+                    |min| length >= min,
+                    "at least",
+                    "items",
+                )?;
+                check_length(
+                    schema,
+                    "maxItems",
+                    path,
+                    |max| length <= max,
+                    "at most",
+                    "items",
+                )?;
+                if let Some(item_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        self.validate_at(item_schema, item, &format!("{path}/{index}"))?;
+                    }
+                }
+            }
+            JsonValue::Object(object) => self.validate_object(schema, object, path)?,
+            JsonValue::Null | JsonValue::Bool(_) => (),
+        }
+
+        Ok(())
+    }
+
+    fn validate_object(
+        &self,
+        schema: &Map<String, JsonValue>,
+        object: &Map<String, JsonValue>,
+        path: &str,
+    ) -> Result<(), SchemaViolation> {
+        if let Some(JsonValue::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(|key| key.as_str()) {
+                if !object.contains_key(key) {
+                    return Err(SchemaViolation::new(
+                        path,
+                        format!("missing required property {key}"),
+                    ));
+                }
+            }
+        }
+
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (key, value) in object {
+            let property_path = format!("{path}/{key}");
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(property_schema) => {
+                    self.validate_at(property_schema, value, &property_path)?
+                }
+                None => match schema.get("additionalProperties") {
+                    Some(JsonValue::Bool(false)) => {
+                        return Err(SchemaViolation::new(
+                            path,
+                            format!("unexpected property {key}"),
+                        ))
+                    }
+                    Some(additional) => self.validate_at(additional, value, &property_path)?,
+                    None => (),
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SchemaViolation {
+    fn new(path: &str, reason: impl Into<String>) -> Self {
+        SchemaViolation {
+            path: path.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "{}: {}", self.path, self.reason)
+        }
+    }
+}
+
+fn has_type(value: &JsonValue, expected: &str) -> bool {
+    match expected {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        expected => type_of(value) == expected,
+    }
+}
+
+fn type_of(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+fn check_bound(
+    schema: &Map<String, JsonValue>,
+    keyword: &str,
+    path: &str,
+    check: impl Fn(f64) -> bool,
+    symbol: &str,
+) -> Result<(), SchemaViolation> {
+    match schema.get(keyword).and_then(|bound| bound.as_f64()) {
+        Some(bound) if !check(bound) => Err(SchemaViolation::new(
+            path,
+            format!("expected a number {symbol} {bound}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_length(
+    schema: &Map<String, JsonValue>,
+    keyword: &str,
+    path: &str,
+    check: impl Fn(usize) -> bool,
+    qualifier: &str,
+    unit: &str,
+) -> Result<(), SchemaViolation> {
+    match schema.get(keyword).and_then(|bound| bound.as_u64()) {
+        Some(bound) if !check(bound as usize) => Err(SchemaViolation::new(
+            path,
+            format!("expected {qualifier} {bound} {unit}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the keywords of a schema and of its sub-schemas, collecting the regular expressions
+fn check_schema(schema: &JsonValue, patterns: &mut HashMap<String, Regex>) -> Result<(), String> {
+    let JsonValue::Object(schema) = schema else {
+        // Boolean schemas
+        return Ok(());
+    };
+    for (keyword, value) in schema {
+        if UNSUPPORTED_KEYWORDS.contains(&keyword.as_str()) {
+            return Err(format!("unsupported keyword {keyword}"));
+        }
+        match (keyword.as_str(), value) {
+            ("pattern", JsonValue::String(pattern)) => {
+                let regex = Regex::new(pattern)
+                    .map_err(|err| format!("invalid pattern {pattern}: {err}"))?;
+                patterns.insert(pattern.clone(), regex);
+            }
+            ("properties", JsonValue::Object(properties)) => {
+                for property_schema in properties.values() {
+                    check_schema(property_schema, patterns)?;
+                }
+            }
+            ("items" | "additionalProperties", sub_schema) => check_schema(sub_schema, patterns)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn read_dir(dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>, TwinSchemaError> {
+    let io_error = |error| TwinSchemaError::Io {
+        path: dir.to_path_buf(),
+        error,
+    };
+    let mut paths = vec![];
+    for entry in dir.read_dir_utf8().map_err(io_error)? {
+        paths.push(entry.map_err(io_error)?.into_path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// The `<fragment>.json` files of a directory, along with the fragment names
+fn schema_files(dir: &Utf8Path) -> Result<Vec<(Utf8PathBuf, String)>, TwinSchemaError> {
+    Ok(read_dir(dir)?
+        .into_iter()
+        .filter(|path| path.is_file() && path.extension() == Some("json"))
+        .filter_map(|path| {
+            let fragment = path.file_stem()?.to_string();
+            Some((path, fragment))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn firmware_schema() -> TwinSchema {
+        TwinSchema::new(json!({
+            "type": "object",
+            "required": ["name", "version"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "version": {"type": "string", "pattern": "^[0-9]+\\.[0-9]+(\\.[0-9]+)?$"},
+                "size": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"enum": ["stable", "beta"]}}
+            },
+            "additionalProperties": false
+        }))
+        .unwrap()
+    }
+
+    #[test_case(json!({"name": "fw", "version": "1.2"}); "minimal")]
+    #[test_case(json!({"name": "fw", "version": "1.2.3", "size": 1024, "tags": ["beta"]}); "complete")]
+    fn valid_fragments(value: JsonValue) {
+        assert_eq!(firmware_schema().validate(&value), Ok(()));
+    }
+
+    #[test_case(json!("1.2"), "expected object, found string")]
+    #[test_case(json!({"name": "fw"}), "missing required property version")]
+    #[test_case(json!({"name": "fw", "version": "v1"}), "/version: expected a string matching ^[0-9]+\\.[0-9]+(\\.[0-9]+)?$")]
+    #[test_case(json!({"name": "", "version": "1.2"}), "/name: expected at least 1 characters")]
+    #[test_case(json!({"name": "fw", "version": "1.2", "size": 1.5}), "/size: expected integer, found number")]
+    #[test_case(json!({"name": "fw", "version": "1.2", "size": -1}), "/size: expected a number >= 0")]
+    #[test_case(json!({"name": "fw", "version": "1.2", "tags": ["alpha"]}), "/tags/0: expected one of \"stable\", \"beta\"")]
+    #[test_case(json!({"name": "fw", "version": "1.2", "url": "http://"}), "unexpected property url")]
+    fn invalid_fragments(value: JsonValue, expected: &str) {
+        let violation = firmware_schema().validate(&value).unwrap_err();
+        assert_eq!(violation.to_string(), expected);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let err = TwinSchema::new(json!({"type": "string", "pattern": "[0-9"})).unwrap_err();
+        assert!(err.starts_with("invalid pattern [0-9"), "{err}");
+    }
+
+    #[test_case(json!({"$ref": "#/$defs/version"}), "$ref")]
+    #[test_case(json!({"oneOf": [{"type": "string"}, {"type": "integer"}]}), "oneOf")]
+    #[test_case(json!({"properties": {"url": {"type": "string", "format": "uri"}}}), "format")]
+    #[test_case(json!({"items": {"anyOf": [{"type": "string"}]}}), "anyOf")]
+    #[test_case(json!({"additionalProperties": {"allOf": [{"type": "string"}]}}), "allOf")]
+    #[test_case(json!({"patternProperties": {"^x-": {"type": "string"}}}), "patternProperties")]
+    fn unsupported_keywords_are_rejected(schema: JsonValue, keyword: &str) {
+        let err = TwinSchema::new(schema).unwrap_err();
+        assert_eq!(err, format!("unsupported keyword {keyword}"));
+    }
+
+    #[test]
+    fn properties_can_be_named_after_keywords() {
+        let schema = TwinSchema::new(json!({
+            "title": "Connectivity",
+            "properties": {"format": {"type": "string"}, "oneOf": {"type": "integer"}}
+        }))
+        .unwrap();
+        assert!(schema
+            .validate(&json!({"format": "json", "oneOf": 1}))
+            .is_ok());
+    }
+
+    #[test]
+    fn schemas_per_entity_type_take_precedence() {
+        let ttd = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(ttd.path()).unwrap();
+        std::fs::write(dir.join("name.json"), r#"{"type": "string"}"#).unwrap();
+        std::fs::create_dir(dir.join("service")).unwrap();
+        std::fs::write(
+            dir.join("service").join("name.json"),
+            r#"{"type": "string", "pattern": "^tedge-"}"#,
+        )
+        .unwrap();
+
+        let schemas = TwinSchemas::load(dir).unwrap();
+        let value = json!("my-app");
+        assert!(schemas
+            .validate(EntityType::ChildDevice, "name", &value)
+            .is_ok());
+        assert!(schemas
+            .validate(EntityType::Service, "name", &value)
+            .is_err());
+        assert!(schemas
+            .validate(EntityType::Service, "name", &JsonValue::Null)
+            .is_ok());
+        assert!(schemas
+            .validate(EntityType::Service, "type", &value)
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_schemas_can_be_skipped() {
+        let ttd = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(ttd.path()).unwrap();
+        std::fs::write(dir.join("name.json"), r#"{"type": "string"}"#).unwrap();
+        std::fs::write(dir.join("type.json"), r#"{"type": "#).unwrap();
+        std::fs::create_dir(dir.join("service")).unwrap();
+        std::fs::write(dir.join("service").join("name.json"), r#"{"oneOf": []}"#).unwrap();
+        std::fs::create_dir(dir.join("devices")).unwrap();
+
+        assert!(TwinSchemas::load(dir).is_err());
+
+        let schemas = TwinSchemas::load_or_skip(dir);
+        assert!(schemas.get(EntityType::Service, "name").is_some());
+        assert!(schemas.get(EntityType::Service, "type").is_none());
+        assert!(schemas
+            .validate(EntityType::Service, "name", &json!(42))
+            .is_err());
+    }
+
+    #[test]
+    fn unknown_entity_type_directories_are_rejected() {
+        let ttd = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(ttd.path()).unwrap();
+        std::fs::create_dir(dir.join("devices")).unwrap();
+
+        let err = TwinSchemas::load(dir).unwrap_err();
+        assert!(matches!(err, TwinSchemaError::UnknownEntityType(_)));
+    }
+}
@@ -1,6 +1,11 @@
 use crate::core::component::TEdgeComponent;
+use crate::core::compression::spawn_compression_actors;
 use crate::core::mapper::start_basic_actors;
+use crate::core::mqtt::cloud_endpoints;
+use crate::core::mqtt::cloud_mqtt_options;
 use crate::core::mqtt::configure_proxy;
+use crate::core::mqtt::use_mqtt5;
+use crate::core::mqtt::websocket_config;
 use crate::flows_config;
 use anyhow::Context;
 use async_trait::async_trait;
@@ -56,20 +61,26 @@ impl TEdgeComponent for AwsMapper {
 
             let rules = bridge_rules(&tedge_config, self.profile.as_ref()).await?;
 
-            let mut cloud_config = tedge_mqtt_bridge::MqttOptions::new(
-                device_id,
-                aws_config.url().or_config_not_set()?.to_string(),
-                8883,
-            );
-            cloud_config.set_clean_session(false);
-            cloud_config.set_keep_alive(aws_config.bridge.keepalive_interval.duration());
-
+            let websocket = websocket_config(&aws_config.bridge.websocket)?;
             let tls_config = tedge_config
                 .mqtt_client_config_rustls(&aws_config)
                 .context("Failed to create MQTT TLS config")?;
-            cloud_config.set_transport(Transport::tls_with_config(tls_config.into()));
-
-            configure_proxy(&tedge_config, &mut cloud_config)?;
+            let primary = (aws_config.url().or_config_not_set()?.to_string(), 8883);
+            let cloud_config =
+                cloud_endpoints(primary, &aws_config.bridge.failover, |host, port| {
+                    let mut cloud_config =
+                        cloud_mqtt_options(&device_id, host, port, websocket.as_ref());
+                    cloud_config.set_clean_session(false);
+                    cloud_config.set_keep_alive(aws_config.bridge.keepalive_interval.duration());
+                    cloud_config
+                        .set_transport(Transport::tls_with_config(tls_config.clone().into()));
+                    if let Some(websocket) = &websocket {
+                        websocket.apply(&mut cloud_config);
+                    }
+                    configure_proxy(&tedge_config, &mut cloud_config)?;
+                    Ok(cloud_config)
+                })?;
+            let cloud_config = use_mqtt5(cloud_config, &aws_config.bridge.mqtt5);
 
             let bridge_name = format!("tedge-mapper-bridge-{prefix}");
             let health_topic = service_health_topic(&mqtt_schema, &device_topic_id, &bridge_name);
@@ -87,6 +98,16 @@ impl TEdgeComponent for AwsMapper {
         } else if tedge_config.proxy.address.or_none().is_some() {
             warn!("`proxy.address` is configured without the built-in bridge enabled. The bridge MQTT connection to the cloud will {} communicate via the configured proxy.", "not".bold())
         }
+        let bridge_name = if tedge_config.mqtt.bridge.built_in {
+            format!("tedge-mapper-bridge-{prefix}")
+        } else {
+            format!("mosquitto-{prefix}-bridge")
+        };
+        let bridge_health_topic = service_health_topic(
+            &mqtt_schema,
+            &tedge_config.mqtt.device_topic_id,
+            &bridge_name,
+        );
         let aws_converter = AwsConverter::new(
             aws_config.cloud_specific.mapper.timestamp,
             &mqtt_schema,
@@ -94,7 +115,20 @@ impl TEdgeComponent for AwsMapper {
             prefix.value().clone(),
             aws_config.mapper.mqtt.max_payload_size.0,
             aws_config.topics.to_string(),
+            &tedge_config.mqtt.device_topic_id,
+        )
+        .with_shadow(
+            aws_config.cloud_specific.mapper.shadow.clone(),
+            bridge_health_topic,
         );
+        let aws_converter = if aws_config.cloud_specific.mapper.jobs.enable {
+            aws_converter.with_jobs(
+                aws_config.cloud_specific.mapper.jobs.clone(),
+                aws_config.device.id()?,
+            )
+        } else {
+            aws_converter
+        };
         let mapper_dir = self.mapper_dir(config_dir);
         let mut flows = crate::mapper_flow_registry(&tedge_config, &mapper_dir).await?;
         aws_converter.persist_builtin_flow(&mut flows).await?;
@@ -105,6 +139,16 @@ impl TEdgeComponent for AwsMapper {
 
         let mut flows_mapper = FlowsMapperBuilder::try_new(flows, service_config).await?;
         flows_mapper.connect(&mut mqtt_actor);
+        crate::spawn_flows_http_actor(&mut runtime, &tedge_config, &mut flows_mapper).await?;
+        spawn_compression_actors(
+            &mut runtime,
+            &tedge_config,
+            &aws_config.mapper.compression,
+            prefix,
+            &mut flows_mapper,
+            &mut mqtt_actor,
+        )
+        .await?;
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
 
@@ -137,6 +181,8 @@ pub async fn resolve_effective_mapper_config(
             bridge: crate::custom::config::BridgeConfig::default(),
             auth_method: crate::custom::config::AuthMethodConfig::Auto,
             credentials_path: None,
+            http: None,
+            operations: Default::default(),
         });
     let mapper_name = match cloud_profile {
         Some(profile) => format!("aws.{profile}"),
@@ -239,6 +285,38 @@ mod tests {
             &rules,
             "thinedge/devices/test-device-id/test-connection"
         ));
+
+        // Jobs are disabled by default
+        assert!(!has_local_subscription(&rules, "aws/things/+/jobs/#"));
+    }
+
+    #[tokio::test]
+    async fn jobs_are_forwarded_only_when_enabled() {
+        let ttd =
+            create_test_dir("aws.url = \"test.test.io\"\naws.mapper.jobs.enable = true").await;
+        let (certificate, key) = make_self_signed_cert("test-device-id");
+        let mapper_dir: camino::Utf8PathBuf = ttd.path().join("mappers/aws").try_into().unwrap();
+        tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+        tokio::fs::write(mapper_dir.join("cert.pem"), certificate.pem())
+            .await
+            .unwrap();
+        tokio::fs::write(mapper_dir.join("key.pem"), key.serialize_pem())
+            .await
+            .unwrap();
+        tokio::fs::write(
+            mapper_dir.join("mapper.toml"),
+            format!(
+                "device.cert_path = \"{mapper_dir}/cert.pem\"\ndevice.key_path = \"{mapper_dir}/key.pem\"\nmapper.jobs.enable = true\n",
+            ),
+        )
+        .await
+        .unwrap();
+        let config = TEdgeConfig::load(ttd.path()).await.unwrap();
+
+        let rules = bridge_rules(&config, None).await.unwrap();
+
+        assert!(has_local_subscription(&rules, "aws/things/+/jobs/#"));
+        assert!(has_remote_subscription(&rules, "$aws/things/+/jobs/#"));
     }
 
     #[tokio::test]
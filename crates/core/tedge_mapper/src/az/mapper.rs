@@ -1,6 +1,11 @@
 use crate::core::component::TEdgeComponent;
+use crate::core::compression::spawn_compression_actors;
 use crate::core::mapper::start_basic_actors;
+use crate::core::mqtt::cloud_endpoints;
+use crate::core::mqtt::cloud_mqtt_options;
 use crate::core::mqtt::configure_proxy;
+use crate::core::mqtt::use_mqtt5;
+use crate::core::mqtt::websocket_config;
 use crate::flows_config;
 use anyhow::Context;
 use async_trait::async_trait;
@@ -56,27 +61,28 @@ impl TEdgeComponent for AzureMapper {
             let remote_clientid = az_config.device.id()?;
             let rules = bridge_rules(&tedge_config, self.profile.as_ref()).await?;
 
-            let mut cloud_config = tedge_mqtt_bridge::MqttOptions::new(
-                &remote_clientid,
-                az_config.url().or_config_not_set()?.to_string(),
-                8883,
-            );
-            cloud_config.set_clean_session(false);
-            cloud_config.set_credentials(
-                format!(
-                    "{}/{remote_clientid}/?api-version=2018-06-30",
-                    az_config.url().or_config_not_set()?
-                ),
-                "",
-            );
-            cloud_config.set_keep_alive(az_config.bridge.keepalive_interval.duration());
-
+            let websocket = websocket_config(&az_config.bridge.websocket)?;
             let tls_config = tedge_config
                 .mqtt_client_config_rustls(&az_config)
                 .context("Failed to create MQTT TLS config")?;
-            cloud_config.set_transport(Transport::tls_with_config(tls_config.into()));
-
-            configure_proxy(&tedge_config, &mut cloud_config)?;
+            let primary = (az_config.url().or_config_not_set()?.to_string(), 8883);
+            let cloud_config =
+                cloud_endpoints(primary, &az_config.bridge.failover, |host, port| {
+                    let username = format!("{host}/{remote_clientid}/?api-version=2018-06-30");
+                    let mut cloud_config =
+                        cloud_mqtt_options(&remote_clientid, host, port, websocket.as_ref());
+                    cloud_config.set_clean_session(false);
+                    cloud_config.set_credentials(username, "");
+                    cloud_config.set_keep_alive(az_config.bridge.keepalive_interval.duration());
+                    cloud_config
+                        .set_transport(Transport::tls_with_config(tls_config.clone().into()));
+                    if let Some(websocket) = &websocket {
+                        websocket.apply(&mut cloud_config);
+                    }
+                    configure_proxy(&tedge_config, &mut cloud_config)?;
+                    Ok(cloud_config)
+                })?;
+            let cloud_config = use_mqtt5(cloud_config, &az_config.bridge.mqtt5);
 
             let built_in_bridge_name = format!("tedge-mapper-bridge-{prefix}");
             let health_topic =
@@ -102,7 +108,10 @@ impl TEdgeComponent for AzureMapper {
             prefix,
             az_config.mapper.mqtt.max_payload_size.0,
             az_config.topics.to_string(),
-        );
+            &tedge_config.mqtt.device_topic_id,
+        )
+        .with_twin(az_config.cloud_specific.mapper.twin.clone())
+        .with_methods(az_config.cloud_specific.mapper.methods.clone());
         let mapper_dir = self.mapper_dir(config_dir);
         let mut flows = crate::mapper_flow_registry(&tedge_config, &mapper_dir).await?;
         az_converter.persist_builtin_flow(&mut flows).await?;
@@ -112,6 +121,16 @@ impl TEdgeComponent for AzureMapper {
 
         let mut flows_mapper = FlowsMapperBuilder::try_new(flows, service_config).await?;
         flows_mapper.connect(&mut mqtt_actor);
+        crate::spawn_flows_http_actor(&mut runtime, &tedge_config, &mut flows_mapper).await?;
+        spawn_compression_actors(
+            &mut runtime,
+            &tedge_config,
+            &az_config.mapper.compression,
+            prefix,
+            &mut flows_mapper,
+            &mut mqtt_actor,
+        )
+        .await?;
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
 
@@ -143,6 +162,8 @@ pub async fn resolve_effective_mapper_config(
             bridge: crate::custom::config::BridgeConfig::default(),
             auth_method: crate::custom::config::AuthMethodConfig::Auto,
             credentials_path: None,
+            http: None,
+            operations: Default::default(),
         });
     let mapper_name = match cloud_profile {
         Some(profile) => format!("az.{profile}"),
@@ -226,6 +247,10 @@ mod tests {
 
         // Device-to-cloud messages
         assert!(has_local_subscription(&rules, "az/messages/events/#"));
+        assert!(!has_local_subscription(
+            &rules,
+            "az/messages/events/compressed=true"
+        ));
 
         // Cloud-to-device messages
         assert!(has_remote_subscription(
@@ -239,8 +264,15 @@ mod tests {
 
         // Digital twin
         assert!(has_local_subscription(&rules, "az/twin/GET/#"));
-        assert!(has_local_subscription(&rules, "az/twin/PATCH/#"));
+        assert!(has_local_subscription(
+            &rules,
+            "az/twin/PATCH/properties/reported/#"
+        ));
         assert!(has_remote_subscription(&rules, "$iothub/twin/res/#"));
+        assert!(has_remote_subscription(
+            &rules,
+            "$iothub/twin/PATCH/properties/desired/#"
+        ));
     }
 
     #[tokio::test]
@@ -276,6 +308,38 @@ mod tests {
         assert!(has_local_subscription(&rules, "custom-az/methods/res/#"));
     }
 
+    #[tokio::test]
+    async fn compressed_telemetry_is_forwarded_only_when_enabled() {
+        let ttd =
+            create_test_dir("az.url = \"test.test.io\"\nmapper.compression.enable = true").await;
+        let (certificate, key) = make_self_signed_cert("test-device-id");
+        let mapper_dir: camino::Utf8PathBuf = ttd.path().join("mappers/az").try_into().unwrap();
+        tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+        tokio::fs::write(mapper_dir.join("cert.pem"), certificate.pem())
+            .await
+            .unwrap();
+        tokio::fs::write(mapper_dir.join("key.pem"), key.serialize_pem())
+            .await
+            .unwrap();
+        tokio::fs::write(
+            mapper_dir.join("mapper.toml"),
+            format!(
+                "device.cert_path = \"{mapper_dir}/cert.pem\"\ndevice.key_path = \"{mapper_dir}/key.pem\"\n",
+            ),
+        )
+        .await
+        .unwrap();
+        let config = TEdgeConfig::load(ttd.path()).await.unwrap();
+
+        let rules = bridge_rules(&config, None).await.unwrap();
+
+        assert!(has_local_subscription(
+            &rules,
+            "az/messages/events/compressed=true"
+        ));
+        assert!(has_local_subscription(&rules, "az/messages/events/#"));
+    }
+
     async fn create_test_dir(toml: &str) -> TempTedgeDir {
         let ttd = TempTedgeDir::new();
         let (user, group) = crate::test_helpers::current_user_group();
@@ -1,6 +1,11 @@
 use crate::core::component::TEdgeComponent;
+use crate::core::compression::spawn_compression_actors;
 use crate::core::mapper::start_basic_actors;
+use crate::core::mqtt::cloud_endpoints;
+use crate::core::mqtt::cloud_mqtt_options;
 use crate::core::mqtt::configure_proxy;
+use crate::core::mqtt::use_mqtt5;
+use crate::core::mqtt::websocket_config;
 use crate::flows_config;
 use anyhow::Context;
 use async_trait::async_trait;
@@ -34,8 +39,8 @@ use tedge_mqtt_bridge::rumqttc::Transport;
 use tedge_mqtt_bridge::use_credentials;
 use tedge_mqtt_bridge::AuthMethod;
 use tedge_mqtt_bridge::BridgeConfig;
+use tedge_mqtt_bridge::CloudEndpoints;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
-use tedge_mqtt_bridge::MqttOptions;
 use tedge_mqtt_bridge::QoS;
 use tedge_mqtt_ext::MqttActorBuilder;
 use tedge_timer_ext::TimerActor;
@@ -78,6 +83,13 @@ impl TEdgeComponent for CumulocityMapper {
         cfg_dir: &TedgePaths,
     ) -> Result<Runtime, anyhow::Error> {
         let c8y_config = tedge_config.mapper_config(&self.profile)?;
+        // Cumulocity doesn't accept compressed payloads over MQTT,
+        // hence no bridge rule forwards the compressed batches and these would be lost
+        let compression = &tedge_config.mapper.compression;
+        let compress_telemetry = compression.enable && compression.http_url.or_none().is_some();
+        if compression.enable && !compress_telemetry {
+            warn!("The telemetry sent to Cumulocity is not compressed: mapper.compression.http_url is not set");
+        }
         let prefix = &c8y_config.bridge.topic_prefix;
         let c8y_mapper_name = format!("tedge-mapper-{prefix}");
         let (mut runtime, mut mqtt_actor) =
@@ -170,6 +182,18 @@ impl TEdgeComponent for CumulocityMapper {
 
         let mut flows_mapper = FlowsMapperBuilder::try_new(flows, service_config).await?;
         flows_mapper.connect(&mut mqtt_actor);
+        crate::spawn_flows_http_actor(&mut runtime, &tedge_config, &mut flows_mapper).await?;
+        if compress_telemetry {
+            spawn_compression_actors(
+                &mut runtime,
+                &tedge_config,
+                &c8y_config.mapper.compression,
+                prefix,
+                &mut flows_mapper,
+                &mut mqtt_actor,
+            )
+            .await?;
+        }
         flows_mapper.connect_fs(&mut fs_watch_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
         c8y_mapper_actor.set_flow_context(flows_mapper.context_handle());
@@ -244,44 +268,10 @@ async fn mqtt_bridge_config(
     c8y_mapper_name: &str,
     cloud_profile: Option<&ProfileName>,
     auth_method: AuthMethod,
-) -> Result<(BridgeConfig, MqttOptions, Publish), anyhow::Error> {
+) -> Result<(BridgeConfig, CloudEndpoints, Publish), anyhow::Error> {
     let use_mqtt_service = c8y_config.cloud_specific.mqtt_service.enabled;
     let bridge_config = bridge_rules(tedge_config, cloud_profile, auth_method).await?;
 
-    let c8y = &c8y_config.mqtt().or_config_not_set()?;
-    let mut c8y_port = c8y.port().into();
-    if use_mqtt_service && c8y_port == MQTT_CORE_TLS_PORT {
-        c8y_port = MQTT_SERVICE_TLS_PORT;
-    }
-    let mut cloud_config = tedge_mqtt_bridge::MqttOptions::new(
-        c8y_config.device.id()?,
-        c8y.host().to_string(),
-        c8y_port,
-    );
-    // Cumulocity tells us not to not set clean session to false, so don't
-    // https://cumulocity.com/docs/device-integration/mqtt/#mqtt-clean-session
-    cloud_config.set_clean_session(true);
-
-    match auth_method {
-        AuthMethod::Certificate => {
-            let tls_config = tedge_config
-                .mqtt_client_config_rustls(c8y_config)
-                .context("Failed to create MQTT TLS config")?;
-            cloud_config.set_transport(Transport::tls_with_config(tls_config.into()));
-        }
-        AuthMethod::Password => {
-            // TODO(marcel): integrate credentials auth into MqttAuthConfig?
-            let (username, password) =
-                read_c8y_credentials(&c8y_config.cloud_specific.credentials_path)?;
-            use_credentials(
-                &mut cloud_config,
-                &*c8y_config.root_cert_path,
-                username,
-                password,
-            )?;
-        }
-    }
-
     let main_device_xid: EntityExternalId = c8y_config.device.id()?.into();
     let service_type = &tedge_config.service.ty;
     let service_type = if service_type.is_empty() {
@@ -334,15 +324,64 @@ async fn mqtt_bridge_config(
         pkid: 0,
     };
 
-    cloud_config.set_last_will(LastWill {
+    let last_will = LastWill {
         topic: "s/us".into(),
         qos: QoS::AtLeastOnce,
         message: format!("{last_will_message_bridge}\n{last_will_message_mapper}").into(),
         retain: false,
-    });
-    cloud_config.set_keep_alive(c8y_config.bridge.keepalive_interval.duration());
+    };
+
+    let tls_config = match auth_method {
+        AuthMethod::Certificate => Some(
+            tedge_config
+                .mqtt_client_config_rustls(c8y_config)
+                .context("Failed to create MQTT TLS config")?,
+        ),
+        AuthMethod::Password => None,
+    };
+    let credentials = match auth_method {
+        AuthMethod::Certificate => None,
+        // TODO(marcel): integrate credentials auth into MqttAuthConfig?
+        AuthMethod::Password => Some(read_c8y_credentials(
+            &c8y_config.cloud_specific.credentials_path,
+        )?),
+    };
+
+    let device_id = c8y_config.device.id()?;
+    let websocket = websocket_config(&c8y_config.bridge.websocket)?;
+    let c8y = &c8y_config.mqtt().or_config_not_set()?;
+    let primary = (c8y.host().to_string(), c8y.port().into());
+    let cloud_config = cloud_endpoints(primary, &c8y_config.bridge.failover, |host, mut port| {
+        if use_mqtt_service && port == MQTT_CORE_TLS_PORT {
+            port = MQTT_SERVICE_TLS_PORT;
+        }
+        let mut cloud_config = cloud_mqtt_options(&device_id, host, port, websocket.as_ref());
+        // Cumulocity tells us not to not set clean session to false, so don't
+        // https://cumulocity.com/docs/device-integration/mqtt/#mqtt-clean-session
+        cloud_config.set_clean_session(true);
+
+        if let Some(tls_config) = &tls_config {
+            cloud_config.set_transport(Transport::tls_with_config(tls_config.clone().into()));
+        }
+        if let Some((username, password)) = &credentials {
+            use_credentials(
+                &mut cloud_config,
+                &*c8y_config.root_cert_path,
+                username.clone(),
+                password.clone(),
+            )?;
+        }
+        if let Some(websocket) = &websocket {
+            websocket.apply(&mut cloud_config);
+        }
+
+        cloud_config.set_last_will(last_will.clone());
+        cloud_config.set_keep_alive(c8y_config.bridge.keepalive_interval.duration());
 
-    configure_proxy(tedge_config, &mut cloud_config)?;
+        configure_proxy(tedge_config, &mut cloud_config)?;
+        Ok(cloud_config)
+    })?;
+    let cloud_config = use_mqtt5(cloud_config, &c8y_config.bridge.mqtt5);
     Ok((bridge_config, cloud_config, reconnect_message_mapper))
 }
 
@@ -378,6 +417,8 @@ pub async fn resolve_effective_mapper_config(
             bridge: crate::custom::config::BridgeConfig::default(),
             auth_method: crate::custom::config::AuthMethodConfig::Auto,
             credentials_path: None,
+            http: None,
+            operations: Default::default(),
         });
     let mapper_name = match cloud_profile {
         Some(profile) => format!("c8y.{profile}"),
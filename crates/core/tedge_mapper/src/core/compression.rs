@@ -0,0 +1,416 @@
+//! Batching and compression of the outbound telemetry of a mapper
+//!
+//! When `mapper.compression.enable` is set, the messages produced by the mapper flows
+//! on the `<cloud>.mapper.compression.input_topics` are diverted from MQTT
+//! to a [BatchDriver](batcher::BatchDriver), which groups them over `mapper.compression.interval`.
+//! Each batch is then serialized as a JSON array, compressed with gzip or zstd,
+//! and either published on `<cloud>.mapper.compression.topic`
+//! or posted to `mapper.compression.http_url`.
+//!
+//! zstd compression requires the `zstd` feature, as it links the zstd C library.
+use crate::core::retry_buffer::RetryBuffer;
+use anyhow::Context;
+use async_trait::async_trait;
+use batcher::BatchDriverInput;
+use batcher::BatchDriverOutput;
+use batcher::Batchable;
+use batcher::BatchingActorBuilder;
+use flate2::write::GzEncoder;
+use mqtt_channel::QoS;
+use mqtt_channel::Topic;
+use mqtt_channel::TopicFilter;
+use serde_json::json;
+use std::io::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tedge_actors::Actor;
+use tedge_actors::ClientMessageBox;
+use tedge_actors::DynSender;
+use tedge_actors::MappingSender;
+use tedge_actors::MessageReceiver;
+use tedge_actors::MessageSink;
+use tedge_actors::MessageSource;
+use tedge_actors::NoConfig;
+use tedge_actors::Runtime;
+use tedge_actors::RuntimeError;
+use tedge_actors::Sender;
+use tedge_actors::SimpleMessageBox;
+use tedge_actors::SimpleMessageBoxBuilder;
+use tedge_config::models::CompressionAlgorithm;
+use tedge_config::models::TopicPrefix;
+use tedge_config::tedge_toml::mapper_config::CompressionTopics;
+use tedge_config::TEdgeConfig;
+use tedge_flows::FlowsMapperBuilder;
+use tedge_http_ext::HttpActor;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpRequestBuilder;
+use tedge_http_ext::HttpResult;
+use tedge_mqtt_ext::MqttActorBuilder;
+use tedge_mqtt_ext::MqttMessage;
+use time::OffsetDateTime;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Delay given to the telemetry messages to reach the batcher once produced, in milliseconds
+const MAXIMUM_MESSAGE_DELAY: u32 = 1000;
+
+/// Spawns the actors batching and compressing the telemetry produced by the mapper flows, if enabled
+///
+/// This must be called once the flows are connected to MQTT, and before the MQTT actor is spawned.
+pub async fn spawn_compression_actors(
+    runtime: &mut Runtime,
+    tedge_config: &TEdgeConfig,
+    topics: &CompressionTopics,
+    topic_prefix: &TopicPrefix,
+    flows_mapper: &mut FlowsMapperBuilder,
+    mqtt_actor: &mut MqttActorBuilder,
+) -> anyhow::Result<()> {
+    if !tedge_config.mapper.compression.enable {
+        return Ok(());
+    }
+    let settings = CompressionSettings::try_new(tedge_config, topics, topic_prefix)?;
+    info!(
+        "Outbound telemetry on {:?} is sent {} compressed every {:?}",
+        settings.input_topics.patterns(),
+        settings.algorithm,
+        settings.interval
+    );
+
+    let mut batcher = BatchingActorBuilder::default()
+        .with_batching_window(settings.interval.as_millis().try_into().unwrap_or(u32::MAX))
+        .with_maximum_message_delay(MAXIMUM_MESSAGE_DELAY);
+    flows_mapper.divert_output(settings.input_topics.clone(), &TelemetrySink(&batcher));
+
+    let http = match &settings.http_url {
+        Some(_) => {
+            let mut http_actor = HttpActor::new(tedge_config.cloud_client_tls_config()).builder();
+            let http = ClientMessageBox::new(&mut http_actor);
+            runtime.spawn(http_actor).await?;
+            Some(http)
+        }
+        None => None,
+    };
+
+    let mut messages = SimpleMessageBoxBuilder::new("Telemetry compressor", 16);
+    batcher.connect_sink(NoConfig, &messages);
+    mqtt_actor.connect_source(NoConfig, &mut messages);
+    let compressor = CompressionActor {
+        settings,
+        messages: messages.build(),
+        http,
+        pending: RetryBuffer::new(MAX_PENDING_BATCHES),
+    };
+
+    runtime.spawn(batcher).await?;
+    runtime.spawn(compressor).await?;
+    Ok(())
+}
+
+/// The settings of the telemetry compression, with the cloud topics resolved against the bridge topic prefix
+#[derive(Debug)]
+struct CompressionSettings {
+    algorithm: CompressionAlgorithm,
+    interval: Duration,
+    input_topics: TopicFilter,
+    topic: Topic,
+    topic_prefix: String,
+    content_type: String,
+    http_url: Option<String>,
+}
+
+impl CompressionSettings {
+    fn try_new(
+        tedge_config: &TEdgeConfig,
+        topics: &CompressionTopics,
+        topic_prefix: &TopicPrefix,
+    ) -> anyhow::Result<Self> {
+        let config = &tedge_config.mapper.compression;
+        if config.algorithm == CompressionAlgorithm::Zstd && !cfg!(feature = "zstd") {
+            anyhow::bail!(ZSTD_UNSUPPORTED);
+        }
+        let mut input_topics = TopicFilter::empty();
+        for topic in topics.input_topics.0.iter() {
+            input_topics
+                .try_add(&format!("{topic_prefix}/{topic}"))
+                .with_context(|| format!("Invalid compression input topic: {topic}"))?;
+        }
+        let topic = Topic::new(&format!("{topic_prefix}/{}", topics.topic))
+            .with_context(|| format!("Invalid compression topic: {}", topics.topic))?;
+        Ok(CompressionSettings {
+            algorithm: config.algorithm,
+            interval: config.interval.duration(),
+            input_topics,
+            topic,
+            topic_prefix: format!("{topic_prefix}/"),
+            content_type: config.content_type.clone(),
+            http_url: config.http_url.or_none().map(|url| url.to_string()),
+        })
+    }
+}
+
+/// An outbound telemetry message, waiting to be sent in a compressed batch
+#[derive(Debug)]
+pub struct TelemetryMessage {
+    sequence: u64,
+    received_at: OffsetDateTime,
+    message: MqttMessage,
+}
+
+impl TelemetryMessage {
+    fn new(message: MqttMessage) -> Self {
+        static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+        TelemetryMessage {
+            sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            received_at: OffsetDateTime::now_utc(),
+            message,
+        }
+    }
+}
+
+impl Batchable for TelemetryMessage {
+    type Key = u64;
+
+    fn key(&self) -> Self::Key {
+        self.sequence
+    }
+
+    fn event_time(&self) -> OffsetDateTime {
+        self.received_at
+    }
+}
+
+/// Feeds the batcher with the messages diverted from MQTT
+struct TelemetrySink<'a>(&'a BatchingActorBuilder<TelemetryMessage>);
+
+impl MessageSink<MqttMessage> for TelemetrySink<'_> {
+    fn get_sender(&self) -> DynSender<MqttMessage> {
+        MappingSender::new(self.0.get_sender(), |message| {
+            Some(BatchDriverInput::Event(TelemetryMessage::new(message)))
+        })
+        .into()
+    }
+}
+
+/// Compresses the batches of telemetry and sends them to the cloud
+struct CompressionActor {
+    settings: CompressionSettings,
+    messages: SimpleMessageBox<BatchDriverOutput<TelemetryMessage>, MqttMessage>,
+    http: Option<ClientMessageBox<HttpRequest, HttpResult>>,
+    /// The compressed batches waiting to be accepted by the HTTP endpoint
+    pending: RetryBuffer<Vec<u8>>,
+}
+
+#[async_trait]
+impl Actor for CompressionActor {
+    fn name(&self) -> &str {
+        "Telemetry compressor"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        loop {
+            let output = if self.pending.is_empty() {
+                self.messages.recv().await
+            } else {
+                // Retry the batches not yet accepted by the HTTP endpoint, even if no new telemetry is produced
+                match tokio::time::timeout(self.settings.interval, self.messages.recv()).await {
+                    Ok(output) => output,
+                    Err(_) => {
+                        self.post_pending_batches().await?;
+                        continue;
+                    }
+                }
+            };
+            let Some(BatchDriverOutput::Batch(batch)) = output else {
+                break;
+            };
+            let payload = match encode_batch(&self.settings, batch) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    error!("Failed to compress a batch of telemetry: {err}");
+                    continue;
+                }
+            };
+            if self.http.is_some() {
+                if self.pending.push(payload).is_some() {
+                    warn!("Dropped the oldest pending batch of telemetry: too many batches are pending");
+                }
+                self.post_pending_batches().await?;
+            } else {
+                let message =
+                    MqttMessage::new(&self.settings.topic, payload).with_qos(QoS::AtLeastOnce);
+                self.messages.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CompressionActor {
+    /// Posts the pending batches in order, stopping on the first one to be retried
+    async fn post_pending_batches(&mut self) -> Result<(), RuntimeError> {
+        let (Some(url), Some(http)) = (&self.settings.http_url, &mut self.http) else {
+            return Ok(());
+        };
+        let settings = &self.settings;
+        let failure = self
+            .pending
+            .post_all(http, |payload| {
+                HttpRequestBuilder::post(url)
+                    .header("content-type", &settings.content_type)
+                    .header("content-encoding", settings.algorithm.to_string())
+                    .bytes(payload.clone())
+                    .build()
+            })
+            .await?;
+        if let Some(err) = failure {
+            warn!(
+                "Failed to post a batch of telemetry to {url}, retrying in {:?}: {err}",
+                settings.interval
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The maximum number of compressed batches kept while the HTTP endpoint is unavailable
+const MAX_PENDING_BATCHES: usize = 100;
+
+const ZSTD_UNSUPPORTED: &str =
+    "zstd compression is not supported by this build of tedge-mapper: enable the `zstd` feature or use gzip";
+
+/// Serializes a batch as a JSON array of `{"topic", "payload"}` objects and compresses it
+///
+/// The topics are relative to the bridge topic prefix and the payloads are inlined when valid JSON.
+fn encode_batch(
+    settings: &CompressionSettings,
+    mut batch: Vec<TelemetryMessage>,
+) -> std::io::Result<Vec<u8>> {
+    batch.sort_by_key(|telemetry| telemetry.sequence);
+    let messages: Vec<_> = batch
+        .iter()
+        .map(|telemetry| {
+            let topic = telemetry.message.topic.name.as_str();
+            let topic = topic.strip_prefix(&settings.topic_prefix).unwrap_or(topic);
+            let payload = telemetry.message.payload_bytes();
+            let payload = serde_json::from_slice(payload)
+                .unwrap_or_else(|_| String::from_utf8_lossy(payload).into());
+            json!({ "topic": topic, "payload": payload })
+        })
+        .collect();
+    let json = serde_json::to_vec(&messages)?;
+    compress(settings.algorithm, &json)
+}
+
+fn compress(algorithm: CompressionAlgorithm, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match algorithm {
+        CompressionAlgorithm::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        #[cfg(not(feature = "zstd"))]
+        CompressionAlgorithm::Zstd => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            ZSTD_UNSUPPORTED,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn batches_are_encoded_in_order_relative_to_the_topic_prefix() {
+        let settings = settings(CompressionAlgorithm::Gzip);
+        let batch = vec![
+            telemetry("az/messages/events/", r#"{"temperature": 21}"#),
+            telemetry("az/messages/events/", "not json"),
+        ];
+        let (first, second) = (batch[0].sequence, batch[1].sequence);
+        assert!(first < second);
+        let batch = batch.into_iter().rev().collect();
+
+        let compressed = encode_batch(&settings, batch).unwrap();
+        let mut json = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut json)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            json!([
+                { "topic": "messages/events/", "payload": { "temperature": 21 } },
+                { "topic": "messages/events/", "payload": "not json" },
+            ])
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn batches_can_be_compressed_with_zstd() {
+        let settings = settings(CompressionAlgorithm::Zstd);
+        let batch = vec![telemetry("az/messages/events/", r#"{"temperature": 21}"#)];
+
+        let compressed = encode_batch(&settings, batch).unwrap();
+        let json = zstd::decode_all(compressed.as_slice()).unwrap();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            json!([{ "topic": "messages/events/", "payload": { "temperature": 21 } }])
+        );
+    }
+
+    #[test]
+    fn topics_are_resolved_against_the_bridge_topic_prefix() {
+        let settings = settings(CompressionAlgorithm::Gzip);
+
+        assert!(settings
+            .input_topics
+            .accept_topic_name("az/messages/events/"));
+        assert!(!settings.input_topics.accept_topic_name("az/twin/GET/"));
+        assert_eq!(settings.topic.name, "az/messages/events/compressed=true");
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_is_rejected_when_not_supported_by_the_build() {
+        let tedge_config = TEdgeConfig::load_toml_str("mapper.compression.algorithm = \"zstd\"");
+        let err = CompressionSettings::try_new(
+            &tedge_config,
+            &az_topics(),
+            &TopicPrefix::try_new("az").unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), ZSTD_UNSUPPORTED);
+    }
+
+    fn settings(algorithm: CompressionAlgorithm) -> CompressionSettings {
+        let tedge_config = TEdgeConfig::load_toml_str(&format!(
+            "mapper.compression.enable = true\nmapper.compression.algorithm = \"{algorithm}\""
+        ));
+        CompressionSettings::try_new(
+            &tedge_config,
+            &az_topics(),
+            &TopicPrefix::try_new("az").unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn az_topics() -> CompressionTopics {
+        CompressionTopics {
+            input_topics: vec!["messages/events/#"].into(),
+            topic: "messages/events/compressed=true".to_string(),
+        }
+    }
+
+    fn telemetry(topic: &str, payload: &str) -> TelemetryMessage {
+        TelemetryMessage::new(MqttMessage::new(&Topic::new_unchecked(topic), payload))
+    }
+}
@@ -1,4 +1,6 @@
 pub mod component;
+pub mod compression;
 pub mod mapper;
 pub mod mappers_dir;
 pub mod mqtt;
+pub mod retry_buffer;
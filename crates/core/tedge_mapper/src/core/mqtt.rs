@@ -1,12 +1,21 @@
+use anyhow::Context;
 use std::sync::Arc;
 use tedge_config::all_or_nothing;
 use tedge_config::models::proxy_scheme::ProxyScheme;
+use tedge_config::models::HostPort;
+use tedge_config::models::MQTT_TLS_PORT;
+use tedge_config::tedge_toml::mapper_config::BridgeFailoverConfig;
+use tedge_config::tedge_toml::mapper_config::BridgeMqtt5Config;
+use tedge_config::tedge_toml::mapper_config::BridgeWebSocketConfig;
 use tedge_config::TEdgeConfig;
 use tedge_mqtt_bridge::rumqttc::Proxy;
 use tedge_mqtt_bridge::rumqttc::ProxyAuth;
 use tedge_mqtt_bridge::rumqttc::ProxyType;
 use tedge_mqtt_bridge::rumqttc::TlsConfiguration;
+use tedge_mqtt_bridge::CloudEndpoints;
+use tedge_mqtt_bridge::Mqtt5Options;
 use tedge_mqtt_bridge::MqttOptions;
+use tedge_mqtt_bridge::WebSocketConfig;
 
 pub fn configure_proxy(
     tedge_config: &TEdgeConfig,
@@ -35,3 +44,67 @@ pub fn configure_proxy(
     }
     Ok(())
 }
+
+/// Returns the WebSocket settings of a cloud bridge, if its connection has to be tunneled over a WebSocket
+pub fn websocket_config(config: &BridgeWebSocketConfig) -> anyhow::Result<Option<WebSocketConfig>> {
+    if !config.enable {
+        return Ok(None);
+    }
+    WebSocketConfig::try_new(&config.path, &config.headers.0).map(Some)
+}
+
+/// Creates the options of a cloud broker connection, using the WebSocket endpoint if any
+///
+/// Once the TLS transport is configured, the connection is tunneled over the WebSocket
+/// using [WebSocketConfig::apply].
+pub fn cloud_mqtt_options(
+    client_id: impl Into<String>,
+    host: String,
+    port: u16,
+    websocket: Option<&WebSocketConfig>,
+) -> MqttOptions {
+    match websocket {
+        Some(websocket) => {
+            let (url, port) = websocket.broker_address(&host, port, true);
+            MqttOptions::new(client_id, url, port)
+        }
+        None => MqttOptions::new(client_id, host, port),
+    }
+}
+
+/// Returns the cloud broker endpoints of a bridge: the primary one, followed by the failover ones
+///
+/// The MQTT options of each endpoint are built by `endpoint_options`, given the host and port of the endpoint.
+/// The failover urls default to the MQTT TLS port when no port is given.
+pub fn cloud_endpoints(
+    primary: (String, u16),
+    failover: &BridgeFailoverConfig,
+    mut endpoint_options: impl FnMut(String, u16) -> anyhow::Result<MqttOptions>,
+) -> anyhow::Result<CloudEndpoints> {
+    let (host, port) = primary;
+    let mut endpoints = CloudEndpoints::new(endpoint_options(host, port)?);
+    for url in failover.urls.0.iter().map(|url| url.trim()) {
+        if url.is_empty() {
+            continue;
+        }
+        let endpoint = HostPort::<MQTT_TLS_PORT>::try_from(url)
+            .with_context(|| format!("Invalid bridge failover url: {url:?}"))?;
+        endpoints = endpoints.with_endpoint(endpoint_options(
+            endpoint.host().to_string(),
+            endpoint.port().0,
+        )?);
+    }
+    Ok(endpoints
+        .with_strategy(failover.strategy)
+        .with_max_failures(failover.max_failures))
+}
+
+/// Connects the bridge to the cloud endpoints using MQTT 5, if enabled
+pub fn use_mqtt5(endpoints: CloudEndpoints, config: &BridgeMqtt5Config) -> CloudEndpoints {
+    if !config.enable {
+        return endpoints;
+    }
+    endpoints.with_mqtt5(Mqtt5Options {
+        topic_alias_max: config.topic_alias_max,
+    })
+}
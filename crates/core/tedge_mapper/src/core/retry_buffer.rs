@@ -0,0 +1,113 @@
+//! Buffering of the requests posted to an HTTP endpoint, retried while the endpoint is unavailable
+//!
+//! Shared by the [compressed telemetry](super::compression) and the HTTP upstream of custom mappers.
+use std::collections::VecDeque;
+use tedge_actors::ClientMessageBox;
+use tedge_actors::RuntimeError;
+use tedge_http_ext::HttpError;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpResponseExt;
+use tedge_http_ext::HttpResult;
+use tracing::error;
+
+/// Requests waiting to be accepted by an HTTP endpoint, the oldest first
+///
+/// When the buffer is full, the oldest request is dropped to make room for a new one.
+#[derive(Debug)]
+pub struct RetryBuffer<T> {
+    requests: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> RetryBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        RetryBuffer {
+            requests: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Appends a request, returning the oldest one if it has been dropped to make room
+    pub fn push(&mut self, request: T) -> Option<T> {
+        let dropped = if self.requests.len() >= self.capacity {
+            self.requests.pop_front()
+        } else {
+            None
+        };
+        self.requests.push_back(request);
+        dropped
+    }
+
+    /// The oldest request, i.e. the next one to be posted
+    pub fn front(&self) -> Option<&T> {
+        self.requests.front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Posts the buffered requests in order, stopping on the first failure that might not be final
+    ///
+    /// The requests accepted by the endpoint are removed from the buffer,
+    /// as are those rejected for good (see [is_retryable]).
+    /// Returns the error of the request to be retried later, if any.
+    pub async fn post_all(
+        &mut self,
+        http: &mut ClientMessageBox<HttpRequest, HttpResult>,
+        build_request: impl Fn(&T) -> Result<HttpRequest, HttpError>,
+    ) -> Result<Option<HttpError>, RuntimeError> {
+        while let Some(request) = self.requests.front() {
+            let response = match build_request(request) {
+                Ok(request) => http.await_response(request).await?,
+                Err(err) => Err(err),
+            };
+            match response.error_for_status() {
+                Ok(_) => {
+                    self.requests.pop_front();
+                }
+                Err(err) if is_retryable(&err) => return Ok(Some(err)),
+                Err(err) => {
+                    error!("Dropping a request that cannot succeed: {err}");
+                    self.requests.pop_front();
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Tells whether a failed request might succeed later
+///
+/// Network errors, server errors, timeouts and rate limits are retried;
+/// any other rejection by the server is final.
+pub fn is_retryable(err: &HttpError) -> bool {
+    match err {
+        HttpError::HyperError(_) | HttpError::HyperUtilError(_) => true,
+        HttpError::HttpStatusError { code, .. } => {
+            code.is_server_error() || matches!(code.as_u16(), 408 | 429)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_requests_are_dropped_when_the_buffer_is_full() {
+        let mut buffer = RetryBuffer::new(2);
+
+        assert_eq!(buffer.push(1), None);
+        assert_eq!(buffer.push(2), None);
+        assert_eq!(buffer.push(3), Some(1));
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.front(), Some(&2));
+    }
+}
@@ -2,14 +2,17 @@
 //!
 //! A mapper's configuration is stored in `mapper.toml` within the mapper directory
 //! (e.g. `/etc/tedge/mappers/thingsboard/mapper.toml`). This file is optional —
-//! it is only needed when the mapper establishes a cloud connection via the MQTT bridge.
+//! it is only needed when the mapper establishes a cloud connection via the MQTT bridge
+//! or the HTTP upstream.
 //!
 //! The full TOML table is available for `${mapper.*}` template expansion in bridge rules.
 
 use anyhow::Context;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use std::collections::BTreeMap;
 use tedge_config::models::CloudType;
+use tedge_config::models::FailoverStrategy;
 use tedge_config::models::HostPort;
 use tedge_config::models::SecondsOrHumanTime;
 use tedge_config::models::MQTT_TLS_PORT;
@@ -60,13 +63,17 @@ pub struct CustomMapperConfig {
     pub url: Option<HostPort<MQTT_TLS_PORT>>,
     /// Device identity and TLS certificate settings.
     pub device: Option<DeviceConfig>,
-    /// MQTT bridge settings (keepalive, clean session).
+    /// MQTT bridge settings (keepalive, clean session, transport).
     pub bridge: BridgeConfig,
     /// Authentication method: auto, certificate, or password.
     pub auth_method: AuthMethodConfig,
     /// Path to a TOML credentials file for username/password authentication.
     /// The file must contain a `[credentials]` section with `username` and `password` fields.
     pub credentials_path: Option<Utf8PathBuf>,
+    /// HTTP upstream to which the flow output is posted, if configured.
+    pub http: Option<HttpUpstreamConfig>,
+    /// Cloud requests mapped to thin-edge commands, indexed by command name.
+    pub operations: BTreeMap<String, OperationConfig>,
 }
 
 /// Device identity and TLS settings.
@@ -118,6 +125,240 @@ pub struct BridgeConfig {
     /// TLS transport control: on, off, or auto (default).
     #[serde(default)]
     pub tls: BridgeTls,
+    /// MQTT over WebSockets, e.g. when only outbound HTTPS is allowed.
+    #[serde(default)]
+    pub websocket: BridgeWebSocket,
+    /// Fallback cloud brokers, tried when the primary `url` cannot be reached.
+    #[serde(default)]
+    pub failover: BridgeFailover,
+    /// MQTT 5 connection to the cloud broker, rather than MQTT 3.1.1.
+    #[serde(default)]
+    pub mqtt5: BridgeMqtt5,
+}
+
+/// WebSocket transport settings for the cloud broker connection.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct BridgeWebSocket {
+    /// Whether the connection is tunneled over a WebSocket. Default: false.
+    #[serde(default)]
+    pub enable: bool,
+    /// HTTP path of the WebSocket endpoint. Default: `/mqtt`.
+    #[serde(default = "BridgeWebSocket::default_path")]
+    pub path: String,
+    /// Extra HTTP headers sent when opening the WebSocket, as `"name: value"` strings.
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+impl BridgeWebSocket {
+    fn default_path() -> String {
+        "/mqtt".to_owned()
+    }
+}
+
+impl Default for BridgeWebSocket {
+    fn default() -> Self {
+        BridgeWebSocket {
+            enable: false,
+            path: BridgeWebSocket::default_path(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Failover settings for the cloud broker connection.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct BridgeFailover {
+    /// Fallback broker URLs, in order of preference. Default: none.
+    #[serde(default)]
+    pub urls: Vec<HostPort<MQTT_TLS_PORT>>,
+    /// How the next broker is chosen: primary, round-robin or random. Default: primary.
+    #[serde(default = "BridgeFailover::default_strategy")]
+    pub strategy: FailoverStrategy,
+    /// Consecutive connection failures before trying the next broker. Default: 3.
+    #[serde(default = "BridgeFailover::default_max_failures")]
+    pub max_failures: u32,
+}
+
+impl BridgeFailover {
+    fn default_strategy() -> FailoverStrategy {
+        FailoverStrategy::Primary
+    }
+
+    fn default_max_failures() -> u32 {
+        3
+    }
+}
+
+impl Default for BridgeFailover {
+    fn default() -> Self {
+        BridgeFailover {
+            urls: Vec::new(),
+            strategy: BridgeFailover::default_strategy(),
+            max_failures: BridgeFailover::default_max_failures(),
+        }
+    }
+}
+
+/// MQTT 5 settings for the cloud broker connection.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct BridgeMqtt5 {
+    /// Whether the bridge connects to the cloud broker using MQTT 5. Default: false.
+    #[serde(default)]
+    pub enable: bool,
+    /// Maximum number of topic aliases used by the bridge, 0 to disable them. Default: 16.
+    #[serde(default = "BridgeMqtt5::default_topic_alias_max")]
+    pub topic_alias_max: u16,
+}
+
+impl BridgeMqtt5 {
+    fn default_topic_alias_max() -> u16 {
+        16
+    }
+}
+
+impl Default for BridgeMqtt5 {
+    fn default() -> Self {
+        BridgeMqtt5 {
+            enable: false,
+            topic_alias_max: BridgeMqtt5::default_topic_alias_max(),
+        }
+    }
+}
+
+/// HTTP upstream settings, for clouds receiving data over HTTP rather than MQTT.
+///
+/// The messages published by the flows on `{topic_prefix}/{path}` are posted to `{url}/{path}`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HttpUpstreamConfig {
+    /// Base URL of the HTTP endpoint, e.g. `https://ingest.example.com/api/v1`.
+    pub url: String,
+    /// Local topic prefix of the messages posted upstream. Default: the mapper name.
+    pub topic_prefix: Option<String>,
+    /// Authentication method: none, bearer, basic or certificate. Default: none.
+    #[serde(default)]
+    pub auth_method: HttpAuthMethod,
+    /// Path to the file containing the token used for bearer authentication.
+    pub token_path: Option<Utf8PathBuf>,
+    /// How messages are grouped into requests.
+    #[serde(default)]
+    pub batch: HttpBatch,
+    /// How failed requests are retried.
+    #[serde(default)]
+    pub retry: HttpRetry,
+}
+
+/// Authentication method for the HTTP upstream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpAuthMethod {
+    /// No authentication.
+    #[default]
+    None,
+    /// `Authorization: Bearer` header, with the token read from `http.token_path`.
+    Bearer,
+    /// `Authorization: Basic` header, with the username and password read from `credentials_path`.
+    Basic,
+    /// Mutual TLS, using the device certificate and private key.
+    Certificate,
+}
+
+/// Batching settings for the HTTP upstream.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HttpBatch {
+    /// Maximum number of messages posted in a single request. Default: 1 (no batching).
+    #[serde(default = "HttpBatch::default_max_size")]
+    pub max_size: usize,
+    /// Maximum delay before a partial batch is posted. Default: 1s.
+    #[serde(default = "HttpBatch::default_interval")]
+    pub interval: SecondsOrHumanTime,
+}
+
+impl HttpBatch {
+    fn default_max_size() -> usize {
+        1
+    }
+
+    fn default_interval() -> SecondsOrHumanTime {
+        "1s".parse().expect("valid duration")
+    }
+}
+
+impl Default for HttpBatch {
+    fn default() -> Self {
+        HttpBatch {
+            max_size: HttpBatch::default_max_size(),
+            interval: HttpBatch::default_interval(),
+        }
+    }
+}
+
+/// Retry settings for the HTTP upstream.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HttpRetry {
+    /// Delay between two attempts to post the buffered requests. Default: 10s.
+    #[serde(default = "HttpRetry::default_interval")]
+    pub interval: SecondsOrHumanTime,
+    /// Maximum number of requests buffered while the endpoint is unreachable,
+    /// the oldest being dropped first. Default: 1000.
+    #[serde(default = "HttpRetry::default_buffer_size")]
+    pub buffer_size: usize,
+}
+
+impl HttpRetry {
+    fn default_interval() -> SecondsOrHumanTime {
+        "10s".parse().expect("valid duration")
+    }
+
+    fn default_buffer_size() -> usize {
+        1000
+    }
+}
+
+impl Default for HttpRetry {
+    fn default() -> Self {
+        HttpRetry {
+            interval: HttpRetry::default_interval(),
+            buffer_size: HttpRetry::default_buffer_size(),
+        }
+    }
+}
+
+/// Mapping of cloud requests to a thin-edge command, and of the command status to cloud responses.
+///
+/// All the fields are `${...}` templates, as bridge rules. Besides the `${tedge.*}` and
+/// `${mapper.*}` config references, they can use `${request.*}` or `${command.*}` references,
+/// resolved against the request or the command being mapped.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct OperationConfig {
+    /// Local topic filter of the requests, as forwarded by the bridge rules.
+    pub topic: String,
+    /// Payload properties a request must have to be mapped to this command,
+    /// e.g. `{ method = "restart" }`. Default: none.
+    #[serde(default, rename = "match")]
+    pub matches: BTreeMap<String, String>,
+    /// Id correlating a request with its responses, e.g. `${request.topic.4}`.
+    pub id: String,
+    /// Entity topic id of the command target. Default: the main device.
+    pub entity: Option<String>,
+    /// Command payload, a JSON object to which the `init` status is added. Default: `{}`.
+    ///
+    /// The `${request.*}` values are JSON-encoded, strings being quoted and escaped.
+    pub payload: Option<String>,
+    /// Responses sent to the cloud, indexed by command status.
+    #[serde(default)]
+    pub status: BTreeMap<String, OperationResponseConfig>,
+}
+
+/// Cloud response sent when a command reaches a given status.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct OperationResponseConfig {
+    /// Local topic of the response, to be forwarded to the cloud by the bridge rules.
+    pub topic: String,
+    /// Response payload.
+    ///
+    /// The `${command.*}` values are JSON-encoded, strings being quoted and escaped.
+    pub payload: String,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -130,6 +371,9 @@ struct RawConfig {
     #[serde(default)]
     auth_method: AuthMethodConfig,
     credentials_path: Option<Utf8PathBuf>,
+    http: Option<HttpUpstreamConfig>,
+    #[serde(default)]
+    operations: BTreeMap<String, OperationConfig>,
 }
 
 /// Reads and parses `mapper.toml` from the given mapper directory.
@@ -169,6 +413,10 @@ pub async fn load_mapper_config(
     let credentials_path = raw
         .credentials_path
         .map(|p| resolve_relative(mapper_dir, p));
+    let http = raw.http.map(|mut h| {
+        h.token_path = h.token_path.map(|p| resolve_relative(mapper_dir, p));
+        h
+    });
 
     let config = CustomMapperConfig {
         table,
@@ -178,6 +426,8 @@ pub async fn load_mapper_config(
         bridge: raw.bridge,
         auth_method: raw.auth_method,
         credentials_path,
+        http,
+        operations: raw.operations,
     };
 
     Ok(Some(config))
@@ -282,6 +532,7 @@ pub async fn read_mapper_credentials(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
     use tedge_test_utils::fs::TempTedgeDir;
 
     #[tokio::test]
@@ -465,6 +716,38 @@ keepalive_interval = "60s"
         assert!(config.bridge.keepalive_interval.is_some());
     }
 
+    #[tokio::test]
+    async fn parses_http_upstream() {
+        let ttd = TempTedgeDir::new();
+        let mapper_dir = ttd.utf8_path().join("mappers/ingest");
+        tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+
+        tokio::fs::write(
+            mapper_dir.join("mapper.toml"),
+            r#"
+[http]
+url = "https://ingest.example.com/api/v1"
+auth_method = "bearer"
+token_path = "/etc/tedge/mappers/ingest/token"
+
+[http.batch]
+max_size = 100
+"#,
+        )
+        .await
+        .unwrap();
+
+        let config = load_mapper_config(&mapper_dir).await.unwrap().unwrap();
+        let http = config.http.unwrap();
+        assert_eq!(http.url, "https://ingest.example.com/api/v1");
+        assert_eq!(http.topic_prefix, None);
+        assert_eq!(http.auth_method, HttpAuthMethod::Bearer);
+        assert_eq!(http.batch.max_size, 100);
+        assert_eq!(http.batch.interval.duration(), Duration::from_secs(1));
+        assert_eq!(http.retry, HttpRetry::default());
+        assert!(config.url.is_none());
+    }
+
     #[tokio::test]
     async fn parses_password_auth_method() {
         let ttd = TempTedgeDir::new();
@@ -654,5 +937,24 @@ credentials_path = "/etc/tedge/mappers/pw/creds.toml"
                 mapper_dir.join("credentials.toml")
             );
         }
+
+        #[tokio::test]
+        async fn relative_http_token_path_is_resolved() {
+            let ttd = TempTedgeDir::new();
+            let mapper_dir = ttd.utf8_path().join("mappers/thingsboard");
+            tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+            tokio::fs::write(
+                mapper_dir.join("mapper.toml"),
+                "[http]\nurl = \"https://ingest.example.com\"\ntoken_path = \"token\"\n",
+            )
+            .await
+            .unwrap();
+
+            let config = load_mapper_config(&mapper_dir).await.unwrap().unwrap();
+            assert_eq!(
+                config.http.unwrap().token_path.unwrap(),
+                mapper_dir.join("token")
+            );
+        }
     }
 }
@@ -0,0 +1,475 @@
+//! HTTP upstream of a custom mapper
+//!
+//! When `mapper.toml` has an `[http]` section, the messages published by the mapper flows
+//! under `http.topic_prefix` are not sent over MQTT but posted to the `http.url` endpoint:
+//! a message published on `{topic_prefix}/{path}` is posted to `{url}/{path}`.
+//!
+//! Messages are grouped per path, in JSON arrays of up to `http.batch.max_size` messages.
+//! Requests failing with a network error, a server error or a rate limit are kept
+//! in a bounded buffer and retried every `http.retry.interval`.
+//! The status of the upstream is published as the health of the `tedge-mapper-bridge-{name}`
+//! service, as done by the MQTT bridge, e.g. `{"status":"down","queued":{"outbound":12}}`.
+use crate::core::retry_buffer::RetryBuffer;
+use crate::custom::config::read_mapper_credentials;
+use crate::custom::config::CustomMapperConfig;
+use crate::custom::config::HttpAuthMethod;
+use crate::custom::config::HttpUpstreamConfig;
+use crate::custom::resolve::resolve_effective_config;
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::prelude::*;
+use camino::Utf8Path;
+use mqtt_channel::QoS;
+use mqtt_channel::Topic;
+use mqtt_channel::TopicFilter;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tedge_actors::Actor;
+use tedge_actors::ClientMessageBox;
+use tedge_actors::MessageReceiver;
+use tedge_actors::MessageSink;
+use tedge_actors::NoConfig;
+use tedge_actors::Runtime;
+use tedge_actors::RuntimeError;
+use tedge_actors::Sender;
+use tedge_actors::SimpleMessageBox;
+use tedge_actors::SimpleMessageBoxBuilder;
+use tedge_config::tedge_toml::MqttAuthClientConfigCloudBroker;
+use tedge_config::tedge_toml::MqttAuthConfigCloudBroker;
+use tedge_config::tedge_toml::PrivateKeyType;
+use tedge_config::TEdgeConfig;
+use tedge_flows::FlowsMapperBuilder;
+use tedge_http_ext::HttpActor;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpRequestBuilder;
+use tedge_http_ext::HttpResult;
+use tedge_mqtt_ext::MqttActorBuilder;
+use tedge_mqtt_ext::MqttMessage;
+use tokio::time::Instant;
+use tracing::info;
+use tracing::warn;
+
+/// Spawns the actor posting the output of the mapper flows to the HTTP upstream, if configured
+///
+/// This must be called once the flows are connected to MQTT, and before the MQTT actor is spawned.
+pub async fn spawn_http_upstream(
+    runtime: &mut Runtime,
+    mapper_name: &str,
+    health_topic: Topic,
+    config: &CustomMapperConfig,
+    tedge_config: &TEdgeConfig,
+    flows_mapper: &mut FlowsMapperBuilder,
+    mqtt_actor: &mut MqttActorBuilder,
+) -> anyhow::Result<()> {
+    let Some(http_config) = &config.http else {
+        return Ok(());
+    };
+    let authorization =
+        authorization_header(http_config, config.credentials_path.as_deref()).await?;
+    let settings = HttpUpstreamSettings::try_new(http_config, mapper_name, health_topic)?;
+    info!(
+        "Messages published on {:?} are posted to {}",
+        settings.topics.patterns(),
+        settings.url
+    );
+
+    let tls_config = match http_config.auth_method {
+        HttpAuthMethod::Certificate => {
+            let effective = resolve_effective_config(config, tedge_config, None, None).await?;
+            let cert_path = effective
+                .cert_path
+                .map(|s| s.value)
+                .context("HTTP certificate authentication requires a device certificate")?;
+            let key_path = effective
+                .key_path
+                .map(|s| s.value)
+                .context("HTTP certificate authentication requires a device private key")?;
+            MqttAuthConfigCloudBroker {
+                ca_path: effective.root_cert_path.value,
+                client: Some(MqttAuthClientConfigCloudBroker {
+                    cert_file: cert_path,
+                    private_key: PrivateKeyType::File(key_path),
+                }),
+            }
+            .to_rustls_client_config()
+            .context("Failed to create HTTP TLS config")?
+        }
+        _ => tedge_config.cloud_client_tls_config(),
+    };
+    let mut http_actor = HttpActor::new(tls_config).builder();
+    let http = ClientMessageBox::new(&mut http_actor);
+
+    let mut messages = SimpleMessageBoxBuilder::new("HTTP upstream", 16);
+    flows_mapper.divert_output(settings.topics.clone(), &messages);
+    mqtt_actor.connect_source(NoConfig, &mut messages);
+    let upstream = HttpUpstreamActor {
+        buffer: RetryBuffer::new(settings.buffer_size),
+        settings,
+        authorization,
+        messages: messages.build(),
+        http,
+        pending: BTreeMap::new(),
+        flush_at: None,
+        retry_at: None,
+        up: true,
+        last_health: None,
+    };
+
+    runtime.spawn(http_actor).await?;
+    runtime.spawn(upstream).await?;
+    Ok(())
+}
+
+/// Builds the `Authorization` header value for the configured authentication method
+async fn authorization_header(
+    config: &HttpUpstreamConfig,
+    credentials_path: Option<&Utf8Path>,
+) -> anyhow::Result<Option<String>> {
+    match config.auth_method {
+        HttpAuthMethod::None | HttpAuthMethod::Certificate => Ok(None),
+        HttpAuthMethod::Bearer => {
+            let token_path = config
+                .token_path
+                .as_ref()
+                .context("http.auth_method = \"bearer\" requires http.token_path to be set")?;
+            let token = tokio::fs::read_to_string(token_path)
+                .await
+                .with_context(|| format!("Failed to read token file '{token_path}'"))?;
+            Ok(Some(format!("Bearer {}", token.trim())))
+        }
+        HttpAuthMethod::Basic => {
+            let credentials_path = credentials_path
+                .context("http.auth_method = \"basic\" requires credentials_path to be set")?;
+            let (username, password) = read_mapper_credentials(credentials_path).await?;
+            let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+            Ok(Some(format!("Basic {credentials}")))
+        }
+    }
+}
+
+/// The settings of the HTTP upstream, with topics resolved against the topic prefix
+#[derive(Debug)]
+struct HttpUpstreamSettings {
+    url: String,
+    topic_prefix: String,
+    topics: TopicFilter,
+    max_size: usize,
+    interval: Duration,
+    retry_interval: Duration,
+    buffer_size: usize,
+    health_topic: Topic,
+}
+
+impl HttpUpstreamSettings {
+    fn try_new(
+        config: &HttpUpstreamConfig,
+        mapper_name: &str,
+        health_topic: Topic,
+    ) -> anyhow::Result<Self> {
+        let topic_prefix = config
+            .topic_prefix
+            .as_deref()
+            .unwrap_or(mapper_name)
+            .trim_end_matches('/')
+            .to_string();
+        let topics = TopicFilter::new(&format!("{topic_prefix}/#"))
+            .with_context(|| format!("Invalid HTTP upstream topic prefix: {topic_prefix}"))?;
+        Ok(HttpUpstreamSettings {
+            url: config.url.trim_end_matches('/').to_string(),
+            topic_prefix,
+            topics,
+            max_size: config.batch.max_size.max(1),
+            interval: config.batch.interval.duration(),
+            retry_interval: config.retry.interval.duration(),
+            buffer_size: config.retry.buffer_size,
+            health_topic,
+        })
+    }
+
+    /// The path of a message relative to the topic prefix
+    fn path<'a>(&self, topic: &'a str) -> &'a str {
+        topic
+            .strip_prefix(&self.topic_prefix)
+            .unwrap_or(topic)
+            .trim_start_matches('/')
+    }
+
+    /// The URL to which the messages with the given path are posted
+    fn url(&self, path: &str) -> String {
+        if path.is_empty() {
+            self.url.clone()
+        } else {
+            format!("{}/{path}", self.url)
+        }
+    }
+}
+
+/// A request waiting to be posted upstream
+#[derive(Debug)]
+struct UpstreamRequest {
+    path: String,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+/// Posts the messages diverted from MQTT to the HTTP upstream
+struct HttpUpstreamActor {
+    settings: HttpUpstreamSettings,
+    authorization: Option<String>,
+    messages: SimpleMessageBox<MqttMessage, MqttMessage>,
+    http: ClientMessageBox<HttpRequest, HttpResult>,
+    /// Messages waiting to be batched, per path
+    pending: BTreeMap<String, Vec<Vec<u8>>>,
+    flush_at: Option<Instant>,
+    /// Requests waiting to be posted, the oldest first
+    buffer: RetryBuffer<UpstreamRequest>,
+    retry_at: Option<Instant>,
+    up: bool,
+    last_health: Option<String>,
+}
+
+#[async_trait]
+impl Actor for HttpUpstreamActor {
+    fn name(&self) -> &str {
+        "HTTP upstream"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        self.publish_health().await?;
+        loop {
+            let deadline = [self.flush_at, self.retry_at].into_iter().flatten().min();
+            let message = match deadline {
+                Some(deadline) => tokio::select! {
+                    () = tokio::time::sleep_until(deadline) => {
+                        self.on_deadline().await?;
+                        continue;
+                    }
+                    message = self.messages.recv() => message,
+                },
+                None => self.messages.recv().await,
+            };
+            let Some(message) = message else {
+                break;
+            };
+            self.push(message);
+            self.post_buffered().await?;
+        }
+        Ok(())
+    }
+}
+
+impl HttpUpstreamActor {
+    /// Adds a message to the batch of its path, queuing the batch when full
+    fn push(&mut self, message: MqttMessage) {
+        let path = self.settings.path(&message.topic.name).to_string();
+        let batch = self.pending.entry(path.clone()).or_default();
+        batch.push(message.payload_bytes().to_vec());
+        if batch.len() >= self.settings.max_size {
+            let batch = self.pending.remove(&path).unwrap_or_default();
+            self.enqueue(path, batch);
+        }
+        if self.pending.is_empty() {
+            self.flush_at = None;
+        } else if self.flush_at.is_none() {
+            self.flush_at = Some(Instant::now() + self.settings.interval);
+        }
+    }
+
+    async fn on_deadline(&mut self) -> Result<(), RuntimeError> {
+        let now = Instant::now();
+        if self.flush_at.is_some_and(|at| at <= now) {
+            self.flush_at = None;
+            for (path, batch) in std::mem::take(&mut self.pending) {
+                self.enqueue(path, batch);
+            }
+        }
+        if self.retry_at.is_some_and(|at| at <= now) {
+            self.retry_at = None;
+        }
+        self.post_buffered().await
+    }
+
+    /// Queues a batch, dropping the oldest request if the buffer is full
+    fn enqueue(&mut self, path: String, batch: Vec<Vec<u8>>) {
+        let (content_type, body) = encode_batch(self.settings.max_size, batch);
+        let request = UpstreamRequest {
+            path,
+            content_type,
+            body,
+        };
+        if let Some(dropped) = self.buffer.push(request) {
+            warn!(
+                "HTTP upstream buffer is full: dropping a request to {}",
+                self.settings.url(&dropped.path)
+            );
+        }
+    }
+
+    /// Posts the buffered requests in order, unless waiting to retry after a failure
+    async fn post_buffered(&mut self) -> Result<(), RuntimeError> {
+        if self.retry_at.is_some() || self.buffer.is_empty() {
+            return self.publish_health().await;
+        }
+        let settings = &self.settings;
+        let authorization = &self.authorization;
+        let failure = self
+            .buffer
+            .post_all(&mut self.http, |request| {
+                upstream_request(settings, authorization.as_deref(), request)
+            })
+            .await?;
+        match failure {
+            None => self.up = true,
+            Some(err) => {
+                warn!(
+                    "Failed to post to {}, retrying in {:?}: {err}",
+                    settings.url, settings.retry_interval
+                );
+                self.retry_at = Some(Instant::now() + settings.retry_interval);
+                self.up = false;
+            }
+        }
+        self.publish_health().await
+    }
+
+    /// Publishes the upstream health, if changed since last published
+    async fn publish_health(&mut self) -> Result<(), RuntimeError> {
+        let health = health_payload(self.up, self.buffer.len());
+        if self.last_health.as_ref() != Some(&health) {
+            let message = MqttMessage::new(&self.settings.health_topic, health.clone())
+                .with_qos(QoS::AtLeastOnce)
+                .with_retain();
+            self.messages.send(message).await?;
+            self.last_health = Some(health);
+        }
+        Ok(())
+    }
+}
+
+fn upstream_request(
+    settings: &HttpUpstreamSettings,
+    authorization: Option<&str>,
+    request: &UpstreamRequest,
+) -> Result<HttpRequest, tedge_http_ext::HttpError> {
+    let mut builder = HttpRequestBuilder::post(settings.url(&request.path))
+        .header("content-type", request.content_type)
+        .bytes(request.body.clone());
+    if let Some(authorization) = authorization {
+        builder = builder.header("authorization", authorization);
+    }
+    builder.build()
+}
+
+/// Encodes a batch of payloads as the body of a request
+///
+/// When batching is enabled, the payloads are sent as a JSON array, inlined when valid JSON.
+/// Otherwise, the payload is sent as is.
+fn encode_batch(max_size: usize, mut batch: Vec<Vec<u8>>) -> (&'static str, Vec<u8>) {
+    if max_size == 1 && batch.len() == 1 {
+        let payload = batch.remove(0);
+        let content_type = match serde_json::from_slice::<serde_json::Value>(&payload) {
+            Ok(_) => "application/json",
+            Err(_) => "text/plain",
+        };
+        return (content_type, payload);
+    }
+    let payloads: Vec<serde_json::Value> = batch
+        .iter()
+        .map(|payload| {
+            serde_json::from_slice(payload)
+                .unwrap_or_else(|_| String::from_utf8_lossy(payload).into())
+        })
+        .collect();
+    let body = serde_json::to_vec(&payloads).expect("JSON values are serializable");
+    ("application/json", body)
+}
+
+fn health_payload(up: bool, queued: usize) -> String {
+    let status = if up { "up" } else { "down" };
+    format!(r#"{{"status":"{status}","queued":{{"outbound":{queued}}}}}"#)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_are_posted_relative_to_the_topic_prefix() {
+        let settings = settings(r#"url = "https://ingest.example.com/api/""#);
+
+        assert!(settings.topics.accept_topic_name("tb/telemetry"));
+        assert!(!settings.topics.accept_topic_name("te/device/main///m/"));
+        assert_eq!(
+            settings.url(settings.path("tb/telemetry/device-1")),
+            "https://ingest.example.com/api/telemetry/device-1"
+        );
+        assert_eq!(
+            settings.url(settings.path("tb")),
+            "https://ingest.example.com/api"
+        );
+    }
+
+    #[test]
+    fn topic_prefix_can_be_overridden() {
+        let settings = settings(
+            r#"
+            url = "https://ingest.example.com"
+            topic_prefix = "upstream/http"
+            "#,
+        );
+
+        assert!(settings.topics.accept_topic_name("upstream/http/events"));
+        assert!(!settings.topics.accept_topic_name("tb/events"));
+        assert_eq!(
+            settings.url(settings.path("upstream/http/events")),
+            "https://ingest.example.com/events"
+        );
+    }
+
+    #[test]
+    fn single_messages_are_posted_as_is() {
+        let (content_type, body) = encode_batch(1, vec![br#"{"temperature":21}"#.to_vec()]);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, br#"{"temperature":21}"#);
+
+        let (content_type, body) = encode_batch(1, vec![b"21.5".to_vec()]);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, b"21.5");
+
+        let (content_type, body) = encode_batch(1, vec![b"not json".to_vec()]);
+        assert_eq!(content_type, "text/plain");
+        assert_eq!(body, b"not json");
+    }
+
+    #[test]
+    fn batches_are_posted_as_json_arrays() {
+        let batch = vec![br#"{"temperature":21}"#.to_vec(), b"not json".to_vec()];
+        let (content_type, body) = encode_batch(10, batch);
+
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!([{ "temperature": 21 }, "not json"])
+        );
+    }
+
+    #[test]
+    fn health_gives_the_number_of_queued_requests() {
+        assert_eq!(
+            health_payload(false, 12),
+            r#"{"status":"down","queued":{"outbound":12}}"#
+        );
+        assert_eq!(
+            health_payload(true, 0),
+            r#"{"status":"up","queued":{"outbound":0}}"#
+        );
+    }
+
+    fn settings(toml: &str) -> HttpUpstreamSettings {
+        let config: HttpUpstreamConfig = toml::from_str(toml).unwrap();
+        let health_topic =
+            Topic::new_unchecked("te/device/main/service/tedge-mapper-bridge-tb/status/health");
+        HttpUpstreamSettings::try_new(&config, "tb", health_topic).unwrap()
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! A user-defined mapper is started with `tedge-mapper <name>`. It reads its configuration
 //! from the mapper directory (`{config_dir}/mappers/{name}/`) and conditionally starts the
-//! built-in MQTT bridge and/or flows engine based on what files are present.
+//! built-in MQTT bridge or HTTP upstream, and the flows engine, based on what files are present.
 
 use crate::core::component::TEdgeComponent;
 use crate::core::mapper::start_basic_actors;
@@ -12,6 +12,8 @@ use crate::custom::config::read_mapper_credentials;
 use crate::custom::config::scan_mappers_shallow;
 use crate::custom::config::BridgeTlsEnable;
 use crate::custom::config::CustomMapperConfig;
+use crate::custom::http_upstream::spawn_http_upstream;
+use crate::custom::operations::spawn_operations;
 use crate::custom::resolve::resolve_effective_config;
 use crate::custom::resolve::EffectiveMapperConfig;
 use anyhow::bail;
@@ -22,6 +24,8 @@ use tedge_actors::MessageSink;
 use tedge_actors::Runtime;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_api::service_health_topic;
+use tedge_config::models::HostPort;
+use tedge_config::models::MQTT_TLS_PORT;
 use tedge_config::tedge_toml::MqttAuthClientConfigCloudBroker;
 use tedge_config::tedge_toml::MqttAuthConfigCloudBroker;
 use tedge_config::tedge_toml::PrivateKeyType;
@@ -33,8 +37,11 @@ use tedge_mqtt_bridge::config_toml::AuthMethod;
 use tedge_mqtt_bridge::load_bridge_rules_from_directory;
 use tedge_mqtt_bridge::rumqttc::Transport;
 use tedge_mqtt_bridge::use_credentials;
+use tedge_mqtt_bridge::CloudEndpoints;
+use tedge_mqtt_bridge::Mqtt5Options;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
 use tedge_mqtt_bridge::MqttOptions;
+use tedge_mqtt_bridge::WebSocketConfig;
 use tedge_utils::paths::ManagedDir;
 use tedge_utils::paths::TedgePaths;
 use tedge_watch_ext::WatchActorBuilder;
@@ -81,6 +88,10 @@ pub enum MapperStartup {
     /// is also always started; the `flows/` directory is created
     /// automatically on startup if it does not exist.
     WithBridge { config: Box<CustomMapperConfig> },
+    /// The mapper directory has no `bridge/` subdirectory, but its `mapper.toml`
+    /// has an `[http]` section. The flows output is posted to the HTTP upstream;
+    /// the flows engine is also always started.
+    WithHttp { config: Box<CustomMapperConfig> },
 }
 
 /// Validates the mapper directory and loads its configuration in one step.
@@ -88,9 +99,11 @@ pub enum MapperStartup {
 /// This is the single point of startup validation for a user-defined mapper.
 /// The validation sequence is:
 /// 1. The mapper directory must exist.
-/// 2. If `bridge/` is present, `mapper.toml` must exist and be valid.
+/// 2. If `bridge/` is present, `mapper.toml` must exist and be valid,
+///    and must not also configure an `[http]` upstream.
 ///
-/// If `bridge/` is absent, [`MapperStartup::FlowsOnly`] is returned — the
+/// If `bridge/` is absent, [`MapperStartup::WithHttp`] is returned when `mapper.toml`
+/// has an `[http]` section, and [`MapperStartup::FlowsOnly`] otherwise — the
 /// flows engine will always be started. The `flows/` directory is created
 /// automatically by [`build_flows_actors`] if it does not exist.
 ///
@@ -126,7 +139,12 @@ pub async fn validate_and_load(
     let has_bridge_dir = mapper_dir.join("bridge").is_dir();
 
     if !has_bridge_dir {
-        return Ok(MapperStartup::FlowsOnly);
+        return match load_mapper_config(mapper_dir).await? {
+            Some(config) if config.http.is_some() => Ok(MapperStartup::WithHttp {
+                config: Box::new(config),
+            }),
+            _ => Ok(MapperStartup::FlowsOnly),
+        };
     }
 
     // 2. bridge/ present — mapper.toml is required for connection settings.
@@ -137,6 +155,13 @@ pub async fn validate_and_load(
              Create a mapper.toml with a top-level 'url' field (e.g. url = \"host:8883\") to use the MQTT bridge."
         )
     })?;
+    if config.http.is_some() {
+        bail!(
+            "Mapper directory '{mapper_dir}' contains a 'bridge/' subdirectory and an [http] \
+             section in 'mapper.toml'. A mapper connects to the cloud either with the MQTT bridge \
+             or over HTTP: remove one of them."
+        );
+    }
 
     Ok(MapperStartup::WithBridge {
         config: Box::new(config),
@@ -171,13 +196,50 @@ pub async fn build_cloud_mqtt_options(
     let url = config.url.as_ref().map(|s| &s.value).with_context(|| {
         format!("'{mapper_dir}/mapper.toml' is missing a 'url' field required for the MQTT bridge")
     })?;
+    let cloud_config =
+        build_endpoint_mqtt_options(config, url, service_name, mapper_dir, tedge_config).await?;
 
-    let mut cloud_config = MqttOptions::new(service_name, url.host().to_string(), url.port().0);
-    cloud_config.set_clean_session(config.bridge.clean_session);
-    if let Some(interval) = &config.bridge.keepalive_interval {
-        cloud_config.set_keep_alive(interval.duration());
+    Ok((cloud_config, config.effective_auth.value))
+}
+
+/// Builds the [`CloudEndpoints`] of the bridge: the primary `url` followed by the
+/// `[bridge.failover]` urls, all sharing the same authentication and transport settings.
+pub async fn build_cloud_endpoints(
+    config: &EffectiveMapperConfig,
+    service_name: &str,
+    mapper_dir: &Utf8Path,
+    tedge_config: &TEdgeConfig,
+) -> anyhow::Result<(CloudEndpoints, AuthMethod)> {
+    let (primary, auth_method) =
+        build_cloud_mqtt_options(config, service_name, mapper_dir, tedge_config).await?;
+
+    let failover = &config.bridge.failover;
+    let mut endpoints = CloudEndpoints::new(primary);
+    for url in &failover.urls {
+        let endpoint =
+            build_endpoint_mqtt_options(config, url, service_name, mapper_dir, tedge_config)
+                .await?;
+        endpoints = endpoints.with_endpoint(endpoint);
+    }
+    let mut endpoints = endpoints
+        .with_strategy(failover.strategy)
+        .with_max_failures(failover.max_failures);
+    if config.bridge.mqtt5.enable {
+        endpoints = endpoints.with_mqtt5(Mqtt5Options {
+            topic_alias_max: config.bridge.mqtt5.topic_alias_max,
+        });
     }
 
+    Ok((endpoints, auth_method))
+}
+
+async fn build_endpoint_mqtt_options(
+    config: &EffectiveMapperConfig,
+    url: &HostPort<MQTT_TLS_PORT>,
+    service_name: &str,
+    mapper_dir: &Utf8Path,
+    tedge_config: &TEdgeConfig,
+) -> anyhow::Result<MqttOptions> {
     let tls_enabled = match config.bridge.tls.enable {
         BridgeTlsEnable::True => true,
         BridgeTlsEnable::False => false,
@@ -187,6 +249,29 @@ pub async fn build_cloud_mqtt_options(
         },
     };
 
+    let websocket = &config.bridge.websocket;
+    let websocket = match websocket.enable {
+        true => Some(
+            WebSocketConfig::try_new(&websocket.path, &websocket.headers).with_context(|| {
+                format!("Invalid [bridge.websocket] settings in '{mapper_dir}/mapper.toml'")
+            })?,
+        ),
+        false => None,
+    };
+
+    let host = url.host().to_string();
+    let mut cloud_config = match &websocket {
+        Some(websocket) => {
+            let (url, port) = websocket.broker_address(&host, url.port().0, tls_enabled);
+            MqttOptions::new(service_name, url, port)
+        }
+        None => MqttOptions::new(service_name, host, url.port().0),
+    };
+    cloud_config.set_clean_session(config.bridge.clean_session);
+    if let Some(interval) = &config.bridge.keepalive_interval {
+        cloud_config.set_keep_alive(interval.duration());
+    }
+
     let ca_path = &config.root_cert_path.value;
 
     if !tls_enabled && config.effective_auth.value == AuthMethod::Certificate {
@@ -263,9 +348,12 @@ pub async fn build_cloud_mqtt_options(
         })?;
     cloud_config.set_client_id(device_id);
 
+    if let Some(websocket) = &websocket {
+        websocket.apply(&mut cloud_config);
+    }
     configure_proxy(tedge_config, &mut cloud_config)?;
 
-    Ok((cloud_config, config.effective_auth.value))
+    Ok(cloud_config)
 }
 
 /// Constructs the flows-mapper builder and its supporting file-watch actors.
@@ -344,13 +432,9 @@ impl TEdgeComponent for CustomMapper {
                 service_health_topic(&mqtt_schema, &device_topic_id, &bridge_service_name);
 
             let effective = resolve_effective_config(config, &tedge_config, None, None).await?;
-            let (cloud_config, effective_auth) = build_cloud_mqtt_options(
-                &effective,
-                &service_name,
-                mapper_dir.path(),
-                &tedge_config,
-            )
-            .await?;
+            let (cloud_config, effective_auth) =
+                build_cloud_endpoints(&effective, &service_name, mapper_dir.path(), &tedge_config)
+                    .await?;
 
             let bridge_rules = load_bridge_rules_from_directory(
                 bridge_dir.path(),
@@ -371,7 +455,7 @@ impl TEdgeComponent for CustomMapper {
             )
             .await;
             runtime.spawn(bridge_actor).await?;
-        } else {
+        } else if matches!(startup, MapperStartup::FlowsOnly) {
             // Flows-only mode: clear any stale retained bridge health message
             // from a previous run that had a bridge configured.
             let clear_msg = bridge_health_clear_message(self, &tedge_config);
@@ -383,9 +467,46 @@ impl TEdgeComponent for CustomMapper {
         let (mut flows_mapper, mut fs_actor, mut cmd_watcher_actor) =
             build_flows_actors(&mapper_dir, &service_name, &tedge_config).await?;
         flows_mapper.connect(&mut mqtt_actor);
+        crate::spawn_flows_http_actor(&mut runtime, &tedge_config, &mut flows_mapper).await?;
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
 
+        if let MapperStartup::WithHttp { ref config } = startup {
+            let mqtt_schema = MqttSchema::with_root(tedge_config.mqtt.topic_root.clone());
+            let health_topic = service_health_topic(
+                &mqtt_schema,
+                &tedge_config.mqtt.device_topic_id,
+                &self.bridge_service_name(),
+            );
+            spawn_http_upstream(
+                &mut runtime,
+                &self.name,
+                health_topic,
+                config,
+                &tedge_config,
+                &mut flows_mapper,
+                &mut mqtt_actor,
+            )
+            .await?;
+        }
+
+        if let MapperStartup::WithBridge { ref config, .. }
+        | MapperStartup::WithHttp { ref config } = startup
+        {
+            if !config.operations.is_empty() {
+                let effective = resolve_effective_config(config, &tedge_config, None, None).await?;
+                spawn_operations(
+                    &mut runtime,
+                    &self.name,
+                    config,
+                    &effective,
+                    &tedge_config,
+                    &mut mqtt_actor,
+                )
+                .await?;
+            }
+        }
+
         runtime.spawn(flows_mapper).await?;
         runtime.spawn(fs_actor).await?;
         runtime.spawn(cmd_watcher_actor).await?;
@@ -577,6 +698,53 @@ mod tests {
             );
         }
 
+        /// [http] in mapper.toml, no bridge/ → WithHttp.
+        #[tokio::test]
+        async fn with_http_when_mapper_toml_has_http_section() {
+            let ttd = TempTedgeDir::new();
+            let config_dir = ttd.utf8_path();
+            let mapper_dir = config_dir.join("mappers/testmapper");
+            tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+            tokio::fs::write(
+                mapper_dir.join("mapper.toml"),
+                "[http]\nurl = \"https://ingest.example.com\"\n",
+            )
+            .await
+            .unwrap();
+
+            let startup = validate_and_load(&mapper_dir, config_dir).await.unwrap();
+            assert!(
+                matches!(startup, MapperStartup::WithHttp { .. }),
+                "Expected WithHttp {{ }}"
+            );
+        }
+
+        /// bridge/ + [http] in mapper.toml → error, as both would connect the mapper.
+        #[tokio::test]
+        async fn errors_when_both_bridge_and_http_are_configured() {
+            let ttd = TempTedgeDir::new();
+            let config_dir = ttd.utf8_path();
+            let mapper_dir = config_dir.join("mappers/testmapper");
+            tokio::fs::create_dir_all(mapper_dir.join("bridge"))
+                .await
+                .unwrap();
+            tokio::fs::write(
+                mapper_dir.join("mapper.toml"),
+                "url = \"mqtt.example.com\"\n[http]\nurl = \"https://ingest.example.com\"\n",
+            )
+            .await
+            .unwrap();
+
+            let err = validate_and_load(&mapper_dir, config_dir)
+                .await
+                .unwrap_err();
+            let msg = format!("{err}");
+            assert!(
+                msg.contains("bridge") && msg.contains("[http]"),
+                "Error should mention bridge/ and [http]: {msg}"
+            );
+        }
+
         /// bridge/ present but no mapper.toml → error mentioning both.
         #[tokio::test]
         async fn errors_when_bridge_without_mapper_toml() {
@@ -986,6 +1154,8 @@ AwEHoUQDQgAEdklRDw9+AAMRbpNMWJutKe4QO/tUlvrBR2swUYN9onxXdKNjJ/k3\n\
                 bridge: BridgeConfig::default(),
                 auth_method: AuthMethodConfig::Auto,
                 credentials_path: None,
+                http: None,
+                operations: Default::default(),
             }
         }
 
@@ -1199,5 +1369,142 @@ AwEHoUQDQgAEdklRDw9+AAMRbpNMWJutKe4QO/tUlvrBR2swUYN9onxXdKNjJ/k3\n\
                 );
             }
         }
+
+        mod websocket {
+            use super::*;
+
+            #[tokio::test]
+            async fn websocket_tunnels_tls_over_https_port() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let (cert, key) = write_cert(ttd.utf8_path()).await;
+                let tedge_config = TEdgeConfig::load_toml_str(&format!(
+                    "device.cert_path = \"{cert}\"\ndevice.key_path = \"{key}\"\n"
+                ));
+                let mut config = make_config(Some("mqtt.example.com:8883"));
+                config.bridge.websocket.enable = true;
+                config.bridge.websocket.headers = vec!["X-Site: plant-1".to_owned()];
+                let effective = resolve(&config, &tedge_config).await;
+
+                let (config, _) =
+                    build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                        .await
+                        .unwrap();
+
+                assert!(
+                    matches!(
+                        config.transport(),
+                        tedge_mqtt_bridge::rumqttc::Transport::Wss(..)
+                    ),
+                    "Transport should be secure WebSocket",
+                );
+                assert_eq!(
+                    config.broker_address(),
+                    ("wss://mqtt.example.com:443/mqtt".to_owned(), 443)
+                );
+            }
+
+            #[tokio::test]
+            async fn websocket_with_password_auth_keeps_explicit_port() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let creds_path = ttd.utf8_path().join("creds.toml");
+                write_creds(&creds_path).await;
+                let tedge_config = TEdgeConfig::load_toml_str("device.id = \"test-device\"");
+                let mut config = make_config(Some("mqtt.example.com:8443"));
+                config.bridge.websocket.enable = true;
+                config.bridge.websocket.path = "/ws".to_owned();
+                config.credentials_path = Some(creds_path);
+                let effective = resolve(&config, &tedge_config).await;
+
+                let (config, _) =
+                    build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                        .await
+                        .unwrap();
+
+                assert!(
+                    matches!(
+                        config.transport(),
+                        tedge_mqtt_bridge::rumqttc::Transport::Wss(..)
+                    ),
+                    "Transport should be secure WebSocket",
+                );
+                assert_eq!(
+                    config.broker_address(),
+                    ("wss://mqtt.example.com:8443/ws".to_owned(), 8443)
+                );
+            }
+
+            #[tokio::test]
+            async fn invalid_websocket_header_is_rejected() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let creds_path = ttd.utf8_path().join("creds.toml");
+                write_creds(&creds_path).await;
+                let tedge_config = TEdgeConfig::load_toml_str("device.id = \"test-device\"");
+                let mut config = make_config(Some("mqtt.example.com:443"));
+                config.bridge.websocket.enable = true;
+                config.bridge.websocket.headers = vec!["X-Site plant-1".to_owned()];
+                config.credentials_path = Some(creds_path);
+                let effective = resolve(&config, &tedge_config).await;
+
+                let err = build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                    .await
+                    .unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    format!("Invalid [bridge.websocket] settings in '{mapper_dir}/mapper.toml'")
+                );
+            }
+        }
+
+        mod failover {
+            use super::*;
+
+            #[tokio::test]
+            async fn failover_urls_are_appended_to_the_primary_endpoint() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let (cert, key) = write_cert(ttd.utf8_path()).await;
+                let tedge_config = TEdgeConfig::load_toml_str(&format!(
+                    "device.cert_path = \"{cert}\"\ndevice.key_path = \"{key}\"\n"
+                ));
+                let mut config = make_config(Some("eu-1.example.com"));
+                config.bridge.failover.urls = vec![
+                    "eu-2.example.com".try_into().unwrap(),
+                    "eu-3.example.com:8884".try_into().unwrap(),
+                ];
+                let effective = resolve(&config, &tedge_config).await;
+
+                let (endpoints, auth) =
+                    build_cloud_endpoints(&effective, "svc", &mapper_dir, &tedge_config)
+                        .await
+                        .unwrap();
+
+                assert_eq!(endpoints.len(), 3);
+                assert!(matches!(auth, AuthMethod::Certificate));
+            }
+
+            #[tokio::test]
+            async fn failover_url_without_tls_is_rejected_for_certificate_auth() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let (cert, key) = write_cert(ttd.utf8_path()).await;
+                let tedge_config = TEdgeConfig::load_toml_str(&format!(
+                    "device.cert_path = \"{cert}\"\ndevice.key_path = \"{key}\"\n"
+                ));
+                let mut config = make_config(Some("eu-1.example.com"));
+                config.bridge.failover.urls = vec!["eu-2.example.com:1883".try_into().unwrap()];
+                let effective = resolve(&config, &tedge_config).await;
+
+                let err = build_cloud_endpoints(&effective, "svc", &mapper_dir, &tedge_config)
+                    .await
+                    .unwrap_err();
+
+                assert!(err
+                    .to_string()
+                    .contains("certificate authentication requires TLS"));
+            }
+        }
     }
 }
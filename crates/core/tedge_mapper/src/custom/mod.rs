@@ -1,3 +1,5 @@
 pub mod config;
+pub mod http_upstream;
 pub mod mapper;
+pub mod operations;
 pub mod resolve;
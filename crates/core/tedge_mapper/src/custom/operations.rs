@@ -0,0 +1,577 @@
+//! Mapping of cloud requests to thin-edge commands
+//!
+//! With custom mappers, the cloud requests are received on arbitrary topics, as forwarded locally
+//! by the bridge rules. Each `[operations.<command>]` section of `mapper.toml` tells how
+//! the requests received on `topic` are mapped to a thin-edge `<command>`,
+//! and how the status transitions of this command are reported back to the cloud.
+//!
+//! - A request is mapped to a command on `te/{entity}/cmd/{command}/{mapper}-{id}`,
+//!   the `id` extracted from the request correlating the command with its responses.
+//! - Each time the command status changes, the response configured for the new status, if any,
+//!   is published, e.g. on `tb/rpc/response/${command.id}`.
+//! - Once `successful` or `failed`, the command is cleared.
+use crate::custom::config::CustomMapperConfig;
+use crate::custom::config::OperationConfig;
+use crate::custom::resolve::EffectiveMapperConfig;
+use anyhow::anyhow;
+use anyhow::Context;
+use async_trait::async_trait;
+use mqtt_channel::QoS;
+use mqtt_channel::Topic;
+use mqtt_channel::TopicFilter;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use tedge_actors::Actor;
+use tedge_actors::MessageReceiver;
+use tedge_actors::MessageSink;
+use tedge_actors::NoConfig;
+use tedge_actors::Runtime;
+use tedge_actors::RuntimeError;
+use tedge_actors::Sender;
+use tedge_actors::SimpleMessageBox;
+use tedge_actors::SimpleMessageBoxBuilder;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::ChannelFilter;
+use tedge_api::mqtt_topics::EntityFilter;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_config::TEdgeConfig;
+use tedge_mqtt_bridge::config_toml::expand_message_template;
+use tedge_mqtt_bridge::config_toml::expand_static_template;
+use tedge_mqtt_bridge::config_toml::MapperConfigLookup;
+use tedge_mqtt_ext::MqttActorBuilder;
+use tedge_mqtt_ext::MqttMessage;
+use tracing::warn;
+
+/// Spawns the actor mapping cloud requests to thin-edge commands, if any operation is configured
+pub async fn spawn_operations(
+    runtime: &mut Runtime,
+    mapper_name: &str,
+    config: &CustomMapperConfig,
+    effective: &EffectiveMapperConfig,
+    tedge_config: &TEdgeConfig,
+    mqtt_actor: &mut MqttActorBuilder,
+) -> anyhow::Result<()> {
+    if config.operations.is_empty() {
+        return Ok(());
+    }
+    let converter =
+        OperationsConverter::try_new(mapper_name, &config.operations, tedge_config, effective)?;
+
+    let mut messages = SimpleMessageBoxBuilder::new("Cloud operations", 16);
+    messages.connect_source(converter.subscriptions(), mqtt_actor);
+    mqtt_actor.connect_source(NoConfig, &mut messages);
+    let actor = OperationsActor {
+        converter,
+        messages: messages.build(),
+    };
+
+    runtime.spawn(actor).await?;
+    Ok(())
+}
+
+/// Maps cloud requests to thin-edge commands and command status updates to cloud responses
+struct OperationsActor {
+    converter: OperationsConverter,
+    messages: SimpleMessageBox<MqttMessage, MqttMessage>,
+}
+
+#[async_trait]
+impl Actor for OperationsActor {
+    fn name(&self) -> &str {
+        "Cloud operations"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        while let Some(message) = self.messages.recv().await {
+            for message in self.converter.convert(&message) {
+                self.messages.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A template which config references have been expanded, leaving only message references
+type Template = toml::Spanned<String>;
+
+fn template(src: String) -> Template {
+    toml::Spanned::new(0..src.len(), src)
+}
+
+/// An `[operations.<command>]` section of `mapper.toml`
+struct Operation {
+    command: String,
+    requests: TopicFilter,
+    matches: Vec<(String, String)>,
+    id: Template,
+    entity: Template,
+    payload: Template,
+    responses: BTreeMap<String, (Template, Template)>,
+}
+
+pub struct OperationsConverter {
+    mqtt_schema: MqttSchema,
+    /// The prefix of the ids of the commands created by this mapper, e.g. `tb-`
+    cmd_id_prefix: String,
+    operations: Vec<Operation>,
+    /// The last status of the commands created from cloud requests, indexed by command topic
+    statuses: HashMap<String, String>,
+}
+
+impl OperationsConverter {
+    pub fn try_new(
+        mapper_name: &str,
+        operations: &BTreeMap<String, OperationConfig>,
+        tedge_config: &TEdgeConfig,
+        mapper_config: &dyn MapperConfigLookup,
+    ) -> anyhow::Result<Self> {
+        let expand = |command: &str, field: &str, src: &str| {
+            expand_static_template(&template(src.to_owned()), tedge_config, None, mapper_config)
+                .map(template)
+                .map_err(|err| anyhow!("Invalid operations.{command}.{field}: {}", err.message))
+        };
+        let default_entity = tedge_config.mqtt.device_topic_id.to_string();
+
+        let mut compiled = Vec::new();
+        for (command, config) in operations {
+            let requests = expand(command, "topic", &config.topic)?;
+            let requests = TopicFilter::new(requests.get_ref())
+                .with_context(|| format!("Invalid operations.{command}.topic"))?;
+            let mut responses = BTreeMap::new();
+            for (status, response) in &config.status {
+                let field = format!("status.{status}");
+                responses.insert(
+                    status.clone(),
+                    (
+                        expand(command, &format!("{field}.topic"), &response.topic)?,
+                        expand(command, &format!("{field}.payload"), &response.payload)?,
+                    ),
+                );
+            }
+            compiled.push(Operation {
+                command: command.clone(),
+                requests,
+                matches: config
+                    .matches
+                    .iter()
+                    .map(|(path, value)| (path.clone(), value.clone()))
+                    .collect(),
+                id: expand(command, "id", &config.id)?,
+                entity: expand(
+                    command,
+                    "entity",
+                    config.entity.as_deref().unwrap_or(&default_entity),
+                )?,
+                payload: expand(
+                    command,
+                    "payload",
+                    config.payload.as_deref().unwrap_or("{}"),
+                )?,
+                responses,
+            });
+        }
+
+        Ok(OperationsConverter {
+            mqtt_schema: MqttSchema::with_root(tedge_config.mqtt.topic_root.clone()),
+            cmd_id_prefix: format!("{mapper_name}-"),
+            operations: compiled,
+            statuses: HashMap::new(),
+        })
+    }
+
+    /// The topics of the cloud requests and of the commands created for these requests
+    pub fn subscriptions(&self) -> TopicFilter {
+        let mut topics = TopicFilter::empty();
+        for operation in &self.operations {
+            topics.add_all(operation.requests.clone());
+            topics.add_all(self.mqtt_schema.topics(
+                EntityFilter::AnyEntity,
+                ChannelFilter::Command(operation.command.as_str().into()),
+            ));
+        }
+        topics
+    }
+
+    pub fn convert(&mut self, message: &MqttMessage) -> Vec<MqttMessage> {
+        if let Ok((_, Channel::Command { operation, cmd_id })) =
+            self.mqtt_schema.entity_channel_of(&message.topic.name)
+        {
+            return self.convert_status(message, &operation.to_string(), &cmd_id);
+        }
+
+        let topic = &message.topic.name;
+        let payload = message.payload_bytes();
+        let mut commands = Vec::new();
+        for operation in &self.operations {
+            if !operation.requests.accept_topic_name(topic) || !operation.accept(payload) {
+                continue;
+            }
+            match self.convert_request(operation, topic, payload) {
+                Ok(command) => commands.push(command),
+                Err(err) => warn!(
+                    "Ignoring request on {topic} for {} command: {err}",
+                    operation.command
+                ),
+            }
+        }
+        commands
+    }
+
+    fn convert_request(
+        &self,
+        operation: &Operation,
+        topic: &str,
+        payload: &[u8],
+    ) -> anyhow::Result<MqttMessage> {
+        let lookup = |path: &str| request_variable(path, topic, payload);
+        let id = expand(&operation.id, &lookup)?;
+        if id.is_empty() || id.contains(['/', '+', '#']) {
+            return Err(anyhow!("invalid request id {id:?}"));
+        }
+        let entity: EntityTopicId = expand(&operation.entity, &lookup)?
+            .parse()
+            .context("invalid entity")?;
+        let json_lookup = |path: &str| request_json_variable(path, topic, payload);
+        let mut command: Map<String, Value> =
+            serde_json::from_str(&expand(&operation.payload, &json_lookup)?)
+                .context("the command payload is not a JSON object")?;
+        command.insert("status".to_string(), "init".into());
+
+        let channel = Channel::Command {
+            operation: operation.command.as_str().into(),
+            cmd_id: format!("{}{id}", self.cmd_id_prefix),
+        };
+        let topic = self.mqtt_schema.topic_for(&entity, &channel);
+        Ok(MqttMessage::new(&topic, Value::Object(command).to_string())
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain())
+    }
+
+    fn convert_status(
+        &mut self,
+        message: &MqttMessage,
+        command: &str,
+        cmd_id: &str,
+    ) -> Vec<MqttMessage> {
+        let Some(operation) = self.operations.iter().find(|op| op.command == command) else {
+            return vec![];
+        };
+        let Some(id) = cmd_id.strip_prefix(&self.cmd_id_prefix) else {
+            return vec![];
+        };
+        let topic = &message.topic.name;
+        let payload = message.payload_bytes();
+        if payload.is_empty() {
+            self.statuses.remove(topic);
+            return vec![];
+        }
+        let Some(status) = json_property(payload, "status") else {
+            return vec![];
+        };
+        if self.statuses.get(topic) == Some(&status) {
+            return vec![];
+        }
+
+        let mut messages = Vec::new();
+        if let Some((response_topic, response_payload)) = operation.responses.get(&status) {
+            let lookup = |path: &str| command_variable(path, id, &status, payload);
+            let json_lookup = |path: &str| command_json_variable(path, id, &status, payload);
+            let response = expand(response_topic, &lookup).and_then(|response_topic| {
+                let response_topic = Topic::new(&response_topic)
+                    .with_context(|| format!("invalid response topic {response_topic:?}"))?;
+                let response_payload = expand(response_payload, &json_lookup)?;
+                Ok(MqttMessage::new(&response_topic, response_payload).with_qos(QoS::AtLeastOnce))
+            });
+            match response {
+                Ok(response) => messages.push(response),
+                Err(err) => warn!("Cannot respond to {status} {command} command {id}: {err}"),
+            }
+        }
+
+        if status == "successful" || status == "failed" {
+            self.statuses.remove(topic);
+            messages.push(
+                MqttMessage::new(&message.topic, "")
+                    .with_qos(QoS::AtLeastOnce)
+                    .with_retain(),
+            );
+        } else {
+            self.statuses.insert(topic.clone(), status);
+        }
+        messages
+    }
+}
+
+impl Operation {
+    /// Tells whether a request payload has all the properties required for this operation
+    fn accept(&self, payload: &[u8]) -> bool {
+        self.matches
+            .iter()
+            .all(|(path, expected)| json_property(payload, path).as_ref() == Some(expected))
+    }
+}
+
+fn expand(template: &Template, lookup: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    expand_message_template(template, lookup).map_err(|err| anyhow!(err.message))
+}
+
+/// Resolves a `${request.*}` reference
+///
+/// - `request.topic`: the request topic, `request.topic.<n>` being its n-th level, from 0
+/// - `request.payload`: the request payload, `request.payload.<path>` being a property of this JSON payload
+fn request_variable(path: &str, topic: &str, payload: &[u8]) -> Option<String> {
+    match path.strip_prefix("request.")? {
+        "topic" => Some(topic.to_string()),
+        "payload" => Some(String::from_utf8_lossy(payload).into_owned()),
+        path => match path.strip_prefix("topic.") {
+            Some(level) => topic
+                .split('/')
+                .nth(level.parse().ok()?)
+                .map(str::to_string),
+            None => json_property(payload, path.strip_prefix("payload.")?),
+        },
+    }
+}
+
+/// Resolves a `${request.*}` reference of a JSON template, the value being JSON-encoded
+///
+/// Strings are quoted and escaped, so `{"name": ${request.payload.name}}` is valid JSON whatever the name.
+/// A request payload that is not JSON is substituted as a string.
+fn request_json_variable(path: &str, topic: &str, payload: &[u8]) -> Option<String> {
+    match path.strip_prefix("request.payload") {
+        Some("") => json_payload(payload),
+        Some(path) => json_value(payload, path.strip_prefix('.')?).map(|value| value.to_string()),
+        None => request_variable(path, topic, payload).map(|value| Value::from(value).to_string()),
+    }
+}
+
+/// Resolves a `${command.*}` reference
+///
+/// - `command.id`: the id extracted from the request
+/// - `command.status`: the command status
+/// - `command.payload`: the command payload, `command.payload.<path>` being a property of this payload
+fn command_variable(path: &str, id: &str, status: &str, payload: &[u8]) -> Option<String> {
+    match path.strip_prefix("command.")? {
+        "id" => Some(id.to_string()),
+        "status" => Some(status.to_string()),
+        "payload" => Some(String::from_utf8_lossy(payload).into_owned()),
+        path => json_property(payload, path.strip_prefix("payload.")?),
+    }
+}
+
+/// Resolves a `${command.*}` reference of a JSON template, the value being JSON-encoded
+///
+/// As for `${request.*}` references, strings are quoted and escaped.
+fn command_json_variable(path: &str, id: &str, status: &str, payload: &[u8]) -> Option<String> {
+    match path.strip_prefix("command.payload") {
+        Some("") => json_payload(payload),
+        Some(path) => json_value(payload, path.strip_prefix('.')?).map(|value| value.to_string()),
+        None => {
+            command_variable(path, id, status, payload).map(|value| Value::from(value).to_string())
+        }
+    }
+}
+
+/// JSON-encodes a whole payload, inlined when valid JSON and as a string otherwise
+fn json_payload(payload: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<Value>(payload) {
+        Ok(value) => Some(value.to_string()),
+        Err(_) => Some(Value::from(String::from_utf8_lossy(payload)).to_string()),
+    }
+}
+
+/// Extracts the property at the given dotted path of a JSON payload
+///
+/// Strings are returned as is, other values being serialized as JSON.
+fn json_property(payload: &[u8], path: &str) -> Option<String> {
+    match json_value(payload, path)? {
+        Value::String(string) => Some(string),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Extracts the value at the given dotted path of a JSON payload
+fn json_value(payload: &[u8], path: &str) -> Option<Value> {
+    let mut value: Value = serde_json::from_slice(payload).ok()?;
+    for key in path.split('.') {
+        value = match value {
+            Value::Object(mut object) => object.remove(key)?,
+            Value::Array(mut array) => {
+                let index: usize = key.parse().ok()?;
+                (index < array.len()).then(|| array.swap_remove(index))?
+            }
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_mqtt_bridge::config_toml::TableMapperLookup;
+
+    const RESTART: &str = r#"
+        [restart]
+        topic = "${mapper.bridge.topic_prefix}/rpc/request/+"
+        match.method = "restart"
+        id = "${request.topic.3}"
+        payload = '{"delay": ${request.payload.params.delay}}'
+
+        [restart.status.executing]
+        topic = "tb/rpc/response/${command.id}"
+        payload = '{"status":${command.status}}'
+
+        [restart.status.failed]
+        topic = "tb/rpc/response/${command.id}"
+        payload = '{"error":${command.payload.reason}}'
+    "#;
+
+    #[test]
+    fn requests_are_mapped_to_commands() {
+        let mut converter = converter(RESTART);
+
+        let commands = converter.convert(&message(
+            "tb/rpc/request/42",
+            r#"{"method":"restart","params":{"delay":5}}"#,
+        ));
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].topic.name, "te/device/main///cmd/restart/tb-42");
+        assert!(commands[0].retain);
+        assert_eq!(
+            serde_json::from_slice::<Value>(commands[0].payload_bytes()).unwrap(),
+            json!({"status": "init", "delay": 5})
+        );
+    }
+
+    #[test]
+    fn request_values_are_json_encoded_in_the_command_payload() {
+        let mut converter = converter(
+            r#"
+            [set_name]
+            topic = "tb/rpc/request/+"
+            id = "${request.topic.3}"
+            payload = '{"name": ${request.payload.params.name}, "topic": ${request.topic}, "request": ${request.payload}}'
+            "#,
+        );
+
+        let request = r#"{"params":{"name":"say \"hi\", }"}}"#;
+        let commands = converter.convert(&message("tb/rpc/request/42", request));
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0].topic.name,
+            "te/device/main///cmd/set_name/tb-42"
+        );
+        assert_eq!(
+            serde_json::from_slice::<Value>(commands[0].payload_bytes()).unwrap(),
+            json!({
+                "status": "init",
+                "name": "say \"hi\", }",
+                "topic": "tb/rpc/request/42",
+                "request": {"params": {"name": "say \"hi\", }"}}
+            })
+        );
+    }
+
+    #[test]
+    fn requests_not_matching_the_operation_are_ignored() {
+        let mut converter = converter(RESTART);
+
+        let commands = converter.convert(&message(
+            "tb/rpc/request/42",
+            r#"{"method":"reboot","params":{"delay":5}}"#,
+        ));
+        assert!(commands.is_empty());
+
+        // The delay is required to build the command payload
+        let commands = converter.convert(&message("tb/rpc/request/42", r#"{"method":"restart"}"#));
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn status_transitions_are_mapped_to_responses() {
+        let mut converter = converter(RESTART);
+        let topic = "te/device/main///cmd/restart/tb-42";
+
+        let responses = converter.convert(&message(topic, r#"{"status":"executing"}"#));
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].topic.name, "tb/rpc/response/42");
+        assert_eq!(
+            responses[0].payload_str().unwrap(),
+            r#"{"status":"executing"}"#
+        );
+
+        // The same status is not reported twice
+        let responses = converter.convert(&message(topic, r#"{"status":"executing"}"#));
+        assert!(responses.is_empty());
+
+        // The command is cleared once completed
+        let responses = converter.convert(&message(
+            topic,
+            r#"{"status":"failed","reason":"not \"allowed\"\n"}"#,
+        ));
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].topic.name, "tb/rpc/response/42");
+        assert_eq!(
+            serde_json::from_slice::<Value>(responses[0].payload_bytes()).unwrap(),
+            json!({"error": "not \"allowed\"\n"})
+        );
+        assert_eq!(responses[1].topic.name, topic);
+        assert!(responses[1].payload_bytes().is_empty());
+        assert!(responses[1].retain);
+    }
+
+    #[test]
+    fn commands_not_created_by_the_mapper_are_ignored() {
+        let mut converter = converter(RESTART);
+
+        let responses = converter.convert(&message(
+            "te/device/main///cmd/restart/c8y-mapper-1234",
+            r#"{"status":"failed"}"#,
+        ));
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected_on_startup() {
+        let operations = toml::from_str(
+            r#"
+            [restart]
+            topic = "tb/rpc/request/+"
+            id = "${item}"
+            "#,
+        )
+        .unwrap();
+        let err = OperationsConverter::try_new(
+            "tb",
+            &operations,
+            &TEdgeConfig::load_toml_str(""),
+            &TableMapperLookup(toml::Table::new()),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("operations.restart.id"), "{err}");
+    }
+
+    fn converter(operations: &str) -> OperationsConverter {
+        let operations = toml::from_str(operations).unwrap();
+        let mapper_config = toml::from_str("bridge.topic_prefix = \"tb\"").unwrap();
+        OperationsConverter::try_new(
+            "tb",
+            &operations,
+            &TEdgeConfig::load_toml_str(""),
+            &TableMapperLookup(mapper_config),
+        )
+        .unwrap()
+    }
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage::new(&Topic::new_unchecked(topic), payload)
+    }
+}
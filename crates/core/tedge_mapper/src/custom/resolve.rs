@@ -28,8 +28,14 @@ use tedge_mqtt_bridge::config_toml::WalkResult;
 
 use crate::custom::config::AuthMethodConfig;
 use crate::custom::config::BridgeConfig;
+use crate::custom::config::BridgeFailover;
+use crate::custom::config::BridgeMqtt5;
 use crate::custom::config::BridgeTls;
+use crate::custom::config::BridgeWebSocket;
 use crate::custom::config::CustomMapperConfig;
+use crate::custom::config::HttpAuthMethod;
+use crate::custom::config::HttpBatch;
+use crate::custom::config::HttpRetry;
 
 /// Tracks the origin of a resolved configuration value.
 #[derive(Debug, Clone)]
@@ -450,8 +456,8 @@ impl MapperParams for EffectiveMapperConfig {
 ///
 /// All optional fields serialise to `null` (via `Option<T>`) rather than being omitted,
 /// so callers can distinguish [`ConfigGetResult::NotSet`] from [`ConfigGetResult::UnknownKey`].
-/// The `device` section is always present (never `null` at the top level) so that sub-keys
-/// such as `device.cert_path` are always reachable in the serialised schema.
+/// The `device` and `http` sections are always present (never `null` at the top level) so that
+/// sub-keys such as `device.cert_path` are always reachable in the serialised schema.
 #[derive(serde::Serialize)]
 struct CustomMapperSchema<'a> {
     url: Option<&'a HostPort<MQTT_TLS_PORT>>,
@@ -459,6 +465,7 @@ struct CustomMapperSchema<'a> {
     bridge: BridgeSchema<'a>,
     auth_method: AuthMethodConfig,
     credentials_path: Option<&'a Utf8PathBuf>,
+    http: HttpSchema<'a>,
 }
 
 #[derive(serde::Serialize)]
@@ -474,6 +481,19 @@ struct BridgeSchema<'a> {
     clean_session: bool,
     keepalive_interval: Option<&'a SecondsOrHumanTime>,
     tls: BridgeTls,
+    websocket: &'a BridgeWebSocket,
+    failover: &'a BridgeFailover,
+    mqtt5: &'a BridgeMqtt5,
+}
+
+#[derive(serde::Serialize)]
+struct HttpSchema<'a> {
+    url: Option<&'a str>,
+    topic_prefix: Option<&'a str>,
+    auth_method: HttpAuthMethod,
+    token_path: Option<&'a Utf8Path>,
+    batch: HttpBatch,
+    retry: HttpRetry,
 }
 
 /// Returns all known schema-level key paths for a custom mapper config (e.g. `"device.cert_path"`).
@@ -489,6 +509,8 @@ pub fn custom_mapper_schema_keys() -> Vec<String> {
         bridge: BridgeConfig::default(),
         auth_method: AuthMethodConfig::Auto,
         credentials_path: None,
+        http: None,
+        operations: Default::default(),
     });
     collect_schema_leaf_keys(&schema, String::new())
 }
@@ -522,6 +544,7 @@ fn collect_schema_leaf_keys(value: &serde_json::Value, prefix: String) -> Vec<St
 /// section was set in `mapper.toml`.
 fn build_custom_mapper_schema(config: &CustomMapperConfig) -> serde_json::Value {
     let d = config.device.as_ref();
+    let h = config.http.as_ref();
     serde_json::to_value(CustomMapperSchema {
         url: config.url.as_ref(),
         device: DeviceSchema {
@@ -534,9 +557,20 @@ fn build_custom_mapper_schema(config: &CustomMapperConfig) -> serde_json::Value
             clean_session: config.bridge.clean_session,
             keepalive_interval: config.bridge.keepalive_interval.as_ref(),
             tls: config.bridge.tls,
+            websocket: &config.bridge.websocket,
+            failover: &config.bridge.failover,
+            mqtt5: &config.bridge.mqtt5,
         },
         auth_method: config.auth_method,
         credentials_path: config.credentials_path.as_ref(),
+        http: HttpSchema {
+            url: h.map(|h| h.url.as_str()),
+            topic_prefix: h.and_then(|h| h.topic_prefix.as_deref()),
+            auth_method: h.map(|h| h.auth_method).unwrap_or_default(),
+            token_path: h.and_then(|h| h.token_path.as_deref()),
+            batch: h.map(|h| h.batch.clone()).unwrap_or_default(),
+            retry: h.map(|h| h.retry.clone()).unwrap_or_default(),
+        },
     })
     .expect("schema serialisation is infallible")
 }
@@ -905,6 +939,8 @@ AwEHoUQDQgAEdklRDw9+AAMRbpNMWJutKe4QO/tUlvrBR2swUYN9onxXdKNjJ/k3\n\
             bridge: BridgeConfig::default(),
             auth_method: AuthMethodConfig::Auto,
             credentials_path: None,
+            http: None,
+            operations: Default::default(),
         }
     }
 
@@ -7,6 +7,8 @@ use crate::c8y::mapper::CumulocityMapper;
 use crate::collectd::mapper::CollectdMapper;
 use crate::core::component::TEdgeComponent;
 use crate::custom::mapper::CustomMapper;
+#[cfg(feature = "sparkplug")]
+use crate::sparkplug::mapper::SparkplugMapper;
 use anyhow::bail;
 use anyhow::Context;
 use camino::Utf8Path;
@@ -26,8 +28,10 @@ use tedge_config::TEdgeConfig;
 use tedge_flows::BaseFlowRegistry;
 use tedge_flows::ConnectedFlowRegistry;
 use tedge_flows::FlowRegistryExt;
+use tedge_flows::FlowsMapperBuilder;
 use tedge_flows::FlowsMapperConfig;
 use tedge_flows::UpdateFlowRegistryError;
+use tedge_http_ext::HttpActor;
 use tedge_signal_ext::SignalActor;
 use tedge_utils::paths::ManagedDir;
 use tedge_utils::paths::TedgePaths;
@@ -73,6 +77,8 @@ pub mod c8y;
 mod collectd;
 mod core;
 mod custom;
+#[cfg(feature = "sparkplug")]
+pub mod sparkplug;
 use crate::custom_mapper_resolve::EffectiveMapperConfig;
 /// Re-export mapper directory warnings for use by CLI commands.
 pub use core::mappers_dir::warn_misconfigured_mapper_dirs;
@@ -115,6 +121,8 @@ fn lookup_component(component_name: MapperName) -> anyhow::Result<Box<dyn TEdgeC
         MapperName::C8y { profile } => Box::new(CumulocityMapper {
             profile: read_and_set_var!(profile, "TEDGE_CLOUD_PROFILE"),
         }),
+        #[cfg(feature = "sparkplug")]
+        MapperName::Sparkplug => Box::new(SparkplugMapper),
         MapperName::UserDefined(mut args) => {
             let name = args.remove(0);
             validate_mapper_name(&name)?;
@@ -174,6 +182,9 @@ pub enum MapperName {
         profile: Option<ProfileName>,
     },
     Collectd,
+    /// Publish the device and its child devices as a Sparkplug B edge node and devices
+    #[cfg(feature = "sparkplug")]
+    Sparkplug,
     /// Run a user-defined mapper from `/etc/tedge/mappers/{name}/`.
     ///
     /// The mapper name must match `[a-z][a-z0-9-]*`.
@@ -203,6 +214,8 @@ impl fmt::Display for MapperName {
                 profile: Some(profile),
             } => write!(f, "tedge-mapper-c8y@{profile}"),
             MapperName::Collectd => write!(f, "tedge-mapper-collectd"),
+            #[cfg(feature = "sparkplug")]
+            MapperName::Sparkplug => write!(f, "tedge-mapper-sparkplug"),
             MapperName::UserDefined(args) => write!(
                 f,
                 "tedge-mapper-{}",
@@ -222,6 +235,8 @@ impl MapperName {
             #[cfg(feature = "c8y")]
             MapperName::C8y { .. } => "tedge-mapper-c8y",
             MapperName::Collectd => "tedge-mapper-collectd",
+            #[cfg(feature = "sparkplug")]
+            MapperName::Sparkplug => "tedge-mapper-sparkplug",
             MapperName::UserDefined(_) => "tedge-mapper",
         }
     }
@@ -338,6 +353,18 @@ pub(crate) async fn mapper_flow_registry(
     Ok(flows)
 }
 
+/// Spawns the HTTP actor used by the flows posting their output over HTTP
+pub(crate) async fn spawn_flows_http_actor(
+    runtime: &mut Runtime,
+    tedge_config: &TEdgeConfig,
+    flows_mapper: &mut FlowsMapperBuilder,
+) -> anyhow::Result<()> {
+    let mut http_actor = HttpActor::new(tedge_config.cloud_client_tls_config()).builder();
+    flows_mapper.connect_http(&mut http_actor);
+    runtime.spawn(http_actor).await?;
+    Ok(())
+}
+
 pub async fn test_cli_flow_registry(
     tedge_config: &TEdgeConfig,
     mapper_dir: impl AsRef<Utf8Path>,
@@ -0,0 +1,293 @@
+//! Sparkplug B mapper component.
+//!
+//! `tedge-mapper sparkplug` publishes the thin-edge device as a Sparkplug edge node,
+//! and its child devices as Sparkplug devices. The Sparkplug broker connection is configured
+//! in `{config_dir}/mappers/sparkplug/mapper.toml`, as for a user-defined mapper,
+//! along with a `[sparkplug]` section giving the Sparkplug group and edge node ids.
+
+use crate::core::component::TEdgeComponent;
+use crate::core::mapper::start_basic_actors;
+use crate::custom::config::load_mapper_config;
+use crate::custom::config::CustomMapperConfig;
+use crate::custom::mapper::build_cloud_endpoints;
+use crate::custom::resolve::resolve_effective_config;
+use anyhow::bail;
+use anyhow::Context;
+use async_trait::async_trait;
+use camino::Utf8Path;
+use sparkplug_mapper_ext::BdSeq;
+use sparkplug_mapper_ext::SparkplugActorBuilder;
+use sparkplug_mapper_ext::SparkplugConfig;
+use sparkplug_mapper_ext::SparkplugConverter;
+use tedge_actors::Runtime;
+use tedge_api::entity_store::EntityRegistrationMessage;
+use tedge_api::entity_store::EntityStore;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::service_health_topic;
+use tedge_config::TEdgeConfig;
+use tedge_mqtt_bridge::load_bridge_rules_from_directory;
+use tedge_mqtt_bridge::persist_bridge_config_file;
+use tedge_mqtt_bridge::rumqttc::LastWill;
+use tedge_mqtt_bridge::rumqttc::QoS;
+use tedge_mqtt_bridge::MqttBridgeActorBuilder;
+use tedge_utils::paths::ManagedDir;
+use tedge_utils::paths::TedgePaths;
+use tracing::info;
+use tracing::warn;
+
+const SPARKPLUG_MAPPER_NAME: &str = "tedge-mapper-sparkplug";
+const SPARKPLUG_BRIDGE_NAME: &str = "tedge-mapper-bridge-sparkplug";
+const DEFAULT_TOPIC_PREFIX: &str = "sp";
+
+pub struct SparkplugMapper;
+
+impl SparkplugMapper {
+    /// Returns the mapper directory path.
+    pub fn mapper_dir(&self, config_dir: &TedgePaths) -> ManagedDir {
+        crate::mapper_dir(config_dir, "sparkplug", None::<&str>)
+    }
+}
+
+#[async_trait]
+impl TEdgeComponent for SparkplugMapper {
+    async fn build(
+        &self,
+        tedge_config: TEdgeConfig,
+        config_dir: &TedgePaths,
+    ) -> Result<Runtime, anyhow::Error> {
+        let mapper_dir = self.mapper_dir(config_dir);
+        let Some(config) = load_mapper_config(mapper_dir.path()).await? else {
+            bail!(
+                "The Sparkplug mapper is not configured: {}/mapper.toml not found",
+                mapper_dir.path()
+            );
+        };
+
+        let (mut runtime, mut mqtt_actor) =
+            start_basic_actors(SPARKPLUG_MAPPER_NAME, &tedge_config).await?;
+        let mqtt_schema = MqttSchema::with_root(tedge_config.mqtt.topic_root.clone());
+
+        // The edge node id defaults to the device id, which is only known once resolved
+        let effective = resolve_effective_config(&config, &tedge_config, None, None).await?;
+        let device_id = effective.device_id.as_ref().map(|id| id.value.as_str());
+        let sparkplug_config = sparkplug_config(&config, device_id)?;
+        let effective = resolve_effective_config(
+            &config,
+            &tedge_config,
+            Some(&overlay(&sparkplug_config)),
+            None,
+        )
+        .await?
+        .with_mapper_name("sparkplug");
+
+        let (cloud_config, auth_method) = build_cloud_endpoints(
+            &effective,
+            SPARKPLUG_MAPPER_NAME,
+            mapper_dir.path(),
+            &tedge_config,
+        )
+        .await?;
+        let bridge_dir = mapper_dir.path().join("bridge");
+        persist_bridge_config_file(
+            &bridge_dir,
+            "rules",
+            include_str!("bridge/rules.toml"),
+            &tedge_config,
+        )
+        .await?;
+        let rules = load_bridge_rules_from_directory(
+            &bridge_dir,
+            &tedge_config,
+            auth_method,
+            None,
+            &effective,
+        )
+        .await?;
+
+        let state_dir = tedge_config.data.path.join("sparkplug");
+        let bd_seq = BdSeq::new(last_bd_seq(&state_dir).await);
+        let entity_store = EntityStore::with_main_device(
+            mqtt_schema.clone(),
+            EntityRegistrationMessage::main_device(None),
+            0,
+            &state_dir,
+            true,
+        )?;
+        let health_topic = service_health_topic(
+            &mqtt_schema,
+            &tedge_config.mqtt.device_topic_id,
+            SPARKPLUG_BRIDGE_NAME,
+        );
+        let converter = SparkplugConverter::new(
+            sparkplug_config.clone(),
+            mqtt_schema,
+            entity_store,
+            health_topic.clone(),
+            bd_seq.clone(),
+        );
+
+        // The node death certificate is published by the Sparkplug broker when the connection is lost.
+        // Each connection uses a new bdSeq, so the death certificate is built afresh on each attempt.
+        tokio::fs::create_dir_all(&state_dir)
+            .await
+            .with_context(|| format!("Failed to create {state_dir}"))?;
+        let cloud_config = cloud_config.with_last_will_provider(move || {
+            let bd_seq = bd_seq.next();
+            persist_bd_seq(&state_dir, bd_seq);
+            let (will_topic, will_payload) = sparkplug_config.node_death_certificate(bd_seq);
+            LastWill::new(will_topic, will_payload, QoS::AtLeastOnce, false)
+        });
+
+        let bridge_actor = MqttBridgeActorBuilder::new(
+            &tedge_config,
+            SPARKPLUG_BRIDGE_NAME,
+            &health_topic,
+            rules,
+            cloud_config,
+            None,
+        )
+        .await;
+        let sparkplug_actor = SparkplugActorBuilder::new(converter, &mut mqtt_actor);
+
+        runtime.spawn(bridge_actor).await?;
+        runtime.spawn(sparkplug_actor).await?;
+        runtime.spawn(mqtt_actor).await?;
+
+        Ok(runtime)
+    }
+}
+
+/// Reads the `[sparkplug]` section of `mapper.toml`, and the local topic prefix of the bridge
+fn sparkplug_config(
+    config: &CustomMapperConfig,
+    device_id: Option<&str>,
+) -> anyhow::Result<SparkplugConfig> {
+    let lookup = |path: &[&str]| -> anyhow::Result<Option<String>> {
+        let mut value = None;
+        let mut table = Some(&config.table);
+        for key in path {
+            value = table.and_then(|table| table.get(*key));
+            table = value.and_then(|value| value.as_table());
+        }
+        match value {
+            None => Ok(None),
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => bail!("{} must be a string", path.join(".")),
+        }
+    };
+
+    let topic_prefix =
+        lookup(&["bridge", "topic_prefix"])?.unwrap_or_else(|| DEFAULT_TOPIC_PREFIX.to_string());
+    let Some(group_id) = lookup(&["sparkplug", "group_id"])? else {
+        bail!("sparkplug.group_id must be set in the Sparkplug mapper.toml");
+    };
+    let edge_node_id = match lookup(&["sparkplug", "edge_node_id"])? {
+        Some(edge_node_id) => edge_node_id,
+        None => device_id
+            .context("sparkplug.edge_node_id must be set, the device id being unknown")?
+            .to_string(),
+    };
+    for (key, id) in [
+        ("sparkplug.group_id", &group_id),
+        ("sparkplug.edge_node_id", &edge_node_id),
+    ] {
+        if id.is_empty() || id.contains(['/', '+', '#']) {
+            bail!("Invalid {key} {id:?}: Sparkplug ids must be non-empty and cannot contain '/', '+' or '#'");
+        }
+    }
+
+    Ok(SparkplugConfig {
+        topic_prefix,
+        group_id,
+        edge_node_id,
+    })
+}
+
+/// The resolved Sparkplug settings, made available to the bridge rules as `${mapper.*}`
+fn overlay(config: &SparkplugConfig) -> toml::Table {
+    let mut bridge = toml::Table::new();
+    bridge.insert("topic_prefix".into(), config.topic_prefix.clone().into());
+    let mut sparkplug = toml::Table::new();
+    sparkplug.insert("group_id".into(), config.group_id.clone().into());
+    sparkplug.insert("edge_node_id".into(), config.edge_node_id.clone().into());
+
+    let mut overlay = toml::Table::new();
+    overlay.insert("bridge".into(), bridge.into());
+    overlay.insert("sparkplug".into(), sparkplug.into());
+    overlay
+}
+
+/// Reads the birth/death sequence number of the previous connection, if any
+async fn last_bd_seq(state_dir: &Utf8Path) -> Option<u64> {
+    let content = tokio::fs::read_to_string(state_dir.join("bdseq"))
+        .await
+        .ok()?;
+    content.trim().parse::<u64>().ok()
+}
+
+/// Persists the birth/death sequence number of a new connection
+///
+/// This is done before connecting, the sequence being resumed from this value after a restart.
+fn persist_bd_seq(state_dir: &Utf8Path, bd_seq: u64) {
+    let path = state_dir.join("bdseq");
+    if let Err(err) = std::fs::write(&path, bd_seq.to_string()) {
+        warn!("Failed to persist the Sparkplug bdSeq to {path}: {err}");
+    }
+    info!("Sparkplug bdSeq: {bd_seq}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    #[tokio::test]
+    async fn bd_seq_is_resumed_from_the_persisted_value() {
+        let ttd = TempTedgeDir::new();
+        let state_dir: &Utf8Path = ttd.utf8_path();
+        assert_eq!(last_bd_seq(state_dir).await, None);
+
+        let bd_seq = BdSeq::new(last_bd_seq(state_dir).await);
+        persist_bd_seq(state_dir, bd_seq.next());
+        persist_bd_seq(state_dir, bd_seq.next());
+        assert_eq!(last_bd_seq(state_dir).await, Some(1));
+
+        let bd_seq = BdSeq::new(last_bd_seq(state_dir).await);
+        assert_eq!(bd_seq.next(), 2);
+    }
+
+    #[test]
+    fn edge_node_id_defaults_to_the_device_id() {
+        let config = mapper_config("[sparkplug]\ngroup_id = \"plant\"\n");
+
+        let sparkplug = sparkplug_config(&config, Some("edge-1")).unwrap();
+
+        assert_eq!(sparkplug.group_id, "plant");
+        assert_eq!(sparkplug.edge_node_id, "edge-1");
+        assert_eq!(sparkplug.topic_prefix, "sp");
+    }
+
+    #[test]
+    fn sparkplug_ids_are_validated() {
+        let config = mapper_config("[sparkplug]\ngroup_id = \"plant/1\"\n");
+        assert!(sparkplug_config(&config, Some("edge-1")).is_err());
+
+        let config = mapper_config("url = \"broker.example.com:8883\"\n");
+        assert!(sparkplug_config(&config, Some("edge-1")).is_err());
+    }
+
+    fn mapper_config(toml: &str) -> CustomMapperConfig {
+        let table: toml::Table = toml::from_str(toml).unwrap();
+        CustomMapperConfig {
+            table,
+            cloud_type: None,
+            url: None,
+            device: None,
+            bridge: crate::custom::config::BridgeConfig::default(),
+            auth_method: crate::custom::config::AuthMethodConfig::Auto,
+            credentials_path: None,
+            http: None,
+            operations: Default::default(),
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Execution of AWS IoT Jobs as thin-edge commands
+//!
+//! - The next pending job of a thing is received on `$aws/things/{thing}/jobs/notify-next`,
+//!   or requested on `$aws/things/{thing}/jobs/$next/get` on startup and when a child device is registered.
+//! - The `operation` of the job document tells which command is created, on `te/{entity}/cmd/{command}/aws-job-{jobId}`,
+//!   the other properties of the job document being used as the command payload.
+//! - The progress of the command is reported on `$aws/things/{thing}/jobs/{jobId}/update`:
+//!   `IN_PROGRESS` while the command is executed, then `SUCCEEDED` or `FAILED`.
+//!
+//! The thing of the main device is the device thing, child devices having their own things,
+//! named after the main device and the child device, e.g. `{device-id}:device:child1`.
+use crate::entity_topic_from_source_name;
+use crate::normalize_source_name;
+use crate::retained;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::SystemTime;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+const COMMAND_ID_PREFIX: &str = "aws-job-";
+
+/// AWS limits the status details of a job execution to 10 entries
+const MAX_STATUS_DETAILS: usize = 10;
+
+#[derive(Clone)]
+pub struct AwsJobs {
+    /// When disabled, all messages are ignored
+    enable: bool,
+
+    /// The bridge topic prefix, e.g. `aws`
+    prefix: String,
+
+    /// The thing name of the main device
+    thing: String,
+
+    /// The topic prefix of the main device, e.g. `te/device/main//`
+    device: String,
+
+    /// The command associated to each supported job operation
+    operations: HashMap<String, String>,
+
+    /// The commands created for jobs, with the last command status reported to AWS
+    commands: HashMap<String, String>,
+
+    /// The child devices for which the pending jobs have been requested
+    children: HashSet<String>,
+}
+
+impl Default for AwsJobs {
+    fn default() -> Self {
+        AwsJobs {
+            enable: true,
+            prefix: "aws".to_string(),
+            thing: String::new(),
+            device: "te/device/main//".to_string(),
+            operations: HashMap::new(),
+            commands: HashMap::new(),
+            children: HashSet::new(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AwsJobs {
+    fn name(&self) -> &str {
+        "aws-jobs"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(enable) = config.bool_property("enable") {
+            self.enable = enable;
+        }
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(thing) = config.string_property("thing") {
+            self.thing = thing.to_owned();
+        }
+        if let Some(device) = config.string_property("device") {
+            self.device = device.to_owned();
+        }
+        if let Some(operations) = config.strings_property("operations") {
+            self.operations = operations
+                .into_iter()
+                .map(|operation| match operation.split_once(':') {
+                    Some((operation, command)) => (operation.to_owned(), command.to_owned()),
+                    None => (operation.to_owned(), operation.to_owned()),
+                })
+                .collect();
+        }
+        if self.enable && self.thing.is_empty() {
+            return Err(ConfigError::IncorrectSetting(
+                "The thing name of the device is required to execute AWS IoT jobs".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        _timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+        Ok(vec![self.get_next_job(&self.thing)])
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+
+        let things_prefix = format!("{}/things/", self.prefix);
+        if let Some((thing, jobs_topic)) = message
+            .topic
+            .strip_prefix(&things_prefix)
+            .and_then(|suffix| suffix.split_once("/jobs/"))
+        {
+            return match jobs_topic {
+                "notify-next" | "$next/get/accepted" => self.on_next_job(thing, &message.payload),
+                _ if jobs_topic.ends_with("/update/rejected") => {
+                    Err(FlowError::UnsupportedMessage(format!(
+                        "AWS IoT rejected the job update {}: {}",
+                        message.topic,
+                        String::from_utf8_lossy(&message.payload)
+                    )))
+                }
+                _ => Ok(vec![]),
+            };
+        }
+
+        match message.topic.split('/').collect::<Vec<_>>()[..] {
+            [_, _, _, _, _, "cmd", _, cmd_id] if cmd_id.starts_with(COMMAND_ID_PREFIX) => {
+                Ok(self.on_command_update(&message.topic, &message.payload))
+            }
+            [_, _, _, _, _] => Ok(self.on_registration(&message.topic, &message.payload)),
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl AwsJobs {
+    fn on_next_job(&mut self, thing: &str, payload: &[u8]) -> Result<Vec<Message>, FlowError> {
+        let Ok(Value::Object(mut notification)) = serde_json::from_slice::<Value>(payload) else {
+            return Err(FlowError::UnsupportedMessage(
+                "Expected a JSON job execution from AWS IoT".to_string(),
+            ));
+        };
+        let Some(Value::Object(mut execution)) = notification.remove("execution") else {
+            // No more pending jobs
+            return Ok(vec![]);
+        };
+        let Some(job_id) = execution
+            .get("jobId")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+        else {
+            return Err(FlowError::UnsupportedMessage(
+                "The job execution has no jobId".to_string(),
+            ));
+        };
+        if execution.get("status").and_then(Value::as_str) != Some("QUEUED") {
+            // The job is already executed by a command
+            return Ok(vec![]);
+        }
+        let Some(entity) = self.entity(thing) else {
+            return Ok(vec![]);
+        };
+
+        let mut document = match execution.remove("jobDocument") {
+            Some(Value::Object(document)) => document,
+            _ => Map::new(),
+        };
+        let operation = match document.remove("operation") {
+            Some(Value::String(operation)) => operation,
+            _ => String::new(),
+        };
+        let Some(command) = self.operations.get(&operation) else {
+            let details = json!({ "reason": format!("Unsupported job operation: {operation:?}") });
+            return Ok(vec![self.update_job(thing, &job_id, "FAILED", details)]);
+        };
+
+        let topic = format!("{entity}/cmd/{command}/{COMMAND_ID_PREFIX}{job_id}");
+        if self.commands.contains_key(&topic) {
+            return Ok(vec![]);
+        }
+        self.commands.insert(topic.clone(), "init".to_string());
+        document.insert("status".to_string(), "init".into());
+        Ok(vec![retained(topic, Value::Object(document).to_string())])
+    }
+
+    fn on_command_update(&mut self, topic: &str, payload: &[u8]) -> Vec<Message> {
+        let Ok(Value::Object(state)) = serde_json::from_slice::<Value>(payload) else {
+            // Command cleared
+            self.commands.remove(topic);
+            return vec![];
+        };
+        let (entity, cmd_id) = match topic.split_once("/cmd/") {
+            Some((entity, command)) => (entity, command.rsplit('/').next().unwrap_or_default()),
+            None => return vec![],
+        };
+        let Some(job_id) = cmd_id.strip_prefix(COMMAND_ID_PREFIX) else {
+            return vec![];
+        };
+        let status = match state.get("status").and_then(Value::as_str) {
+            None | Some("init") => return vec![],
+            Some(status) => status.to_owned(),
+        };
+        if self.commands.get(topic) == Some(&status) {
+            return vec![];
+        }
+        let job_status = match status.as_str() {
+            "successful" => "SUCCEEDED",
+            "failed" => "FAILED",
+            _ => "IN_PROGRESS",
+        };
+        let is_final = job_status != "IN_PROGRESS";
+        self.commands.insert(topic.to_string(), status);
+
+        let thing = self.thing(entity);
+        let mut messages = vec![self.update_job(&thing, job_id, job_status, status_details(state))];
+        if is_final {
+            messages.push(retained(topic.to_string(), String::new()));
+        }
+        messages
+    }
+
+    /// Request the pending jobs of a child device on registration
+    fn on_registration(&mut self, topic: &str, payload: &[u8]) -> Vec<Message> {
+        let Ok(Value::Object(registration)) = serde_json::from_slice::<Value>(payload) else {
+            return vec![];
+        };
+        if registration.get("@type").and_then(Value::as_str) != Some("child-device") {
+            return vec![];
+        }
+        let thing = self.thing(topic);
+        if !self.children.insert(thing.clone()) {
+            return vec![];
+        }
+        vec![self.get_next_job(&thing)]
+    }
+
+    fn update_job(&self, thing: &str, job_id: &str, status: &str, details: Value) -> Message {
+        let topic = format!("{}/things/{thing}/jobs/{job_id}/update", self.prefix);
+        let payload = json!({ "status": status, "statusDetails": details });
+        Message::new(topic, payload.to_string())
+    }
+
+    fn get_next_job(&self, thing: &str) -> Message {
+        let topic = format!("{}/things/{thing}/jobs/$next/get", self.prefix);
+        Message::new(topic, "{}")
+    }
+
+    /// The thing name of an entity
+    fn thing(&self, entity: &str) -> String {
+        let entity = entity.trim_end_matches('/');
+        if entity == self.device.trim_end_matches('/') {
+            self.thing.clone()
+        } else {
+            format!("{}:{}", self.thing, normalize_source_name(entity))
+        }
+    }
+
+    /// The entity of a thing, if this thing is the device thing or one of its children
+    fn entity(&self, thing: &str) -> Option<String> {
+        if thing == self.thing {
+            return Some(self.device.clone());
+        }
+        let source = thing.strip_prefix(&self.thing)?.strip_prefix(':')?;
+        let root = self.device.split('/').next().unwrap_or("te");
+        Some(entity_topic_from_source_name(root, source))
+    }
+}
+
+/// AWS expects the status details of a job execution to be a map of strings
+fn status_details(state: Map<String, Value>) -> Value {
+    let details: Map<String, Value> = state
+        .into_iter()
+        .filter(|(key, _)| key != "status")
+        .map(|(key, value)| match value {
+            Value::String(value) => (key, Value::String(value)),
+            value => (key, Value::String(value.to_string())),
+        })
+        .take(MAX_STATUS_DETAILS)
+        .collect();
+    Value::Object(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn jobs_are_executed_as_commands() {
+        let mut jobs = aws_jobs();
+
+        let output = on_message(
+            &mut jobs,
+            "aws/things/my-device/jobs/notify-next",
+            &json!({
+                "timestamp": 1700000000,
+                "execution": {
+                    "jobId": "job-1",
+                    "status": "QUEUED",
+                    "jobDocument": {"operation": "software_update", "updateList": []},
+                }
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///cmd/software_update/aws-job-job-1".into(),
+                json!({"updateList": [], "status": "init"}).to_string()
+            )]
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/main///cmd/software_update/aws-job-job-1",
+            r#"{"status":"executing","updateList":[]}"#,
+        );
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "aws/things/my-device/jobs/job-1/update",
+                json!({"status": "IN_PROGRESS", "statusDetails": {"updateList": "[]"}}).to_string()
+            )]
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/main///cmd/software_update/aws-job-job-1",
+            r#"{"status":"failed","reason":"no space left"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![
+                Message::new(
+                    "aws/things/my-device/jobs/job-1/update",
+                    json!({"status": "FAILED", "statusDetails": {"reason": "no space left"}})
+                        .to_string()
+                ),
+                retained(
+                    "te/device/main///cmd/software_update/aws-job-job-1".into(),
+                    "".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn job_operations_can_be_mapped_to_other_commands() {
+        let mut jobs = aws_jobs();
+
+        let output = on_message(
+            &mut jobs,
+            "aws/things/my-device/jobs/$next/get/accepted",
+            &json!({
+                "execution": {
+                    "jobId": "job-2",
+                    "status": "QUEUED",
+                    "jobDocument": {"operation": "reboot"},
+                }
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output[0].topic,
+            "te/device/main///cmd/restart/aws-job-job-2"
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "aws/things/my-device/jobs/notify-next",
+            &json!({
+                "execution": {
+                    "jobId": "job-3",
+                    "status": "QUEUED",
+                    "jobDocument": {"operation": "self_destruct"},
+                }
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "aws/things/my-device/jobs/job-3/update",
+                json!({"status": "FAILED", "statusDetails": {"reason": "Unsupported job operation: \"self_destruct\""}}).to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn child_devices_have_their_own_things() {
+        let mut jobs = aws_jobs();
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/child1//",
+            r#"{"@type":"child-device"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "aws/things/my-device:device:child1/jobs/$next/get",
+                "{}"
+            )]
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "aws/things/my-device:device:child1/jobs/notify-next",
+            &json!({
+                "execution": {
+                    "jobId": "job-4",
+                    "status": "QUEUED",
+                    "jobDocument": {"operation": "restart"},
+                }
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output[0].topic,
+            "te/device/child1///cmd/restart/aws-job-job-4"
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/child1///cmd/restart/aws-job-job-4",
+            r#"{"status":"successful"}"#,
+        );
+        assert_eq!(
+            output[0].topic,
+            "aws/things/my-device:device:child1/jobs/job-4/update"
+        );
+    }
+
+    fn aws_jobs() -> AwsJobs {
+        let mut jobs = AwsJobs::default();
+        jobs.set_config(JsonValue::from(json!({
+            "thing": "my-device",
+            "operations": ["software_update", "restart", "reboot:restart"],
+        })))
+        .unwrap();
+        jobs
+    }
+
+    fn on_message(jobs: &mut AwsJobs, topic: &str, payload: &str) -> Vec<Message> {
+        jobs.on_message(
+            SystemTime::now(),
+            &Message::new(topic, payload),
+            &FlowContextHandle::default(),
+        )
+        .unwrap()
+    }
+}
@@ -1,6 +1,9 @@
 use std::time::SystemTime;
+use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_config::models::TopicPrefix;
+use tedge_config::tedge_toml::mapper_config::AwsJobsConfig;
+use tedge_config::tedge_toml::mapper_config::AwsShadowConfig;
 use tedge_flows::ConfigError;
 use tedge_flows::ConnectedFlowRegistry;
 use tedge_flows::FlowContextHandle;
@@ -8,17 +11,32 @@ use tedge_flows::FlowError;
 use tedge_flows::FlowRegistryExt;
 use tedge_flows::JsonValue;
 use tedge_flows::Message;
+use tedge_flows::Transport;
 use tedge_flows::UpdateFlowRegistryError;
+use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::Topic;
 use tedge_utils::timestamp::TimeFormat;
 
+mod jobs;
+mod shadow;
+pub use jobs::AwsJobs;
+pub use shadow::AwsShadow;
+
 pub struct AwsConverter {
     input_topics: String,
     topic_prefix: TopicPrefix,
+    /// The root of the thin-edge topics, e.g. `te`
+    topic_root: String,
+    /// The topic prefix of the main device, e.g. `te/device/main//`
+    device_topic: String,
     errors_topic: Topic,
     size_threshold: usize,
     add_timestamp: bool,
     time_format: TimeFormat,
+    shadow: AwsShadowConfig,
+    bridge_health_topic: Option<Topic>,
+    jobs: AwsJobsConfig,
+    thing_name: String,
 }
 
 impl AwsConverter {
@@ -29,16 +47,43 @@ impl AwsConverter {
         topic_prefix: TopicPrefix,
         max_payload_size: u32,
         input_topics: String,
+        device_topic_id: &EntityTopicId,
     ) -> Self {
         let errors_topic = mqtt_schema.error_topic();
         let size_threshold = max_payload_size as usize;
         AwsConverter {
             input_topics,
             topic_prefix,
+            topic_root: mqtt_schema.root.clone(),
+            device_topic: format!("{}/{device_topic_id}", mqtt_schema.root),
             errors_topic,
             size_threshold,
             add_timestamp,
             time_format,
+            shadow: AwsShadowConfig::default(),
+            bridge_health_topic: None,
+            jobs: AwsJobsConfig::default(),
+            thing_name: String::new(),
+        }
+    }
+
+    /// Set how the entity twin data is synchronised with device shadows
+    ///
+    /// The shadows are reconciled with the local twin data each time the bridge reports to be up.
+    pub fn with_shadow(self, shadow: AwsShadowConfig, bridge_health_topic: Topic) -> Self {
+        AwsConverter {
+            shadow,
+            bridge_health_topic: Some(bridge_health_topic),
+            ..self
+        }
+    }
+
+    /// Set how AWS IoT Jobs are executed, the jobs being those of the given thing and its children
+    pub fn with_jobs(self, jobs: AwsJobsConfig, thing_name: String) -> Self {
+        AwsConverter {
+            jobs,
+            thing_name,
+            ..self
         }
     }
 
@@ -48,6 +93,12 @@ impl AwsConverter {
     ) -> Result<(), UpdateFlowRegistryError> {
         flows
             .persist_builtin_flow("mea", self.builtin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("shadow", self.shadow_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("jobs", self.jobs_flow().as_str())
             .await
     }
 
@@ -82,6 +133,97 @@ errors.mqtt.topic = "{errors_topic}"
             version = env!("CARGO_PKG_VERSION"),
         )
     }
+
+    /// The flow synchronising the entity twin data with AWS IoT device shadows
+    ///
+    /// The flow is persisted even when the synchronisation is disabled,
+    /// so a previously enabled synchronisation is effectively stopped.
+    fn shadow_flow(&self) -> String {
+        let mut step_config = format!(
+            r#"enable = {enable}, prefix = "{topic_prefix}", device = "{device}", named = {named}"#,
+            enable = self.shadow.enable,
+            topic_prefix = self.topic_prefix,
+            device = self.device_topic,
+            named = self.shadow.named,
+        );
+        let mut input_topics = vec![
+            format!("{}/+/+/+/+/twin/+", self.topic_root),
+            format!("{}/shadow/#", self.topic_prefix),
+        ];
+        if let Some(command) = &self.shadow.desired_command {
+            step_config.push_str(&format!(r#", desired_command = "{command}""#));
+        }
+        if let Some(health_topic) = &self.bridge_health_topic {
+            step_config.push_str(&format!(
+                r#", bridge_health_topic = "{}""#,
+                health_topic.name
+            ));
+            input_topics.push(health_topic.name.clone());
+        }
+        let input_topics: Vec<String> = input_topics
+            .iter()
+            .map(|topic| format!(r#""{topic}""#))
+            .collect();
+
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = [{input_topics}]
+
+steps = [
+    {{ builtin = "aws-shadow", config = {{ {step_config} }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+
+# Shadow deltas are applied as local twin updates, which are then reported back
+expect_loop = true
+"#,
+            input_topics = input_topics.join(", "),
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+
+    /// The flow executing AWS IoT Jobs as thin-edge commands
+    fn jobs_flow(&self) -> String {
+        let operations: Vec<String> = self
+            .jobs
+            .operations
+            .iter()
+            .map(|(operation, command)| format!(r#""{operation}:{command}""#))
+            .collect();
+
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = [
+    "{topic_prefix}/things/+/jobs/notify-next",
+    "{topic_prefix}/things/+/jobs/$next/get/accepted",
+    "{topic_prefix}/things/+/jobs/+/update/rejected",
+    "{topic_root}/+/+/+/+",
+    "{topic_root}/+/+/+/+/cmd/+/+",
+]
+
+steps = [
+    {{ builtin = "aws-jobs", config = {{ enable = {enable}, prefix = "{topic_prefix}", device = "{device}", thing = "{thing}", operations = [{operations}] }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+
+# The commands created for jobs are cleared once completed
+expect_loop = true
+"#,
+            topic_prefix = self.topic_prefix,
+            topic_root = self.topic_root,
+            device = self.device_topic,
+            enable = self.jobs.enable,
+            thing = self.thing_name,
+            operations = operations.join(", "),
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
 }
 
 // We need to reduce the number of levels in the topic because AWS IoT only supports topics with 7
@@ -99,6 +241,15 @@ fn normalize_source_name(topic: &str) -> String {
         .join(":")
 }
 
+/// Best-effort inverse of [normalize_source_name], e.g. `device:child1` => `te/device/child1//`
+///
+/// Empty topic segments are assumed to be trailing ones.
+fn entity_topic_from_source_name(topic_root: &str, source: &str) -> String {
+    let mut segments: Vec<&str> = source.split(':').collect();
+    segments.resize(4, "");
+    format!("{topic_root}/{}", segments.join("/"))
+}
+
 #[derive(Clone, Default)]
 pub struct SetAwsTopic {
     prefix: String,
@@ -143,8 +294,20 @@ impl tedge_flows::Transformer for SetAwsTopic {
     }
 }
 
+fn retained(topic: String, payload: String) -> Message {
+    Message {
+        transport: Some(Transport::Mqtt {
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Message::new(topic, payload)
+    }
+}
+
 pub fn load_builtin_transformers(flows: &mut impl FlowRegistryExt) {
     flows.register_builtin(SetAwsTopic::default());
+    flows.register_builtin(AwsShadow::default());
+    flows.register_builtin(AwsJobs::default());
 }
 
 #[cfg(test)]
@@ -744,6 +907,27 @@ mod tests {
         assert_eq!(res[0], expected_msg);
     }
 
+    #[test]
+    fn shadow_and_jobs_flows_follow_the_device_topic_id() {
+        let converter = AwsConverter::new(
+            false,
+            &MqttSchema::with_root("tedge".to_string()),
+            TimeFormat::Rfc3339,
+            TopicPrefix::try_from("aws").unwrap(),
+            AWS_MQTT_PAYLOAD_LIMIT,
+            TE_MEA_TOPICS.to_string(),
+            &"device/gateway//".parse().unwrap(),
+        );
+
+        let shadow_flow = converter.shadow_flow();
+        assert!(shadow_flow.contains(r#"input.mqtt.topics = ["tedge/+/+/+/+/twin/+", "#));
+        assert!(shadow_flow.contains(r#"device = "tedge/device/gateway//""#));
+
+        let jobs_flow = converter.jobs_flow();
+        assert!(jobs_flow.contains(r#""tedge/+/+/+/+/cmd/+/+","#));
+        assert!(jobs_flow.contains(r#"device = "tedge/device/gateway//""#));
+    }
+
     async fn create_test_converter(
         add_timestamp: bool,
         size_threshold: Option<u32>,
@@ -756,6 +940,7 @@ mod tests {
             TopicPrefix::try_from(prefix.unwrap_or("aws")).unwrap(),
             size_threshold.unwrap_or(AWS_MQTT_PAYLOAD_LIMIT),
             TE_MEA_TOPICS.to_string(),
+            &EntityTopicId::default_main_device(),
         );
         let temp_dir = tempfile::TempDir::new().expect("Failed to create temp dir");
         let flows_dir = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf()).unwrap();
@@ -0,0 +1,540 @@
+//! Synchronisation of the entity twin data with AWS IoT device shadows
+//!
+//! - The main device is synchronised with the classic shadow of the thing,
+//!   child devices and services with named shadows, e.g. `device:child1`.
+//! - The local `twin/*` fragments are published as `reported` state on `$aws/things/{thing}/shadow/update`.
+//! - The `shadow/update/delta` documents are applied as local twin updates or as a command,
+//!   documents older than the last version applied being ignored.
+//! - On startup and whenever the bridge reconnects, the shadows are requested on `shadow/get`
+//!   and reconciled with the local twin data.
+use crate::entity_topic_from_source_name;
+use crate::normalize_source_name;
+use crate::retained;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+/// The key used for the classic shadow of the thing
+const CLASSIC_SHADOW: &str = "";
+
+#[derive(Clone)]
+pub struct AwsShadow {
+    /// When disabled, all messages are ignored
+    enable: bool,
+
+    /// The bridge topic prefix, e.g. `aws`
+    prefix: String,
+
+    /// The topic prefix of the main device, e.g. `te/device/main//`
+    device: String,
+
+    /// Whether child devices and services are synchronised with named shadows
+    named: bool,
+
+    /// The command created on delta updates, if any
+    desired_command: Option<String>,
+
+    /// The health topic of the bridge, used to reconcile the shadows on reconnect
+    bridge_health_topic: Option<String>,
+
+    /// The known shadows, indexed by shadow name
+    shadows: HashMap<String, Shadow>,
+}
+
+#[derive(Clone, Default)]
+struct Shadow {
+    /// The topic prefix of the synchronised entity
+    entity: String,
+
+    /// The version of the last desired state applied
+    version: Option<u64>,
+
+    /// The twin fragments published locally, a cleared fragment being null
+    reported: Map<String, Value>,
+}
+
+impl Default for AwsShadow {
+    fn default() -> Self {
+        AwsShadow {
+            enable: true,
+            prefix: "aws".to_string(),
+            device: "te/device/main//".to_string(),
+            named: true,
+            desired_command: None,
+            bridge_health_topic: None,
+            shadows: HashMap::new(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AwsShadow {
+    fn name(&self) -> &str {
+        "aws-shadow"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(enable) = config.bool_property("enable") {
+            self.enable = enable;
+        }
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(device) = config.string_property("device") {
+            self.device = device.to_owned();
+        }
+        if let Some(named) = config.bool_property("named") {
+            self.named = named;
+        }
+        self.desired_command = config
+            .string_property("desired_command")
+            .filter(|command| !command.is_empty())
+            .map(str::to_owned);
+        self.bridge_health_topic = config
+            .string_property("bridge_health_topic")
+            .map(str::to_owned);
+        Ok(())
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        _timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+        self.shadow_mut(CLASSIC_SHADOW);
+        Ok(self.get_all_shadows())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+
+        if Some(&message.topic) == self.bridge_health_topic.as_ref() {
+            return Ok(self.on_bridge_health(&message.payload));
+        }
+
+        if let Some(suffix) = message
+            .topic
+            .strip_prefix(&self.prefix)
+            .and_then(|suffix| suffix.strip_prefix("/shadow/"))
+        {
+            let (name, operation) = match suffix.strip_prefix("name/") {
+                Some(named) => named.split_once('/').unwrap_or((named, "")),
+                None => (CLASSIC_SHADOW, suffix),
+            };
+            return match operation {
+                "update/delta" => self.on_delta(name, &message.payload),
+                "get/accepted" => self.on_get_accepted(name, &message.payload),
+                "get/rejected" => Ok(self.on_get_rejected(name, &message.payload)),
+                "update/rejected" => self.on_update_rejected(name, &message.payload),
+                _ => Ok(vec![]),
+            };
+        }
+
+        match message.topic.split('/').collect::<Vec<_>>()[..] {
+            [_, _, _, _, _, "twin", fragment] if !fragment.is_empty() => {
+                self.on_local_twin(&message.topic, fragment, &message.payload)
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl AwsShadow {
+    /// Publish a local twin fragment as reported state, an empty payload clearing the property
+    fn on_local_twin(
+        &mut self,
+        topic: &str,
+        fragment: &str,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        let entity = topic
+            .strip_suffix(&format!("/twin/{fragment}"))
+            .unwrap_or(topic);
+        let name = if entity == self.device {
+            CLASSIC_SHADOW.to_string()
+        } else if self.named {
+            normalize_source_name(topic)
+        } else {
+            return Ok(vec![]);
+        };
+        let value = if payload.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(payload).map_err(|_| {
+                FlowError::UnsupportedMessage(format!(
+                    "Twin fragment {fragment} is not a JSON value"
+                ))
+            })?
+        };
+
+        let shadow = self.shadow_mut(&name);
+        shadow.entity = entity.to_string();
+        if shadow.reported.get(fragment) == Some(&value) {
+            return Ok(vec![]);
+        }
+        shadow.reported.insert(fragment.to_string(), value.clone());
+
+        let reported = Map::from_iter([(fragment.to_string(), value)]);
+        Ok(vec![self.update_reported(&name, reported)])
+    }
+
+    fn on_delta(&mut self, name: &str, payload: &[u8]) -> Result<Vec<Message>, FlowError> {
+        let mut document = parse_object(payload)?;
+        let Some(version) = document.get("version").and_then(Value::as_u64) else {
+            return Err(FlowError::UnsupportedMessage(
+                "The shadow delta document has no version".to_string(),
+            ));
+        };
+        let Some(Value::Object(delta)) = document.remove("state") else {
+            return Ok(vec![]);
+        };
+
+        let shadow = self.shadow_mut(name);
+        if shadow.version.is_some_and(|current| version <= current) {
+            // Deltas can be received out of order
+            return Ok(vec![]);
+        }
+        shadow.version = Some(version);
+        Ok(self.apply_desired(name, version, delta))
+    }
+
+    /// Apply the pending delta and push the local fragments that are not in the reported state
+    fn on_get_accepted(&mut self, name: &str, payload: &[u8]) -> Result<Vec<Message>, FlowError> {
+        let mut document = parse_object(payload)?;
+        let version = document.get("version").and_then(Value::as_u64);
+        let mut state = match document.remove("state") {
+            Some(Value::Object(state)) => state,
+            _ => Map::new(),
+        };
+        let delta = match state.remove("delta") {
+            Some(Value::Object(delta)) => delta,
+            _ => Map::new(),
+        };
+        let reported = match state.remove("reported") {
+            Some(Value::Object(reported)) => reported,
+            _ => Map::new(),
+        };
+
+        let shadow = self.shadow_mut(name);
+        let outdated: Map<String, Value> = shadow
+            .reported
+            .iter()
+            .filter(|(fragment, value)| {
+                reported.get(fragment.as_str()).unwrap_or(&Value::Null) != *value
+            })
+            .map(|(fragment, value)| (fragment.clone(), value.clone()))
+            .collect();
+        let is_newer = match (shadow.version, version) {
+            (Some(current), Some(version)) => version > current,
+            _ => true,
+        };
+        if is_newer {
+            shadow.version = version.or(shadow.version);
+        }
+
+        let mut messages = vec![];
+        if !outdated.is_empty() {
+            messages.push(self.update_reported(name, outdated));
+        }
+        if is_newer && !delta.is_empty() {
+            messages.extend(self.apply_desired(name, version.unwrap_or_default(), delta));
+        }
+        Ok(messages)
+    }
+
+    /// A shadow that doesn't exist yet is created from the local twin data
+    fn on_get_rejected(&mut self, name: &str, payload: &[u8]) -> Vec<Message> {
+        if error_code(payload) != Some(404) {
+            return vec![];
+        }
+        let reported = self.shadow_mut(name).reported.clone();
+        if reported.is_empty() {
+            return vec![];
+        }
+        vec![self.update_reported(name, reported)]
+    }
+
+    /// The updates are sent without version, hence are only rejected on errors
+    fn on_update_rejected(&self, name: &str, payload: &[u8]) -> Result<Vec<Message>, FlowError> {
+        Err(FlowError::UnsupportedMessage(format!(
+            "AWS IoT rejected the update of the shadow {}: {}",
+            self.shadow_topic(name, "update"),
+            String::from_utf8_lossy(payload)
+        )))
+    }
+
+    fn on_bridge_health(&self, payload: &[u8]) -> Vec<Message> {
+        let is_up = match serde_json::from_slice::<Value>(payload) {
+            Ok(Value::Object(health)) => health.get("status") == Some(&json!("up")),
+            Ok(Value::Number(status)) => status.as_u64() == Some(1),
+            _ => false,
+        };
+        if is_up {
+            self.get_all_shadows()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Turn a desired state into local twin updates or into a command
+    fn apply_desired(&self, name: &str, version: u64, desired: Map<String, Value>) -> Vec<Message> {
+        let entity = self.entity(name);
+
+        if let Some(command) = &self.desired_command {
+            let topic = format!("{entity}/cmd/{command}/aws-shadow-{version}");
+            let payload = json!({
+                "status": "init",
+                "version": version,
+                "desired": desired,
+            });
+            return vec![retained(topic, payload.to_string())];
+        }
+
+        desired
+            .into_iter()
+            .map(|(fragment, value)| {
+                let topic = format!("{entity}/twin/{fragment}");
+                let payload = match value {
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+                retained(topic, payload)
+            })
+            .collect()
+    }
+
+    /// The entity associated to a shadow, falling back to the entity encoded in the shadow name
+    fn entity(&self, name: &str) -> String {
+        if name == CLASSIC_SHADOW {
+            return self.device.clone();
+        }
+        if let Some(shadow) = self.shadows.get(name).filter(|s| !s.entity.is_empty()) {
+            return shadow.entity.clone();
+        }
+        let root = self.device.split('/').next().unwrap_or("te");
+        entity_topic_from_source_name(root, name)
+    }
+
+    fn update_reported(&self, name: &str, reported: Map<String, Value>) -> Message {
+        let payload = json!({ "state": { "reported": reported } });
+        Message::new(self.shadow_topic(name, "update"), payload.to_string())
+    }
+
+    fn get_shadow(&self, name: &str) -> Message {
+        Message::new(self.shadow_topic(name, "get"), "")
+    }
+
+    fn get_all_shadows(&self) -> Vec<Message> {
+        let mut names: Vec<&String> = self.shadows.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| self.get_shadow(name))
+            .collect()
+    }
+
+    fn shadow_topic(&self, name: &str, operation: &str) -> String {
+        if name == CLASSIC_SHADOW {
+            format!("{}/shadow/{operation}", self.prefix)
+        } else {
+            format!("{}/shadow/name/{name}/{operation}", self.prefix)
+        }
+    }
+
+    fn shadow_mut(&mut self, name: &str) -> &mut Shadow {
+        self.shadows.entry(name.to_string()).or_default()
+    }
+}
+
+fn parse_object(payload: &[u8]) -> Result<Map<String, Value>, FlowError> {
+    match serde_json::from_slice(payload) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Err(FlowError::UnsupportedMessage(
+            "Expected a JSON shadow document from AWS IoT".to_string(),
+        )),
+    }
+}
+
+fn error_code(payload: &[u8]) -> Option<u64> {
+    parse_object(payload)
+        .ok()
+        .and_then(|error| error.get("code").and_then(Value::as_u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn local_twin_fragments_are_reported() {
+        let mut shadow = AwsShadow::default();
+
+        let output = on_message(
+            &mut shadow,
+            "te/device/main///twin/firmware",
+            r#"{"version":"1.0"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "aws/shadow/update",
+                json!({"state": {"reported": {"firmware": {"version": "1.0"}}}}).to_string()
+            )]
+        );
+
+        let output = on_message(&mut shadow, "te/device/child1///twin/location", "");
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "aws/shadow/name/device:child1/update",
+                json!({"state": {"reported": {"location": null}}}).to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn named_shadows_can_be_disabled() {
+        let mut shadow = AwsShadow::default();
+        shadow
+            .set_config(JsonValue::from(json!({"named": false})))
+            .unwrap();
+
+        let output = on_message(&mut shadow, "te/device/child1///twin/location", "{}");
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn deltas_are_applied_in_order() {
+        let mut shadow = AwsShadow::default();
+
+        let output = on_message(
+            &mut shadow,
+            "aws/shadow/name/device:child1/update/delta",
+            &json!({"version": 5, "state": {"interval": 10}}).to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/child1///twin/interval".into(),
+                "10".into()
+            )]
+        );
+
+        let output = on_message(
+            &mut shadow,
+            "aws/shadow/name/device:child1/update/delta",
+            &json!({"version": 4, "state": {"interval": 5}}).to_string(),
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn deltas_can_be_mapped_to_a_command() {
+        let mut shadow = AwsShadow::default();
+        shadow
+            .set_config(JsonValue::from(
+                json!({"desired_command": "set_desired_state"}),
+            ))
+            .unwrap();
+
+        let output = on_message(
+            &mut shadow,
+            "aws/shadow/update/delta",
+            &json!({"version": 8, "state": {"interval": 30}}).to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///cmd/set_desired_state/aws-shadow-8".into(),
+                json!({"status": "init", "version": 8, "desired": {"interval": 30}}).to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn shadows_are_reconciled_on_reconnect() {
+        let mut shadow = AwsShadow::default();
+        shadow
+            .set_config(JsonValue::from(json!({"bridge_health_topic": "te/device/main/service/tedge-mapper-bridge-aws/status/health"})))
+            .unwrap();
+        on_message(&mut shadow, "te/device/main///twin/firmware", r#""1.0""#);
+        on_message(&mut shadow, "te/device/main///twin/os", r#""linux""#);
+
+        let output = on_message(
+            &mut shadow,
+            "te/device/main/service/tedge-mapper-bridge-aws/status/health",
+            r#"{"status":"up"}"#,
+        );
+        assert_eq!(output, vec![Message::new("aws/shadow/get", "")]);
+
+        let output = on_message(
+            &mut shadow,
+            "aws/shadow/get/accepted",
+            &json!({
+                "version": 3,
+                "state": {
+                    "desired": {"interval": 20},
+                    "reported": {"firmware": "0.9", "os": "linux"},
+                    "delta": {"interval": 20},
+                }
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![
+                Message::new(
+                    "aws/shadow/update",
+                    json!({"state": {"reported": {"firmware": "1.0"}}}).to_string()
+                ),
+                retained("te/device/main///twin/interval".into(), "20".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_updates_are_reported_as_errors() {
+        let mut shadow = AwsShadow::default();
+
+        let result = shadow.on_message(
+            SystemTime::now(),
+            &Message::new(
+                "aws/shadow/update/rejected",
+                r#"{"code":400,"message":"Missing required node: state"}"#,
+            ),
+            &FlowContextHandle::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    fn on_message(shadow: &mut AwsShadow, topic: &str, payload: &str) -> Vec<Message> {
+        shadow
+            .on_message(
+                SystemTime::now(),
+                &Message::new(topic, payload),
+                &FlowContextHandle::default(),
+            )
+            .unwrap()
+    }
+}
@@ -1,11 +1,22 @@
+use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_config::models::timestamp::TimeFormat;
 use tedge_config::models::TopicPrefix;
+use tedge_config::tedge_toml::mapper_config::AzMethodsConfig;
+use tedge_config::tedge_toml::mapper_config::AzTwinConfig;
 use tedge_flows::ConnectedFlowRegistry;
 use tedge_flows::FlowRegistryExt;
+use tedge_flows::Message;
+use tedge_flows::Transport;
 use tedge_flows::UpdateFlowRegistryError;
+use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::Topic;
 
+mod methods;
+mod twin;
+pub use methods::AzureMethods;
+pub use twin::AzureTwin;
+
 pub struct AzureConverter {
     input_topics: String,
     output_topic: Topic,
@@ -13,6 +24,13 @@ pub struct AzureConverter {
     add_timestamp: bool,
     time_format: TimeFormat,
     size_threshold: usize,
+    topic_prefix: TopicPrefix,
+    /// The topic prefix of the main device, e.g. `te/device/main//`
+    device_topic: String,
+    device_twin_topics: String,
+    device_command_topics: String,
+    twin: AzTwinConfig,
+    methods: AzMethodsConfig,
 }
 
 impl AzureConverter {
@@ -23,10 +41,12 @@ impl AzureConverter {
         topic_prefix: &TopicPrefix,
         max_payload_size: u32,
         input_topics: String,
+        device_topic_id: &EntityTopicId,
     ) -> Self {
         let output_topic = Topic::new_unchecked(&format!("{topic_prefix}/messages/events/"));
         let errors_topic = mqtt_schema.error_topic();
         let size_threshold = max_payload_size as usize;
+        let device_topic = format!("{}/{device_topic_id}", mqtt_schema.root);
         AzureConverter {
             input_topics,
             output_topic,
@@ -34,15 +54,37 @@ impl AzureConverter {
             add_timestamp,
             time_format,
             size_threshold,
+            topic_prefix: topic_prefix.clone(),
+            device_twin_topics: format!("{device_topic}/twin/+"),
+            device_command_topics: format!("{device_topic}/cmd/+/+"),
+            device_topic,
+            twin: AzTwinConfig::default(),
+            methods: AzMethodsConfig::default(),
         }
     }
 
+    /// Set how the twin data of the main device is synchronised with the device twin
+    pub fn with_twin(self, twin: AzTwinConfig) -> Self {
+        AzureConverter { twin, ..self }
+    }
+
+    /// Set which direct methods are mapped to thin-edge commands
+    pub fn with_methods(self, methods: AzMethodsConfig) -> Self {
+        AzureConverter { methods, ..self }
+    }
+
     pub async fn persist_builtin_flow(
         &self,
         flows: &mut ConnectedFlowRegistry,
     ) -> Result<(), UpdateFlowRegistryError> {
         flows
             .persist_builtin_flow("mea", self.builtin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("twin", self.twin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("methods", self.methods_flow().as_str())
             .await
     }
 
@@ -77,7 +119,120 @@ errors.mqtt.topic = "{errors_topic}"
             version = env!("CARGO_PKG_VERSION"),
         )
     }
+
+    /// The flow synchronising the twin data of the main device with the Azure IoT Hub device twin
+    ///
+    /// The flow is persisted even when the synchronisation is disabled,
+    /// so a previously enabled synchronisation is effectively stopped.
+    pub fn twin_flow(&self) -> String {
+        let desired_command = match &self.twin.desired_command {
+            Some(command) => format!(r#", desired_command = "{command}""#),
+            None => "".to_string(),
+        };
+
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{twin_topics}", "{prefix}/twin/res/#", "{prefix}/twin/PATCH/properties/desired/#"]
+
+steps = [
+    {{ builtin = "azure-twin", config = {{ enable = {enable}, prefix = "{prefix}", entity = "{entity}"{desired_command} }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+
+# Desired properties are applied as local twin updates, which are then reported back
+expect_loop = true
+"#,
+            prefix = self.topic_prefix,
+            twin_topics = self.device_twin_topics,
+            entity = self.device_topic,
+            enable = self.twin.enable,
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+
+    /// The flow mapping Azure IoT Hub direct methods to thin-edge commands
+    pub fn methods_flow(&self) -> String {
+        let operations: Vec<String> = self
+            .methods
+            .operations
+            .iter()
+            .map(|(method, operation)| format!(r#""{method}:{operation}""#))
+            .collect();
+
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{prefix}/methods/POST/#", "{command_topics}"]
+
+steps = [
+    {{ builtin = "azure-methods", config = {{ enable = {enable}, prefix = "{prefix}", entity = "{entity}", operations = [{operations}], timeout = "{timeout}" }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+
+# The commands created for direct methods are cleared once completed
+expect_loop = true
+"#,
+            prefix = self.topic_prefix,
+            command_topics = self.device_command_topics,
+            entity = self.device_topic,
+            enable = self.methods.enable,
+            operations = operations.join(", "),
+            timeout = humantime::format_duration(self.methods.timeout),
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+}
+
+pub fn load_builtin_transformers(flows: &mut impl FlowRegistryExt) {
+    flows.register_builtin(AzureTwin::default());
+    flows.register_builtin(AzureMethods::default());
 }
-pub fn load_builtin_transformers(_flows: &mut impl FlowRegistryExt) {
-    // nothing specific for az
+
+/// Extract a parameter from the query part of an Azure IoT Hub topic, e.g. `$rid=42`
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn retained(topic: String, payload: String) -> Message {
+    Message {
+        transport: Some(Transport::Mqtt {
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Message::new(topic, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn twin_and_methods_flows_follow_the_device_topic_id() {
+        let converter = AzureConverter::new(
+            false,
+            &MqttSchema::with_root("tedge".to_string()),
+            TimeFormat::Rfc3339,
+            &TopicPrefix::try_new("az").unwrap(),
+            1024,
+            r#"["tedge/+/+/+/+/m/+"]"#.to_string(),
+            &"device/gateway//".parse().unwrap(),
+        );
+
+        let twin_flow = converter.twin_flow();
+        assert!(twin_flow.contains(r#"input.mqtt.topics = ["tedge/device/gateway///twin/+", "#));
+        assert!(twin_flow.contains(r#"entity = "tedge/device/gateway//""#));
+
+        let methods_flow = converter.methods_flow();
+        assert!(methods_flow.contains(r#""tedge/device/gateway///cmd/+/+"]"#));
+        assert!(methods_flow.contains(r#"entity = "tedge/device/gateway//""#));
+    }
 }
@@ -0,0 +1,355 @@
+//! Mapping of Azure IoT Hub direct methods to thin-edge commands
+//!
+//! - A direct method received on `$iothub/methods/POST/{method}/?$rid={rid}`
+//!   creates the associated command on `te/device/main///cmd/{operation}/azure-{rid}`,
+//!   the method payload being used as the command payload.
+//! - When the command reaches a final state, the command payload is sent back as the method response
+//!   on `$iothub/methods/res/{status}/?$rid={rid}` and the command is cleared.
+//! - If the command doesn't reach a final state in time, a timeout is returned to Azure.
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+use crate::query_param;
+use crate::retained;
+
+const COMMAND_ID_PREFIX: &str = "azure-";
+
+#[derive(Clone)]
+pub struct AzureMethods {
+    /// When disabled, all messages are ignored
+    enable: bool,
+
+    /// The bridge topic prefix, e.g. `az`
+    prefix: String,
+
+    /// The topic prefix of the target entity, e.g. `te/device/main//`
+    entity: String,
+
+    /// The operation associated to each supported method
+    operations: HashMap<String, String>,
+
+    /// How long to wait for a command to reach a final state
+    timeout: Duration,
+
+    /// The commands created for direct methods, indexed by command topic
+    pending: HashMap<String, PendingMethod>,
+}
+
+#[derive(Clone)]
+struct PendingMethod {
+    rid: String,
+    deadline: SystemTime,
+    responded: bool,
+}
+
+impl Default for AzureMethods {
+    fn default() -> Self {
+        AzureMethods {
+            enable: true,
+            prefix: "az".to_string(),
+            entity: "te/device/main//".to_string(),
+            operations: HashMap::from([("restart".to_string(), "restart".to_string())]),
+            timeout: Duration::from_secs(60),
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AzureMethods {
+    fn name(&self) -> &str {
+        "azure-methods"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(enable) = config.bool_property("enable") {
+            self.enable = enable;
+        }
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(entity) = config.string_property("entity") {
+            self.entity = entity.to_owned();
+        }
+        if let Some(operations) = config.strings_property("operations") {
+            self.operations = operations
+                .into_iter()
+                .map(|method| match method.split_once(':') {
+                    Some((method, operation)) => (method.to_owned(), operation.to_owned()),
+                    None => (method.to_owned(), method.to_owned()),
+                })
+                .collect();
+        }
+        if let Some(timeout) = config.string_property("timeout") {
+            let Ok(duration) = humantime::parse_duration(timeout) else {
+                return Err(ConfigError::IncorrectSetting(format!(
+                    "Invalid timeout: not a duration: {timeout}"
+                )));
+            };
+            self.timeout = duration;
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+
+        if let Some(command) = message
+            .topic
+            .strip_prefix(&self.entity)
+            .and_then(|suffix| suffix.strip_prefix("/cmd/"))
+        {
+            return Ok(self.on_command_update(command, &message.payload));
+        }
+
+        let methods_prefix = format!("{}/methods/POST/", self.prefix);
+        if let Some(request) = message.topic.strip_prefix(&methods_prefix) {
+            let Some((method, rid)) = request
+                .split_once("/?")
+                .and_then(|(method, query)| Some((method, query_param(query, "$rid")?)))
+            else {
+                return Err(FlowError::UnsupportedMessage(format!(
+                    "Not a direct method request: {}",
+                    message.topic
+                )));
+            };
+            return Ok(self.on_method(timestamp, method, rid, &message.payload));
+        }
+
+        Ok(vec![])
+    }
+
+    fn is_periodic(&self) -> bool {
+        true
+    }
+
+    fn on_interval(
+        &mut self,
+        timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let mut expired = vec![];
+        for pending in self.pending.values_mut() {
+            if !pending.responded && pending.deadline <= timestamp {
+                pending.responded = true;
+                expired.push(pending.rid.clone());
+            }
+        }
+        let error = serde_json::json!({ "error": "Timeout waiting for the command to complete" });
+        Ok(expired
+            .iter()
+            .map(|rid| self.response(504, rid, error.to_string()))
+            .collect())
+    }
+}
+
+impl AzureMethods {
+    fn on_method(
+        &mut self,
+        timestamp: SystemTime,
+        method: &str,
+        rid: &str,
+        payload: &[u8],
+    ) -> Vec<Message> {
+        let Some(operation) = self.operations.get(method) else {
+            let error = serde_json::json!({ "error": format!("Unsupported method: {method}") });
+            return vec![self.response(404, rid, error.to_string())];
+        };
+
+        let mut command = match serde_json::from_slice::<Value>(payload) {
+            _ if payload.is_empty() => Map::new(),
+            Ok(Value::Null) => Map::new(),
+            Ok(Value::Object(command)) => command,
+            _ => {
+                let error =
+                    serde_json::json!({ "error": "Expected a JSON object as method payload" });
+                return vec![self.response(400, rid, error.to_string())];
+            }
+        };
+        command.insert("status".to_string(), "init".into());
+
+        let topic = format!("{}/cmd/{operation}/{COMMAND_ID_PREFIX}{rid}", self.entity);
+        self.pending.insert(
+            topic.clone(),
+            PendingMethod {
+                rid: rid.to_string(),
+                deadline: timestamp + self.timeout,
+                responded: false,
+            },
+        );
+        vec![retained(topic, Value::Object(command).to_string())]
+    }
+
+    fn on_command_update(&mut self, command: &str, payload: &[u8]) -> Vec<Message> {
+        let Some((_, cmd_id)) = command.split_once('/') else {
+            return vec![];
+        };
+        if !cmd_id.starts_with(COMMAND_ID_PREFIX) {
+            return vec![];
+        }
+        let Ok(Value::Object(state)) = serde_json::from_slice::<Value>(payload) else {
+            return vec![];
+        };
+        let status = match state.get("status").and_then(Value::as_str) {
+            Some("successful") => 200,
+            Some("failed") => 500,
+            _ => return vec![],
+        };
+
+        // No response is sent if the method has already timed out,
+        // or if the mapper has been restarted while the command was executed (as on restart)
+        let topic = format!("{}/cmd/{command}", self.entity);
+        let mut messages = vec![];
+        if let Some(pending) = self.pending.remove(&topic).filter(|p| !p.responded) {
+            let response = Value::Object(state).to_string();
+            messages.push(self.response(status, &pending.rid, response));
+        }
+        messages.push(retained(topic, String::new()));
+        messages
+    }
+
+    fn response(&self, status: u16, rid: &str, payload: String) -> Message {
+        let topic = format!("{}/methods/res/{status}/?$rid={rid}", self.prefix);
+        Message::new(topic, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn direct_methods_are_mapped_to_commands() {
+        let mut methods = AzureMethods::default();
+
+        let output = on_message(
+            &mut methods,
+            "az/methods/POST/restart/?$rid=42",
+            r#"{"delay":10}"#,
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///cmd/restart/azure-42".into(),
+                json!({"delay": 10, "status": "init"}).to_string()
+            )]
+        );
+
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/azure-42",
+            r#"{"delay":10, "status":"executing"}"#,
+        );
+        assert!(output.is_empty());
+
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/azure-42",
+            r#"{"delay":10, "status":"successful"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![
+                Message::new(
+                    "az/methods/res/200/?$rid=42",
+                    json!({"delay": 10, "status": "successful"}).to_string()
+                ),
+                retained("te/device/main///cmd/restart/azure-42".into(), "".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn methods_can_be_mapped_to_custom_operations() {
+        let mut methods = AzureMethods::default();
+        methods
+            .set_config(JsonValue::from(
+                json!({"operations": ["reboot:restart", "firmware"]}),
+            ))
+            .unwrap();
+
+        let output = on_message(&mut methods, "az/methods/POST/reboot/?$rid=1", "");
+        assert_eq!(output[0].topic, "te/device/main///cmd/restart/azure-1");
+
+        let output = on_message(&mut methods, "az/methods/POST/firmware/?$rid=2", "null");
+        assert_eq!(output[0].topic, "te/device/main///cmd/firmware/azure-2");
+
+        let output = on_message(&mut methods, "az/methods/POST/restart/?$rid=3", "");
+        assert_eq!(output[0].topic, "az/methods/res/404/?$rid=3");
+    }
+
+    #[test]
+    fn failed_commands_are_reported_as_errors() {
+        let mut methods = AzureMethods::default();
+        on_message(&mut methods, "az/methods/POST/restart/?$rid=7", "{}");
+
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/azure-7",
+            r#"{"status":"failed","reason":"not allowed"}"#,
+        );
+        assert_eq!(output[0].topic, "az/methods/res/500/?$rid=7");
+    }
+
+    #[test]
+    fn a_timeout_is_returned_when_the_command_takes_too_long() {
+        let mut methods = AzureMethods::default();
+        methods
+            .set_config(JsonValue::from(json!({"timeout": "10s"})))
+            .unwrap();
+        let start = SystemTime::now();
+        methods
+            .on_message(
+                start,
+                &Message::new("az/methods/POST/restart/?$rid=5", ""),
+                &FlowContextHandle::default(),
+            )
+            .unwrap();
+
+        let context = FlowContextHandle::default();
+        let output = methods.on_interval(start + Duration::from_secs(5), &context);
+        assert!(output.unwrap().is_empty());
+        let output = methods.on_interval(start + Duration::from_secs(11), &context);
+        assert_eq!(output.unwrap()[0].topic, "az/methods/res/504/?$rid=5");
+
+        // The command is cleared, without a second response, once completed
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/azure-5",
+            r#"{"status":"successful"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///cmd/restart/azure-5".into(),
+                "".into()
+            )]
+        );
+    }
+
+    fn on_message(methods: &mut AzureMethods, topic: &str, payload: &str) -> Vec<Message> {
+        methods
+            .on_message(
+                SystemTime::now(),
+                &Message::new(topic, payload),
+                &FlowContextHandle::default(),
+            )
+            .unwrap()
+    }
+}
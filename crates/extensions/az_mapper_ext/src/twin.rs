@@ -0,0 +1,488 @@
+//! Synchronisation of the twin data of the main device with the Azure IoT Hub device twin
+//!
+//! - The local `twin/*` fragments are pushed as reported properties
+//!   on `$iothub/twin/PATCH/properties/reported/?$rid={rid}`.
+//! - The desired-property patches received on `$iothub/twin/PATCH/properties/desired/?$version={version}`
+//!   are applied as local twin updates or as a command.
+//! - The version of the desired properties is tracked, and the full twin is requested
+//!   on `$iothub/twin/GET/?$rid={rid}` on startup and whenever a patch is received out of order.
+//!   This request is sent again if no response is received in time,
+//!   as the request or its response is lost when sent while the bridge is not connected.
+use serde_json::Map;
+use serde_json::Value;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+use crate::query_param;
+use crate::retained;
+
+#[derive(Clone)]
+pub struct AzureTwin {
+    /// When disabled, all messages are ignored
+    enable: bool,
+
+    /// The bridge topic prefix, e.g. `az`
+    prefix: String,
+
+    /// The topic prefix of the synchronised entity, e.g. `te/device/main//`
+    entity: String,
+
+    /// The command created on desired-property updates, if any
+    desired_command: Option<String>,
+
+    /// The version of the last desired properties applied
+    desired_version: Option<u64>,
+
+    /// The full twin request waiting for a response
+    pending_get: Option<PendingGet>,
+
+    next_rid: u64,
+}
+
+/// How long to wait for the full twin before requesting it again
+const GET_TWIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy)]
+struct PendingGet {
+    rid: u64,
+    deadline: SystemTime,
+}
+
+impl Default for AzureTwin {
+    fn default() -> Self {
+        AzureTwin {
+            enable: true,
+            prefix: "az".to_string(),
+            entity: "te/device/main//".to_string(),
+            desired_command: None,
+            desired_version: None,
+            pending_get: None,
+            next_rid: 1,
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AzureTwin {
+    fn name(&self) -> &str {
+        "azure-twin"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(enable) = config.bool_property("enable") {
+            self.enable = enable;
+        }
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(entity) = config.string_property("entity") {
+            self.entity = entity.to_owned();
+        }
+        self.desired_command = config
+            .string_property("desired_command")
+            .filter(|command| !command.is_empty())
+            .map(str::to_owned);
+        Ok(())
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+        Ok(vec![self.get_twin(timestamp)])
+    }
+
+    fn is_periodic(&self) -> bool {
+        true
+    }
+
+    fn on_interval(
+        &mut self,
+        timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        match self.pending_get {
+            Some(pending) if self.enable && pending.deadline <= timestamp => {
+                Ok(vec![self.get_twin(timestamp)])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if !self.enable {
+            return Ok(vec![]);
+        }
+        let (path, query) = message
+            .topic
+            .split_once('?')
+            .unwrap_or((message.topic.as_str(), ""));
+
+        if let Some(fragment) = path
+            .strip_prefix(&self.entity)
+            .and_then(|suffix| suffix.strip_prefix("/twin/"))
+        {
+            return self.report(fragment, &message.payload);
+        }
+
+        let Some(path) = path
+            .strip_prefix(&self.prefix)
+            .and_then(|path| path.strip_prefix("/twin/"))
+        else {
+            return Ok(vec![]);
+        };
+        if path == "PATCH/properties/desired/" {
+            let version = query_param(query, "$version").and_then(|v| v.parse().ok());
+            return self.on_desired_patch(timestamp, version, &message.payload);
+        }
+        if let Some(status) = path
+            .strip_prefix("res/")
+            .and_then(|status| status.strip_suffix('/'))
+        {
+            let rid = query_param(query, "$rid").and_then(|rid| rid.parse().ok());
+            return self.on_response(status, rid, &message.payload);
+        }
+        Ok(vec![])
+    }
+}
+
+impl AzureTwin {
+    /// Pushes a local twin fragment as a reported property, an empty payload clearing the property
+    fn report(&mut self, fragment: &str, payload: &[u8]) -> Result<Vec<Message>, FlowError> {
+        if fragment.is_empty() {
+            return Ok(vec![]);
+        }
+        let value = if payload.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(payload).map_err(|_| {
+                FlowError::UnsupportedMessage(format!(
+                    "Twin fragment {fragment} is not a JSON value"
+                ))
+            })?
+        };
+        let rid = self.next_rid();
+        let patch = Value::Object(Map::from_iter([(fragment.to_string(), value)]));
+        let topic = format!("{}/twin/PATCH/properties/reported/?$rid={rid}", self.prefix);
+        Ok(vec![Message::new(topic, patch.to_string())])
+    }
+
+    fn on_desired_patch(
+        &mut self,
+        timestamp: SystemTime,
+        version: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        let patch = parse_object(payload)?;
+        let version = version.or_else(|| patch.get("$version").and_then(Value::as_u64));
+        match (self.desired_version, version) {
+            (Some(current), Some(version)) if version <= current => Ok(vec![]),
+            (Some(current), Some(version)) if version == current + 1 => {
+                self.desired_version = Some(version);
+                Ok(self.apply_desired(version, patch))
+            }
+            // A patch has been missed or the current version is unknown
+            _ if self.pending_get.is_some() => Ok(vec![]),
+            _ => Ok(vec![self.get_twin(timestamp)]),
+        }
+    }
+
+    fn on_response(
+        &mut self,
+        status: &str,
+        rid: Option<u64>,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        let is_get_response = rid.is_some() && rid == self.pending_get.map(|pending| pending.rid);
+        if is_get_response {
+            self.pending_get = None;
+        }
+        if !status.starts_with('2') {
+            return Err(FlowError::UnsupportedMessage(format!(
+                "Azure IoT Hub rejected the twin request {} with status {status}",
+                rid.map(|rid| rid.to_string()).unwrap_or_default()
+            )));
+        }
+        if !is_get_response {
+            return Ok(vec![]);
+        }
+
+        let mut twin = parse_object(payload)?;
+        let Some(Value::Object(desired)) = twin.remove("desired") else {
+            return Err(FlowError::UnsupportedMessage(
+                "The device twin has no desired properties".to_string(),
+            ));
+        };
+        let Some(version) = desired.get("$version").and_then(Value::as_u64) else {
+            return Err(FlowError::UnsupportedMessage(
+                "The desired properties of the device twin have no version".to_string(),
+            ));
+        };
+        self.desired_version = Some(version);
+        Ok(self.apply_desired(version, desired))
+    }
+
+    /// Turns desired properties into local twin updates or into a command
+    fn apply_desired(&self, version: u64, desired: Map<String, Value>) -> Vec<Message> {
+        let properties: Map<String, Value> = desired
+            .into_iter()
+            .filter(|(key, _)| !key.starts_with('$'))
+            .collect();
+
+        if let Some(command) = &self.desired_command {
+            let topic = format!("{}/cmd/{command}/azure-twin-{version}", self.entity);
+            let payload = serde_json::json!({
+                "status": "init",
+                "version": version,
+                "desired": properties,
+            });
+            return vec![retained(topic, payload.to_string())];
+        }
+
+        properties
+            .into_iter()
+            .map(|(fragment, value)| {
+                let topic = format!("{}/twin/{fragment}", self.entity);
+                let payload = match value {
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+                retained(topic, payload)
+            })
+            .collect()
+    }
+
+    /// Requests the full twin, superseding any previous request
+    fn get_twin(&mut self, timestamp: SystemTime) -> Message {
+        let rid = self.next_rid();
+        self.pending_get = Some(PendingGet {
+            rid,
+            deadline: timestamp + GET_TWIN_TIMEOUT,
+        });
+        Message::new(format!("{}/twin/GET/?$rid={rid}", self.prefix), "")
+    }
+
+    fn next_rid(&mut self) -> u64 {
+        let rid = self.next_rid;
+        self.next_rid += 1;
+        rid
+    }
+}
+
+fn parse_object(payload: &[u8]) -> Result<Map<String, Value>, FlowError> {
+    match serde_json::from_slice(payload) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Err(FlowError::UnsupportedMessage(
+            "Expected a JSON object from Azure IoT Hub".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn local_twin_fragments_are_reported() {
+        let mut twin = AzureTwin::default();
+
+        let output = on_message(
+            &mut twin,
+            "te/device/main///twin/firmware",
+            r#"{"version":"1.0"}"#,
+        );
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "az/twin/PATCH/properties/reported/?$rid=1",
+                json!({"firmware": {"version": "1.0"}}).to_string()
+            )]
+        );
+
+        let output = on_message(&mut twin, "te/device/main///twin/firmware", "");
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "az/twin/PATCH/properties/reported/?$rid=2",
+                json!({"firmware": null}).to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn desired_properties_are_applied_in_order() {
+        let mut twin = AzureTwin::default();
+        let get = twin.on_startup(SystemTime::now(), &FlowContextHandle::default());
+        assert_eq!(get.unwrap(), vec![Message::new("az/twin/GET/?$rid=1", "")]);
+
+        let output = on_message(
+            &mut twin,
+            "az/twin/res/200/?$rid=1",
+            &json!({
+                "desired": {"interval": 10, "$version": 4},
+                "reported": {"$version": 1},
+            })
+            .to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///twin/interval".into(),
+                "10".into()
+            )]
+        );
+
+        let output = on_message(
+            &mut twin,
+            "az/twin/PATCH/properties/desired/?$version=5",
+            &json!({"interval": 20, "$version": 5}).to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///twin/interval".into(),
+                "20".into()
+            )]
+        );
+
+        // Stale patches are ignored
+        let output = on_message(
+            &mut twin,
+            "az/twin/PATCH/properties/desired/?$version=5",
+            &json!({"interval": 20, "$version": 5}).to_string(),
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn out_of_order_patches_trigger_a_full_twin_request() {
+        let mut twin = AzureTwin::default();
+        twin.desired_version = Some(1);
+
+        let output = on_message(
+            &mut twin,
+            "az/twin/PATCH/properties/desired/?$version=3",
+            &json!({"interval": 30, "$version": 3}).to_string(),
+        );
+        assert_eq!(output, vec![Message::new("az/twin/GET/?$rid=1", "")]);
+
+        // No other request is sent while waiting for the full twin
+        let output = on_message(
+            &mut twin,
+            "az/twin/PATCH/properties/desired/?$version=4",
+            &json!({"interval": 40, "$version": 4}).to_string(),
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn the_full_twin_is_requested_again_when_no_response_is_received() {
+        let mut twin = AzureTwin::default();
+        let start = SystemTime::now();
+        let get = twin.on_startup(start, &FlowContextHandle::default());
+        assert_eq!(get.unwrap(), vec![Message::new("az/twin/GET/?$rid=1", "")]);
+
+        let output = twin
+            .on_interval(
+                start + Duration::from_secs(1),
+                &FlowContextHandle::default(),
+            )
+            .unwrap();
+        assert!(output.is_empty());
+
+        let output = twin
+            .on_interval(start + GET_TWIN_TIMEOUT, &FlowContextHandle::default())
+            .unwrap();
+        assert_eq!(output, vec![Message::new("az/twin/GET/?$rid=2", "")]);
+
+        // Only the response to the latest request is applied
+        let output = on_message(
+            &mut twin,
+            "az/twin/res/200/?$rid=2",
+            &json!({"desired": {"interval": 10, "$version": 4}}).to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///twin/interval".into(),
+                "10".into()
+            )]
+        );
+        let output = twin
+            .on_interval(start + 3 * GET_TWIN_TIMEOUT, &FlowContextHandle::default())
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn desired_properties_can_be_mapped_to_a_command() {
+        let mut twin = AzureTwin::default();
+        twin.set_config(JsonValue::from(json!({"desired_command": "set_desired"})))
+            .unwrap();
+        twin.desired_version = Some(7);
+
+        let output = on_message(
+            &mut twin,
+            "az/twin/PATCH/properties/desired/?$version=8",
+            &json!({"interval": 30, "$version": 8}).to_string(),
+        );
+        assert_eq!(
+            output,
+            vec![retained(
+                "te/device/main///cmd/set_desired/azure-twin-8".into(),
+                json!({"status": "init", "version": 8, "desired": {"interval": 30}}).to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn nothing_is_synchronised_when_disabled() {
+        let mut twin = AzureTwin::default();
+        twin.set_config(JsonValue::from(json!({"enable": false})))
+            .unwrap();
+
+        let get = twin.on_startup(SystemTime::now(), &FlowContextHandle::default());
+        assert!(get.unwrap().is_empty());
+        let output = on_message(&mut twin, "te/device/main///twin/firmware", "{}");
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn rejected_requests_are_reported_as_errors() {
+        let mut twin = AzureTwin::default();
+        let result = twin.on_message(
+            SystemTime::now(),
+            &Message::new("az/twin/res/400/?$rid=3", ""),
+            &FlowContextHandle::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    fn on_message(twin: &mut AzureTwin, topic: &str, payload: &str) -> Vec<Message> {
+        twin.on_message(
+            SystemTime::now(),
+            &Message::new(topic, payload),
+            &FlowContextHandle::default(),
+        )
+        .unwrap()
+    }
+}
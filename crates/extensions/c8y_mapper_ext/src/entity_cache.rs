@@ -232,6 +232,7 @@ impl EntityCache {
             r#type: entity.r#type,
             parent,
             health_endpoint: entity.health_endpoint,
+            ttl: entity.ttl,
             twin_data: entity.twin_data,
         };
 
@@ -280,6 +281,7 @@ impl EntityCache {
                 .health_endpoint
                 .clone()
                 .or_else(|| existing_entity.health_endpoint.clone()),
+            ttl: entity.ttl.or(existing_entity.ttl),
             twin_data: existing_entity.twin_data.clone(),
         };
 
@@ -550,6 +552,7 @@ impl From<FlowContextEntity> for CloudEntityMetadata {
             r#type: context.r#type,
             external_id: Some(external_id.clone()),
             health_endpoint: context.health_endpoint,
+            ttl: None,
             twin_data,
         };
 
@@ -621,6 +624,7 @@ mod tests {
             external_id: Some("bad+id".into()),
             parent: None,
             health_endpoint: None,
+            ttl: None,
             twin_data: Map::new(),
         });
 
@@ -4,6 +4,8 @@ use crate::mea::get_entity_parent_metadata;
 use crate::service_monitor::convert_health_status_message;
 use std::str::FromStr;
 use std::time::SystemTime;
+use tedge_api::health::HealthStatus;
+use tedge_api::health::Status;
 use tedge_api::mqtt_topics::Channel;
 use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
@@ -19,6 +21,7 @@ pub struct HealthStatusConverter {
     mqtt_schema: MqttSchema,
     main_device: EntityTopicId,
     c8y_prefix: TopicPrefix,
+    bridge_status: Option<Status>,
 }
 
 impl Default for HealthStatusConverter {
@@ -27,6 +30,7 @@ impl Default for HealthStatusConverter {
             mqtt_schema: MqttSchema::default(),
             main_device: EntityTopicId::default_main_device(),
             c8y_prefix: TopicPrefix::try_new("c8y").unwrap(),
+            bridge_status: None,
         }
     }
 }
@@ -64,6 +68,9 @@ impl tedge_flows::Transformer for HealthStatusConverter {
                 let Some(entity) = get_entity_metadata(context, entity_id.as_str()) else {
                     return Ok(vec![]);
                 };
+                if self.is_unchanged_bridge_status(&entity, message) {
+                    return Ok(vec![]);
+                }
                 self.convert(context, entity, message)
             }
 
@@ -76,6 +83,22 @@ impl tedge_flows::Transformer for HealthStatusConverter {
 }
 
 impl HealthStatusConverter {
+    /// The built-in bridge republishes its health status when the depth of its queues changes:
+    /// only actual status changes are forwarded to Cumulocity
+    fn is_unchanged_bridge_status(
+        &mut self,
+        entity: &CloudEntityMetadata,
+        message: &Message,
+    ) -> bool {
+        if entity.display_name() != format!("tedge-mapper-bridge-{}", self.c8y_prefix) {
+            return false;
+        }
+        let status = serde_json::from_slice::<HealthStatus>(&message.payload)
+            .unwrap_or_default()
+            .status;
+        self.bridge_status.replace(status.clone()) == Some(status)
+    }
+
     pub fn convert(
         &self,
         context: &FlowContextHandle,
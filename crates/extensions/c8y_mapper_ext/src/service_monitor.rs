@@ -207,6 +207,7 @@ mod tests {
                 r#type: EntityType::Service,
                 parent,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             },
         );
@@ -300,6 +301,7 @@ mod tests {
                 r#type: EntityType::Service,
                 parent,
                 health_endpoint: None,
+                ttl: None,
                 twin_data: Map::new(),
             },
         );
@@ -0,0 +1,81 @@
+use crate::converter::SparkplugConverter;
+use async_trait::async_trait;
+use std::convert::Infallible;
+use tedge_actors::Actor;
+use tedge_actors::Builder;
+use tedge_actors::DynSender;
+use tedge_actors::MessageReceiver;
+use tedge_actors::MessageSink;
+use tedge_actors::MessageSource;
+use tedge_actors::NoConfig;
+use tedge_actors::RuntimeError;
+use tedge_actors::RuntimeRequest;
+use tedge_actors::RuntimeRequestSink;
+use tedge_actors::Sender;
+use tedge_actors::SimpleMessageBox;
+use tedge_actors::SimpleMessageBoxBuilder;
+use tedge_mqtt_ext::MqttMessage;
+use tedge_mqtt_ext::TopicFilter;
+
+/// An actor publishing the thin-edge device and its child devices as a Sparkplug B edge node
+pub struct SparkplugActor {
+    converter: SparkplugConverter,
+    messages: SimpleMessageBox<MqttMessage, MqttMessage>,
+}
+
+#[async_trait]
+impl Actor for SparkplugActor {
+    fn name(&self) -> &str {
+        "Sparkplug"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        while let Some(message) = self.messages.recv().await {
+            for message in self.converter.convert(&message) {
+                self.messages.send(message).await?
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct SparkplugActorBuilder {
+    converter: SparkplugConverter,
+    message_box: SimpleMessageBoxBuilder<MqttMessage, MqttMessage>,
+}
+
+impl SparkplugActorBuilder {
+    pub fn new(
+        converter: SparkplugConverter,
+        mqtt: &mut (impl MessageSource<MqttMessage, TopicFilter> + MessageSink<MqttMessage>),
+    ) -> Self {
+        let mut message_box = SimpleMessageBoxBuilder::new("Sparkplug", 16);
+        mqtt.connect_sink(converter.input_topics(), &message_box);
+        message_box.connect_sink(NoConfig, mqtt);
+        SparkplugActorBuilder {
+            converter,
+            message_box,
+        }
+    }
+}
+
+impl RuntimeRequestSink for SparkplugActorBuilder {
+    fn get_signal_sender(&self) -> DynSender<RuntimeRequest> {
+        self.message_box.get_signal_sender()
+    }
+}
+
+impl Builder<SparkplugActor> for SparkplugActorBuilder {
+    type Error = Infallible;
+
+    fn try_build(self) -> Result<SparkplugActor, Self::Error> {
+        Ok(self.build())
+    }
+
+    fn build(self) -> SparkplugActor {
+        SparkplugActor {
+            converter: self.converter,
+            messages: self.message_box.build(),
+        }
+    }
+}
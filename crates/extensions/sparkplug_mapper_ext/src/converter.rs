@@ -0,0 +1,834 @@
+use crate::payload::Metric;
+use crate::payload::MetricValue;
+use crate::payload::Payload;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tedge_api::entity::EntityMetadata;
+use tedge_api::entity::EntityType;
+use tedge_api::entity_store::EntityRegistrationMessage;
+use tedge_api::entity_store::EntityStore;
+use tedge_api::health::HealthStatus;
+use tedge_api::health::Status;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::ChannelFilter;
+use tedge_api::mqtt_topics::EntityFilter;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::IdGenerator;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_mqtt_ext::MqttMessage;
+use tedge_mqtt_ext::QoS;
+use tedge_mqtt_ext::Topic;
+use tedge_mqtt_ext::TopicFilter;
+use tracing::debug;
+use tracing::warn;
+
+/// The Sparkplug B topic namespace
+pub const NAMESPACE: &str = "spBv1.0";
+
+const BD_SEQ: &str = "bdSeq";
+const REBIRTH: &str = "Node Control/Rebirth";
+const NODE_REBOOT: &str = "Node Control/Reboot";
+const DEVICE_REBOOT: &str = "Device Control/Reboot";
+const COMMANDS: &str = "Commands/";
+
+#[derive(Clone, Debug)]
+pub struct SparkplugConfig {
+    /// The local topic prefix of the Sparkplug messages, as forwarded by the bridge, e.g. `sp`
+    pub topic_prefix: String,
+    pub group_id: String,
+    pub edge_node_id: String,
+}
+
+impl SparkplugConfig {
+    /// The death certificate of the node, to be registered as the last will of a Sparkplug connection
+    ///
+    /// Returns the Sparkplug topic, i.e. without the local topic prefix, along with the payload.
+    pub fn node_death_certificate(&self, bd_seq: u64) -> (String, Vec<u8>) {
+        let topic = format!("{NAMESPACE}/{}/NDEATH/{}", self.group_id, self.edge_node_id);
+        let payload = Payload::new(
+            now_millis(),
+            None,
+            vec![Metric::new(BD_SEQ, MetricValue::Int64(bd_seq as i64))],
+        );
+        (topic, payload.encode())
+    }
+}
+
+/// The birth/death sequence number, shared by the converter and the last will of the Sparkplug connection
+///
+/// A new `bdSeq`, in the range 0-255, is used for each connection to the Sparkplug broker.
+#[derive(Clone, Debug)]
+pub struct BdSeq(Arc<AtomicU64>);
+
+impl BdSeq {
+    /// Continues the sequence from the `bdSeq` of a previous connection, if any
+    pub fn new(previous: Option<u64>) -> Self {
+        BdSeq(Arc::new(AtomicU64::new(
+            previous.map_or(u64::MAX, |bd_seq| bd_seq % 256),
+        )))
+    }
+
+    /// Moves to the `bdSeq` of a new connection, returning it
+    pub fn next(&self) -> u64 {
+        let next = |bd_seq: u64| match bd_seq {
+            u64::MAX => 0,
+            bd_seq => (bd_seq + 1) % 256,
+        };
+        let previous = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |bd_seq| {
+                Some(next(bd_seq))
+            })
+            .unwrap_or_else(|bd_seq| bd_seq);
+        next(previous)
+    }
+
+    /// The `bdSeq` of the current connection
+    pub fn current(&self) -> u64 {
+        match self.0.load(Ordering::SeqCst) {
+            u64::MAX => 0,
+            bd_seq => bd_seq,
+        }
+    }
+}
+
+/// Publishes the thin-edge device as a Sparkplug B edge node, and its child devices as Sparkplug devices
+///
+/// - The node and its devices are born each time the bridge to the Sparkplug broker is up,
+///   and on rebirth requests (`Node Control/Rebirth`).
+/// - A device is born when registered while the node is online, and dies when deregistered.
+/// - The measurements of the main device and its services are published as `NDATA`,
+///   those of a child device and its services as `DDATA`.
+///   A new metric triggers a rebirth, as birth certificates must list all the metrics.
+/// - The `NCMD` and `DCMD` messages are mapped to thin-edge commands.
+pub struct SparkplugConverter {
+    config: SparkplugConfig,
+    mqtt_schema: MqttSchema,
+    entity_store: EntityStore,
+    bridge_health_topic: Topic,
+    /// The birth/death sequence number of the current MQTT session
+    bd_seq: BdSeq,
+    /// The sequence number of the next message published by the node
+    seq: u64,
+    online: bool,
+    /// The latest metrics of the node and of each device, indexed by device entity
+    metrics: HashMap<Option<EntityTopicId>, BTreeMap<String, MetricValue>>,
+    /// The devices which birth certificate has been published, indexed by Sparkplug device id
+    born_devices: BTreeMap<String, EntityTopicId>,
+    command_ids: IdGenerator,
+    command_count: u64,
+}
+
+impl SparkplugConverter {
+    pub fn new(
+        config: SparkplugConfig,
+        mqtt_schema: MqttSchema,
+        entity_store: EntityStore,
+        bridge_health_topic: Topic,
+        bd_seq: BdSeq,
+    ) -> Self {
+        SparkplugConverter {
+            config,
+            mqtt_schema,
+            entity_store,
+            bridge_health_topic,
+            bd_seq,
+            seq: 0,
+            online: false,
+            metrics: HashMap::new(),
+            born_devices: BTreeMap::new(),
+            command_ids: IdGenerator::new("sparkplug"),
+            command_count: 0,
+        }
+    }
+
+    pub fn input_topics(&self) -> TopicFilter {
+        let mut topics = TopicFilter::empty();
+        for channel in [
+            ChannelFilter::EntityMetadata,
+            ChannelFilter::Measurement,
+            ChannelFilter::AnyCommand,
+        ] {
+            topics.add_all(self.mqtt_schema.topics(EntityFilter::AnyEntity, channel));
+        }
+        let node_commands = self.sparkplug_topic("NCMD", None);
+        let device_commands = self.sparkplug_topic("DCMD", Some("+"));
+        for topic in [
+            &self.bridge_health_topic.name,
+            &node_commands.name,
+            &device_commands.name,
+        ] {
+            topics.add_unchecked(topic);
+        }
+        topics
+    }
+
+    pub fn convert(&mut self, message: &MqttMessage) -> Vec<MqttMessage> {
+        let topic = &message.topic.name;
+        let payload = message.payload_bytes();
+        if topic == &self.bridge_health_topic.name {
+            return self.process_bridge_health(payload);
+        }
+        if let Some(command) = topic.strip_prefix(&self.sparkplug_topic_prefix()) {
+            return self.process_sparkplug_command(command, payload);
+        }
+        match self.mqtt_schema.entity_channel_of(topic) {
+            Ok((entity, Channel::EntityMetadata)) => self.process_registration(entity, payload),
+            Ok((entity, Channel::Measurement { measurement_type })) => {
+                self.process_measurement(&entity, &measurement_type, payload)
+            }
+            Ok((_, Channel::Command { cmd_id, .. })) => {
+                self.process_command_status(message, &cmd_id)
+            }
+            _ => vec![],
+        }
+    }
+
+    fn process_bridge_health(&mut self, payload: &[u8]) -> Vec<MqttMessage> {
+        let status = serde_json::from_slice::<HealthStatus>(payload)
+            .map(|health| health.status)
+            .unwrap_or_default();
+        if status == Status::Up {
+            self.online = true;
+            self.rebirth()
+        } else {
+            self.online = false;
+            self.born_devices.clear();
+            vec![]
+        }
+    }
+
+    fn process_registration(&mut self, entity: EntityTopicId, payload: &[u8]) -> Vec<MqttMessage> {
+        if payload.is_empty() {
+            let mut messages = Vec::new();
+            for removed in self.entity_store.deregister_entity(&entity) {
+                self.metrics.remove(&Some(removed.topic_id.clone()));
+                if removed.r#type == EntityType::ChildDevice {
+                    let device_id = sparkplug_device_id(&removed);
+                    if self.born_devices.remove(&device_id).is_some() {
+                        messages.push(self.device_death(&device_id));
+                    }
+                }
+            }
+            return messages;
+        }
+
+        let registration = match EntityRegistrationMessage::try_from(entity.clone(), payload) {
+            Ok(registration) => registration,
+            Err(err) => {
+                warn!("Ignoring invalid registration of {entity}: {err}");
+                return vec![];
+            }
+        };
+        let registered = match self.entity_store.update(registration) {
+            Ok(registered) => registered,
+            Err(err) => {
+                warn!("Failed to register {entity}: {err}");
+                return vec![];
+            }
+        };
+
+        let mut messages = Vec::new();
+        for entity in registered {
+            let entity = entity.reg_message.topic_id;
+            if self.online && self.is_child_device(&entity) {
+                messages.extend(self.device_birth(&entity));
+            }
+        }
+        messages
+    }
+
+    fn process_measurement(
+        &mut self,
+        entity: &EntityTopicId,
+        measurement_type: &str,
+        payload: &[u8],
+    ) -> Vec<MqttMessage> {
+        let Some((device, metric_prefix)) = self.sparkplug_device_of(entity) else {
+            debug!("Ignoring measurement of unregistered entity {entity}");
+            return vec![];
+        };
+        let Ok(Value::Object(measurement)) = serde_json::from_slice(payload) else {
+            warn!("Ignoring invalid measurement on {entity}");
+            return vec![];
+        };
+
+        let mut prefix = metric_prefix;
+        if !measurement_type.is_empty() {
+            prefix.push(measurement_type.to_string());
+        }
+        let mut metrics = Vec::new();
+        flatten_measurement(&prefix, &measurement, &mut metrics);
+        if metrics.is_empty() {
+            return vec![];
+        }
+
+        let known_metrics = self.metrics.entry(device.clone()).or_default();
+        let new_metric = metrics
+            .iter()
+            .any(|metric| !known_metrics.contains_key(&metric.name));
+        for metric in &metrics {
+            known_metrics.insert(metric.name.clone(), metric.value.clone());
+        }
+
+        if !self.online {
+            return vec![];
+        }
+        match device {
+            None if new_metric => self.rebirth(),
+            None => vec![self.node_data(metrics)],
+            Some(device) if new_metric => self.device_birth(&device).into_iter().collect(),
+            Some(device) => {
+                let device_id = self.sparkplug_device_id_of(&device);
+                if self.born_devices.contains_key(&device_id) {
+                    vec![self.device_data(&device_id, metrics)]
+                } else {
+                    self.device_birth(&device).into_iter().collect()
+                }
+            }
+        }
+    }
+
+    fn process_sparkplug_command(&mut self, topic: &str, payload: &[u8]) -> Vec<MqttMessage> {
+        let payload = match Payload::decode(payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Ignoring invalid Sparkplug command: {err}");
+                return vec![];
+            }
+        };
+
+        let target = match topic.split('/').collect::<Vec<_>>()[..] {
+            ["NCMD", node] if node == self.config.edge_node_id => {
+                self.entity_store.main_device().clone()
+            }
+            ["DCMD", node, device] if node == self.config.edge_node_id => {
+                match self.born_devices.get(device) {
+                    Some(entity) => entity.clone(),
+                    None => {
+                        warn!("Ignoring command for unknown Sparkplug device {device}");
+                        return vec![];
+                    }
+                }
+            }
+            _ => return vec![],
+        };
+
+        let mut messages = Vec::new();
+        for metric in payload.metrics {
+            match (metric.name.as_str(), &metric.value) {
+                (REBIRTH, MetricValue::Boolean(true)) if self.online => {
+                    messages.extend(self.rebirth())
+                }
+                (NODE_REBOOT | DEVICE_REBOOT, MetricValue::Boolean(true)) => {
+                    messages.push(self.new_command(&target, "restart", Map::new()))
+                }
+                (name, value) => match name.strip_prefix(COMMANDS) {
+                    Some(operation)
+                        if !operation.is_empty() && !operation.contains(['/', '+', '#']) =>
+                    {
+                        match command_payload(value) {
+                            Some(payload) => {
+                                messages.push(self.new_command(&target, operation, payload))
+                            }
+                            None => warn!("Ignoring {name} command with invalid payload"),
+                        }
+                    }
+                    _ => debug!("Ignoring unsupported Sparkplug command metric {name}"),
+                },
+            }
+        }
+        messages
+    }
+
+    /// Clears the commands created from Sparkplug commands once completed
+    fn process_command_status(&mut self, message: &MqttMessage, cmd_id: &str) -> Vec<MqttMessage> {
+        if !self.command_ids.is_generator_of(cmd_id) {
+            return vec![];
+        }
+        let status = serde_json::from_slice::<Value>(message.payload_bytes())
+            .ok()
+            .and_then(|payload| payload.get("status")?.as_str().map(str::to_string));
+        match status.as_deref() {
+            Some("successful") | Some("failed") => vec![MqttMessage::new(&message.topic, "")
+                .with_qos(QoS::AtLeastOnce)
+                .with_retain()],
+            _ => vec![],
+        }
+    }
+
+    fn new_command(
+        &mut self,
+        target: &EntityTopicId,
+        operation: &str,
+        mut payload: Map<String, Value>,
+    ) -> MqttMessage {
+        self.command_count += 1;
+        let cmd_id =
+            self.command_ids
+                .new_id_with_str(&format!("{}-{}", now_millis(), self.command_count));
+        let channel = Channel::Command {
+            operation: operation.into(),
+            cmd_id,
+        };
+        payload.insert("status".to_string(), "init".into());
+        MqttMessage::new(
+            &self.mqtt_schema.topic_for(target, &channel),
+            Value::Object(payload).to_string(),
+        )
+        .with_qos(QoS::AtLeastOnce)
+        .with_retain()
+    }
+
+    /// Publishes the birth certificates of the node and of all its devices
+    fn rebirth(&mut self) -> Vec<MqttMessage> {
+        self.seq = 0;
+        self.born_devices.clear();
+
+        let mut metrics = vec![
+            Metric::new(BD_SEQ, MetricValue::Int64(self.bd_seq.current() as i64)),
+            Metric::new(REBIRTH, MetricValue::Boolean(false)),
+        ];
+        metrics.extend(self.current_metrics(&None));
+        let mut messages = vec![self.publish("NBIRTH", None, metrics)];
+
+        let devices: Vec<EntityTopicId> = self
+            .entity_store
+            .entity_topic_ids()
+            .filter(|entity| self.is_child_device(entity))
+            .cloned()
+            .collect();
+        for device in devices {
+            messages.extend(self.device_birth(&device));
+        }
+        messages
+    }
+
+    fn device_birth(&mut self, device: &EntityTopicId) -> Option<MqttMessage> {
+        let device_id = self.sparkplug_device_id_of(device);
+        if device_id.is_empty() {
+            return None;
+        }
+        let metrics = self.current_metrics(&Some(device.clone()));
+        self.born_devices.insert(device_id.clone(), device.clone());
+        Some(self.publish("DBIRTH", Some(&device_id), metrics))
+    }
+
+    fn device_death(&mut self, device_id: &str) -> MqttMessage {
+        self.publish("DDEATH", Some(device_id), vec![])
+    }
+
+    fn node_data(&mut self, metrics: Vec<Metric>) -> MqttMessage {
+        self.publish("NDATA", None, metrics)
+    }
+
+    fn device_data(&mut self, device_id: &str, metrics: Vec<Metric>) -> MqttMessage {
+        self.publish("DDATA", Some(device_id), metrics)
+    }
+
+    /// Builds a birth, death or data message, sent at QoS 0 and not retained as required by Sparkplug B
+    ///
+    /// Redelivered on reconnect, such messages would be received with a stale `seq`.
+    fn publish(
+        &mut self,
+        message_type: &str,
+        device_id: Option<&str>,
+        metrics: Vec<Metric>,
+    ) -> MqttMessage {
+        let seq = self.seq;
+        self.seq = (self.seq + 1) % 256;
+        let payload = Payload::new(now_millis(), Some(seq), metrics);
+        MqttMessage::new(
+            &self.sparkplug_topic(message_type, device_id),
+            payload.encode(),
+        )
+        .with_qos(QoS::AtMostOnce)
+    }
+
+    fn current_metrics(&self, device: &Option<EntityTopicId>) -> Vec<Metric> {
+        self.metrics
+            .get(device)
+            .into_iter()
+            .flatten()
+            .map(|(name, value)| Metric::new(name, value.clone()))
+            .collect()
+    }
+
+    fn sparkplug_topic_prefix(&self) -> String {
+        format!(
+            "{}/{NAMESPACE}/{}/",
+            self.config.topic_prefix, self.config.group_id
+        )
+    }
+
+    fn sparkplug_topic(&self, message_type: &str, device_id: Option<&str>) -> Topic {
+        let mut topic = format!(
+            "{}{message_type}/{}",
+            self.sparkplug_topic_prefix(),
+            self.config.edge_node_id
+        );
+        if let Some(device_id) = device_id {
+            topic.push('/');
+            topic.push_str(device_id);
+        }
+        Topic::new_unchecked(&topic)
+    }
+
+    fn is_child_device(&self, entity: &EntityTopicId) -> bool {
+        self.entity_store
+            .get(entity)
+            .is_some_and(|metadata| metadata.r#type == EntityType::ChildDevice)
+    }
+
+    fn sparkplug_device_id_of(&self, device: &EntityTopicId) -> String {
+        self.entity_store
+            .get(device)
+            .map(sparkplug_device_id)
+            .unwrap_or_default()
+    }
+
+    /// The Sparkplug device of an entity, `None` standing for the node, along with the metric name prefix
+    ///
+    /// The metrics of a service are published by its device, prefixed by the service name.
+    fn sparkplug_device_of(
+        &self,
+        entity: &EntityTopicId,
+    ) -> Option<(Option<EntityTopicId>, Vec<String>)> {
+        let metadata = self.entity_store.get(entity)?;
+        match metadata.r#type {
+            EntityType::MainDevice => Some((None, vec![])),
+            EntityType::ChildDevice => Some((Some(entity.clone()), vec![])),
+            EntityType::Service => {
+                let service_name = entity
+                    .default_service_name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| sparkplug_device_id(metadata));
+                let parent = metadata.parent.as_ref()?;
+                let (device, _) = self.sparkplug_device_of(parent)?;
+                Some((device, vec![service_name]))
+            }
+        }
+    }
+}
+
+/// The Sparkplug id of a child device: its external id, falling back to its default device name
+///
+/// The characters forbidden in a Sparkplug topic level are replaced by `_`.
+fn sparkplug_device_id(metadata: &EntityMetadata) -> String {
+    let id = match (
+        &metadata.external_id,
+        metadata.topic_id.default_device_name(),
+    ) {
+        (Some(external_id), _) => external_id.as_ref().to_string(),
+        (None, Some(name)) => name.to_string(),
+        (None, None) => metadata.topic_id.to_string().trim_matches('/').to_string(),
+    };
+    id.replace(['/', '+', '#'], "_")
+}
+
+/// Flattens a thin-edge measurement into Sparkplug metrics, the names of nested values being joined by `/`
+fn flatten_measurement(
+    prefix: &[String],
+    measurement: &Map<String, Value>,
+    metrics: &mut Vec<Metric>,
+) {
+    for (key, value) in measurement {
+        if prefix.is_empty() && key == "time" {
+            continue;
+        }
+        let mut name = prefix.to_vec();
+        name.push(key.clone());
+        match value {
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    metrics.push(Metric::new(name.join("/"), MetricValue::Double(number)))
+                }
+            }
+            Value::Object(group) => flatten_measurement(&name, group, metrics),
+            _ => (),
+        }
+    }
+}
+
+/// The payload of a command created from a `Commands/<operation>` metric
+///
+/// A string metric must be a JSON object, a boolean `true` standing for an empty payload,
+/// and other values being given as `{"value": <value>}`.
+fn command_payload(value: &MetricValue) -> Option<Map<String, Value>> {
+    match value {
+        MetricValue::String(json) => serde_json::from_str(json).ok(),
+        MetricValue::Boolean(true) => Some(Map::new()),
+        MetricValue::Boolean(false) | MetricValue::Null => None,
+        value => {
+            let mut payload = Map::new();
+            payload.insert("value".to_string(), value.to_json());
+            Some(payload)
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const HEALTH: &str = "te/device/main/service/tedge-mapper-bridge-sparkplug/status/health";
+
+    #[test]
+    fn births_are_published_once_the_bridge_is_up() {
+        let (_dir, mut converter) = converter();
+        assert!(converter
+            .convert(&message(
+                "te/device/child1//",
+                r#"{"@type":"child-device"}"#
+            ))
+            .is_empty());
+
+        let messages = converter.convert(&message(HEALTH, r#"{"status":"up"}"#));
+
+        assert_eq!(
+            topics(&messages),
+            [
+                "sp/spBv1.0/plant/NBIRTH/edge",
+                "sp/spBv1.0/plant/DBIRTH/edge/child1"
+            ]
+        );
+        let nbirth = decode(&messages[0]);
+        assert_eq!(nbirth.seq, Some(0));
+        assert_eq!(nbirth.metric("bdSeq").unwrap().value, MetricValue::Int64(7));
+        assert_eq!(decode(&messages[1]).seq, Some(1));
+    }
+
+    #[test]
+    fn measurements_are_published_as_data() {
+        let (_dir, mut converter) = online_converter();
+        converter.convert(&message(
+            "te/device/child1//",
+            r#"{"@type":"child-device"}"#,
+        ));
+
+        // A new metric triggers a rebirth of the device
+        let messages = converter.convert(&message(
+            "te/device/child1///m/env",
+            r#"{"temperature": 21.5, "time": "2026-10-18T10:00:00Z"}"#,
+        ));
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/DBIRTH/edge/child1"]);
+        assert_eq!(messages[0].qos, QoS::AtMostOnce);
+        assert_eq!(
+            decode(&messages[0])
+                .metric("env/temperature")
+                .unwrap()
+                .value,
+            MetricValue::Double(21.5)
+        );
+
+        let messages = converter.convert(&message(
+            "te/device/child1///m/env",
+            r#"{"temperature": 22}"#,
+        ));
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/DDATA/edge/child1"]);
+        assert_eq!(
+            decode(&messages[0]).metrics,
+            vec![Metric::new("env/temperature", MetricValue::Double(22.0))]
+        );
+        assert_eq!(messages[0].qos, QoS::AtMostOnce);
+        assert!(!messages[0].retain);
+    }
+
+    #[test]
+    fn new_node_metrics_trigger_a_rebirth() {
+        let (_dir, mut converter) = online_converter();
+        converter.convert(&message(
+            "te/device/main/service/pump",
+            r#"{"@type":"service"}"#,
+        ));
+
+        let messages = converter.convert(&message(
+            "te/device/main/service/pump/m/",
+            r#"{"pressure": {"inlet": 2.5}}"#,
+        ));
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/NBIRTH/edge"]);
+        let nbirth = decode(&messages[0]);
+        assert_eq!(nbirth.seq, Some(0));
+        assert_eq!(
+            nbirth.metric("pump/pressure/inlet").unwrap().value,
+            MetricValue::Double(2.5)
+        );
+
+        let messages = converter.convert(&message(
+            "te/device/main/service/pump/m/",
+            r#"{"pressure": {"inlet": 2.7}}"#,
+        ));
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/NDATA/edge"]);
+        assert_eq!(decode(&messages[0]).seq, Some(1));
+    }
+
+    #[test]
+    fn rebirth_requests_are_honored() {
+        let (_dir, mut converter) = online_converter();
+
+        let messages = converter.convert(&sparkplug_command(
+            "sp/spBv1.0/plant/NCMD/edge",
+            Metric::new("Node Control/Rebirth", MetricValue::Boolean(true)),
+        ));
+
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/NBIRTH/edge"]);
+    }
+
+    #[test]
+    fn sparkplug_commands_are_mapped_to_thin_edge_commands() {
+        let (_dir, mut converter) = online_converter();
+        converter.convert(&message(
+            "te/device/child1//",
+            r#"{"@type":"child-device"}"#,
+        ));
+        converter.convert(&message(HEALTH, r#"{"status":"up"}"#));
+
+        let messages = converter.convert(&sparkplug_command(
+            "sp/spBv1.0/plant/DCMD/edge/child1",
+            Metric::new(
+                "Commands/software_update",
+                MetricValue::String(r#"{"updateList":[]}"#.into()),
+            ),
+        ));
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0]
+            .topic
+            .name
+            .starts_with("te/device/child1///cmd/software_update/sparkplug-"));
+        assert_eq!(
+            serde_json::from_slice::<Value>(messages[0].payload_bytes()).unwrap(),
+            serde_json::json!({"status": "init", "updateList": []})
+        );
+
+        let messages = converter.convert(&sparkplug_command(
+            "sp/spBv1.0/plant/NCMD/edge",
+            Metric::new("Node Control/Reboot", MetricValue::Boolean(true)),
+        ));
+        assert!(messages[0]
+            .topic
+            .name
+            .starts_with("te/device/main///cmd/restart/sparkplug-"));
+
+        // The command is cleared once completed
+        let status = message(&messages[0].topic.name, r#"{"status":"successful"}"#);
+        let messages = converter.convert(&status);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].payload_bytes().is_empty());
+        assert!(messages[0].retain);
+    }
+
+    #[test]
+    fn deregistered_devices_die() {
+        let (_dir, mut converter) = online_converter();
+        converter.convert(&message(
+            "te/device/child1//",
+            r#"{"@type":"child-device"}"#,
+        ));
+
+        let messages = converter.convert(&message("te/device/child1//", ""));
+
+        assert_eq!(topics(&messages), ["sp/spBv1.0/plant/DDEATH/edge/child1"]);
+    }
+
+    #[test]
+    fn death_certificate_holds_the_bd_seq() {
+        let (_dir, converter) = converter();
+
+        let (topic, payload) = converter.config.node_death_certificate(7);
+
+        assert_eq!(topic, "spBv1.0/plant/NDEATH/edge");
+        let payload = Payload::decode(&payload).unwrap();
+        assert_eq!(payload.seq, None);
+        assert_eq!(
+            payload.metric("bdSeq").unwrap().value,
+            MetricValue::Int64(7)
+        );
+    }
+
+    #[test]
+    fn bd_seq_is_incremented_on_each_connection() {
+        let bd_seq = BdSeq::new(None);
+        assert_eq!(bd_seq.next(), 0);
+        assert_eq!(bd_seq.next(), 1);
+        assert_eq!(bd_seq.current(), 1);
+
+        let bd_seq = BdSeq::new(Some(255));
+        assert_eq!(bd_seq.next(), 0);
+    }
+
+    #[test]
+    fn births_hold_the_bd_seq_of_the_current_connection() {
+        let (_dir, mut converter) = converter();
+        converter.bd_seq.next();
+
+        let messages = converter.convert(&message(HEALTH, r#"{"status":"up"}"#));
+
+        assert_eq!(topics(&messages)[0], "sp/spBv1.0/plant/NBIRTH/edge");
+        assert_eq!(
+            decode(&messages[0]).metric("bdSeq").unwrap().value,
+            MetricValue::Int64(8)
+        );
+    }
+
+    fn converter() -> (TempDir, SparkplugConverter) {
+        let dir = tempfile::tempdir().unwrap();
+        let entity_store = EntityStore::with_main_device(
+            MqttSchema::default(),
+            EntityRegistrationMessage::main_device(Some("edge".into())),
+            0,
+            dir.path(),
+            true,
+        )
+        .unwrap();
+        let config = SparkplugConfig {
+            topic_prefix: "sp".into(),
+            group_id: "plant".into(),
+            edge_node_id: "edge".into(),
+        };
+        let converter = SparkplugConverter::new(
+            config,
+            MqttSchema::default(),
+            entity_store,
+            Topic::new_unchecked(HEALTH),
+            BdSeq::new(Some(7)),
+        );
+        (dir, converter)
+    }
+
+    fn online_converter() -> (TempDir, SparkplugConverter) {
+        let (dir, mut converter) = converter();
+        converter.convert(&message(HEALTH, r#"{"status":"up"}"#));
+        (dir, converter)
+    }
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage::new(&Topic::new_unchecked(topic), payload)
+    }
+
+    fn sparkplug_command(topic: &str, metric: Metric) -> MqttMessage {
+        let payload = Payload::new(now_millis(), None, vec![metric]);
+        MqttMessage::new(&Topic::new_unchecked(topic), payload.encode())
+    }
+
+    fn topics(messages: &[MqttMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.topic.name.as_str()).collect()
+    }
+
+    fn decode(message: &MqttMessage) -> Payload {
+        Payload::decode(message.payload_bytes()).unwrap()
+    }
+}
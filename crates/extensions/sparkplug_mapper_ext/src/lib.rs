@@ -0,0 +1,12 @@
+//! Sparkplug B support: the thin-edge device is published as a Sparkplug edge node,
+//! and its child devices as Sparkplug devices.
+mod actor;
+mod converter;
+pub mod payload;
+
+pub use actor::SparkplugActor;
+pub use actor::SparkplugActorBuilder;
+pub use converter::BdSeq;
+pub use converter::SparkplugConfig;
+pub use converter::SparkplugConverter;
+pub use converter::NAMESPACE;
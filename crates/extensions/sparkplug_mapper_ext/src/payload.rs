@@ -0,0 +1,367 @@
+//! Sparkplug B payloads
+//!
+//! Only the subset of the Sparkplug B protobuf schema used by the mapper is supported:
+//! the payload timestamp, sequence number and metrics, a metric being a named scalar value.
+//! Datasets, templates, properties and metadata are skipped when decoding.
+//!
+//! ```protobuf
+//! message Payload {
+//!     optional uint64 timestamp = 1;
+//!     repeated Metric metrics = 2;
+//!     optional uint64 seq = 3;
+//! }
+//! message Metric {
+//!     optional string name = 1;
+//!     optional uint64 alias = 2;
+//!     optional uint64 timestamp = 3;
+//!     optional uint32 datatype = 4;
+//!     optional bool is_null = 7;
+//!     oneof value {
+//!         uint32 int_value = 10;
+//!         uint64 long_value = 11;
+//!         float float_value = 12;
+//!         double double_value = 13;
+//!         bool boolean_value = 14;
+//!         string string_value = 15;
+//!         ...
+//!     }
+//! }
+//! ```
+
+use tedge_utils::protobuf::encode_bytes_field;
+use tedge_utils::protobuf::encode_fixed32_field;
+use tedge_utils::protobuf::encode_fixed64_field;
+use tedge_utils::protobuf::encode_varint_field;
+use tedge_utils::protobuf::FIXED32;
+use tedge_utils::protobuf::FIXED64;
+use tedge_utils::protobuf::LENGTH_DELIMITED;
+use tedge_utils::protobuf::VARINT;
+
+/// A Sparkplug B payload
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Payload {
+    pub timestamp: Option<u64>,
+    pub metrics: Vec<Metric>,
+    pub seq: Option<u64>,
+}
+
+/// A Sparkplug B metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub timestamp: Option<u64>,
+    pub value: MetricValue,
+}
+
+/// The value of a metric, along with its Sparkplug data type
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricValue {
+    Int64(i64),
+    UInt64(u64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    String(String),
+    Null,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("Truncated Sparkplug payload")]
+    Truncated,
+
+    #[error("Invalid varint in Sparkplug payload")]
+    InvalidVarint,
+
+    #[error("Unsupported wire type {0} in Sparkplug payload")]
+    UnsupportedWireType(u64),
+
+    #[error("Invalid UTF-8 string in Sparkplug payload")]
+    InvalidString,
+}
+
+// Sparkplug B data types
+const INT8: u32 = 1;
+const INT16: u32 = 2;
+const INT32: u32 = 3;
+const INT64: u32 = 4;
+const UINT64: u32 = 8;
+const FLOAT: u32 = 9;
+const DOUBLE: u32 = 10;
+const BOOLEAN: u32 = 11;
+const STRING: u32 = 12;
+
+impl Payload {
+    pub fn new(timestamp: u64, seq: Option<u64>, metrics: Vec<Metric>) -> Self {
+        Payload {
+            timestamp: Some(timestamp),
+            metrics,
+            seq,
+        }
+    }
+
+    /// The metric with the given name, if any
+    pub fn metric(&self, name: &str) -> Option<&Metric> {
+        self.metrics.iter().find(|metric| metric.name == name)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(timestamp) = self.timestamp {
+            encode_varint_field(&mut buf, 1, timestamp);
+        }
+        for metric in &self.metrics {
+            encode_bytes_field(&mut buf, 2, &metric.encode());
+        }
+        if let Some(seq) = self.seq {
+            encode_varint_field(&mut buf, 3, seq);
+        }
+        buf
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut payload = Payload::default();
+        while !bytes.is_empty() {
+            match decode_field(&mut bytes)? {
+                (1, Field::Varint(timestamp)) => payload.timestamp = Some(timestamp),
+                (2, Field::Bytes(metric)) => payload.metrics.push(Metric::decode(metric)?),
+                (3, Field::Varint(seq)) => payload.seq = Some(seq),
+                _ => (),
+            }
+        }
+        Ok(payload)
+    }
+}
+
+impl Metric {
+    pub fn new(name: impl Into<String>, value: MetricValue) -> Self {
+        Metric {
+            name: name.into(),
+            timestamp: None,
+            value,
+        }
+    }
+
+    pub fn with_timestamp(self, timestamp: u64) -> Self {
+        Metric {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_bytes_field(&mut buf, 1, self.name.as_bytes());
+        if let Some(timestamp) = self.timestamp {
+            encode_varint_field(&mut buf, 3, timestamp);
+        }
+        encode_varint_field(&mut buf, 4, self.value.datatype() as u64);
+        match &self.value {
+            MetricValue::Int64(value) => encode_varint_field(&mut buf, 11, *value as u64),
+            MetricValue::UInt64(value) => encode_varint_field(&mut buf, 11, *value),
+            MetricValue::Float(value) => {
+                encode_fixed32_field(&mut buf, 12, *value);
+            }
+            MetricValue::Double(value) => {
+                encode_fixed64_field(&mut buf, 13, *value);
+            }
+            MetricValue::Boolean(value) => encode_varint_field(&mut buf, 14, *value as u64),
+            MetricValue::String(value) => encode_bytes_field(&mut buf, 15, value.as_bytes()),
+            MetricValue::Null => encode_varint_field(&mut buf, 7, 1),
+        }
+        buf
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut name = String::new();
+        let mut timestamp = None;
+        let mut datatype = 0;
+        let mut value = MetricValue::Null;
+        while !bytes.is_empty() {
+            match decode_field(&mut bytes)? {
+                (1, Field::Bytes(bytes)) => name = decode_string(bytes)?,
+                (3, Field::Varint(ts)) => timestamp = Some(ts),
+                (4, Field::Varint(dt)) => datatype = dt as u32,
+                (10, Field::Varint(int)) => {
+                    value = match datatype {
+                        INT8 | INT16 | INT32 => MetricValue::Int64(int as u32 as i32 as i64),
+                        _ => MetricValue::UInt64(int as u32 as u64),
+                    }
+                }
+                (11, Field::Varint(long)) => {
+                    value = match datatype {
+                        INT64 => MetricValue::Int64(long as i64),
+                        _ => MetricValue::UInt64(long),
+                    }
+                }
+                (12, Field::Fixed32(float)) => value = MetricValue::Float(f32::from_bits(float)),
+                (13, Field::Fixed64(double)) => value = MetricValue::Double(f64::from_bits(double)),
+                (14, Field::Varint(boolean)) => value = MetricValue::Boolean(boolean != 0),
+                (15, Field::Bytes(string)) => value = MetricValue::String(decode_string(string)?),
+                _ => (),
+            }
+        }
+        Ok(Metric {
+            name,
+            timestamp,
+            value,
+        })
+    }
+}
+
+impl MetricValue {
+    fn datatype(&self) -> u32 {
+        match self {
+            MetricValue::Int64(_) => INT64,
+            MetricValue::UInt64(_) => UINT64,
+            MetricValue::Float(_) => FLOAT,
+            MetricValue::Double(_) => DOUBLE,
+            MetricValue::Boolean(_) => BOOLEAN,
+            MetricValue::String(_) => STRING,
+            MetricValue::Null => DOUBLE,
+        }
+    }
+
+    /// The value as a JSON value, to be used in a thin-edge command payload
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            MetricValue::Int64(value) => (*value).into(),
+            MetricValue::UInt64(value) => (*value).into(),
+            MetricValue::Float(value) => (*value).into(),
+            MetricValue::Double(value) => (*value).into(),
+            MetricValue::Boolean(value) => (*value).into(),
+            MetricValue::String(value) => value.clone().into(),
+            MetricValue::Null => serde_json::Value::Null,
+        }
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+fn decode_varint(bytes: &mut &[u8]) -> Result<u64, DecodeError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes.split_first().ok_or(DecodeError::Truncated)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidVarint)
+}
+
+fn decode_fixed<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    if bytes.len() < N {
+        return Err(DecodeError::Truncated);
+    }
+    let (value, rest) = bytes.split_at(N);
+    *bytes = rest;
+    Ok(value.try_into().unwrap())
+}
+
+fn decode_field<'a>(bytes: &mut &'a [u8]) -> Result<(u64, Field<'a>), DecodeError> {
+    let key = decode_varint(bytes)?;
+    let field = match key & 0x7 {
+        VARINT => Field::Varint(decode_varint(bytes)?),
+        FIXED64 => Field::Fixed64(u64::from_le_bytes(decode_fixed(bytes)?)),
+        LENGTH_DELIMITED => {
+            let len = decode_varint(bytes)? as usize;
+            if bytes.len() < len {
+                return Err(DecodeError::Truncated);
+            }
+            let (value, rest) = bytes.split_at(len);
+            *bytes = rest;
+            Field::Bytes(value)
+        }
+        FIXED32 => Field::Fixed32(u32::from_le_bytes(decode_fixed(bytes)?)),
+        wire_type => return Err(DecodeError::UnsupportedWireType(wire_type)),
+    };
+    Ok((key >> 3, field))
+}
+
+fn decode_string(bytes: &[u8]) -> Result<String, DecodeError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_are_decoded_as_encoded() {
+        let payload = Payload::new(
+            1_700_000_000_000,
+            Some(42),
+            vec![
+                Metric::new("bdSeq", MetricValue::Int64(3)),
+                Metric::new("temperature", MetricValue::Double(21.5)).with_timestamp(1234),
+                Metric::new("Node Control/Rebirth", MetricValue::Boolean(false)),
+                Metric::new("name", MetricValue::String("pump".into())),
+                Metric::new("offset", MetricValue::Int64(-7)),
+                Metric::new("unset", MetricValue::Null),
+            ],
+        );
+
+        assert_eq!(Payload::decode(&payload.encode()), Ok(payload));
+    }
+
+    #[test]
+    fn metrics_are_encoded_with_their_datatype() {
+        let payload = Payload::new(1, None, vec![Metric::new("t", MetricValue::Double(1.0))]);
+
+        assert_eq!(
+            payload.encode(),
+            vec![
+                0x08, 0x01, // timestamp = 1
+                0x12, 0x0e, // metric, 14 bytes
+                0x0a, 0x01, b't', // name = "t"
+                0x20, 0x0a, // datatype = Double
+                0x69, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, // double_value = 1.0
+            ]
+        );
+    }
+
+    #[test]
+    fn signed_int_values_are_decoded() {
+        // An Int32 metric with int_value = -1, as encoded by a Sparkplug host
+        let metric = [
+            0x0a, 0x01, b'x', // name = "x"
+            0x20, 0x03, // datatype = Int32
+            0x50, 0xff, 0xff, 0xff, 0xff, 0x0f, // int_value = 0xffffffff
+        ];
+        let mut bytes = vec![0x12, metric.len() as u8];
+        bytes.extend_from_slice(&metric);
+
+        let payload = Payload::decode(&bytes).unwrap();
+        assert_eq!(payload.metrics[0].value, MetricValue::Int64(-1));
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() {
+        let mut bytes = Payload::new(1, Some(0), vec![]).encode();
+        encode_bytes_field(&mut bytes, 4, b"some-uuid");
+        encode_bytes_field(&mut bytes, 5, b"body");
+
+        assert_eq!(
+            Payload::decode(&bytes),
+            Ok(Payload::new(1, Some(0), vec![]))
+        );
+    }
+
+    #[test]
+    fn truncated_payloads_are_rejected() {
+        let bytes =
+            Payload::new(1, Some(0), vec![Metric::new("t", MetricValue::Double(1.0))]).encode();
+
+        assert_eq!(
+            Payload::decode(&bytes[..bytes.len() - 4]),
+            Err(DecodeError::Truncated)
+        );
+    }
+}
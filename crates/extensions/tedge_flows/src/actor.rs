@@ -5,6 +5,7 @@ use crate::flow::FlowOutput;
 use crate::flow::FlowResult;
 use crate::flow::Message;
 use crate::flow::SourceTag;
+use crate::modbus::ModbusClient;
 use crate::params::is_params_file;
 use crate::registry::FlowRegistryExt;
 use crate::registry::RegistrationStatus;
@@ -21,12 +22,17 @@ use std::collections::HashSet;
 use std::time::Duration;
 use std::time::SystemTime;
 use tedge_actors::Actor;
+use tedge_actors::ClientMessageBox;
 use tedge_actors::DynSender;
 use tedge_actors::MessageReceiver;
 use tedge_actors::RuntimeError;
 use tedge_actors::Sender;
 use tedge_actors::SimpleMessageBox;
 use tedge_file_system_ext::FsWatchEvent;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpRequestBuilder;
+use tedge_http_ext::HttpResponseExt;
+use tedge_http_ext::HttpResult;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::SubscriptionDiff;
@@ -47,6 +53,7 @@ pub struct FlowsMapper {
     messages: SimpleMessageBox<InputMessage, SubscriptionDiff>,
     mqtt_sender: DynSender<MqttMessage>,
     watch_request_sender: DynSender<WatchRequest>,
+    http: Option<ClientMessageBox<HttpRequest, HttpResult>>,
     subscriptions: TopicFilter,
     watched_commands: HashSet<String>,
     processor: MessageProcessor<ConnectedFlowRegistry>,
@@ -60,6 +67,7 @@ impl FlowsMapper {
         messages: SimpleMessageBox<InputMessage, SubscriptionDiff>,
         mqtt_sender: DynSender<MqttMessage>,
         watch_request_sender: DynSender<WatchRequest>,
+        http: Option<ClientMessageBox<HttpRequest, HttpResult>>,
         subscriptions: TopicFilter,
         processor: MessageProcessor<ConnectedFlowRegistry>,
     ) -> Self {
@@ -70,6 +78,7 @@ impl FlowsMapper {
             messages,
             mqtt_sender,
             watch_request_sender,
+            http,
             subscriptions,
             watched_commands,
             processor,
@@ -472,6 +481,59 @@ impl FlowsMapper {
                     error!(target: "flows", "{flow}: cannot flush {path}: {err}");
                 }
             }
+            FlowOutput::Modbus {
+                device,
+                registers,
+                client,
+            } => {
+                // The connection is kept open across messages, and re-opened on the next message after a connection error
+                let mut connection = client.lock().unwrap().take();
+                for message in messages {
+                    let mut open_connection = match connection.take() {
+                        Some(open_connection) => open_connection,
+                        None => match ModbusClient::connect(device).await {
+                            Ok(open_connection) => open_connection,
+                            Err(err) => {
+                                error!(target: "flows", "{flow}: cannot connect to {device}: {err}");
+                                break;
+                            }
+                        },
+                    };
+                    let result = open_connection.write_message(registers, &message).await;
+                    if let Err(err) = &result {
+                        error!(target: "flows", "{flow}: cannot write to {device}: {err}");
+                    }
+                    if !result.is_err_and(|err| err.is_connection_error()) {
+                        connection = Some(open_connection);
+                    }
+                }
+                *client.lock().unwrap() = connection;
+            }
+            FlowOutput::Http { url, headers } => {
+                if messages.is_empty() {
+                    return Ok(());
+                }
+                let Some(http) = self.http.as_mut() else {
+                    error!(target: "flows", "{flow}: cannot post to {url}: no HTTP client");
+                    return Ok(());
+                };
+                for message in messages {
+                    let request = headers
+                        .iter()
+                        .fold(HttpRequestBuilder::post(url), |request, (name, value)| {
+                            request.header(name, value)
+                        })
+                        .bytes(message.payload)
+                        .build();
+                    let response = match request {
+                        Ok(request) => http.await_response(request).await?,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = response.and_then(|response| response.error_for_status()) {
+                        error!(target: "flows", "{flow}: cannot post to {url}: {err}");
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -3,10 +3,13 @@ use crate::flow::FlowInput;
 use crate::flow::FlowOutput;
 use crate::js_runtime::JsRuntime;
 use crate::js_script::JsScript;
+use crate::modbus::ModbusDevice;
+use crate::modbus::ModbusRegister;
 use crate::params::is_params_file;
 use crate::params::MapperParams;
 use crate::params::Params;
 use crate::steps::FlowStep;
+use crate::transformers::into_prometheus::REMOTE_WRITE_HEADERS;
 use crate::transformers::BuiltinTransformers;
 use crate::LoadError;
 use camino::Utf8Component;
@@ -16,11 +19,13 @@ use glob::glob;
 use serde::Deserialize;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::time::Duration;
 use tedge_mqtt_ext::Topic;
 use tedge_mqtt_ext::TopicFilter;
+use tedge_watch_ext::Framing;
 use tokio::fs::read_to_string;
 use tracing::error;
 use tracing::info;
@@ -86,6 +91,15 @@ pub struct InputConfig {
 
     #[serde(default, deserialize_with = "deserialize_one_or_many")]
     process: Vec<ProcessInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    modbus: Vec<ModbusInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    udp: Vec<UdpInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    unix_socket: Vec<UnixSocketInputConfig>,
 }
 
 #[derive(Clone, Deserialize)]
@@ -120,6 +134,48 @@ pub struct ProcessInputConfig {
     interval: Option<IntervalConfig>,
 }
 
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct ModbusInputConfig {
+    #[serde(flatten)]
+    device: ModbusDevice,
+
+    registers: Vec<ModbusRegister>,
+
+    /// Default to the device URL, e.g. `modbus://192.168.1.10:502/1`
+    topic: Option<String>,
+
+    /// Default to 10 seconds
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_human_interval")]
+    interval: Option<IntervalConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct UdpInputConfig {
+    /// Local address to bind, e.g. `127.0.0.1:8125`
+    address: String,
+
+    /// Default to `datagram`
+    framing: Option<String>,
+
+    /// Default to the bound address, e.g. `udp://127.0.0.1:8125`
+    topic: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct UnixSocketInputConfig {
+    path: Utf8PathBuf,
+
+    /// Default to `datagram`
+    framing: Option<String>,
+
+    /// Default to path
+    topic: Option<String>,
+}
+
 #[derive(Deserialize)]
 #[cfg_attr(test, derive(Debug, Eq, PartialEq))]
 pub enum OutputConfig {
@@ -128,6 +184,25 @@ pub enum OutputConfig {
 
     #[serde(rename = "file")]
     File { path: Utf8PathBuf },
+
+    #[serde(rename = "modbus")]
+    Modbus {
+        #[serde(flatten)]
+        device: ModbusDevice,
+        registers: Vec<ModbusRegister>,
+    },
+
+    #[serde(rename = "http")]
+    Http {
+        url: String,
+
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+
+        /// Set the headers of Prometheus remote-write requests
+        #[serde(default)]
+        remote_write: bool,
+    },
 }
 
 #[derive(Clone)]
@@ -512,10 +587,73 @@ impl InputConfig {
                     })
                 })
                 .collect::<Result<_, ConfigError>>()?,
+            modbus: self
+                .modbus
+                .into_iter()
+                .map(|input| {
+                    Ok(ModbusInputConfig {
+                        device: input.device.substitute_params(params),
+                        registers: input.registers,
+                        topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                        interval: input
+                            .interval
+                            .map(|i| i.substitute_params(params))
+                            .transpose()?,
+                    })
+                })
+                .collect::<Result<_, ConfigError>>()?,
+            udp: self
+                .udp
+                .into_iter()
+                .map(|input| UdpInputConfig {
+                    address: params.substitute_inner_paths(&input.address),
+                    framing: input.framing,
+                    topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                })
+                .collect(),
+            unix_socket: self
+                .unix_socket
+                .into_iter()
+                .map(|input| UnixSocketInputConfig {
+                    path: params.substitute_inner_paths(input.path.as_str()).into(),
+                    framing: input.framing,
+                    topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                })
+                .collect(),
         })
     }
 }
 
+fn parse_framing(framing: Option<String>) -> Result<Framing, ConfigError> {
+    framing
+        .map(|framing| framing.parse().map_err(ConfigError::IncorrectSetting))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+impl ModbusDevice {
+    fn substitute_params(self, params: &Params<&dyn MapperParams>) -> Self {
+        ModbusDevice {
+            host: params.substitute_inner_paths(&self.host),
+            ..self
+        }
+    }
+}
+
+fn validate_modbus_registers(registers: &[ModbusRegister]) -> Result<(), ConfigError> {
+    if registers.is_empty() {
+        return Err(ConfigError::IncorrectSetting(
+            "No Modbus registers configured".to_string(),
+        ));
+    }
+    for register in registers {
+        register.validate().map_err(|err| {
+            ConfigError::IncorrectSetting(format!("Invalid Modbus register {err}"))
+        })?;
+    }
+    Ok(())
+}
+
 fn deserialize_one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
     D: serde::de::Deserializer<'de>,
@@ -607,6 +745,59 @@ impl InputConfig {
             inputs.push(input);
         }
 
+        for ModbusInputConfig {
+            device,
+            registers,
+            topic,
+            interval,
+        } in self.modbus
+        {
+            validate_modbus_registers(&registers)?;
+            let topic = topic.unwrap_or_else(|| device.to_string());
+            let interval = match interval.map(|i| i.duration()) {
+                None => DEFAULT_MODBUS_INTERVAL,
+                Some(Ok(interval)) if !interval.is_zero() => interval,
+                Some(Ok(_)) => return Err(ConfigError::IncorrectInterval("0s".to_string())),
+                Some(Err(e)) => return Err(e),
+            };
+            inputs.push(FlowInput::PollModbus {
+                topic,
+                device,
+                registers,
+                interval,
+            });
+        }
+
+        for UdpInputConfig {
+            address,
+            framing,
+            topic,
+        } in self.udp
+        {
+            let framing = parse_framing(framing)?;
+            let topic = topic.unwrap_or_else(|| format!("udp://{address}"));
+            inputs.push(FlowInput::StreamUdp {
+                topic,
+                address,
+                framing,
+            });
+        }
+
+        for UnixSocketInputConfig {
+            path,
+            framing,
+            topic,
+        } in self.unix_socket
+        {
+            let framing = parse_framing(framing)?;
+            let topic = topic.unwrap_or_else(|| path.to_string());
+            inputs.push(FlowInput::StreamUnixSocket {
+                topic,
+                path,
+                framing,
+            });
+        }
+
         Ok(inputs)
     }
 }
@@ -620,6 +811,22 @@ impl OutputConfig {
             OutputConfig::File { path } => Ok(OutputConfig::File {
                 path: params.substitute_inner_paths(path.as_str()).into(),
             }),
+            OutputConfig::Modbus { device, registers } => Ok(OutputConfig::Modbus {
+                device: device.substitute_params(params),
+                registers,
+            }),
+            OutputConfig::Http {
+                url,
+                headers,
+                remote_write,
+            } => Ok(OutputConfig::Http {
+                url: params.substitute_inner_paths(&url),
+                headers: headers
+                    .into_iter()
+                    .map(|(name, value)| (name, params.substitute_inner_paths(&value)))
+                    .collect(),
+                remote_write,
+            }),
         }
     }
 }
@@ -633,6 +840,39 @@ impl TryFrom<OutputConfig> for FlowOutput {
                 topic: topic.map(into_topic).transpose()?,
             },
             OutputConfig::File { path } => FlowOutput::File { path },
+            OutputConfig::Modbus { device, registers } => {
+                validate_modbus_registers(&registers)?;
+                FlowOutput::Modbus {
+                    device,
+                    registers,
+                    client: Default::default(),
+                }
+            }
+            OutputConfig::Http {
+                url,
+                headers,
+                remote_write,
+            } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(ConfigError::IncorrectSetting(format!(
+                        "Not an HTTP URL: {url}"
+                    )));
+                }
+                let mut all_headers = BTreeMap::new();
+                if remote_write {
+                    for (name, value) in REMOTE_WRITE_HEADERS {
+                        all_headers.insert(name.to_string(), value.to_string());
+                    }
+                }
+                // The headers set by the user take precedence over the remote-write headers
+                for (name, value) in headers {
+                    all_headers.insert(name.to_lowercase(), value);
+                }
+                FlowOutput::Http {
+                    url,
+                    headers: all_headers.into_iter().collect(),
+                }
+            }
         })
     }
 }
@@ -688,6 +928,8 @@ where
     }
 }
 
+const DEFAULT_MODBUS_INTERVAL: Duration = Duration::from_secs(10);
+
 fn default_output() -> OutputConfig {
     OutputConfig::Mqtt { topic: None }
 }
@@ -1338,6 +1580,150 @@ topic = "te/device/main///e/"
         )
     }
 
+    #[test]
+    fn modbus_inputs_are_polled_every_10s_by_default() {
+        let flow_toml = r#"
+        [input.modbus]
+        host = "192.168.1.10"
+        topic = "te/device/plc///m/registers"
+
+        [[input.modbus.registers]]
+        name = "temperature"
+        address = 100
+        type = "i16"
+        scale = 0.1
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let input = flow
+            .input
+            .into_flow_inputs(Utf8Path::new("/flows"))
+            .unwrap();
+        assert_eq!(
+            input,
+            vec![FlowInput::PollModbus {
+                topic: "te/device/plc///m/registers".into(),
+                device: ModbusDevice {
+                    host: "192.168.1.10".into(),
+                    port: 502,
+                    unit_id: 1,
+                },
+                registers: vec![ModbusRegister {
+                    name: "temperature".into(),
+                    address: 100,
+                    table: crate::modbus::RegisterTable::Holding,
+                    data_type: Some(crate::modbus::DataType::I16),
+                    scale: Some(0.1),
+                    byte_order: crate::modbus::ByteOrder::Big,
+                }],
+                interval: Duration::from_secs(10),
+            }]
+        )
+    }
+
+    #[test]
+    fn modbus_registers_are_validated() {
+        let flow_toml = r#"
+        [input.modbus]
+        host = "192.168.1.10"
+        registers = [{ name = "pump", address = 1, table = "coil", type = "f32" }]
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let result = flow.input.into_flow_inputs(Utf8Path::new("/flows"));
+        assert!(matches!(result, Err(ConfigError::IncorrectSetting(_))));
+    }
+
+    #[test]
+    fn http_outputs_can_set_the_remote_write_headers() {
+        let flow_toml = r#"
+        [output.http]
+        url = "http://prometheus:9090/api/v1/write"
+        remote_write = true
+        headers = { "X-Prometheus-Remote-Write-Version" = "0.1.0", Authorization = "Bearer token" }
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let FlowOutput::Http { url, headers } = FlowOutput::try_from(flow.output).unwrap() else {
+            panic!("Expected an HTTP output");
+        };
+        assert_eq!(url, "http://prometheus:9090/api/v1/write");
+        assert_eq!(
+            headers,
+            vec![
+                ("authorization".to_string(), "Bearer token".to_string()),
+                ("content-encoding".to_string(), "snappy".to_string()),
+                (
+                    "content-type".to_string(),
+                    "application/x-protobuf".to_string()
+                ),
+                (
+                    "x-prometheus-remote-write-version".to_string(),
+                    "0.1.0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn http_output_urls_are_validated() {
+        let flow_toml = r#"
+        [output.http]
+        url = "prometheus:9090/api/v1/write"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let result = FlowOutput::try_from(flow.output);
+        assert!(matches!(result, Err(ConfigError::IncorrectSetting(_))));
+    }
+
+    #[test]
+    fn socket_inputs_can_be_deserialized() {
+        let flow_toml = r#"
+        [input.udp]
+        address = "127.0.0.1:8125"
+        framing = "newline"
+
+        [input.unix_socket]
+        path = "/run/app/log.sock"
+        topic = "app/log"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let input = flow
+            .input
+            .into_flow_inputs(Utf8Path::new("/flows"))
+            .unwrap();
+        assert_eq!(
+            input,
+            vec![
+                FlowInput::StreamUdp {
+                    topic: "udp://127.0.0.1:8125".into(),
+                    address: "127.0.0.1:8125".into(),
+                    framing: Framing::Newline,
+                },
+                FlowInput::StreamUnixSocket {
+                    topic: "app/log".into(),
+                    path: "/run/app/log.sock".into(),
+                    framing: Framing::Datagram,
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn socket_framing_is_validated() {
+        let flow_toml = r#"
+        [input.udp]
+        address = "127.0.0.1:514"
+        framing = "octet-counting"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let result = flow.input.into_flow_inputs(Utf8Path::new("/flows"));
+        assert!(matches!(result, Err(ConfigError::IncorrectSetting(_))));
+    }
+
     #[tokio::test]
     async fn flow_missing_input_section_entirely_returns_no_input_error() {
         let flow_toml = r#"
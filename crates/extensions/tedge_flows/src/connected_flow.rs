@@ -8,8 +8,11 @@ use crate::input_source::CommandPollingSource;
 use crate::input_source::CommandStreamingSource;
 use crate::input_source::FilePollingSource;
 use crate::input_source::FileStreamingSource;
+use crate::input_source::ModbusPollingSource;
 use crate::input_source::PollingSource;
 use crate::input_source::StreamingSource;
+use crate::input_source::UdpStreamingSource;
+use crate::input_source::UnixSocketStreamingSource;
 use crate::params::MapperParams;
 use crate::registry::FlowRegistry;
 use crate::registry::FlowStore;
@@ -237,6 +240,8 @@ pub(crate) fn watch_request_topic(request: &WatchRequest) -> &str {
     match request {
         WatchRequest::WatchFile { topic, .. }
         | WatchRequest::WatchCommand { topic, .. }
+        | WatchRequest::WatchUdp { topic, .. }
+        | WatchRequest::WatchUnixSocket { topic, .. }
         | WatchRequest::UnWatch { topic } => topic,
     }
 }
@@ -255,6 +260,22 @@ fn streaming_source(flow_name: String, input: FlowInput) -> Option<Box<dyn Strea
             flow_name, topic, command, cwd,
         ))),
 
+        FlowInput::StreamUdp {
+            topic,
+            address,
+            framing,
+        } => Some(Box::new(UdpStreamingSource::new(
+            flow_name, topic, address, framing,
+        ))),
+
+        FlowInput::StreamUnixSocket {
+            topic,
+            path,
+            framing,
+        } => Some(Box::new(UnixSocketStreamingSource::new(
+            flow_name, topic, path, framing,
+        ))),
+
         _ => None,
     }
 }
@@ -276,6 +297,15 @@ fn polling_source(input: FlowInput) -> Option<Box<dyn PollingSource>> {
             topic, command, cwd, interval,
         ))),
 
+        FlowInput::PollModbus {
+            topic,
+            device,
+            registers,
+            interval,
+        } => Some(Box::new(ModbusPollingSource::new(
+            topic, device, registers, interval,
+        ))),
+
         _ => None,
     }
 }
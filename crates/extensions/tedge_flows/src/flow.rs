@@ -1,5 +1,8 @@
 use crate::input_source::PollingSourceError;
 use crate::js_runtime::JsRuntime;
+use crate::modbus::ModbusClient;
+use crate::modbus::ModbusDevice;
+use crate::modbus::ModbusRegister;
 use crate::stats::Counter;
 use crate::steps::FlowStep;
 use crate::FlowContextUpdate;
@@ -9,12 +12,15 @@ use serde_json::json;
 use serde_json::Value;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::SystemTime;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::Topic;
 use tedge_mqtt_ext::TopicFilter;
+use tedge_watch_ext::Framing;
 use tedge_watch_ext::WatchError;
 use tokio::time::Instant;
 use tracing::error;
@@ -93,12 +99,42 @@ pub enum FlowInput {
         command: String,
         cwd: Utf8PathBuf,
     },
+    PollModbus {
+        topic: String,
+        device: ModbusDevice,
+        registers: Vec<ModbusRegister>,
+        interval: Duration,
+    },
+    StreamUdp {
+        topic: String,
+        address: String,
+        framing: Framing,
+    },
+    StreamUnixSocket {
+        topic: String,
+        path: Utf8PathBuf,
+        framing: Framing,
+    },
 }
 
 #[derive(Clone)]
 pub enum FlowOutput {
-    Mqtt { topic: Option<Topic> },
-    File { path: Utf8PathBuf },
+    Mqtt {
+        topic: Option<Topic>,
+    },
+    File {
+        path: Utf8PathBuf,
+    },
+    Modbus {
+        device: ModbusDevice,
+        registers: Vec<ModbusRegister>,
+        /// The connection to the device, shared by the clones of this output
+        client: Arc<Mutex<Option<ModbusClient>>>,
+    },
+    Http {
+        url: String,
+        headers: Vec<(String, String)>,
+    },
 }
 
 /// The final outcome of a sequence of transformations applied by a flow to a message
@@ -505,6 +541,15 @@ impl Display for FlowInput {
             FlowInput::StreamCommand { command, .. } => {
                 write!(f, "Streaming command: {command}")
             }
+            FlowInput::PollModbus { device, .. } => {
+                write!(f, "Polling Modbus device: {device}")
+            }
+            FlowInput::StreamUdp { address, .. } => {
+                write!(f, "Receiving UDP: {address}")
+            }
+            FlowInput::StreamUnixSocket { path, .. } => {
+                write!(f, "Receiving Unix socket: {path}")
+            }
         }
     }
 }
@@ -523,14 +568,20 @@ impl FlowInput {
             FlowInput::PollFile { topic, .. }
             | FlowInput::PollCommand { topic, .. }
             | FlowInput::StreamFile { topic, .. }
-            | FlowInput::StreamCommand { topic, .. } => Some(topic),
+            | FlowInput::StreamCommand { topic, .. }
+            | FlowInput::PollModbus { topic, .. }
+            | FlowInput::StreamUdp { topic, .. }
+            | FlowInput::StreamUnixSocket { topic, .. } => Some(topic),
         }
     }
 
     pub fn is_streaming(&self) -> bool {
         matches!(
             self,
-            FlowInput::StreamFile { .. } | FlowInput::StreamCommand { .. }
+            FlowInput::StreamFile { .. }
+                | FlowInput::StreamCommand { .. }
+                | FlowInput::StreamUdp { .. }
+                | FlowInput::StreamUnixSocket { .. }
         )
     }
 
@@ -540,7 +591,10 @@ impl FlowInput {
             FlowInput::PollFile { topic, .. }
             | FlowInput::PollCommand { topic, .. }
             | FlowInput::StreamFile { topic, .. }
-            | FlowInput::StreamCommand { topic, .. } => topic == &message.topic,
+            | FlowInput::StreamCommand { topic, .. }
+            | FlowInput::PollModbus { topic, .. }
+            | FlowInput::StreamUdp { topic, .. }
+            | FlowInput::StreamUnixSocket { topic, .. } => topic == &message.topic,
         }
     }
 }
@@ -1,9 +1,13 @@
 use crate::flow::Message;
+use crate::modbus::ModbusClient;
+use crate::modbus::ModbusDevice;
+use crate::modbus::ModbusRegister;
 use crate::next_deadline_after;
 use async_trait::async_trait;
 use camino::Utf8PathBuf;
 use std::time::Duration;
 use std::time::SystemTime;
+use tedge_watch_ext::Framing;
 use tedge_watch_ext::WatchRequest;
 use tokio::time::Instant;
 
@@ -188,6 +192,136 @@ impl StreamingSource for FileStreamingSource {
     }
 }
 
+pub struct UdpStreamingSource {
+    flow: String,
+    topic: String,
+    address: String,
+    framing: Framing,
+}
+
+impl UdpStreamingSource {
+    pub fn new(flow: String, topic: String, address: String, framing: Framing) -> Self {
+        UdpStreamingSource {
+            flow,
+            topic,
+            address,
+            framing,
+        }
+    }
+}
+
+impl StreamingSource for UdpStreamingSource {
+    fn watch_request(&self) -> Option<WatchRequest> {
+        Some(WatchRequest::WatchUdp {
+            topic: self.flow.clone(),
+            address: self.address.clone(),
+            framing: self.framing,
+        })
+    }
+
+    fn input_topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+pub struct UnixSocketStreamingSource {
+    flow: String,
+    topic: String,
+    path: Utf8PathBuf,
+    framing: Framing,
+}
+
+impl UnixSocketStreamingSource {
+    pub fn new(flow: String, topic: String, path: Utf8PathBuf, framing: Framing) -> Self {
+        UnixSocketStreamingSource {
+            flow,
+            topic,
+            path,
+            framing,
+        }
+    }
+}
+
+impl StreamingSource for UnixSocketStreamingSource {
+    fn watch_request(&self) -> Option<WatchRequest> {
+        Some(WatchRequest::WatchUnixSocket {
+            topic: self.flow.clone(),
+            path: self.path.clone(),
+            framing: self.framing,
+        })
+    }
+
+    fn input_topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+pub struct ModbusPollingSource {
+    topic: String,
+    device: ModbusDevice,
+    registers: Vec<ModbusRegister>,
+    client: Option<ModbusClient>,
+    poll: PollInterval,
+}
+
+impl ModbusPollingSource {
+    pub fn new(
+        topic: String,
+        device: ModbusDevice,
+        registers: Vec<ModbusRegister>,
+        interval: Duration,
+    ) -> Self {
+        ModbusPollingSource {
+            topic,
+            device,
+            registers,
+            client: None,
+            poll: PollInterval::new(interval),
+        }
+    }
+}
+
+#[async_trait]
+impl PollingSource for ModbusPollingSource {
+    async fn poll(&mut self, timestamp: SystemTime) -> Result<Vec<Message>, PollingSourceError> {
+        let cannot_poll = |err: crate::modbus::ModbusError| PollingSourceError::CannotPoll {
+            resource: self.device.to_string(),
+            error: err.to_string(),
+        };
+
+        // The connection is kept open across polls, and re-opened on the next poll after an error
+        let mut client = match self.client.take() {
+            Some(client) => client,
+            None => ModbusClient::connect(&self.device)
+                .await
+                .map_err(cannot_poll)?,
+        };
+        let payload = client
+            .read_measurement(&self.registers)
+            .await
+            .map_err(cannot_poll)?;
+        self.client = Some(client);
+
+        Ok(vec![Message::with_timestamp(
+            self.topic.clone(),
+            payload,
+            timestamp,
+        )])
+    }
+
+    fn next_deadline(&self) -> Instant {
+        self.poll.next_deadline
+    }
+
+    fn is_ready(&self, now: Instant) -> bool {
+        self.poll.is_ready(now)
+    }
+
+    fn update_after_poll(&mut self, now: Instant) {
+        self.poll.update_after_poll(now);
+    }
+}
+
 struct PollInterval {
     polling_interval: Duration,
     next_deadline: Instant,
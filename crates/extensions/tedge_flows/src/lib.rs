@@ -7,6 +7,7 @@ mod js_lib;
 mod js_runtime;
 mod js_script;
 mod js_value;
+mod modbus;
 mod params;
 mod registry;
 mod runtime;
@@ -39,16 +40,22 @@ use std::path::PathBuf;
 use std::time::Duration;
 use tedge_actors::fan_in_message_type;
 use tedge_actors::Builder;
+use tedge_actors::ChannelError;
+use tedge_actors::ClientMessageBox;
 use tedge_actors::CloneSender;
 use tedge_actors::DynSender;
 use tedge_actors::MessageSink;
 use tedge_actors::MessageSource;
 use tedge_actors::NoConfig;
 use tedge_actors::NullSender;
+use tedge_actors::RequestEnvelope;
 use tedge_actors::RuntimeRequest;
 use tedge_actors::RuntimeRequestSink;
+use tedge_actors::Sender;
 use tedge_actors::SimpleMessageBoxBuilder;
 use tedge_file_system_ext::FsWatchEvent;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpResult;
 use tedge_mqtt_ext::DynSubscriptions;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::MqttRequest;
@@ -159,6 +166,7 @@ pub struct FlowsMapperBuilder {
     message_box: SimpleMessageBoxBuilder<InputMessage, SubscriptionDiff>,
     mqtt_sender: DynSender<MqttMessage>,
     watch_request_sender: DynSender<WatchRequest>,
+    http: Option<ClientMessageBox<HttpRequest, HttpResult>>,
     processor: MessageProcessor<ConnectedFlowRegistry>,
 }
 
@@ -181,6 +189,7 @@ impl FlowsMapperBuilder {
             message_box,
             mqtt_sender,
             watch_request_sender,
+            http: None,
             processor,
         })
     }
@@ -205,6 +214,29 @@ impl FlowsMapperBuilder {
         self.mqtt_sender = mqtt.get_sender().sender_clone();
     }
 
+    /// Divert to `sink` the output messages published on `topics`, instead of sending them over MQTT
+    ///
+    /// This must be called after [FlowsMapperBuilder::connect].
+    pub fn divert_output(&mut self, topics: TopicFilter, sink: &impl MessageSink<MqttMessage>) {
+        let mqtt = std::mem::replace(&mut self.mqtt_sender, NullSender.into());
+        self.mqtt_sender = DivertingSender {
+            topics,
+            diverted: sink.get_sender(),
+            mqtt,
+        }
+        .into();
+    }
+
+    /// Connect the HTTP actor used by the flows with an `http` output
+    ///
+    /// Without an HTTP actor, the messages of these flows are dropped with an error.
+    pub fn connect_http(
+        &mut self,
+        http: &mut impl MessageSink<RequestEnvelope<HttpRequest, HttpResult>>,
+    ) {
+        self.http = Some(ClientMessageBox::new(http));
+    }
+
     pub fn connect_fs(&mut self, fs: &mut impl MessageSource<FsWatchEvent, PathBuf>) {
         fs.connect_mapped_sink(
             self.processor.registry.config_dir().into(),
@@ -222,6 +254,34 @@ impl FlowsMapperBuilder {
     }
 }
 
+/// Routes the messages published on some topics to a peer other than the MQTT actor
+struct DivertingSender {
+    topics: TopicFilter,
+    diverted: DynSender<MqttMessage>,
+    mqtt: DynSender<MqttMessage>,
+}
+
+impl Clone for DivertingSender {
+    fn clone(&self) -> Self {
+        DivertingSender {
+            topics: self.topics.clone(),
+            diverted: self.diverted.sender_clone(),
+            mqtt: self.mqtt.sender_clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sender<MqttMessage> for DivertingSender {
+    async fn send(&mut self, message: MqttMessage) -> Result<(), ChannelError> {
+        if self.topics.accept(&message) {
+            self.diverted.send(message).await
+        } else {
+            self.mqtt.send(message).await
+        }
+    }
+}
+
 impl MessageSource<WatchRequest, NoConfig> for FlowsMapperBuilder {
     fn connect_sink(&mut self, _config: NoConfig, peer: &impl MessageSink<WatchRequest>) {
         self.watch_request_sender = peer.get_sender();
@@ -254,6 +314,7 @@ impl Builder<FlowsMapper> for FlowsMapperBuilder {
             self.message_box.build(),
             self.mqtt_sender,
             self.watch_request_sender,
+            self.http,
             subscriptions,
             self.processor,
         )
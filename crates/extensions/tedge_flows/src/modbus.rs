@@ -0,0 +1,726 @@
+//! A minimal Modbus TCP client, reading and writing the registers of a device as named values
+use crate::flow::Message;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Number;
+use serde_json::Value;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of registers written by a Write Multiple Registers request
+const MAX_WRITE_REGISTERS: usize = 123;
+
+/// A Modbus TCP device
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct ModbusDevice {
+    pub host: String,
+
+    #[serde(default = "default_port")]
+    pub port: u16,
+
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+}
+
+fn default_port() -> u16 {
+    502
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+impl Display for ModbusDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "modbus://{}:{}/{}", self.host, self.port, self.unit_id)
+    }
+}
+
+/// A named value stored in one or several registers of a Modbus device
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ModbusRegister {
+    pub name: String,
+
+    pub address: u16,
+
+    #[serde(default)]
+    pub table: RegisterTable,
+
+    /// Defaults to `bool` for coils and discrete inputs, and to `u16` for registers
+    #[serde(rename = "type")]
+    pub data_type: Option<DataType>,
+
+    /// Factor applied to the raw value when read, and removed when written
+    pub scale: Option<f64>,
+
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+}
+
+// The scale is checked to be a finite number by `ModbusRegister::validate`
+impl Eq for ModbusRegister {}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize)]
+pub enum RegisterTable {
+    #[serde(rename = "coil")]
+    Coil,
+
+    #[serde(rename = "discrete_input")]
+    DiscreteInput,
+
+    #[serde(rename = "input")]
+    Input,
+
+    #[default]
+    #[serde(rename = "holding")]
+    Holding,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    Bool,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+/// Order of the bytes of a multi-register value, as sent on the wire
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteOrder {
+    /// `ABCD`: most significant word and byte first
+    #[default]
+    Big,
+
+    /// `DCBA`: least significant word and byte first
+    Little,
+
+    /// `BADC`: most significant word first, with the bytes of each word swapped
+    BigSwap,
+
+    /// `CDAB`: least significant word first, with the bytes of each word in big-endian order
+    LittleSwap,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ModbusError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("No response received within {0:?}")]
+    Timeout(Duration),
+
+    #[error("Modbus exception {code} for function {function:#04x}")]
+    Exception { function: u8, code: u8 },
+
+    #[error("Invalid Modbus response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Cannot write {name}: {reason}")]
+    InvalidWrite { name: String, reason: String },
+
+    #[error("{error} (already written: {})", .written.join(", "))]
+    PartialWrite {
+        written: Vec<String>,
+        error: Box<ModbusError>,
+    },
+}
+
+impl ModbusError {
+    /// Returns true if the connection to the device cannot be used anymore after this error
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            ModbusError::Io(_) | ModbusError::Timeout(_) | ModbusError::InvalidResponse(_) => true,
+            ModbusError::PartialWrite { error, .. } => error.is_connection_error(),
+            _ => false,
+        }
+    }
+}
+
+impl ModbusRegister {
+    /// Check that the register type, table and scale are consistent
+    pub fn validate(&self) -> Result<(), String> {
+        let data_type = self.data_type();
+        match (self.table, data_type) {
+            (RegisterTable::Coil | RegisterTable::DiscreteInput, DataType::Bool) => {}
+            (RegisterTable::Coil | RegisterTable::DiscreteInput, _) => {
+                return Err(format!(
+                    "{}: coils and discrete inputs can only be of type bool",
+                    self.name
+                ))
+            }
+            (_, DataType::Bool) => {
+                return Err(format!("{}: registers cannot be of type bool", self.name))
+            }
+            _ => {}
+        }
+        if let Some(scale) = self.scale {
+            if !scale.is_finite() || scale == 0.0 {
+                return Err(format!(
+                    "{}: the scale must be a non-zero number",
+                    self.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn data_type(&self) -> DataType {
+        self.data_type.unwrap_or(match self.table {
+            RegisterTable::Coil | RegisterTable::DiscreteInput => DataType::Bool,
+            RegisterTable::Input | RegisterTable::Holding => DataType::U16,
+        })
+    }
+
+    /// Number of coils or registers used to store the value
+    fn count(&self) -> u16 {
+        match self.data_type() {
+            DataType::Bool | DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+        }
+    }
+
+    /// Decode the value stored in the given registers, returning `None` for non-finite values
+    fn decode(&self, words: &[u16]) -> Option<Value> {
+        let mut bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        self.byte_order.reorder(&mut bytes);
+
+        let raw = match self.data_type() {
+            DataType::Bool => Number::from(u8::from(words.first()? != &0)),
+            DataType::U16 => Number::from(u16::from_be_bytes(bytes[..2].try_into().ok()?)),
+            DataType::I16 => Number::from(i16::from_be_bytes(bytes[..2].try_into().ok()?)),
+            DataType::U32 => Number::from(u32::from_be_bytes(bytes[..4].try_into().ok()?)),
+            DataType::I32 => Number::from(i32::from_be_bytes(bytes[..4].try_into().ok()?)),
+            DataType::U64 => Number::from(u64::from_be_bytes(bytes[..8].try_into().ok()?)),
+            DataType::I64 => Number::from(i64::from_be_bytes(bytes[..8].try_into().ok()?)),
+            DataType::F32 => {
+                Number::from_f64(f32::from_be_bytes(bytes[..4].try_into().ok()?) as f64)?
+            }
+            DataType::F64 => Number::from_f64(f64::from_be_bytes(bytes[..8].try_into().ok()?))?,
+        };
+
+        match self.scale {
+            None => Some(Value::Number(raw)),
+            Some(scale) => Number::from_f64(raw.as_f64()? * scale).map(Value::Number),
+        }
+    }
+
+    /// Encode a value into the registers to be written
+    fn encode(&self, value: &Value) -> Result<Vec<u16>, ModbusError> {
+        let invalid = |reason: &str| ModbusError::InvalidWrite {
+            name: self.name.clone(),
+            reason: reason.to_string(),
+        };
+        let value = match value {
+            Value::Bool(b) => f64::from(u8::from(*b)),
+            Value::Number(n) => n.as_f64().ok_or_else(|| invalid("not a number"))?,
+            _ => return Err(invalid("not a number")),
+        };
+        let raw = value / self.scale.unwrap_or(1.0);
+
+        let integer = |min: f64, max: f64| {
+            let raw = raw.round();
+            if raw < min || raw > max {
+                Err(invalid("out of range"))
+            } else {
+                Ok(raw)
+            }
+        };
+        let mut bytes = match self.data_type() {
+            DataType::Bool => vec![0, u8::from(raw != 0.0)],
+            DataType::U16 => (integer(0.0, u16::MAX as f64)? as u16)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::I16 => (integer(i16::MIN as f64, i16::MAX as f64)? as i16)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::U32 => (integer(0.0, u32::MAX as f64)? as u32)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::I32 => (integer(i32::MIN as f64, i32::MAX as f64)? as i32)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::U64 => (integer(0.0, u64::MAX as f64)? as u64)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::I64 => (integer(i64::MIN as f64, i64::MAX as f64)? as i64)
+                .to_be_bytes()
+                .to_vec(),
+            DataType::F32 => (raw as f32).to_be_bytes().to_vec(),
+            DataType::F64 => raw.to_be_bytes().to_vec(),
+        };
+        self.byte_order.reorder(&mut bytes);
+
+        Ok(bytes
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect())
+    }
+}
+
+impl ByteOrder {
+    /// Convert bytes between the wire order and big-endian order
+    ///
+    /// Each conversion being its own inverse, this is used for both reading and writing.
+    fn reorder(self, bytes: &mut [u8]) {
+        match self {
+            ByteOrder::Big => {}
+            ByteOrder::Little => bytes.reverse(),
+            ByteOrder::BigSwap => bytes.chunks_mut(2).for_each(|word| word.reverse()),
+            ByteOrder::LittleSwap => {
+                bytes.reverse();
+                bytes.chunks_mut(2).for_each(|word| word.reverse())
+            }
+        }
+    }
+}
+
+/// A connection to a Modbus TCP device
+pub struct ModbusClient {
+    stream: TcpStream,
+    unit_id: u8,
+    transaction_id: u16,
+}
+
+impl ModbusClient {
+    pub async fn connect(device: &ModbusDevice) -> Result<Self, ModbusError> {
+        let address = (device.host.as_str(), device.port);
+        let stream = tokio::time::timeout(REQUEST_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| ModbusError::Timeout(REQUEST_TIMEOUT))??;
+        Ok(ModbusClient {
+            stream,
+            unit_id: device.unit_id,
+            transaction_id: 0,
+        })
+    }
+
+    /// Read all the given registers as a thin-edge measurement
+    pub async fn read_measurement(
+        &mut self,
+        registers: &[ModbusRegister],
+    ) -> Result<String, ModbusError> {
+        let mut measurement = Map::new();
+        for register in registers {
+            if let Some(value) = self.read(register).await? {
+                measurement.insert(register.name.clone(), value);
+            }
+        }
+        Ok(Value::Object(measurement).to_string())
+    }
+
+    /// Write the values of a JSON object to the registers with the same names
+    ///
+    /// The `time` property of a measurement is ignored.
+    /// All the values are encoded before any is written, so an invalid message is not partially applied.
+    /// Consecutive holding registers are written with a single request;
+    /// if a later request fails, the error tells which registers have already been written.
+    pub async fn write_message(
+        &mut self,
+        registers: &[ModbusRegister],
+        message: &Message,
+    ) -> Result<(), ModbusError> {
+        let Ok(Value::Object(values)) = serde_json::from_slice(&message.payload) else {
+            return Err(ModbusError::InvalidWrite {
+                name: message.topic.clone(),
+                reason: "the payload is not a JSON object".to_string(),
+            });
+        };
+        let mut writes = vec![];
+        for (name, value) in values.iter().filter(|(name, _)| *name != "time") {
+            let Some(register) = registers.iter().find(|r| &r.name == name) else {
+                return Err(ModbusError::InvalidWrite {
+                    name: name.clone(),
+                    reason: "unknown register".to_string(),
+                });
+            };
+            if !matches!(register.table, RegisterTable::Coil | RegisterTable::Holding) {
+                return Err(ModbusError::InvalidWrite {
+                    name: register.name.clone(),
+                    reason: "only coils and holding registers can be written".to_string(),
+                });
+            }
+            writes.push((register, register.encode(value)?));
+        }
+
+        let mut written = vec![];
+        for (names, pdu) in write_requests(writes) {
+            if let Err(error) = self.request(pdu).await {
+                return Err(if written.is_empty() {
+                    error
+                } else {
+                    ModbusError::PartialWrite {
+                        written,
+                        error: Box::new(error),
+                    }
+                });
+            }
+            written.extend(names);
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self, register: &ModbusRegister) -> Result<Option<Value>, ModbusError> {
+        let function = match register.table {
+            RegisterTable::Coil => 0x01,
+            RegisterTable::DiscreteInput => 0x02,
+            RegisterTable::Holding => 0x03,
+            RegisterTable::Input => 0x04,
+        };
+        let count = register.count();
+        let mut pdu = vec![function];
+        pdu.extend_from_slice(&register.address.to_be_bytes());
+        pdu.extend_from_slice(&count.to_be_bytes());
+
+        let response = self.request(pdu).await?;
+        let Some((&byte_count, data)) = response.split_first() else {
+            return Err(ModbusError::InvalidResponse("missing byte count".into()));
+        };
+        let words: Vec<u16> = match register.table {
+            RegisterTable::Coil | RegisterTable::DiscreteInput => data
+                .first()
+                .map(|bits| u16::from(bits & 1))
+                .into_iter()
+                .collect(),
+            RegisterTable::Holding | RegisterTable::Input => {
+                if usize::from(byte_count) != 2 * usize::from(count)
+                    || data.len() < 2 * usize::from(count)
+                {
+                    return Err(ModbusError::InvalidResponse(format!(
+                        "expected {count} registers for {}",
+                        register.name
+                    )));
+                }
+                data.chunks(2)
+                    .take(count.into())
+                    .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                    .collect()
+            }
+        };
+        if words.is_empty() {
+            return Err(ModbusError::InvalidResponse(format!(
+                "no value for {}",
+                register.name
+            )));
+        }
+        Ok(register.decode(&words))
+    }
+
+    /// Send a request and return the response PDU, without the function code
+    async fn request(&mut self, pdu: Vec<u8>) -> Result<Vec<u8>, ModbusError> {
+        tokio::time::timeout(REQUEST_TIMEOUT, self.exchange(pdu))
+            .await
+            .map_err(|_| ModbusError::Timeout(REQUEST_TIMEOUT))?
+    }
+
+    async fn exchange(&mut self, pdu: Vec<u8>) -> Result<Vec<u8>, ModbusError> {
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let function = pdu[0];
+
+        // MBAP header: transaction id, protocol id (0), length of the unit id and PDU, unit id
+        let mut request = Vec::with_capacity(7 + pdu.len());
+        request.extend_from_slice(&self.transaction_id.to_be_bytes());
+        request.extend_from_slice(&[0, 0]);
+        request.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        request.push(self.unit_id);
+        request.extend_from_slice(&pdu);
+        self.stream.write_all(&request).await?;
+
+        let mut header = [0u8; 7];
+        self.stream.read_exact(&mut header).await?;
+        let transaction_id = u16::from_be_bytes([header[0], header[1]]);
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if transaction_id != self.transaction_id || length < 2 {
+            return Err(ModbusError::InvalidResponse(format!(
+                "unexpected header {header:02x?}"
+            )));
+        }
+        let mut response = vec![0u8; length - 1];
+        self.stream.read_exact(&mut response).await?;
+
+        match response[0] {
+            f if f == function => Ok(response.split_off(1)),
+            f if f == function | 0x80 => Err(ModbusError::Exception {
+                function,
+                code: response.get(1).copied().unwrap_or_default(),
+            }),
+            f => Err(ModbusError::InvalidResponse(format!(
+                "unexpected function code {f:#04x}"
+            ))),
+        }
+    }
+}
+
+/// Build the requests writing the given values, along with the names of the registers written by each
+///
+/// Holding registers are written first, one request per block of consecutive registers,
+/// then coils, one request per coil.
+fn write_requests(writes: Vec<(&ModbusRegister, Vec<u16>)>) -> Vec<(Vec<String>, Vec<u8>)> {
+    let (mut holding, coils): (Vec<_>, Vec<_>) = writes
+        .into_iter()
+        .partition(|(register, _)| register.table == RegisterTable::Holding);
+    holding.sort_by_key(|(register, _)| register.address);
+
+    let mut blocks: Vec<(u16, Vec<String>, Vec<u16>)> = vec![];
+    for (register, words) in holding {
+        match blocks.last_mut() {
+            Some((address, names, block))
+                if usize::from(*address) + block.len() == usize::from(register.address)
+                    && block.len() + words.len() <= MAX_WRITE_REGISTERS =>
+            {
+                names.push(register.name.clone());
+                block.extend(words);
+            }
+            _ => blocks.push((register.address, vec![register.name.clone()], words)),
+        }
+    }
+
+    let mut requests = vec![];
+    for (address, names, words) in blocks {
+        let mut pdu = vec![];
+        if let [word] = words.as_slice() {
+            pdu.push(0x06);
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&word.to_be_bytes());
+        } else {
+            pdu.push(0x10);
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(words.len() as u16).to_be_bytes());
+            pdu.push((2 * words.len()) as u8);
+            pdu.extend(words.iter().flat_map(|w| w.to_be_bytes()));
+        }
+        requests.push((names, pdu));
+    }
+    for (register, words) in coils {
+        let mut pdu = vec![0x05];
+        pdu.extend_from_slice(&register.address.to_be_bytes());
+        pdu.extend_from_slice(if words.iter().any(|w| *w != 0) {
+            &[0xff, 0x00]
+        } else {
+            &[0x00, 0x00]
+        });
+        requests.push((vec![register.name.clone()], pdu));
+    }
+    requests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn decoding_values_with_byte_order_and_scale() {
+        let register = |data_type, byte_order, scale| ModbusRegister {
+            name: "value".to_string(),
+            address: 0,
+            table: RegisterTable::Holding,
+            data_type: Some(data_type),
+            scale,
+            byte_order,
+        };
+
+        let value = register(DataType::U16, ByteOrder::Big, Some(0.1));
+        assert_eq!(value.decode(&[215]), Some(json!(21.5)));
+
+        let value = register(DataType::I16, ByteOrder::Big, None);
+        assert_eq!(value.decode(&[0xfffe]), Some(json!(-2)));
+
+        // 0x12345678 as ABCD, DCBA, BADC and CDAB
+        for (byte_order, words) in [
+            (ByteOrder::Big, [0x1234, 0x5678]),
+            (ByteOrder::Little, [0x7856, 0x3412]),
+            (ByteOrder::BigSwap, [0x3412, 0x7856]),
+            (ByteOrder::LittleSwap, [0x5678, 0x1234]),
+        ] {
+            let value = register(DataType::U32, byte_order, None);
+            assert_eq!(
+                value.decode(&words),
+                Some(json!(0x12345678)),
+                "{byte_order:?}"
+            );
+            assert_eq!(
+                value.encode(&json!(0x12345678)).unwrap(),
+                words,
+                "{byte_order:?}"
+            );
+        }
+
+        let value = register(DataType::F32, ByteOrder::Big, None);
+        let [a, b, c, d] = 21.5f32.to_be_bytes();
+        let words = [u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d])];
+        assert_eq!(value.decode(&words), Some(json!(21.5)));
+    }
+
+    #[test]
+    fn encoding_values_with_scale() {
+        let setpoint = ModbusRegister {
+            name: "setpoint".to_string(),
+            address: 0,
+            table: RegisterTable::Holding,
+            data_type: Some(DataType::I16),
+            scale: Some(0.1),
+            byte_order: ByteOrder::Big,
+        };
+        assert_eq!(setpoint.encode(&json!(-2.5)).unwrap(), vec![0xffe7]);
+        assert!(setpoint.encode(&json!(5000)).is_err());
+        assert!(setpoint.encode(&json!("high")).is_err());
+    }
+
+    #[test]
+    fn validating_registers() {
+        let register: ModbusRegister =
+            toml::from_str("name = \"pump\"\naddress = 1\ntable = \"coil\"").unwrap();
+        assert!(register.validate().is_ok());
+
+        let register: ModbusRegister =
+            toml::from_str("name = \"pump\"\naddress = 1\ntable = \"coil\"\ntype = \"u16\"")
+                .unwrap();
+        assert!(register.validate().is_err());
+
+        let register: ModbusRegister =
+            toml::from_str("name = \"temperature\"\naddress = 1\nscale = 0.0").unwrap();
+        assert!(register.validate().is_err());
+    }
+
+    #[test]
+    fn consecutive_holding_registers_are_written_with_a_single_request() {
+        let registers: Vec<ModbusRegister> = vec![
+            toml::from_str("name = \"mode\"\naddress = 10").unwrap(),
+            toml::from_str("name = \"setpoint\"\naddress = 11\ntype = \"u32\"").unwrap(),
+            toml::from_str("name = \"limit\"\naddress = 20").unwrap(),
+            toml::from_str("name = \"pump\"\naddress = 0\ntable = \"coil\"").unwrap(),
+        ];
+        let writes = vec![
+            (&registers[3], vec![1]),
+            (&registers[1], vec![0x0001, 0x0002]),
+            (&registers[2], vec![7]),
+            (&registers[0], vec![3]),
+        ];
+
+        let requests = write_requests(writes);
+        assert_eq!(
+            requests,
+            vec![
+                (
+                    vec!["mode".to_string(), "setpoint".to_string()],
+                    vec![0x10, 0, 10, 0, 3, 6, 0, 3, 0, 1, 0, 2]
+                ),
+                (vec!["limit".to_string()], vec![0x06, 0, 20, 0, 7]),
+                (vec!["pump".to_string()], vec![0x05, 0, 0, 0xff, 0]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reading_and_writing_registers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(simulator(listener));
+
+        let device = ModbusDevice {
+            host: "127.0.0.1".to_string(),
+            port,
+            unit_id: 1,
+        };
+        let registers: Vec<ModbusRegister> = vec![
+            toml::from_str("name = \"temperature\"\naddress = 0\nscale = 0.1").unwrap(),
+            toml::from_str("name = \"pump\"\naddress = 0\ntable = \"coil\"").unwrap(),
+            toml::from_str("name = \"counter\"\naddress = 1\ntype = \"u32\"").unwrap(),
+        ];
+        let mut client = ModbusClient::connect(&device).await.unwrap();
+
+        let measurement = client.read_measurement(&registers).await.unwrap();
+        assert_eq!(measurement, r#"{"counter":0,"pump":0,"temperature":0.0}"#);
+
+        let update = Message::new(
+            "setpoints",
+            r#"{"temperature": 21.5, "counter": 70000, "pump": true}"#,
+        );
+        client.write_message(&registers, &update).await.unwrap();
+
+        let measurement = client.read_measurement(&registers).await.unwrap();
+        assert_eq!(
+            measurement,
+            r#"{"counter":70000,"pump":1,"temperature":21.5}"#
+        );
+
+        let unknown = Message::new("setpoints", r#"{"pressure": 1013}"#);
+        let error = client
+            .write_message(&registers, &unknown)
+            .await
+            .unwrap_err();
+        assert!(!error.is_connection_error());
+
+        // The connection is still usable after an invalid write
+        let measurement = client.read_measurement(&registers).await.unwrap();
+        assert_eq!(
+            measurement,
+            r#"{"counter":70000,"pump":1,"temperature":21.5}"#
+        );
+    }
+
+    /// A Modbus TCP server with 16 coils and 16 holding registers
+    async fn simulator(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut coils = [false; 16];
+        let mut registers = [0u16; 16];
+        loop {
+            let mut header = [0u8; 7];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let mut pdu = vec![0u8; length - 1];
+            stream.read_exact(&mut pdu).await.unwrap();
+
+            let address = usize::from(u16::from_be_bytes([pdu[1], pdu[2]]));
+            let arg = u16::from_be_bytes([pdu[3], pdu[4]]);
+            let response = match pdu[0] {
+                0x01 => vec![0x01, 1, u8::from(coils[address])],
+                0x03 => {
+                    let mut response = vec![0x03, 2 * arg as u8];
+                    for register in &registers[address..address + usize::from(arg)] {
+                        response.extend_from_slice(&register.to_be_bytes());
+                    }
+                    response
+                }
+                0x05 => {
+                    coils[address] = arg == 0xff00;
+                    pdu.clone()
+                }
+                0x06 => {
+                    registers[address] = arg;
+                    pdu.clone()
+                }
+                0x10 => {
+                    for (i, word) in pdu[6..].chunks(2).enumerate() {
+                        registers[address + i] = u16::from_be_bytes([word[0], word[1]]);
+                    }
+                    pdu[..5].to_vec()
+                }
+                f => vec![f | 0x80, 0x01],
+            };
+
+            let mut frame = header[..4].to_vec();
+            frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+            frame.push(header[6]);
+            frame.extend_from_slice(&response);
+            stream.write_all(&frame).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::measurement_series::MeasurementSeries;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+/// Convert thin-edge measurements into [InfluxDB line protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/)
+///
+/// A measurement published on `te/device/child///m/environment` with a payload `{"temperature": 21.5}`
+/// is converted into a single line: `tedge,entity=device/child//,type=environment temperature=21.5 <ns>`.
+///
+/// - The entity topic id and measurement type are given as tags.
+/// - All the numeric values are given as float fields, nested series being named `<group>_<series>`.
+/// - The timestamp is taken from the `time` property, defaulting to the processing time, with a nanosecond precision.
+#[derive(Clone)]
+pub struct IntoInfluxDb {
+    topic_root: String,
+    measurement: String,
+    topic: Option<String>,
+}
+
+impl Default for IntoInfluxDb {
+    fn default() -> Self {
+        IntoInfluxDb {
+            topic_root: "te".to_string(),
+            measurement: "tedge".to_string(),
+            topic: None,
+        }
+    }
+}
+
+impl Transformer for IntoInfluxDb {
+    fn name(&self) -> &str {
+        "into-influxdb-line-protocol"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.topic_root = topic_root.to_owned();
+        }
+        if let Some(measurement) = config.string_property("measurement") {
+            if measurement.is_empty() {
+                return Err(ConfigError::IncorrectSetting(format!(
+                    "Empty measurement name configured for {} step",
+                    self.name()
+                )));
+            }
+            self.measurement = measurement.to_owned();
+        }
+        self.topic = config.string_property("topic").map(str::to_owned);
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let series = MeasurementSeries::parse(&self.topic_root, timestamp, message)?;
+        if series.values.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        let _ = write!(
+            line,
+            ",entity={},type={}",
+            escape(&series.entity, &[',', '=', ' ']),
+            escape(&series.measurement_type, &[',', '=', ' '])
+        );
+        for (i, (name, value)) in series.values.iter().enumerate() {
+            let separator = if i == 0 { ' ' } else { ',' };
+            let _ = write!(
+                line,
+                "{separator}{}={value}",
+                escape(name, &[',', '=', ' '])
+            );
+        }
+        let _ = write!(line, " {}", series.time.unix_timestamp_nanos());
+
+        let topic = self.topic.as_ref().unwrap_or(&message.topic);
+        Ok(vec![Message::new(topic, line)])
+    }
+}
+
+/// Escape the characters that are special for line protocol identifiers
+fn escape(identifier: &str, special_chars: &[char]) -> String {
+    let mut escaped = String::with_capacity(identifier.len());
+    for c in identifier.chars() {
+        if special_chars.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converting_measurements_into_line_protocol() {
+        let context = FlowContextHandle::default();
+        let mut step = IntoInfluxDb::default();
+
+        let payload = json!({
+            "time": "2025-11-13T16:13:34Z",
+            "temperature": 21.5,
+            "pressure": { "inlet": 1013, "outlet": 980.5 },
+        });
+        let input = Message::new("te/device/child///m/environment", payload.to_string());
+        let output = step
+            .on_message(SystemTime::now(), &input, &context)
+            .unwrap();
+
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "te/device/child///m/environment",
+                "tedge,entity=device/child//,type=environment pressure_inlet=1013,pressure_outlet=980.5,temperature=21.5 1763050414000000000"
+            )]
+        );
+    }
+
+    #[test]
+    fn configuring_the_measurement_name_and_topic() {
+        let context = FlowContextHandle::default();
+        let mut step = IntoInfluxDb::default();
+        let config = json!({ "measurement": "site metrics", "topic": "influxdb/write" });
+        step.set_config(config.into()).unwrap();
+
+        let input = Message::new("te/device/main/service/my,app/m/", r#"{"cpu": 12}"#);
+        let now = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1763050414);
+        let output = step.on_message(now, &input, &context).unwrap();
+
+        assert_eq!(
+            output,
+            vec![Message::new(
+                "influxdb/write",
+                r"site\ metrics,entity=device/main/service/my\,app,type=ThinEdgeMeasurement cpu=12 1763050414000000000"
+            )]
+        );
+    }
+
+    #[test]
+    fn rejecting_non_measurements() {
+        let context = FlowContextHandle::default();
+        let mut step = IntoInfluxDb::default();
+
+        let input = Message::new("te/device/main///a/high-temperature", "{}");
+        assert!(step
+            .on_message(SystemTime::now(), &input, &context)
+            .is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::measurement_series::MeasurementSeries;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use std::time::SystemTime;
+use tedge_utils::protobuf::encode_bytes_field;
+use tedge_utils::protobuf::encode_fixed64_field;
+use tedge_utils::protobuf::encode_varint_field;
+
+/// The headers of the HTTP requests posting remote-write payloads to a Prometheus endpoint
+pub(crate) const REMOTE_WRITE_HEADERS: [(&str, &str); 3] = [
+    ("content-encoding", "snappy"),
+    ("content-type", "application/x-protobuf"),
+    ("x-prometheus-remote-write-version", "0.1.0"),
+];
+
+/// Convert thin-edge measurements into [Prometheus remote-write](https://prometheus.io/docs/specs/prw/remote_write_spec/) requests
+///
+/// Each measurement is converted into a snappy-compressed protobuf `WriteRequest`,
+/// with one time-series per numeric value of the measurement.
+///
+/// - The metric name is the name of the series, nested series being named `<group>_<series>`,
+///   possibly prefixed with a configured `prefix`.
+/// - The entity topic id and measurement type are given as `entity` and `type` labels.
+/// - The sample timestamp is taken from the `time` property, defaulting to the processing time,
+///   with a millisecond precision.
+#[derive(Clone)]
+pub struct IntoPrometheus {
+    topic_root: String,
+    prefix: Option<String>,
+    topic: Option<String>,
+}
+
+impl Default for IntoPrometheus {
+    fn default() -> Self {
+        IntoPrometheus {
+            topic_root: "te".to_string(),
+            prefix: None,
+            topic: None,
+        }
+    }
+}
+
+impl Transformer for IntoPrometheus {
+    fn name(&self) -> &str {
+        "into-prometheus-remote-write"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.topic_root = topic_root.to_owned();
+        }
+        self.prefix = config.string_property("prefix").map(metric_name);
+        self.topic = config.string_property("topic").map(str::to_owned);
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let series = MeasurementSeries::parse(&self.topic_root, timestamp, message)?;
+        if series.values.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let timestamp_ms = (series.time.unix_timestamp_nanos() / 1_000_000) as i64;
+        let mut write_request = vec![];
+        for (name, value) in &series.values {
+            let name = match &self.prefix {
+                None => metric_name(name),
+                Some(prefix) => format!("{prefix}_{}", metric_name(name)),
+            };
+            // The labels of a time-series must be sorted by name
+            let labels = [
+                ("__name__", name.as_str()),
+                ("entity", series.entity.as_str()),
+                ("type", series.measurement_type.as_str()),
+            ];
+            encode_bytes_field(
+                &mut write_request,
+                1,
+                &time_series(&labels, *value, timestamp_ms),
+            );
+        }
+
+        let payload = snap::raw::Encoder::new()
+            .compress_vec(&write_request)
+            .map_err(|err| {
+                FlowError::UnsupportedMessage(format!("Failed to compress write request: {err}"))
+            })?;
+        let topic = self.topic.as_ref().unwrap_or(&message.topic);
+        Ok(vec![Message::new(topic, payload)])
+    }
+}
+
+/// Turn a series name into a valid Prometheus metric name, matching `[a-zA-Z_:][a-zA-Z0-9_:]*`
+fn metric_name(name: &str) -> String {
+    let mut metric_name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if metric_name.is_empty() || metric_name.starts_with(|c: char| c.is_ascii_digit()) {
+        metric_name.insert(0, '_');
+    }
+    metric_name
+}
+
+/// Encode a `TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }`
+fn time_series(labels: &[(&str, &str)], value: f64, timestamp_ms: i64) -> Vec<u8> {
+    let mut buffer = vec![];
+    for (name, value) in labels {
+        // Label { string name = 1; string value = 2; }
+        let mut label = vec![];
+        encode_bytes_field(&mut label, 1, name.as_bytes());
+        encode_bytes_field(&mut label, 2, value.as_bytes());
+        encode_bytes_field(&mut buffer, 1, &label);
+    }
+
+    // Sample { double value = 1; int64 timestamp = 2; }
+    let mut sample = vec![];
+    encode_fixed64_field(&mut sample, 1, value);
+    encode_varint_field(&mut sample, 2, timestamp_ms as u64);
+    encode_bytes_field(&mut buffer, 2, &sample);
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converting_measurements_into_remote_write_requests() {
+        let context = FlowContextHandle::default();
+        let mut step = IntoPrometheus::default();
+
+        let input = Message::new(
+            "te/device/main///m/env",
+            json!({ "time": 1763050414, "temperature": 21.5 }).to_string(),
+        );
+        let output = step
+            .on_message(SystemTime::now(), &input, &context)
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "te/device/main///m/env");
+
+        let write_request = snap::raw::Decoder::new()
+            .decompress_vec(&output[0].payload)
+            .unwrap();
+        // WriteRequest { TimeSeries { Label, Label, Label, Sample } }
+        let expected: Vec<u8> = [
+            &[0x0a, 0x51][..],
+            &[0x0a, 0x17, 0x0a, 0x08],
+            b"__name__",
+            &[0x12, 0x0b],
+            b"temperature",
+            &[0x0a, 0x17, 0x0a, 0x06],
+            b"entity",
+            &[0x12, 0x0d],
+            b"device/main//",
+            &[0x0a, 0x0b, 0x0a, 0x04],
+            b"type",
+            &[0x12, 0x03],
+            b"env",
+            &[0x12, 0x10, 0x09],
+            &21.5f64.to_le_bytes(),
+            // timestamp = 1763050414000 ms
+            &[0x10, 0xb0, 0xbf, 0xf9, 0xef, 0xa7, 0x33],
+        ]
+        .concat();
+        assert_eq!(write_request, expected);
+    }
+
+    #[test]
+    fn producing_one_time_series_per_value() {
+        let context = FlowContextHandle::default();
+        let mut step = IntoPrometheus::default();
+        step.set_config(json!({ "prefix": "tedge", "topic": "prometheus/write" }).into())
+            .unwrap();
+
+        let input = Message::new(
+            "te/device/main///m/env",
+            json!({ "temperature": 21.5, "pressure": { "inlet": 1013 } }).to_string(),
+        );
+        let output = step
+            .on_message(SystemTime::now(), &input, &context)
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "prometheus/write");
+
+        let write_request = snap::raw::Decoder::new()
+            .decompress_vec(&output[0].payload)
+            .unwrap();
+        let contains = |needle: &[u8]| write_request.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"tedge_pressure_inlet"));
+        assert!(contains(b"tedge_temperature"));
+    }
+
+    #[test]
+    fn sanitizing_metric_names() {
+        assert_eq!(metric_name("temperature"), "temperature");
+        assert_eq!(metric_name("flow-rate.m3/h"), "flow_rate_m3_h");
+        assert_eq!(metric_name("1st_sensor"), "_1st_sensor");
+    }
+}
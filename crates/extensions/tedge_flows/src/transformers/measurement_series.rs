@@ -0,0 +1,152 @@
+use crate::FlowError;
+use crate::Message;
+use serde_json::Value;
+use std::time::SystemTime;
+use tedge_utils::timestamp::IsoOrUnix;
+use time::OffsetDateTime;
+
+/// The measurement type used when a measurement is published on `<root>/<entity>/m/`
+const DEFAULT_MEASUREMENT_TYPE: &str = "ThinEdgeMeasurement";
+
+/// The numeric values of a thin-edge measurement,
+/// along with the entity and measurement type extracted from the message topic.
+///
+/// Used by the transformers converting measurements into time-series formats.
+pub struct MeasurementSeries {
+    /// Entity topic id, e.g. `device/main//`
+    pub entity: String,
+
+    /// Measurement type, e.g. `environment`
+    pub measurement_type: String,
+
+    /// Event time, taken from the `time` property or defaulting to the processing time
+    pub time: OffsetDateTime,
+
+    /// Numeric values, nested series being named `<group>_<series>`
+    pub values: Vec<(String, f64)>,
+}
+
+impl MeasurementSeries {
+    /// Parse a measurement published on `<topic_root>/<entity>/m/<type>`
+    pub fn parse(
+        topic_root: &str,
+        timestamp: SystemTime,
+        message: &Message,
+    ) -> Result<Self, FlowError> {
+        let (entity, measurement_type) =
+            measurement_topic(topic_root, &message.topic).ok_or_else(|| {
+                FlowError::UnsupportedMessage(format!("Not a measurement topic: {}", message.topic))
+            })?;
+
+        let Ok(Value::Object(mut payload)) = serde_json::from_slice(message.payload.as_slice())
+        else {
+            return Err(FlowError::UnsupportedMessage(
+                "Not a thin-edge measurement: expecting a JSON object".to_string(),
+            ));
+        };
+
+        let time = match payload.remove("time") {
+            None => OffsetDateTime::from(timestamp),
+            Some(time) => IsoOrUnix::try_from(&time)
+                .map_err(|err| {
+                    FlowError::UnsupportedMessage(format!(
+                        "Not a thin-edge measurement: invalid time: {err}"
+                    ))
+                })?
+                .into_inner(),
+        };
+
+        let mut values = vec![];
+        for (name, value) in payload {
+            match value {
+                Value::Number(number) => values.extend(number.as_f64().map(|v| (name, v))),
+                Value::Object(group) => {
+                    for (series, value) in group {
+                        if let Some(v) = value.as_f64() {
+                            values.push((format!("{name}_{series}"), v))
+                        }
+                    }
+                }
+                // Non-numeric values, such as the `type` of a measurement, are not time-series
+                _ => {}
+            }
+        }
+
+        Ok(MeasurementSeries {
+            entity,
+            measurement_type,
+            time,
+            values,
+        })
+    }
+}
+
+/// Split a measurement topic into an entity topic id and a measurement type
+fn measurement_topic(topic_root: &str, topic: &str) -> Option<(String, String)> {
+    let entity_and_channel = topic.strip_prefix(topic_root)?.strip_prefix('/')?;
+    let segments: Vec<&str> = entity_and_channel.split('/').collect();
+    match segments.as_slice() {
+        [a, b, c, d, "m", measurement_type] => {
+            let measurement_type = if measurement_type.is_empty() {
+                DEFAULT_MEASUREMENT_TYPE
+            } else {
+                measurement_type
+            };
+            Some((format!("{a}/{b}/{c}/{d}"), measurement_type.to_string()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parsing_measurements() {
+        let payload = json!({
+            "time": 1763050414,
+            "temperature": 21.5,
+            "pressure": { "inlet": 1013, "outlet": 980.5 },
+            "type": "ignored",
+        });
+        let message = Message::new("te/device/child///m/environment", payload.to_string());
+
+        let series = MeasurementSeries::parse("te", SystemTime::now(), &message).unwrap();
+
+        assert_eq!(series.entity, "device/child//");
+        assert_eq!(series.measurement_type, "environment");
+        assert_eq!(series.time.unix_timestamp(), 1763050414);
+        assert_eq!(
+            series.values,
+            vec![
+                ("pressure_inlet".to_string(), 1013.0),
+                ("pressure_outlet".to_string(), 980.5),
+                ("temperature".to_string(), 21.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn measurement_type_defaults_to_thin_edge_measurement() {
+        let message = Message::new("te/device/main///m/", r#"{"temperature": 21.5}"#);
+
+        let series = MeasurementSeries::parse("te", SystemTime::now(), &message).unwrap();
+
+        assert_eq!(series.entity, "device/main//");
+        assert_eq!(series.measurement_type, "ThinEdgeMeasurement");
+    }
+
+    #[test]
+    fn rejecting_non_measurements() {
+        let event = Message::new("te/device/main///e/login", r#"{"text": "logged in"}"#);
+        assert!(MeasurementSeries::parse("te", SystemTime::now(), &event).is_err());
+
+        let other_root = Message::new("xx/device/main///m/env", r#"{"temperature": 21.5}"#);
+        assert!(MeasurementSeries::parse("te", SystemTime::now(), &other_root).is_err());
+
+        let not_json = Message::new("te/device/main///m/env", "21.5");
+        assert!(MeasurementSeries::parse("te", SystemTime::now(), &not_json).is_err());
+    }
+}
@@ -11,7 +11,12 @@ use std::time::SystemTime;
 mod add_timestamp;
 mod group_measurements;
 mod ignore_topics;
+mod into_influxdb;
+pub(crate) mod into_prometheus;
 mod limit_payload_size;
+mod measurement_series;
+mod parse_statsd;
+mod parse_syslog;
 mod set_topic;
 mod skip_mosquitto_health_status;
 mod update_context;
@@ -85,6 +90,10 @@ impl Default for BuiltinTransformers {
         transformers.register(group_measurements::GroupMeasurements::default());
         transformers.register(limit_payload_size::LimitPayloadSize::default());
         transformers.register(ignore_topics::IgnoreTopics::default());
+        transformers.register(into_influxdb::IntoInfluxDb::default());
+        transformers.register(into_prometheus::IntoPrometheus::default());
+        transformers.register(parse_statsd::ParseStatsd::default());
+        transformers.register(parse_syslog::ParseSyslog::default());
         transformers.register(set_topic::SetTopic::default());
         transformers.register(skip_mosquitto_health_status::SkipMosquittoHealthStatus);
         transformers.register(update_context::UpdateContext::default());
@@ -0,0 +1,241 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use serde_json::Map;
+use serde_json::Number;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Convert [StatsD](https://github.com/statsd/statsd/blob/master/docs/metric_types.md) lines into thin-edge measurements
+///
+/// Each message, possibly made of several `<name>:<value>|<type>[|@<sample-rate>][|#<tags>]` lines,
+/// is converted into a single measurement with one value per metric name.
+///
+/// - Counters (`c`) are scaled by their sample rate and summed over the message.
+/// - Gauges (`g`) are given their last value, signed values (`+3`, `-2`) being applied as deltas to the previous value.
+/// - Timers (`ms`), histograms (`h`) and distributions (`d`) are given their last value.
+/// - Sets (`s`) are ignored, as are the tags.
+#[derive(Clone)]
+pub struct ParseStatsd {
+    topic: String,
+    gauges: HashMap<String, f64>,
+}
+
+impl Default for ParseStatsd {
+    fn default() -> Self {
+        ParseStatsd {
+            topic: "te/device/main///m/statsd".to_string(),
+            gauges: HashMap::new(),
+        }
+    }
+}
+
+impl Transformer for ParseStatsd {
+    fn name(&self) -> &str {
+        "parse-statsd"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(topic) = config.string_property("topic") {
+            self.topic = topic.to_owned();
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(payload) = message.payload_str() else {
+            return Err(FlowError::UnsupportedMessage(
+                "Not a UTF8 payload".to_string(),
+            ));
+        };
+
+        let mut values: Vec<(String, f64)> = vec![];
+        for line in payload.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let Some(metric) = StatsdMetric::parse(line) else {
+                return Err(FlowError::UnsupportedMessage(format!(
+                    "Not a StatsD metric: {line}"
+                )));
+            };
+            let value = match metric.kind {
+                MetricKind::Counter => {
+                    let count = metric.value / metric.sample_rate;
+                    match values.iter_mut().find(|(name, _)| name == metric.name) {
+                        Some((_, total)) => *total += count,
+                        None => values.push((metric.name.to_string(), count)),
+                    }
+                    continue;
+                }
+                MetricKind::Gauge { delta: true } => {
+                    let gauge = self.gauges.entry(metric.name.to_string()).or_default();
+                    *gauge += metric.value;
+                    *gauge
+                }
+                MetricKind::Gauge { delta: false } => {
+                    self.gauges.insert(metric.name.to_string(), metric.value);
+                    metric.value
+                }
+                MetricKind::Timer => metric.value,
+                MetricKind::Set => continue,
+            };
+            match values.iter_mut().find(|(name, _)| name == metric.name) {
+                Some((_, last)) => *last = value,
+                None => values.push((metric.name.to_string(), value)),
+            }
+        }
+        if values.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut measurement = Map::new();
+        if let Ok(time) = OffsetDateTime::from(timestamp).format(&Rfc3339) {
+            measurement.insert("time".into(), time.into());
+        }
+        for (name, value) in values {
+            if let Some(value) = Number::from_f64(value) {
+                measurement.insert(name, Value::Number(value));
+            }
+        }
+        Ok(vec![Message::new(
+            &self.topic,
+            Value::Object(measurement).to_string(),
+        )])
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct StatsdMetric<'a> {
+    name: &'a str,
+    value: f64,
+    kind: MetricKind,
+    sample_rate: f64,
+}
+
+#[derive(Debug, PartialEq)]
+enum MetricKind {
+    Counter,
+    Gauge { delta: bool },
+    Timer,
+    Set,
+}
+
+impl<'a> StatsdMetric<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let (name, rest) = line.split_once(':')?;
+        let mut fields = rest.split('|');
+        let value = fields.next()?;
+        let kind = match fields.next()? {
+            "c" => MetricKind::Counter,
+            "g" => MetricKind::Gauge {
+                delta: value.starts_with(['+', '-']),
+            },
+            "ms" | "h" | "d" => MetricKind::Timer,
+            "s" => MetricKind::Set,
+            _ => return None,
+        };
+        let value = match kind {
+            MetricKind::Set => 0.0,
+            _ => value.parse::<f64>().ok().filter(|v| v.is_finite())?,
+        };
+        let mut sample_rate = 1.0;
+        for field in fields {
+            if let Some(rate) = field.strip_prefix('@') {
+                sample_rate = rate.parse::<f64>().ok().filter(|r| *r > 0.0)?;
+            }
+        }
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(StatsdMetric {
+            name,
+            value,
+            kind,
+            sample_rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn parsing_statsd_lines() {
+        assert_eq!(
+            StatsdMetric::parse("requests:1|c|@0.1|#region:eu"),
+            Some(StatsdMetric {
+                name: "requests",
+                value: 1.0,
+                kind: MetricKind::Counter,
+                sample_rate: 0.1,
+            })
+        );
+        assert_eq!(
+            StatsdMetric::parse("queue.size:-3|g"),
+            Some(StatsdMetric {
+                name: "queue.size",
+                value: -3.0,
+                kind: MetricKind::Gauge { delta: true },
+                sample_rate: 1.0,
+            })
+        );
+        assert_eq!(StatsdMetric::parse("latency:abc|ms"), None);
+        assert_eq!(StatsdMetric::parse("latency:12|x"), None);
+        assert_eq!(StatsdMetric::parse("latency"), None);
+    }
+
+    #[test]
+    fn converting_statsd_lines_into_measurements() {
+        let context = FlowContextHandle::default();
+        let mut step = ParseStatsd::default();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1763050414);
+
+        let input = Message::new(
+            "udp://0.0.0.0:8125",
+            "requests:1|c\nrequests:1|c|@0.5\nlatency:320|ms\nusers:alice|s\nqueue:10|g",
+        );
+        let output = step.on_message(now, &input, &context).unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "te/device/main///m/statsd");
+        let measurement: Value = serde_json::from_slice(&output[0].payload).unwrap();
+        assert_eq!(
+            measurement,
+            json!({
+                "time": "2025-11-13T16:13:34Z",
+                "requests": 3.0,
+                "latency": 320.0,
+                "queue": 10.0,
+            })
+        );
+
+        // Gauge deltas are applied to the previous values
+        let input = Message::new("udp://0.0.0.0:8125", "queue:-4|g\nqueue:+1|g");
+        let output = step.on_message(now, &input, &context).unwrap();
+        let measurement: Value = serde_json::from_slice(&output[0].payload).unwrap();
+        assert_eq!(measurement["queue"], json!(7.0));
+    }
+
+    #[test]
+    fn rejecting_malformed_lines() {
+        let context = FlowContextHandle::default();
+        let mut step = ParseStatsd::default();
+
+        let input = Message::new("udp://0.0.0.0:8125", "requests:1|c\nnot a metric");
+        assert!(step
+            .on_message(SystemTime::now(), &input, &context)
+            .is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use serde_json::Map;
+use serde_json::Value;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::Date;
+use time::Month;
+use time::OffsetDateTime;
+use time::Time;
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// Convert syslog messages, either [RFC 5424](https://www.rfc-editor.org/rfc/rfc5424) or [RFC 3164](https://www.rfc-editor.org/rfc/rfc3164),
+/// into thin-edge events
+///
+/// The message text is given as the event `text`, along with the syslog `severity`, `facility`,
+/// and any `hostname`, `app_name`, `proc_id`, `msg_id` and `structured_data`.
+/// The event time is taken from the syslog timestamp, defaulting to the processing time.
+/// RFC 3164 timestamps having no year nor timezone, these are interpreted as UTC times of the current year.
+#[derive(Clone)]
+pub struct ParseSyslog {
+    topic: String,
+}
+
+impl Default for ParseSyslog {
+    fn default() -> Self {
+        ParseSyslog {
+            topic: "te/device/main///e/syslog".to_string(),
+        }
+    }
+}
+
+impl Transformer for ParseSyslog {
+    fn name(&self) -> &str {
+        "parse-syslog"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(topic) = config.string_property("topic") {
+            self.topic = topic.to_owned();
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let now = OffsetDateTime::from(timestamp);
+        let Some(syslog) = message
+            .payload_str()
+            .and_then(|line| SyslogMessage::parse(line, now))
+        else {
+            return Err(FlowError::UnsupportedMessage(
+                "Not a syslog message".to_string(),
+            ));
+        };
+        Ok(vec![Message::new(&self.topic, syslog.into_event(now))])
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+struct SyslogMessage<'a> {
+    priority: u8,
+    time: Option<OffsetDateTime>,
+    hostname: Option<&'a str>,
+    app_name: Option<&'a str>,
+    proc_id: Option<&'a str>,
+    msg_id: Option<&'a str>,
+    structured_data: Option<&'a str>,
+    text: &'a str,
+}
+
+impl<'a> SyslogMessage<'a> {
+    fn parse(line: &'a str, now: OffsetDateTime) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (priority, rest) = line.strip_prefix('<')?.split_once('>')?;
+        if priority.is_empty() || priority.len() > 3 {
+            return None;
+        }
+        let priority = priority.parse::<u8>().ok().filter(|p| *p < 192)?;
+
+        match rest.strip_prefix("1 ") {
+            Some(rest) => Self::parse_rfc5424(priority, rest),
+            None => Some(Self::parse_rfc3164(priority, rest, now)),
+        }
+    }
+
+    /// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`, following `<PRI>1 `
+    fn parse_rfc5424(priority: u8, rest: &'a str) -> Option<Self> {
+        let mut fields = rest.splitn(6, ' ');
+        let time = nil_value(fields.next()?).and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok());
+        let hostname = nil_value(fields.next()?);
+        let app_name = nil_value(fields.next()?);
+        let proc_id = nil_value(fields.next()?);
+        let msg_id = nil_value(fields.next()?);
+        let rest = fields.next()?;
+
+        let (structured_data, text) = match rest.strip_prefix('-') {
+            Some(text) => (None, text),
+            None => {
+                let len = structured_data_len(rest)?;
+                (Some(&rest[..len]), &rest[len..])
+            }
+        };
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+        Some(SyslogMessage {
+            priority,
+            time,
+            hostname,
+            app_name,
+            proc_id,
+            msg_id,
+            structured_data,
+            text,
+        })
+    }
+
+    /// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, following `<PRI>`, the hostname being possibly omitted
+    fn parse_rfc3164(priority: u8, rest: &'a str, now: OffsetDateTime) -> Self {
+        let mut message = SyslogMessage {
+            priority,
+            text: rest,
+            ..Default::default()
+        };
+        let Some(time) = rest.get(..15).and_then(|t| rfc3164_time(t, now)) else {
+            return message;
+        };
+        message.time = Some(time);
+        let mut rest = rest[15..].trim_start();
+
+        let is_tag = |token: &str| token.ends_with(':') || token.contains('[');
+        let (first, after_first) = rest.split_once(' ').unwrap_or((rest, ""));
+        if !is_tag(first) {
+            message.hostname = Some(first);
+            rest = after_first;
+        }
+        let (tag, after_tag) = rest.split_once(' ').unwrap_or((rest, ""));
+        if is_tag(tag) {
+            let tag = tag.trim_end_matches(':');
+            match tag.split_once('[') {
+                Some((app_name, proc_id)) => {
+                    message.app_name = Some(app_name);
+                    message.proc_id = Some(proc_id.trim_end_matches(']'));
+                }
+                None => message.app_name = Some(tag),
+            }
+            rest = after_tag;
+        }
+        message.text = rest;
+        message
+    }
+
+    fn into_event(self, now: OffsetDateTime) -> String {
+        let time = self.time.unwrap_or(now);
+        let mut event = Map::new();
+        event.insert("text".into(), self.text.into());
+        if let Ok(time) = time.format(&Rfc3339) {
+            event.insert("time".into(), time.into());
+        }
+        event.insert(
+            "severity".into(),
+            SEVERITIES[usize::from(self.priority % 8)].into(),
+        );
+        event.insert(
+            "facility".into(),
+            FACILITIES[usize::from(self.priority / 8)].into(),
+        );
+        for (key, value) in [
+            ("hostname", self.hostname),
+            ("app_name", self.app_name),
+            ("proc_id", self.proc_id),
+            ("msg_id", self.msg_id),
+            ("structured_data", self.structured_data),
+        ] {
+            if let Some(value) = value {
+                event.insert(key.into(), value.into());
+            }
+        }
+        Value::Object(event).to_string()
+    }
+}
+
+fn nil_value(field: &str) -> Option<&str> {
+    (field != "-").then_some(field)
+}
+
+/// Length of the `[id param="value"]...` structured data elements at the start of the string
+fn structured_data_len(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while bytes.get(i) == Some(&b'[') {
+        let mut escaped = false;
+        loop {
+            i += 1;
+            match *bytes.get(i)? {
+                b'\\' if !escaped => escaped = true,
+                b']' if !escaped => break,
+                _ => escaped = false,
+            }
+        }
+        i += 1;
+    }
+    (i > 0).then_some(i)
+}
+
+/// Parse a `Mmm dd hh:mm:ss` timestamp, assuming a UTC time of the current year
+fn rfc3164_time(timestamp: &str, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    let month = match timestamp.get(..3)? {
+        "Jan" => Month::January,
+        "Feb" => Month::February,
+        "Mar" => Month::March,
+        "Apr" => Month::April,
+        "May" => Month::May,
+        "Jun" => Month::June,
+        "Jul" => Month::July,
+        "Aug" => Month::August,
+        "Sep" => Month::September,
+        "Oct" => Month::October,
+        "Nov" => Month::November,
+        "Dec" => Month::December,
+        _ => return None,
+    };
+    let day = timestamp.get(4..6)?.trim_start().parse().ok()?;
+    let mut hms = timestamp.get(7..)?.split(':').map(|n| n.parse::<u8>().ok());
+    let time_of_day = Time::from_hms(hms.next()??, hms.next()??, hms.next()??).ok()?;
+
+    let date = Date::from_calendar_date(now.year(), month, day).ok()?;
+    let datetime = date.with_time(time_of_day).assume_utc();
+    if datetime > now + time::Duration::days(1) {
+        // A message sent in December, and processed in January
+        let date = Date::from_calendar_date(now.year() - 1, month, day).ok()?;
+        return Some(date.with_time(time_of_day).assume_utc());
+    }
+    Some(datetime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn now() -> OffsetDateTime {
+        // 2025-11-13T16:13:34Z
+        OffsetDateTime::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1763050414))
+    }
+
+    #[test]
+    fn parsing_rfc5424_messages() {
+        let line = r#"<165>1 2025-11-13T16:10:00.003Z plc-gw app 1234 ID47 [exampleSDID@32473 iut="3" eventSource="App\]"] An application event"#;
+        let message = SyslogMessage::parse(line, now()).unwrap();
+
+        assert_eq!(
+            message,
+            SyslogMessage {
+                priority: 165,
+                time: OffsetDateTime::parse("2025-11-13T16:10:00.003Z", &Rfc3339).ok(),
+                hostname: Some("plc-gw"),
+                app_name: Some("app"),
+                proc_id: Some("1234"),
+                msg_id: Some("ID47"),
+                structured_data: Some(r#"[exampleSDID@32473 iut="3" eventSource="App\]"]"#),
+                text: "An application event",
+            }
+        );
+    }
+
+    #[test]
+    fn parsing_rfc5424_messages_with_nil_values() {
+        let line = "<34>1 - - su - - - 'su root' failed for lonvick";
+        let message = SyslogMessage::parse(line, now()).unwrap();
+
+        assert_eq!(
+            message,
+            SyslogMessage {
+                priority: 34,
+                app_name: Some("su"),
+                text: "'su root' failed for lonvick",
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parsing_rfc3164_messages() {
+        let line = "<34>Nov  3 22:14:15 mymachine su[1234]: 'su root' failed for lonvick";
+        let message = SyslogMessage::parse(line, now()).unwrap();
+        assert_eq!(
+            message,
+            SyslogMessage {
+                priority: 34,
+                time: OffsetDateTime::parse("2025-11-03T22:14:15Z", &Rfc3339).ok(),
+                hostname: Some("mymachine"),
+                app_name: Some("su"),
+                proc_id: Some("1234"),
+                text: "'su root' failed for lonvick",
+                ..Default::default()
+            }
+        );
+
+        // As sent to /dev/log, without hostname
+        let line = "<13>Dec 31 23:59:59 logger: hello";
+        let message = SyslogMessage::parse(line, now()).unwrap();
+        assert_eq!(
+            message,
+            SyslogMessage {
+                priority: 13,
+                time: OffsetDateTime::parse("2024-12-31T23:59:59Z", &Rfc3339).ok(),
+                app_name: Some("logger"),
+                text: "hello",
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn converting_syslog_messages_into_events() {
+        let context = FlowContextHandle::default();
+        let mut step = ParseSyslog::default();
+        step.set_config(json!({ "topic": "te/device/main///e/auth" }).into())
+            .unwrap();
+
+        let input = Message::new(
+            "udp://0.0.0.0:514",
+            "<34>Nov  3 22:14:15 mymachine su: 'su root' failed",
+        );
+        let output = step.on_message(now().into(), &input, &context).unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "te/device/main///e/auth");
+        let event: Value = serde_json::from_slice(&output[0].payload).unwrap();
+        assert_eq!(
+            event,
+            json!({
+                "text": "'su root' failed",
+                "time": "2025-11-03T22:14:15Z",
+                "severity": "crit",
+                "facility": "auth",
+                "hostname": "mymachine",
+                "app_name": "su",
+            })
+        );
+    }
+
+    #[test]
+    fn rejecting_non_syslog_messages() {
+        let context = FlowContextHandle::default();
+        let mut step = ParseSyslog::default();
+
+        for payload in ["hello", "<>1 - - - - - -", "<999>hello"] {
+            let input = Message::new("udp://0.0.0.0:514", payload);
+            assert!(step
+                .on_message(SystemTime::now(), &input, &context)
+                .is_err());
+        }
+    }
+}
@@ -93,6 +93,7 @@ impl HealthMonitorBuilder {
             r#type: EntityType::Service,
             parent: Some(service.device_topic_id.entity().clone()),
             health_endpoint: None,
+            ttl: None,
             twin_data,
         };
         let registration_message = registration_message.to_mqtt_message(mqtt_schema);
@@ -154,6 +154,12 @@ impl HttpRequestBuilder {
         HttpRequestBuilder { body, ..self }
     }
 
+    /// Send raw bytes as body
+    pub fn bytes(self, content: impl Into<Bytes>) -> Self {
+        let body = Ok(Full::new(content.into()).map_err(infallible).boxed());
+        HttpRequestBuilder { body, ..self }
+    }
+
     /// Send a  body
     pub fn body(self, content: impl Into<Body>) -> Self {
         let body = Ok(content.into());
@@ -58,6 +58,12 @@ impl<C: Clock> CustomBackoff<C> {
             .expect("ExponentialBackoff has no max elapsed time")
     }
 
+    /// Restarts from the initial interval, e.g. when switching to a new broker endpoint
+    pub fn reset(&mut self) {
+        self.eb.reset();
+        self.last_state = State::Failure;
+    }
+
     pub fn mark_success(&mut self) {
         if self.last_state == State::Failure {
             self.last_state = State::Success;
@@ -134,6 +140,18 @@ mod tests {
         assert_eq!(backoff.backoff(), Duration::from_secs(60));
     }
 
+    #[test]
+    fn backoff_is_30_seconds_after_reset() {
+        let now = Instant::now();
+        let clock = IterClock::new([now]);
+        let mut backoff = deterministic_backoff(&clock);
+        let _ = backoff.backoff();
+        let _ = backoff.backoff();
+        backoff.reset();
+        assert_eq!(backoff.backoff().as_secs(), 30);
+        assert_eq!(backoff.backoff().as_secs(), 60);
+    }
+
     /// Creates a [CustomBackoff] with randomization disabled for deterministic testing
     fn deterministic_backoff(clock: &IterClock) -> CustomBackoff<&IterClock> {
         let mut backoff = CustomBackoff::new(
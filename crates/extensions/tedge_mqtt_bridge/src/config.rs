@@ -3,9 +3,12 @@ use crate::config_toml::ExpandError;
 use crate::config_toml::ExpandedBridgeRule;
 use crate::config_toml::MapperConfigLookup;
 use crate::config_toml::NonExpansionReason;
+use crate::config_toml::Priority;
+use crate::config_toml::RetainPolicy;
 use crate::topics::matches_ignore_dollar_prefix;
 use crate::topics::TopicConverter;
 use crate::AuthMethod;
+use anyhow::Context;
 use ariadne::Color;
 use ariadne::Label;
 use ariadne::Report;
@@ -14,12 +17,20 @@ use ariadne::Source;
 use camino::Utf8Path;
 use certificate::parse_root_certificate::create_tls_config;
 use certificate::parse_root_certificate::create_tls_config_without_client_cert;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
 use rumqttc::valid_filter;
 use rumqttc::valid_topic;
 use rumqttc::MqttOptions;
+use rumqttc::Publish;
+use rumqttc::QoS;
 use rumqttc::Transport;
 use std::borrow::Cow;
+use std::fmt;
 use std::path::Path;
+use std::time::Duration;
+use tedge_config::models::MQTT_TLS_PORT;
 use tedge_config::tedge_toml::CloudConfig;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
@@ -49,6 +60,96 @@ pub fn use_credentials(
     Ok(())
 }
 
+/// The port used for MQTT over WebSockets when the cloud URL uses the default MQTT/TLS port
+pub const WEBSOCKET_TLS_PORT: u16 = 443;
+
+/// Settings to tunnel the bridge MQTT connection over a WebSocket
+///
+/// ```
+/// use tedge_mqtt_bridge::MqttOptions;
+/// use tedge_mqtt_bridge::WebSocketConfig;
+///
+/// let websocket = WebSocketConfig::try_new("/mqtt", ["X-Site: plant-1"]).unwrap();
+/// let (url, port) = websocket.broker_address("example.cumulocity.com", 8883, true);
+/// assert_eq!(url, "wss://example.cumulocity.com:443/mqtt");
+///
+/// let mut options = MqttOptions::new("device", url, port);
+/// websocket.apply(&mut options);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSocketConfig {
+    path: String,
+    headers: HeaderMap,
+}
+
+impl WebSocketConfig {
+    /// Settings for a WebSocket endpoint served on the given HTTP path
+    ///
+    /// The extra HTTP headers sent along the WebSocket upgrade request are given as `name: value` strings.
+    pub fn try_new(
+        path: &str,
+        headers: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> anyhow::Result<Self> {
+        let path = match path.starts_with('/') {
+            true => path.to_owned(),
+            false => format!("/{path}"),
+        };
+
+        let mut header_map = HeaderMap::new();
+        for header in headers {
+            let header = header.as_ref();
+            let (name, value) = header.split_once(':').with_context(|| {
+                format!("Invalid WebSocket header {header:?}: expected 'name: value'")
+            })?;
+            let name = HeaderName::try_from(name.trim())
+                .with_context(|| format!("Invalid WebSocket header name {name:?}"))?;
+            let value = HeaderValue::try_from(value.trim())
+                .with_context(|| format!("Invalid WebSocket header value for {name}"))?;
+            header_map.append(name, value);
+        }
+
+        Ok(WebSocketConfig {
+            path,
+            headers: header_map,
+        })
+    }
+
+    /// The broker address and port to be used to create the [MqttOptions]
+    ///
+    /// When tunneling MQTT over WebSockets, the broker address is the URL of the WebSocket endpoint.
+    /// The default MQTT/TLS port (8883) is replaced by the HTTPS port (443),
+    /// as WebSocket endpoints are served along HTTPS.
+    pub fn broker_address(&self, host: &str, port: u16, tls: bool) -> (String, u16) {
+        let port = match (tls, port) {
+            (true, MQTT_TLS_PORT) => WEBSOCKET_TLS_PORT,
+            _ => port,
+        };
+        let scheme = if tls { "wss" } else { "ws" };
+        (format!("{scheme}://{host}:{port}{}", self.path), port)
+    }
+
+    /// Tunnels the connection over a WebSocket, keeping the TLS and authentication settings
+    ///
+    /// This must be called once the transport has been configured,
+    /// e.g. by [use_key_and_cert] or [use_credentials].
+    pub fn apply(&self, config: &mut MqttOptions) {
+        let transport = match config.transport() {
+            Transport::Tls(tls_config) => Transport::Wss(tls_config),
+            Transport::Tcp => Transport::Ws,
+            transport => transport,
+        };
+        config.set_transport(transport);
+
+        if !self.headers.is_empty() {
+            let headers = self.headers.clone();
+            config.set_request_modifier(move |mut request: http::Request<()>| {
+                request.headers_mut().extend(headers.clone());
+                async move { request }
+            });
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct BridgeConfig {
     local_to_remote: Vec<BridgeRule>,
@@ -84,6 +185,98 @@ pub struct BridgeRule {
     topic_filter: Cow<'static, str>,
     prefix_to_remove: Cow<'static, str>,
     prefix_to_add: Cow<'static, str>,
+    options: RuleOptions,
+}
+
+/// Settings overriding how the messages forwarded by a rule are published
+///
+/// By default, a message is forwarded with its original QoS and retain flag, and without rate limit.
+/// The message expiry interval and user properties are only used when the target speaks MQTT 5.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleOptions {
+    /// The QoS used to publish the forwarded messages, in place of their original QoS
+    pub qos: Option<QoS>,
+
+    /// How the retain flag of the forwarded messages is set
+    pub retain: RetainPolicy,
+
+    /// Messages received in excess of this limit are dropped
+    pub rate_limit: Option<RateLimit>,
+
+    /// How long the forwarded messages are kept by the target before being discarded if not delivered
+    pub message_expiry: Option<Duration>,
+
+    /// MQTT 5 user properties attached to the forwarded messages
+    pub user_properties: Vec<(String, String)>,
+
+    /// The priority class of the forwarded messages, when waiting to be published
+    pub priority: Priority,
+}
+
+/// A token-bucket rate limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The maximum number of messages forwarded per second, on average
+    pub max_rate: f64,
+
+    /// The maximum number of messages forwarded in a row
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A rate limit with a burst size matching one second of messages
+    pub fn new(max_rate: f64) -> Self {
+        RateLimit {
+            max_rate,
+            burst: (max_rate.ceil() as u32).max(1),
+        }
+    }
+}
+
+impl RuleOptions {
+    pub fn is_default(&self) -> bool {
+        *self == RuleOptions::default()
+    }
+
+    /// Returns the message to be published on the target, with its QoS and retain flag overridden
+    pub fn apply(&self, publish: &Publish) -> Publish {
+        let mut forwarded = publish.clone();
+        if let Some(qos) = self.qos {
+            forwarded.qos = qos;
+        }
+        match self.retain {
+            RetainPolicy::Keep => (),
+            RetainPolicy::Force => forwarded.retain = true,
+            RetainPolicy::Strip => forwarded.retain = false,
+        }
+        forwarded
+    }
+}
+
+impl fmt::Display for RuleOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(qos) = self.qos {
+            settings.push(format!("qos={}", qos as u8));
+        }
+        if self.retain != RetainPolicy::Keep {
+            settings.push(format!("retain={}", self.retain));
+        }
+        if let Some(limit) = self.rate_limit {
+            settings.push(format!("max_rate={}/s", limit.max_rate));
+            settings.push(format!("burst={}", limit.burst));
+        }
+        if let Some(expiry) = self.message_expiry {
+            settings.push(format!("message_expiry={}s", expiry.as_secs()));
+        }
+        for (name, value) in &self.user_properties {
+            settings.push(format!("user_property={name}:{value}"));
+        }
+        if self.priority != Priority::Normal {
+            settings.push(format!("priority={}", self.priority));
+        }
+        write!(f, "{}", settings.join(" "))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -131,6 +324,7 @@ impl BridgeRule {
             topic_filter: prefix_to_remove.clone() + base_topic_filter.clone(),
             prefix_to_remove,
             prefix_to_add,
+            options: RuleOptions::default(),
         };
 
         validate_topic(&r.prefix_to_add)?;
@@ -158,10 +352,26 @@ impl BridgeRule {
         })
     }
 
+    pub fn with_options(self, options: RuleOptions) -> Self {
+        BridgeRule { options, ..self }
+    }
+
     pub fn topic_filter(&self) -> &str {
         &self.topic_filter
     }
 
+    /// The filter matching the topics of the messages forwarded by this rule on the target
+    pub fn target_filter(&self) -> Cow<'_, str> {
+        match self.topic_filter.strip_prefix(&*self.prefix_to_remove) {
+            Some(suffix) => self.prefix_to_add.clone() + suffix,
+            None => self.prefix_to_add.clone(),
+        }
+    }
+
+    pub fn options(&self) -> &RuleOptions {
+        &self.options
+    }
+
     pub fn prefix_to_add(&self) -> &str {
         &self.prefix_to_add
     }
@@ -258,12 +468,15 @@ impl BridgeConfig {
         rules: Vec<ExpandedBridgeRule>,
     ) -> Result<(), InvalidBridgeRule> {
         for rule in rules {
+            let options = rule.options;
             match rule.direction {
                 Direction::Outbound => {
                     self.forward_from_local(rule.topic, rule.local_prefix, rule.remote_prefix)?;
+                    set_last_rule_options(&mut self.local_to_remote, options);
                 }
                 Direction::Inbound => {
                     self.forward_from_remote(rule.topic, rule.local_prefix, rule.remote_prefix)?;
+                    set_last_rule_options(&mut self.remote_to_local, options);
                 }
                 Direction::Bidirectional => {
                     self.forward_bidirectionally(
@@ -271,6 +484,8 @@ impl BridgeConfig {
                         rule.local_prefix,
                         rule.remote_prefix,
                     )?;
+                    set_last_rule_options(&mut self.local_to_remote, options.clone());
+                    set_last_rule_options(&mut self.remote_to_local, options);
                 }
             }
         }
@@ -290,6 +505,12 @@ impl BridgeConfig {
     }
 }
 
+fn set_last_rule_options(rules: &mut [BridgeRule], options: RuleOptions) {
+    if let Some(rule) = rules.last_mut() {
+        rule.options = options;
+    }
+}
+
 pub fn expand_bridge_rules(
     file_path: &Utf8Path,
     toml_template: &str,
@@ -467,6 +688,18 @@ mod tests {
             assert_eq!(rule.apply("aws/test-connection"), Some(cloud_topic.into()))
         }
 
+        #[test]
+        fn target_filter_matches_the_forwarded_topics() {
+            let rule = BridgeRule::try_new("s/us/#".into(), "c8y/".into(), "".into()).unwrap();
+            assert_eq!(rule.target_filter(), "s/us/#");
+
+            let cloud_topic = "thinedge/devices/my-device/test-connection";
+            let rule =
+                BridgeRule::try_new("".into(), "aws/test-connection".into(), cloud_topic.into())
+                    .unwrap();
+            assert_eq!(rule.target_filter(), cloud_topic);
+        }
+
         #[test]
         fn allows_empty_input_prefix() {
             let rule = BridgeRule::try_new("test/#".into(), "".into(), "output/".into()).unwrap();
@@ -564,6 +797,106 @@ mod tests {
         }
     }
 
+    mod rule_options {
+        use super::*;
+
+        #[test]
+        fn overrides_qos_and_retain_flag() {
+            let options = RuleOptions {
+                qos: Some(QoS::AtMostOnce),
+                retain: RetainPolicy::Strip,
+                ..RuleOptions::default()
+            };
+            let mut publish = Publish::new("c8y/debug", QoS::AtLeastOnce, "payload");
+            publish.retain = true;
+
+            let forwarded = options.apply(&publish);
+            assert_eq!(forwarded.qos, QoS::AtMostOnce);
+            assert!(!forwarded.retain);
+            assert_eq!(forwarded.payload, publish.payload);
+
+            let forwarded = RuleOptions::default().apply(&publish);
+            assert_eq!(forwarded, publish);
+        }
+
+        #[test]
+        fn are_applied_to_both_directions_of_a_bidirectional_rule() {
+            let options = RuleOptions {
+                qos: Some(QoS::AtLeastOnce),
+                retain: RetainPolicy::Force,
+                rate_limit: Some(RateLimit::new(5.0)),
+                ..RuleOptions::default()
+            };
+            let mut config = BridgeConfig::new();
+            config
+                .add_expanded_rules(vec![
+                    ExpandedBridgeRule {
+                        local_prefix: "c8y/".into(),
+                        remote_prefix: "".into(),
+                        direction: Direction::Outbound,
+                        topic: "s/us".into(),
+                        options: RuleOptions::default(),
+                    },
+                    ExpandedBridgeRule {
+                        local_prefix: "c8y/".into(),
+                        remote_prefix: "".into(),
+                        direction: Direction::Bidirectional,
+                        topic: "inventory/#".into(),
+                        options: options.clone(),
+                    },
+                ])
+                .unwrap();
+
+            assert!(config.local_to_remote()[0].options().is_default());
+            assert_eq!(config.local_to_remote()[1].options(), &options);
+            assert_eq!(config.remote_to_local()[0].options(), &options);
+        }
+    }
+
+    mod websocket {
+        use super::*;
+
+        #[test]
+        fn default_tls_port_is_replaced_by_https_port() {
+            let websocket = WebSocketConfig::try_new("mqtt", Vec::<String>::new()).unwrap();
+
+            assert_eq!(
+                websocket.broker_address("example.com", 8883, true),
+                ("wss://example.com:443/mqtt".to_owned(), 443)
+            );
+            assert_eq!(
+                websocket.broker_address("example.com", 8443, true),
+                ("wss://example.com:8443/mqtt".to_owned(), 8443)
+            );
+            assert_eq!(
+                websocket.broker_address("localhost", 8080, false),
+                ("ws://localhost:8080/mqtt".to_owned(), 8080)
+            );
+        }
+
+        #[test]
+        fn transport_is_tunneled_over_websocket() {
+            let websocket = WebSocketConfig::try_new("/mqtt", ["X-Site: plant-1"]).unwrap();
+            let mut opts = MqttOptions::new("device", "ws://localhost:8080/mqtt", 8080);
+
+            websocket.apply(&mut opts);
+
+            assert!(matches!(opts.transport(), Transport::Ws));
+        }
+
+        #[test]
+        fn headers_must_be_name_value_pairs() {
+            let err = WebSocketConfig::try_new("/mqtt", ["X-Site"]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Invalid WebSocket header \"X-Site\": expected 'name: value'"
+            );
+
+            let err = WebSocketConfig::try_new("/mqtt", ["X Site: plant-1"]).unwrap_err();
+            assert_eq!(err.to_string(), "Invalid WebSocket header name \"X Site\"");
+        }
+    }
+
     mod validate_filter {
         use super::*;
 
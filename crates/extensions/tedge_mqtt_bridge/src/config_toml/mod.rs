@@ -9,13 +9,17 @@ pub use mapper_config::MapperConfigLookup;
 pub use mapper_config::MapperKeyResult;
 pub use mapper_config::TableMapperLookup;
 pub use mapper_config::WalkResult;
+pub use parsing::template::expand_message_template;
+pub use parsing::template::expand_static_template;
 
 use serde::Deserialize;
 use serde::Serialize;
 use serde_spanned::Spanned;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::marker::PhantomData;
 use std::str::FromStr;
+use tedge_config::models::SecondsOrHumanTime;
 use tedge_config::models::TemplatesSet;
 use tedge_config::tedge_toml::ConfigNotSet;
 use tedge_config::tedge_toml::ParseKeyError;
@@ -25,6 +29,9 @@ use tedge_config::tedge_toml::ReadableKey;
 use tedge_config::TEdgeConfig;
 use yansi::Paint as _;
 
+use crate::config::RateLimit;
+use crate::config::RuleOptions;
+
 use parsing::parse_condition_with_error;
 use parsing::template::expand_config_template;
 use parsing::template::expand_loop_template;
@@ -57,6 +64,7 @@ pub struct ExpandedBridgeRule {
     pub remote_prefix: String,
     pub direction: Direction,
     pub topic: String,
+    pub options: RuleOptions,
 }
 
 #[derive(Debug)]
@@ -250,10 +258,27 @@ impl PersistedBridgeConfig {
                 String::new()
             });
 
+            let options = expand_user_properties(&rule.user_properties, static_cfg, cloud_profile)
+                .and_then(|user_properties| {
+                    rule_options(
+                        rule.qos.as_ref(),
+                        rule.retain,
+                        rule.max_rate.as_ref(),
+                        rule.burst.as_ref(),
+                        rule.message_expiry.as_ref(),
+                        user_properties,
+                        rule.priority,
+                    )
+                })
+                .unwrap_or_else(|e| {
+                    errors.push(e);
+                    RuleOptions::default()
+                });
             let expanded = ExpandedBridgeRule {
                 local_prefix: final_local_prefix,
                 remote_prefix: final_remote_prefix,
                 direction: rule.direction,
+                options,
                 topic: expand_spanned(
                     &rule.topic,
                     static_cfg(),
@@ -338,6 +363,24 @@ impl PersistedBridgeConfig {
                 <_>::default()
             });
 
+            let options =
+                expand_user_properties(&template.user_properties, static_cfg, cloud_profile)
+                    .and_then(|user_properties| {
+                        rule_options(
+                            template.qos.as_ref(),
+                            template.retain,
+                            template.max_rate.as_ref(),
+                            template.burst.as_ref(),
+                            template.message_expiry.as_ref(),
+                            user_properties,
+                            template.priority,
+                        )
+                    })
+                    .unwrap_or_else(|e| {
+                        errors.push(e);
+                        RuleOptions::default()
+                    });
+
             if iterable.0.is_empty() {
                 let template_config = TemplateConfig {
                     r#for: "",
@@ -373,6 +416,7 @@ impl PersistedBridgeConfig {
                     local_prefix: final_local_prefix.clone(),
                     remote_prefix: final_remote_prefix.clone(),
                     direction: template.direction,
+                    options: options.clone(),
                     topic: expand_spanned(
                         &template.topic,
                         template_config,
@@ -419,6 +463,14 @@ struct StaticBridgeRule {
     direction: Direction,
     topic: Spanned<Template>,
     r#if: Option<Spanned<String>>,
+    qos: Option<Spanned<u8>>,
+    retain: Option<RetainPolicy>,
+    max_rate: Option<Spanned<f64>>,
+    burst: Option<Spanned<u32>>,
+    message_expiry: Option<Spanned<SecondsOrHumanTime>>,
+    #[serde(default)]
+    user_properties: BTreeMap<String, Spanned<Template>>,
+    priority: Option<Priority>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -430,6 +482,114 @@ struct TemplateBridgeRule {
     remote_prefix: Option<Spanned<Template>>,
     direction: Direction,
     r#if: Option<Spanned<String>>,
+    qos: Option<Spanned<u8>>,
+    retain: Option<RetainPolicy>,
+    max_rate: Option<Spanned<f64>>,
+    burst: Option<Spanned<u32>>,
+    message_expiry: Option<Spanned<SecondsOrHumanTime>>,
+    #[serde(default)]
+    user_properties: BTreeMap<String, Spanned<Template>>,
+    priority: Option<Priority>,
+}
+
+/// Validates the publish settings of a rule
+fn rule_options(
+    qos: Option<&Spanned<u8>>,
+    retain: Option<RetainPolicy>,
+    max_rate: Option<&Spanned<f64>>,
+    burst: Option<&Spanned<u32>>,
+    message_expiry: Option<&Spanned<SecondsOrHumanTime>>,
+    user_properties: Vec<(String, String)>,
+    priority: Option<Priority>,
+) -> Result<RuleOptions, ExpandError> {
+    let qos = qos
+        .map(|qos| match qos.get_ref() {
+            0 => Ok(rumqttc::QoS::AtMostOnce),
+            1 => Ok(rumqttc::QoS::AtLeastOnce),
+            2 => Ok(rumqttc::QoS::ExactlyOnce),
+            n => Err(ExpandError {
+                message: format!("Invalid QoS: {n}"),
+                help: Some("Use 0, 1 or 2".into()),
+                span: qos.span(),
+            }),
+        })
+        .transpose()?;
+
+    let rate_limit = match (max_rate, burst) {
+        (None, None) => None,
+        (None, Some(burst)) => {
+            return Err(ExpandError {
+                message: "A burst size is only meaningful along a 'max_rate'".into(),
+                help: Some("Add a 'max_rate' to this rule".into()),
+                span: burst.span(),
+            })
+        }
+        (Some(max_rate), burst) => {
+            let rate = *max_rate.get_ref();
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(ExpandError {
+                    message: format!("Invalid max_rate: {rate}"),
+                    help: Some("Use a positive number of messages per second".into()),
+                    span: max_rate.span(),
+                });
+            }
+            let mut limit = RateLimit::new(rate);
+            if let Some(burst) = burst {
+                if *burst.get_ref() == 0 {
+                    return Err(ExpandError {
+                        message: "Invalid burst: 0".into(),
+                        help: Some("Use a burst size of at least 1 message".into()),
+                        span: burst.span(),
+                    });
+                }
+                limit.burst = *burst.get_ref();
+            }
+            Some(limit)
+        }
+    };
+
+    let message_expiry = message_expiry
+        .map(|expiry| {
+            let duration = expiry.get_ref().duration();
+            if duration.as_secs() == 0 || duration.as_secs() > u64::from(u32::MAX) {
+                return Err(ExpandError {
+                    message: format!("Invalid message_expiry: {}", expiry.get_ref()),
+                    help: Some("Use a number of seconds between 1 and 4294967295".into()),
+                    span: expiry.span(),
+                });
+            }
+            Ok(duration)
+        })
+        .transpose()?;
+
+    Ok(RuleOptions {
+        qos,
+        retain: retain.unwrap_or_default(),
+        rate_limit,
+        message_expiry,
+        user_properties,
+        priority: priority.unwrap_or_default(),
+    })
+}
+
+/// Expands the values of the MQTT 5 user properties of a rule
+fn expand_user_properties<'a>(
+    user_properties: &BTreeMap<String, Spanned<Template>>,
+    config: impl Fn() -> StaticTemplateConfig<'a>,
+    cloud_profile: Option<&ProfileName>,
+) -> Result<Vec<(String, String)>, ExpandError> {
+    user_properties
+        .iter()
+        .map(|(name, value)| {
+            let value = expand_spanned(
+                value,
+                config(),
+                cloud_profile,
+                &format!("Failed to expand user property {name:?}"),
+            )?;
+            Ok((name.clone(), value))
+        })
+        .collect()
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -440,6 +600,50 @@ pub enum Direction {
     Bidirectional,
 }
 
+/// How the retain flag of the messages forwarded by a rule is set
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RetainPolicy {
+    /// Keep the retain flag of the received message
+    #[default]
+    Keep,
+
+    /// Always set the retain flag
+    Force,
+
+    /// Always clear the retain flag
+    Strip,
+}
+
+/// The priority class of the messages forwarded by a rule
+///
+/// The messages of a higher class are published first, the messages of a lower class
+/// being only given a quota of the publications while higher class messages are waiting.
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    strum::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Priority {
+    High,
+
+    #[default]
+    Normal,
+
+    Low,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum Condition {
     AuthMethod(AuthMethod),
@@ -1420,6 +1624,195 @@ c8y.smartrest.templates = ["a", "b"]
             assert_eq!(expanded.0[3].topic, "c/us/#");
         }
 
+        #[test]
+        fn rules_can_override_qos_retain_and_rate() {
+            let toml = r#"
+local_prefix = "c8y/"
+remote_prefix = ""
+
+[[rule]]
+topic = "debug/#"
+direction = "outbound"
+qos = 0
+retain = "strip"
+max_rate = 10
+burst = 50
+
+[[template_rule]]
+for = ['s', 't']
+topic = "${item}/ds"
+direction = "inbound"
+qos = 1
+max_rate = 2.5
+
+[[rule]]
+topic = "s/us"
+direction = "outbound"
+"#;
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            let tedge_config = tedge_config::TEdgeConfig::load_toml_str("");
+
+            let (rules, _) = config
+                .expand(
+                    &tedge_config,
+                    AuthMethod::Certificate,
+                    None,
+                    &TableMapperLookup(toml::Table::new()),
+                )
+                .unwrap();
+
+            assert_eq!(
+                rules[0].options,
+                RuleOptions {
+                    qos: Some(rumqttc::QoS::AtMostOnce),
+                    retain: RetainPolicy::Strip,
+                    rate_limit: Some(RateLimit {
+                        max_rate: 10.0,
+                        burst: 50
+                    }),
+                    ..RuleOptions::default()
+                }
+            );
+            assert_eq!(
+                rules[0].options.to_string(),
+                "qos=0 retain=strip max_rate=10/s burst=50"
+            );
+            assert!(rules[1].options.is_default());
+            assert_eq!(rules[2].topic, "s/ds");
+            assert_eq!(rules[2].options.to_string(), "qos=1 max_rate=2.5/s burst=3");
+            assert_eq!(rules[3].options, rules[2].options);
+        }
+
+        #[test]
+        fn rules_can_set_mqtt5_properties() {
+            let toml = r#"
+local_prefix = "c8y/"
+remote_prefix = ""
+
+[[rule]]
+topic = "measurement/#"
+direction = "outbound"
+message_expiry = "10m"
+user_properties = { site = "${mapper.site}", source = "thin-edge" }
+"#;
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            let tedge_config = tedge_config::TEdgeConfig::load_toml_str("");
+
+            let (rules, _) = config
+                .expand(
+                    &tedge_config,
+                    AuthMethod::Certificate,
+                    None,
+                    &TableMapperLookup(toml::from_str("site = \"plant-1\"").unwrap()),
+                )
+                .unwrap();
+
+            assert_eq!(
+                rules[0].options.message_expiry,
+                Some(std::time::Duration::from_secs(600))
+            );
+            assert_eq!(
+                rules[0].options.user_properties,
+                vec![
+                    ("site".to_owned(), "plant-1".to_owned()),
+                    ("source".to_owned(), "thin-edge".to_owned()),
+                ]
+            );
+            assert_eq!(
+                rules[0].options.to_string(),
+                "message_expiry=600s user_property=site:plant-1 user_property=source:thin-edge"
+            );
+        }
+
+        #[test]
+        fn rules_can_set_a_priority_class() {
+            let toml = r#"
+local_prefix = "c8y/"
+remote_prefix = ""
+
+[[rule]]
+topic = "s/us"
+direction = "outbound"
+priority = "high"
+
+[[template_rule]]
+for = ['measurement/measurements/create', 'event/events/create']
+topic = "${item}"
+direction = "outbound"
+priority = "low"
+"#;
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            let tedge_config = tedge_config::TEdgeConfig::load_toml_str("");
+
+            let (rules, _) = config
+                .expand(
+                    &tedge_config,
+                    AuthMethod::Certificate,
+                    None,
+                    &TableMapperLookup(toml::Table::new()),
+                )
+                .unwrap();
+
+            assert_eq!(rules[0].options.priority, Priority::High);
+            assert_eq!(rules[0].options.to_string(), "priority=high");
+            assert_eq!(rules[1].options.priority, Priority::Low);
+            assert_eq!(rules[2].options.priority, Priority::Low);
+        }
+
+        #[test]
+        fn unknown_priority_classes_are_rejected() {
+            let toml = r#"
+[[rule]]
+local_prefix = "c8y/"
+remote_prefix = ""
+topic = "s/us"
+direction = "outbound"
+priority = "urgent"
+"#;
+            assert!(toml::from_str::<PersistedBridgeConfig>(toml).is_err());
+        }
+
+        #[test]
+        fn invalid_rule_options_are_rejected() {
+            let cases = [
+                ("qos = 3", "Invalid QoS: 3"),
+                ("max_rate = 0", "Invalid max_rate: 0"),
+                (
+                    "burst = 10",
+                    "A burst size is only meaningful along a 'max_rate'",
+                ),
+                ("max_rate = 1\nburst = 0", "Invalid burst: 0"),
+                ("message_expiry = 0", "Invalid message_expiry: 0"),
+            ];
+            for (options, expected_error) in cases {
+                let toml = format!(
+                    r#"
+local_prefix = "c8y/"
+remote_prefix = ""
+
+[[rule]]
+topic = "debug/#"
+direction = "outbound"
+{options}
+"#
+                );
+                let config: PersistedBridgeConfig = toml::from_str(&toml).unwrap();
+                let tedge_config = tedge_config::TEdgeConfig::load_toml_str("");
+
+                let errors = config
+                    .expand(
+                        &tedge_config,
+                        AuthMethod::Certificate,
+                        None,
+                        &TableMapperLookup(toml::Table::new()),
+                    )
+                    .unwrap_err();
+
+                assert_eq!(errors.len(), 1, "{options}");
+                assert_eq!(errors[0].message, expected_error);
+            }
+        }
+
         #[test]
         fn multiple_variables_in_template() {
             let toml = r#"
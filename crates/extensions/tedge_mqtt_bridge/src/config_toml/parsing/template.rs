@@ -3,6 +3,7 @@
 //! Templates can contain:
 //! - `${tedge.some.key}` - config variable references
 //! - `${varname}` - template variables (for template rules)
+//! - `${request.some.path}` / `${command.some.path}` - message references (for operation templates)
 //! - literal text
 //!
 //! This module uses a two-stage lexer/parser approach:
@@ -150,6 +151,8 @@ pub enum TemplateComponent<'src> {
     Mapper(String, OffsetSpan),
     /// The loop item variable `${item}`
     Item,
+    /// A message reference like `${request.payload.id}` - stores the whole path `request.payload.id`
+    Message(String, OffsetSpan),
     /// Literal text
     Text(&'src str),
 }
@@ -197,7 +200,15 @@ where
         .to(TemplateComponent::Item)
         .labelled("'item'");
 
-    choice((config_ref, mapper_ref, item_var))
+    let message_ref = select! { Token::Ident(s) if s == "request" || s == "command" => s }
+        .then_ignore(just(Token::Dot))
+        .then(dotted_path())
+        .map_with(|(root, parts), e| {
+            TemplateComponent::Message(format!("{root}.{}", parts.join(".")), e.span())
+        })
+        .labelled("message reference (e.g. 'request.payload.id')");
+
+    choice((config_ref, mapper_ref, item_var, message_ref))
 }
 
 /// Parser for a complete variable: VarStart content VarEnd
@@ -474,6 +485,102 @@ pub fn expand_config_template(
                     span: span.into(),
                 });
             }
+            TemplateComponent::Message(path, key_span) => {
+                return Err(message_reference_error(&path, key_span));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn message_reference_error(path: &str, span: OffsetSpan) -> ExpandError {
+    ExpandError {
+        message: format!("Variable '{path}' is only valid inside operation templates"),
+        help: Some("Use 'tedge.<key>' for config references".into()),
+        span: span.into(),
+    }
+}
+
+/// Expand the config references of a template evaluated per message
+///
+/// The `${tedge.*}` and `${mapper.*}` references are expanded once for all,
+/// while the `${request.*}` and `${command.*}` references are kept as is,
+/// to be expanded for each message by [expand_message_template].
+pub fn expand_static_template(
+    src: &toml::Spanned<String>,
+    config: &TEdgeConfig,
+    cloud_profile: Option<&ProfileName>,
+    mapper_config: &dyn MapperConfigLookup,
+) -> Result<String, ExpandError> {
+    let components = parse_template(src)?;
+    let mut result = String::new();
+
+    for (component, span) in components {
+        match component {
+            TemplateComponent::Text(text) => result.push_str(text),
+            TemplateComponent::Config(key, span) => {
+                let value =
+                    super::super::expand_config_key(&key, config, cloud_profile, span.into())?;
+                result.push_str(&value);
+            }
+            TemplateComponent::Mapper(path, key_span) => {
+                let value = expand_mapper_component(&path, key_span, span, mapper_config)?;
+                result.push_str(&value);
+            }
+            TemplateComponent::Message(path, _) => {
+                result.push_str(&format!("${{{path}}}"));
+            }
+            TemplateComponent::Item => {
+                return Err(ExpandError {
+                    message: "Variable 'item' is only valid inside template rules".into(),
+                    help: Some("Use 'request.<path>' for message references".into()),
+                    span: span.into(),
+                });
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Expand the message references of a template, using `lookup` to resolve them
+///
+/// The config references must have been expanded beforehand by [expand_static_template].
+pub fn expand_message_template(
+    src: &toml::Spanned<String>,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, ExpandError> {
+    let components = parse_template(src)?;
+    let mut result = String::new();
+
+    for (component, span) in components {
+        match component {
+            TemplateComponent::Text(text) => result.push_str(text),
+            TemplateComponent::Message(path, key_span) => match lookup(&path) {
+                Some(value) => result.push_str(&value),
+                None => {
+                    return Err(ExpandError {
+                        message: format!("Variable '{path}' is not set"),
+                        help: None,
+                        span: key_span.into(),
+                    })
+                }
+            },
+            TemplateComponent::Config(..) | TemplateComponent::Mapper(..) => {
+                return Err(ExpandError {
+                    message: "Config references must be expanded before message references".into(),
+                    help: None,
+                    span: span.into(),
+                });
+            }
+            TemplateComponent::Item => {
+                return Err(ExpandError {
+                    message: "Variable 'item' is only valid inside template rules".into(),
+                    help: None,
+                    span: span.into(),
+                });
+            }
         }
     }
 
@@ -515,6 +622,9 @@ pub fn expand_loop_template(
             TemplateComponent::Item => {
                 result.push_str(ctx.loop_var_value);
             }
+            TemplateComponent::Message(path, key_span) => {
+                return Err(message_reference_error(&path, key_span));
+            }
         }
     }
 
@@ -584,6 +694,70 @@ mod tests {
         assert_eq!(stringified, raw_input);
     }
 
+    #[test]
+    fn parses_message_reference() {
+        let input = toml_spanned("rpc/${request.payload.id}");
+        let components = parse_template(&input).unwrap();
+        assert_eq!(components.len(), 2);
+        let TemplateComponent::Message(ref path, span) = components[1].0 else {
+            panic!("Expected message reference, got: {:?}", components[1].0);
+        };
+        assert_eq!(path, "request.payload.id");
+        assert_eq!(
+            extract_toml_span(&input, span.into_range()),
+            "request.payload.id"
+        );
+    }
+
+    #[test]
+    fn config_template_rejects_message_reference() {
+        use crate::config_toml::TableMapperLookup;
+        let config = TEdgeConfig::load_toml_str("");
+        let input = toml_spanned("${command.id}");
+        let err = expand_config_template(
+            &input,
+            &config,
+            None,
+            &TableMapperLookup(toml::Table::new()),
+        )
+        .unwrap_err();
+        assert!(
+            err.message
+                .contains("only valid inside operation templates"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn static_template_keeps_message_references() {
+        use crate::config_toml::TableMapperLookup;
+        let config = TEdgeConfig::load_toml_str("");
+        let mapper: toml::Table = toml::from_str("bridge.topic_prefix = \"tb\"").unwrap();
+        let input = toml_spanned("${mapper.bridge.topic_prefix}/rpc/response/${command.id}");
+        let expanded =
+            expand_static_template(&input, &config, None, &TableMapperLookup(mapper)).unwrap();
+        assert_eq!(expanded, "tb/rpc/response/${command.id}");
+    }
+
+    #[test]
+    fn message_template_is_expanded_with_lookup() {
+        let input = toml_spanned("tb/rpc/response/${command.id}");
+        let expanded = expand_message_template(&input, &|path| {
+            (path == "command.id").then(|| "42".to_string())
+        })
+        .unwrap();
+        assert_eq!(expanded, "tb/rpc/response/42");
+
+        let input = toml_spanned("${request.payload.missing}");
+        let err = expand_message_template(&input, &|_| None).unwrap_err();
+        assert!(
+            err.message.contains("request.payload.missing"),
+            "{}",
+            err.message
+        );
+    }
+
     #[test]
     fn config_template_rejects_item_variable() {
         use crate::config_toml::TableMapperLookup;
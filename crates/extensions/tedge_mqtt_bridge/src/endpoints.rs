@@ -0,0 +1,339 @@
+use crate::mqtt5::Mqtt5Options;
+use rumqttc::LastWill;
+use rumqttc::MqttOptions;
+use std::fmt;
+use std::sync::Arc;
+pub use tedge_config::models::FailoverStrategy;
+use tokio::sync::watch;
+
+/// The cloud broker endpoints of a bridge, with the strategy used to fail over from one to another
+///
+/// The first endpoint is the primary one, the bridge connects to it first.
+/// After `max_failures` consecutive connection failures, the bridge tries the next endpoint,
+/// as chosen by the [FailoverStrategy].
+///
+/// By default, the bridge connects to the endpoints using MQTT 3.1.1.
+/// MQTT 5 is used instead when [Self::with_mqtt5] is given the MQTT 5 specific settings.
+///
+/// ```
+/// use tedge_mqtt_bridge::CloudEndpoints;
+/// use tedge_mqtt_bridge::FailoverStrategy;
+/// use tedge_mqtt_bridge::MqttOptions;
+///
+/// let endpoints = CloudEndpoints::new(MqttOptions::new("device", "eu-1.example.com", 8883))
+///     .with_endpoint(MqttOptions::new("device", "eu-2.example.com", 8883))
+///     .with_strategy(FailoverStrategy::RoundRobin)
+///     .with_max_failures(5);
+/// assert_eq!(endpoints.len(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CloudEndpoints {
+    endpoints: Vec<MqttOptions>,
+    strategy: FailoverStrategy,
+    max_failures: u32,
+    mqtt5: Option<Mqtt5Options>,
+    last_will_provider: Option<LastWillProvider>,
+}
+
+impl CloudEndpoints {
+    pub fn new(primary: MqttOptions) -> Self {
+        CloudEndpoints {
+            endpoints: vec![primary],
+            strategy: FailoverStrategy::Primary,
+            max_failures: 3,
+            mqtt5: None,
+            last_will_provider: None,
+        }
+    }
+
+    /// Adds a fallback endpoint, tried after the previous ones
+    pub fn with_endpoint(mut self, endpoint: MqttOptions) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    pub fn with_strategy(self, strategy: FailoverStrategy) -> Self {
+        Self { strategy, ..self }
+    }
+
+    /// The number of consecutive connection failures after which the next endpoint is tried
+    pub fn with_max_failures(self, max_failures: u32) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            ..self
+        }
+    }
+
+    /// Connects to the endpoints using MQTT 5 rather than MQTT 3.1.1
+    pub fn with_mqtt5(self, mqtt5: Mqtt5Options) -> Self {
+        Self {
+            mqtt5: Some(mqtt5),
+            ..self
+        }
+    }
+
+    /// Registers the same last will on all the endpoints
+    pub fn with_last_will(mut self, will: LastWill) -> Self {
+        for endpoint in &mut self.endpoints {
+            endpoint.set_last_will(will.clone());
+        }
+        self
+    }
+
+    /// Registers a last will built afresh for each connection attempt, whatever the endpoint
+    ///
+    /// The last will of the first connection attempt is built right away.
+    pub fn with_last_will_provider(
+        self,
+        provider: impl Fn() -> LastWill + Send + Sync + 'static,
+    ) -> Self {
+        let provider = LastWillProvider(Arc::new(provider));
+        let endpoints = self.with_last_will(provider.last_will());
+        Self {
+            last_will_provider: Some(provider),
+            ..endpoints
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    pub(crate) fn endpoints_mut(&mut self) -> impl Iterator<Item = &mut MqttOptions> {
+        self.endpoints.iter_mut()
+    }
+
+    pub(crate) fn primary(&self) -> &MqttOptions {
+        &self.endpoints[0]
+    }
+
+    pub(crate) fn mqtt5(&self) -> Option<&Mqtt5Options> {
+        self.mqtt5.as_ref()
+    }
+
+    pub(crate) fn last_will_provider(&self) -> Option<&LastWillProvider> {
+        self.last_will_provider.as_ref()
+    }
+}
+
+/// Builds the last will of each connection attempt
+#[derive(Clone)]
+pub(crate) struct LastWillProvider(Arc<dyn Fn() -> LastWill + Send + Sync>);
+
+impl LastWillProvider {
+    pub fn last_will(&self) -> LastWill {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for LastWillProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LastWillProvider")
+    }
+}
+
+impl From<MqttOptions> for CloudEndpoints {
+    fn from(primary: MqttOptions) -> Self {
+        CloudEndpoints::new(primary)
+    }
+}
+
+/// The outcome of a connection attempt, as observed by a half bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionOutcome {
+    /// The connection has been acknowledged by the broker
+    Connected,
+    /// An established connection has been lost
+    Lost,
+    /// The broker could not be reached
+    Failed,
+}
+
+/// Tracks the connection failures to choose the endpoint to connect to
+pub(crate) struct Failover {
+    endpoints: Vec<MqttOptions>,
+    strategy: FailoverStrategy,
+    max_failures: u32,
+    current: usize,
+    failures: u32,
+    tried: Vec<bool>,
+    tx_endpoint: watch::Sender<String>,
+}
+
+impl Failover {
+    pub fn new(endpoints: CloudEndpoints) -> (Self, watch::Receiver<String>) {
+        let (tx_endpoint, rx_endpoint) = watch::channel(endpoint_name(endpoints.primary()));
+        let mut tried = vec![false; endpoints.len()];
+        tried[0] = true;
+        let failover = Failover {
+            endpoints: endpoints.endpoints,
+            strategy: endpoints.strategy,
+            max_failures: endpoints.max_failures,
+            current: 0,
+            failures: 0,
+            tried,
+            tx_endpoint,
+        };
+        (failover, rx_endpoint)
+    }
+
+    /// Updates the failure count, returning the new endpoint to connect to, if any
+    ///
+    /// The flag returned along the new endpoint tells if this endpoint has not been tried yet
+    /// since the last successful connection, in which case there is no point to back off.
+    pub fn update(&mut self, outcome: ConnectionOutcome) -> Option<(&MqttOptions, bool)> {
+        let next = match outcome {
+            ConnectionOutcome::Connected => {
+                self.failures = 0;
+                self.tried.fill(false);
+                self.tried[self.current] = true;
+                return None;
+            }
+            ConnectionOutcome::Lost => {
+                self.failures = 0;
+                match self.strategy {
+                    // Fail back to the primary endpoint
+                    FailoverStrategy::Primary if self.current != 0 => 0,
+                    _ => return None,
+                }
+            }
+            ConnectionOutcome::Failed => {
+                self.failures += 1;
+                if self.failures < self.max_failures || self.endpoints.len() < 2 {
+                    return None;
+                }
+                self.failures = 0;
+                self.next_endpoint()
+            }
+        };
+
+        self.current = next;
+        let untried = !std::mem::replace(&mut self.tried[next], true);
+        let endpoint = &self.endpoints[next];
+        self.tx_endpoint.send_replace(endpoint_name(endpoint));
+        Some((endpoint, untried))
+    }
+
+    fn next_endpoint(&self) -> usize {
+        let count = self.endpoints.len();
+        match self.strategy {
+            FailoverStrategy::Primary | FailoverStrategy::RoundRobin => (self.current + 1) % count,
+            FailoverStrategy::Random => (self.current + fastrand::usize(1..count)) % count,
+        }
+    }
+}
+
+/// The name of an endpoint, as published on the bridge health status
+pub(crate) fn endpoint_name(endpoint: &MqttOptions) -> String {
+    let (address, port) = endpoint.broker_address();
+    if address.contains("://") {
+        // A websocket URL already includes the port
+        address
+    } else {
+        format!("{address}:{port}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_strategy_fails_over_in_order_and_back_to_the_primary() {
+        let (mut failover, endpoint) = failover(FailoverStrategy::Primary, 2);
+
+        assert_eq!(next(&mut failover, ConnectionOutcome::Failed), None);
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("b:8883".into(), true))
+        );
+        assert_eq!(*endpoint.borrow(), "b:8883");
+        assert_eq!(next(&mut failover, ConnectionOutcome::Connected), None);
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Lost),
+            Some(("a:8883".into(), true))
+        );
+        assert_eq!(*endpoint.borrow(), "a:8883");
+    }
+
+    #[test]
+    fn round_robin_strategy_stays_on_the_current_endpoint_once_connected() {
+        let (mut failover, _) = failover(FailoverStrategy::RoundRobin, 1);
+
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("b:8883".into(), true))
+        );
+        assert_eq!(next(&mut failover, ConnectionOutcome::Connected), None);
+        assert_eq!(next(&mut failover, ConnectionOutcome::Lost), None);
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("c:8883".into(), true))
+        );
+    }
+
+    #[test]
+    fn endpoints_are_flagged_as_tried_until_a_connection_succeeds() {
+        let (mut failover, _) = failover(FailoverStrategy::RoundRobin, 1);
+
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("b:8883".into(), true))
+        );
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("c:8883".into(), true))
+        );
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("a:8883".into(), false))
+        );
+        assert_eq!(
+            next(&mut failover, ConnectionOutcome::Failed),
+            Some(("b:8883".into(), false))
+        );
+    }
+
+    #[test]
+    fn random_strategy_never_picks_the_current_endpoint() {
+        let (mut failover, _) = failover(FailoverStrategy::Random, 1);
+
+        let mut current = "a:8883".to_owned();
+        for _ in 0..20 {
+            let (endpoint, _) = next(&mut failover, ConnectionOutcome::Failed).unwrap();
+            assert_ne!(endpoint, current);
+            current = endpoint;
+        }
+    }
+
+    #[test]
+    fn a_single_endpoint_is_never_switched() {
+        let endpoints = CloudEndpoints::new(MqttOptions::new("device", "a", 8883));
+        let (mut failover, _) = Failover::new(endpoints.with_max_failures(1));
+
+        assert_eq!(next(&mut failover, ConnectionOutcome::Failed), None);
+        assert_eq!(next(&mut failover, ConnectionOutcome::Lost), None);
+    }
+
+    fn failover(
+        strategy: FailoverStrategy,
+        max_failures: u32,
+    ) -> (Failover, watch::Receiver<String>) {
+        let endpoints = CloudEndpoints::new(MqttOptions::new("device", "a", 8883))
+            .with_endpoint(MqttOptions::new("device", "b", 8883))
+            .with_endpoint(MqttOptions::new("device", "c", 8883))
+            .with_strategy(strategy)
+            .with_max_failures(max_failures);
+        Failover::new(endpoints)
+    }
+
+    fn next(failover: &mut Failover, outcome: ConnectionOutcome) -> Option<(String, bool)> {
+        failover
+            .update(outcome)
+            .map(|(endpoint, untried)| (endpoint_name(endpoint), untried))
+    }
+}
@@ -1,30 +1,44 @@
 use crate::overall_status;
+use crate::queue::BridgeQueue;
 use crate::BridgeAsyncClient;
 use crate::BridgeMessageSender;
 use crate::MqttClient;
 use crate::Status;
-use rumqttc::ConnectionError;
 use rumqttc::Event;
 use rumqttc::Incoming;
 use rumqttc::Publish;
 use rumqttc::QoS;
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
+
+/// How often the depth of the bridge queues is checked, to be published along the bridge health
+const QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(10);
 
 /// A tool for monitoring and publishing the health of the two bridge halves
 ///
 /// When [Self::monitor] runs, this will watch the status of the bridge halves, and notify the
 /// relevant MQTT topic about the overall health.
+/// When persistent queues are used, the health status also gives the number of queued messages
+/// per direction, e.g. `{"status":"up","queued":{"outbound":12,"inbound":0}}`.
+/// When the bridge has failover endpoints, the health status also gives the active cloud endpoint,
+/// e.g. `{"status":"up","endpoint":"eu-2.example.com:8883"}`.
 pub struct BridgeHealthMonitor {
     topic: String,
     rx_status: mpsc::Receiver<(&'static str, Status)>,
     companion_bridge_half: BridgeMessageSender,
+    queues: Vec<BridgeQueue>,
+    active_endpoint: Option<watch::Receiver<String>>,
 }
 
 impl BridgeHealthMonitor {
     pub(crate) fn new<Client: MqttClient + 'static>(
         topic: String,
         bridge_half: &BridgeAsyncClient<Client>,
+        queues: Vec<BridgeQueue>,
+        active_endpoint: Option<watch::Receiver<String>>,
     ) -> (mpsc::Sender<(&'static str, Status)>, Self) {
         let (tx, rx_status) = mpsc::channel(10);
         (
@@ -33,24 +47,36 @@ impl BridgeHealthMonitor {
                 topic,
                 rx_status,
                 companion_bridge_half: bridge_half.clone_sender(),
+                queues,
+                active_endpoint,
             },
         )
     }
 
     pub async fn monitor(mut self) -> ! {
         let mut statuses = HashMap::from([("local", None), ("cloud", None)]);
-        let mut last_status = None;
+        let mut last_health = None;
+        let mut queue_depth_check = tokio::time::interval(QUEUE_DEPTH_INTERVAL);
         loop {
-            let (name, status) = self.rx_status.recv().await.unwrap();
-            *statuses.entry(name).or_insert(Some(status)) = Some(status);
-
-            let status = statuses.values().fold(Some(Status::Up), overall_status);
-            if last_status != status {
-                last_status = status;
+            tokio::select! {
+                status = self.rx_status.recv() => {
+                    let (name, status) = status.unwrap();
+                    *statuses.entry(name).or_insert(Some(status)) = Some(status);
+                    for queue in self.queues.iter().filter(|queue| queue.target() == name) {
+                        queue.set_target_connected(status == Status::Up);
+                    }
+                }
+                _ = queue_depth_check.tick(), if !self.queues.is_empty() => {}
+            }
 
-                let mut health_msg =
-                    Publish::new(&self.topic, QoS::AtLeastOnce, status.unwrap().json());
+            let Some(status) = statuses.values().fold(Some(Status::Up), overall_status) else {
+                continue;
+            };
+            let health = self.health_payload(status);
+            if last_health.as_ref() != Some(&health) {
+                let mut health_msg = Publish::new(&self.topic, QoS::AtLeastOnce, health.clone());
                 health_msg.retain = true;
+                last_health = Some(health);
 
                 // Publish the health message over MQTT, but with no duplicate for the companion
                 // as this message doesn't have to be acknowledged
@@ -58,9 +84,28 @@ impl BridgeHealthMonitor {
             }
         }
     }
-}
 
-type NotificationRes = Result<Event, ConnectionError>;
+    fn health_payload(&self, status: Status) -> String {
+        let mut fields = Vec::new();
+        if let Some(endpoint) = &self.active_endpoint {
+            fields.push(format!(r#""endpoint":"{}""#, endpoint.borrow().as_str()));
+        }
+        if !self.queues.is_empty() {
+            let depths: Vec<_> = self
+                .queues
+                .iter()
+                .map(|queue| format!(r#""{}":{}"#, queue.name(), queue.depth()))
+                .collect();
+            fields.push(format!(r#""queued":{{{}}}"#, depths.join(",")));
+        }
+        if fields.is_empty() {
+            return status.json().to_string();
+        }
+
+        let status = status.json().trim_end_matches('}');
+        format!("{status},{}}}", fields.join(","))
+    }
+}
 
 /// A client for [BridgeHealthMonitor]
 ///
@@ -80,7 +125,7 @@ impl BridgeHealth {
         }
     }
 
-    pub async fn update(&mut self, result: &NotificationRes) {
+    pub async fn update(&mut self, result: &Result<Event, impl Display>) {
         let name = self.name;
         let err = match result {
             Ok(event) => {
@@ -1,19 +1,21 @@
 mod backoff;
 pub mod config;
 pub mod config_toml;
+mod endpoints;
 pub mod persist;
+mod priority;
 #[cfg(test)]
 mod test_helpers;
 mod topics;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use camino::Utf8PathBuf;
 use futures::channel::mpsc as futures_mpsc;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 pub use rumqttc;
 use rumqttc::ClientError;
-use rumqttc::ConnectionError;
 use rumqttc::Event;
 use rumqttc::Incoming;
 use rumqttc::LastWill;
@@ -22,7 +24,6 @@ use rumqttc::Outgoing;
 use rumqttc::PubAck;
 use rumqttc::PubRec;
 use rumqttc::Publish;
-use rumqttc::Request;
 use rumqttc::SubscribeFilter;
 use rumqttc::Transport;
 use std::borrow::Cow;
@@ -35,6 +36,7 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 use tedge_actors::Actor;
 use tedge_actors::Builder;
 use tedge_actors::DynSender;
@@ -42,6 +44,7 @@ use tedge_actors::RuntimeError;
 use tedge_actors::RuntimeRequest;
 use tedge_actors::RuntimeRequestSink;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tracing::Instrument;
 
@@ -49,7 +52,20 @@ pub type MqttConfig = mqtt_channel::Config;
 
 use crate::health::BridgeHealth;
 use crate::health::BridgeHealthMonitor;
+use crate::mqtt5::Mqtt5Client;
+pub use crate::mqtt5::Mqtt5Options;
 use crate::mqtt_logging::LoggingAsyncClient;
+use crate::mqtt_logging::LoggingEventLoop;
+use crate::priority::PriorityLanes;
+use crate::queue::BridgeQueue;
+use crate::queue::QueueAck;
+pub use crate::queue::QueueConfig;
+pub use crate::stats::BridgeStats;
+pub use crate::stats::DirectionStats;
+use crate::stats::HalfBridgeStats;
+pub use crate::stats::RuleStats;
+use crate::stats::StatsPublisher;
+use crate::stats::StatsRecorder;
 pub use mqtt_channel::DebugPayload;
 pub use mqtt_channel::MqttError;
 pub use mqtt_channel::MqttMessage;
@@ -59,10 +75,16 @@ use tedge_config::tedge_toml::TEdgeConfigReaderMqttBridgeReconnectPolicy;
 use tedge_config::TEdgeConfig;
 
 use crate::backoff::CustomBackoff;
+use crate::endpoints::ConnectionOutcome;
+use crate::endpoints::Failover;
 use crate::topics::matches_ignore_dollar_prefix;
+use crate::topics::RateLimiter;
 use crate::topics::TopicConverter;
 pub use config::*;
 pub use config_toml::AuthMethod;
+pub use config_toml::Priority;
+pub use endpoints::CloudEndpoints;
+pub use endpoints::FailoverStrategy;
 pub use persist::load_bridge_rules_from_directory;
 pub use persist::persist_bridge_config_file;
 pub use persist::visit_bridge_config_dir;
@@ -84,7 +106,10 @@ macro_rules! log_event {
 
 // We have to declare these modules here as they depend on the macro defined above
 mod health;
+mod mqtt5;
 mod mqtt_logging;
+mod queue;
+mod stats;
 
 pub struct MqttBridgeActorBuilder {
     tasks: Vec<JoinHandle<()>>,
@@ -99,7 +124,7 @@ impl MqttBridgeActorBuilder {
         service_name: &str,
         health_topic: &Topic,
         rules: BridgeConfig,
-        mut cloud_config: MqttOptions,
+        cloud_endpoints: impl Into<CloudEndpoints>,
         on_cloud_reconnect: Option<Publish>,
     ) -> Self {
         let mut local_config = MqttOptions::new(
@@ -132,11 +157,6 @@ impl MqttBridgeActorBuilder {
         ));
         local_config.set_clean_session(false);
 
-        let reconnect_policy = tedge_config.mqtt.bridge.reconnect_policy.clone();
-
-        cloud_config.set_manual_acks(true);
-        cloud_config.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
-
         // When configured with a low max inflight count of messages, rumqttc might reuse the pkid of message not acknowledged yet
         // leading to the confusing messages:
         // 2024-09-10T16:13:23.497043857Z  INFO rumqttc::state: Collision on packet id = 1
@@ -147,11 +167,113 @@ impl MqttBridgeActorBuilder {
         //
         // To prevent that, rumqttc inflight is set far bigger than the number of expected inflight messages.
         let in_flight: u16 = 100;
-        cloud_config.set_inflight(in_flight * 5);
-        let (local_client, local_event_loop) =
-            LoggingAsyncClient::new(local_config, in_flight.into(), "local".into());
-        let (cloud_client, cloud_event_loop) =
-            LoggingAsyncClient::new(cloud_config, in_flight.into(), "cloud".into());
+        let mut cloud_endpoints = cloud_endpoints.into();
+        for cloud_config in cloud_endpoints.endpoints_mut() {
+            cloud_config.set_manual_acks(true);
+            cloud_config.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+            cloud_config.set_inflight(in_flight * 5);
+        }
+        let local = LoggingAsyncClient::new(local_config, in_flight.into(), "local".into());
+        let primary = cloud_endpoints.primary().clone();
+        let mqtt5 = cloud_endpoints.mqtt5().copied();
+        let last_will_provider = cloud_endpoints.last_will_provider().cloned();
+        let (failover, active_endpoint) = match cloud_endpoints.len() {
+            1 => (None, None),
+            _ => {
+                let (failover, active_endpoint) = Failover::new(cloud_endpoints);
+                (Some(failover), Some(active_endpoint))
+            }
+        };
+        let bridge = BridgeTasks {
+            tedge_config,
+            service_name,
+            health_topic,
+            rules,
+            active_endpoint,
+            on_cloud_reconnect,
+            in_flight: in_flight.into(),
+        };
+
+        let tasks = match mqtt5 {
+            None => {
+                let (cloud_client, mut cloud_event_loop) =
+                    LoggingAsyncClient::new(primary, in_flight.into(), "cloud".into());
+                if let Some(failover) = failover {
+                    cloud_event_loop = cloud_event_loop.with_failover(failover);
+                }
+                if let Some(provider) = last_will_provider {
+                    cloud_event_loop = cloud_event_loop.with_last_will_provider(provider);
+                }
+                bridge.spawn(local, (cloud_client, cloud_event_loop))
+            }
+            Some(mqtt5) => {
+                let (cloud_client, mut cloud_event_loop) = Mqtt5Client::new(
+                    &primary,
+                    mqtt5,
+                    &bridge.rules,
+                    in_flight.into(),
+                    "cloud".into(),
+                );
+                if let Some(failover) = failover {
+                    cloud_event_loop = cloud_event_loop.with_failover(failover);
+                }
+                if let Some(provider) = last_will_provider {
+                    cloud_event_loop = cloud_event_loop.with_last_will_provider(provider);
+                }
+                bridge.spawn(local, (cloud_client, cloud_event_loop))
+            }
+        };
+        let (signal_tx, signal_rx) = futures_mpsc::channel(1);
+
+        Self {
+            tasks,
+            signal_tx,
+            signal_rx,
+        }
+    }
+
+    pub(crate) fn build_actor(self) -> MqttBridgeActor {
+        MqttBridgeActor {
+            tasks: self.tasks.into_iter().collect(),
+            signal_rx: self.signal_rx,
+        }
+    }
+}
+
+/// The settings shared by the tasks of a bridge, whatever the MQTT version used to connect the cloud
+struct BridgeTasks<'a> {
+    tedge_config: &'a TEdgeConfig,
+    service_name: &'a str,
+    health_topic: &'a Topic,
+    rules: BridgeConfig,
+    active_endpoint: Option<watch::Receiver<String>>,
+    on_cloud_reconnect: Option<Publish>,
+    in_flight: usize,
+}
+
+impl BridgeTasks<'_> {
+    /// Spawns the two halves of the bridge, along the health monitor and the queue draining tasks
+    fn spawn<CloudClient, CloudEvents>(
+        self,
+        local: (LoggingAsyncClient, LoggingEventLoop),
+        cloud: (CloudClient, CloudEvents),
+    ) -> Vec<JoinHandle<()>>
+    where
+        CloudClient: MqttClient + 'static,
+        CloudEvents: MqttEvents + 'static,
+    {
+        let BridgeTasks {
+            tedge_config,
+            service_name,
+            health_topic,
+            rules,
+            active_endpoint,
+            on_cloud_reconnect,
+            in_flight,
+        } = self;
+        let (local_client, local_event_loop) = local;
+        let (cloud_client, cloud_event_loop) = cloud;
+        let reconnect_policy = tedge_config.mqtt.bridge.reconnect_policy.clone();
 
         let local_topics: Vec<_> = rules
             .local_subscriptions()
@@ -162,12 +284,29 @@ impl MqttBridgeActorBuilder {
             .map(|t| SubscribeFilter::new(t.to_owned(), QoS::AtLeastOnce))
             .collect();
 
-        let [cloud_target, local_target] =
-            bidirectional_channel(cloud_client.clone(), local_client.clone(), in_flight.into());
+        let priority_quota = tedge_config.mqtt.bridge.priority.quota;
+        let (cloud_target, local_target) = bidirectional_channel(
+            cloud_client.clone(),
+            local_client.clone(),
+            in_flight,
+            priority_quota,
+        );
         let [(convert_local, bidir_local), (convert_cloud, bidir_cloud)] =
             rules.converters_and_bidirectional_topic_filters();
-        let (tx_status, monitor) =
-            BridgeHealthMonitor::new(health_topic.name.clone(), &local_target);
+        let stats = StatsRecorder::new(&convert_local, &convert_cloud);
+        let outbound_queue = open_queue(tedge_config, service_name, "outbound");
+        let inbound_queue = open_queue(tedge_config, service_name, "inbound");
+        let queues = outbound_queue
+            .iter()
+            .chain(&inbound_queue)
+            .cloned()
+            .collect();
+        let (tx_status, monitor) = BridgeHealthMonitor::new(
+            health_topic.name.clone(),
+            &local_target,
+            queues,
+            active_endpoint,
+        );
         let cloud_tx = cloud_target.clone_sender();
         let local_tx = local_target.clone_sender();
         let monitor_task = tokio::spawn(
@@ -176,8 +315,29 @@ impl MqttBridgeActorBuilder {
             }
             .instrument(tracing::Span::current()),
         );
-        let tasks = vec![
-            monitor_task,
+        let mut tasks = vec![monitor_task];
+        let stats_interval = tedge_config.mqtt.bridge.stats.interval.duration();
+        if !stats_interval.is_zero() {
+            let publisher = StatsPublisher {
+                recorder: stats.clone(),
+                interval: stats_interval,
+                measurement_topic: stats::measurement_topic(&health_topic.name),
+                path: stats_path(tedge_config, service_name),
+                local: local_target.clone_sender(),
+            };
+            tasks.push(tokio::spawn(
+                publisher.run().instrument(tracing::Span::current()),
+            ));
+        }
+        if let Some(queue) = &outbound_queue {
+            let drain = queue.clone().drain(cloud_target.clone_sender());
+            tasks.push(tokio::spawn(drain.instrument(tracing::Span::current())));
+        }
+        if let Some(queue) = &inbound_queue {
+            let drain = queue.clone().drain(local_target.clone_sender());
+            tasks.push(tokio::spawn(drain.instrument(tracing::Span::current())));
+        }
+        tasks.extend([
             tokio::spawn(
                 half_bridge(
                     local_event_loop,
@@ -191,6 +351,8 @@ impl MqttBridgeActorBuilder {
                     reconnect_policy.clone(),
                     None,
                     local_tx,
+                    outbound_queue,
+                    stats.local_half(),
                 )
                 .instrument(tracing::Span::current()),
             ),
@@ -207,47 +369,92 @@ impl MqttBridgeActorBuilder {
                     reconnect_policy,
                     on_cloud_reconnect,
                     cloud_tx,
+                    inbound_queue,
+                    stats.cloud_half(),
                 )
                 .instrument(tracing::Span::current()),
             ),
-        ];
-        let (signal_tx, signal_rx) = futures_mpsc::channel(1);
+        ]);
+        tasks
+    }
+}
 
-        Self {
-            tasks,
-            signal_tx,
-            signal_rx,
-        }
+/// Opens the persistent queue of a bridge direction, if enabled
+///
+/// If the queue cannot be opened, the bridge runs without it, keeping messages in memory only.
+fn open_queue(
+    tedge_config: &TEdgeConfig,
+    service_name: &str,
+    direction: &'static str,
+) -> Option<BridgeQueue> {
+    let queue_config = &tedge_config.mqtt.bridge.queue;
+    let (enabled, target) = match direction {
+        "outbound" => (queue_config.outbound, "cloud"),
+        _ => (queue_config.inbound, "local"),
+    };
+    if !enabled {
+        return None;
     }
 
-    pub(crate) fn build_actor(self) -> MqttBridgeActor {
-        MqttBridgeActor {
-            tasks: self.tasks.into_iter().collect(),
-            signal_rx: self.signal_rx,
+    let config = QueueConfig {
+        path: tedge_config
+            .data
+            .path
+            .join("bridge")
+            .join(service_name)
+            .join(format!("{direction}.queue")),
+        max_size: queue_config.max_size,
+        max_age: queue_config.max_age.duration(),
+        drop_oldest: queue_config.drop_oldest.0.clone(),
+        drain_rate: queue_config.drain_rate,
+        priority_quota: tedge_config.mqtt.bridge.priority.quota,
+    };
+    let path = config.path.clone();
+    match BridgeQueue::open(direction, target, config) {
+        Ok(queue) => Some(queue),
+        Err(err) => {
+            log_event!(error: direction, "Failed to open the bridge queue {path}: {err}");
+            None
         }
     }
 }
 
-fn bidirectional_channel<Client: MqttClient + 'static>(
-    cloud_client: Client,
-    local_client: Client,
+/// The path of the file where are saved the traffic stats of a bridge
+pub fn stats_path(tedge_config: &TEdgeConfig, service_name: &str) -> Utf8PathBuf {
+    tedge_config
+        .data
+        .path
+        .join("bridge")
+        .join(service_name)
+        .join("stats.json")
+}
+
+fn bidirectional_channel<CloudClient: MqttClient + 'static, LocalClient: MqttClient + 'static>(
+    cloud_client: CloudClient,
+    local_client: LocalClient,
     buffer: usize,
-) -> [BridgeAsyncClient<Client>; 2] {
+    priority_quota: u32,
+) -> (
+    BridgeAsyncClient<CloudClient>,
+    BridgeAsyncClient<LocalClient>,
+) {
     let (tx_first, rx_first) = mpsc::channel(buffer);
     let (tx_second, rx_second) = mpsc::channel(buffer);
-    [
-        BridgeAsyncClient::new(cloud_client, tx_first, rx_second),
-        BridgeAsyncClient::new(local_client, tx_second, rx_first),
-    ]
+    (
+        BridgeAsyncClient::new(cloud_client, tx_first, rx_second, priority_quota),
+        BridgeAsyncClient::new(local_client, tx_second, rx_first, priority_quota),
+    )
 }
 
 enum BridgeMessage {
     /// A message to be published to a given target topic
     ///
-    /// This message will have to be acknowledged to its source by the companion half bridge
+    /// This message will have to be acknowledged by the companion half bridge,
+    /// either to its source or to the queue it has been read from
     BridgePub {
         target_topic: String,
-        publish: Publish,
+        forwarded: Forwarded,
+        priority: Priority,
     },
 
     /// A message to be acknowledged on the target
@@ -261,17 +468,46 @@ enum BridgeMessage {
     Pub { publish: Publish },
 }
 
+/// A message published by a half bridge, as seen by its companion waiting for the acknowledgement
+enum Forwarded {
+    /// A message received from the source, to be acknowledged to the source
+    ///
+    /// The published message can differ from the source message,
+    /// when the bridge rule overrides its QoS or retain flag.
+    Received { publish: Publish, source: Publish },
+
+    /// A message read from a persistent queue, to be removed from the queue
+    ///
+    /// The message expiry interval is reduced by the time spent in the queue since `received_at`.
+    Queued {
+        publish: Publish,
+        ack: QueueAck,
+        received_at: SystemTime,
+    },
+}
+
+impl Forwarded {
+    fn publish(&self) -> &Publish {
+        match self {
+            Forwarded::Received { publish, .. } | Forwarded::Queued { publish, .. } => publish,
+        }
+    }
+}
+
 /// Wraps the target of an half bridge with a channel to its half bridge companion.
 ///
 /// So when a message is received and published by this half,
 /// the companion will await for that message to be acknowledged by the target
 /// before acknowledging to the source.
+///
+/// The messages waiting to be published on the target, e.g. while the target is not connected,
+/// are published by order of priority (see [PriorityLanes]).
 struct BridgeAsyncClient<Client: MqttClient> {
     /// MQTT target for the messages
     target: Client,
 
     /// Receives messages from the companion half bridge
-    rx: mpsc::Receiver<Option<(String, Publish)>>,
+    rx: mpsc::Receiver<Option<(String, Forwarded)>>,
 
     /// Sends messages to a background task that forwards the messages to the target and companion
     sender: BridgeMessageSender,
@@ -284,7 +520,7 @@ struct BridgeAsyncClient<Client: MqttClient> {
 }
 
 impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
-    pub async fn recv(&mut self) -> Option<Option<(String, Publish)>> {
+    pub async fn recv(&mut self) -> Option<Option<(String, Forwarded)>> {
         self.rx.recv().await
     }
 
@@ -294,8 +530,9 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
 
     fn new(
         target: Client,
-        tx: mpsc::Sender<Option<(String, Publish)>>,
-        rx: mpsc::Receiver<Option<(String, Publish)>>,
+        tx: mpsc::Sender<Option<(String, Forwarded)>>,
+        rx: mpsc::Receiver<Option<(String, Forwarded)>>,
+        priority_quota: u32,
     ) -> Self {
         let (unbounded_tx, unbounded_rx) = mpsc::unbounded_channel();
         let companion_bridge_half = BridgeAsyncClient {
@@ -305,12 +542,18 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
             published: Arc::new(AtomicUsize::new(0)),
             acknowledged: Arc::new(AtomicUsize::new(0)),
         };
-        companion_bridge_half.spawn_publisher(tx, unbounded_rx);
+        companion_bridge_half.spawn_publisher(tx, unbounded_rx, priority_quota);
         companion_bridge_half
     }
 
-    fn publish(&mut self, target_topic: String, publish: Publish) {
-        self.sender.publish(target_topic, publish)
+    fn publish(
+        &mut self,
+        target_topic: String,
+        publish: Publish,
+        source: Publish,
+        priority: Priority,
+    ) {
+        self.sender.publish(target_topic, publish, source, priority)
     }
 
     fn ack(&mut self, publish: Publish) {
@@ -327,28 +570,62 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
 
     fn spawn_publisher(
         &self,
-        tx: mpsc::Sender<Option<(String, Publish)>>,
+        tx: mpsc::Sender<Option<(String, Forwarded)>>,
         mut unbounded_rx: mpsc::UnboundedReceiver<BridgeMessage>,
+        priority_quota: u32,
     ) {
         let target = self.target.clone();
         let published = self.published.clone();
         let acknowledged = self.acknowledged.clone();
         tokio::spawn(
             async move {
-                while let Some(message) = unbounded_rx.recv().await {
+                let mut lanes = PriorityLanes::new(priority_quota);
+                loop {
+                    // All the messages received so far are sorted by priority before publishing the next one
+                    let message = if lanes.is_empty() {
+                        match unbounded_rx.recv().await {
+                            Some(message) => message,
+                            None => break,
+                        }
+                    } else if let Ok(message) = unbounded_rx.try_recv() {
+                        message
+                    } else {
+                        let (target_topic, forwarded): (String, Forwarded) = lanes.pop().unwrap();
+                        let received_at = match forwarded {
+                            Forwarded::Queued {
+                                ack, received_at, ..
+                            } if target.has_expired(&target_topic, received_at) => {
+                                tracing::info!(target: "MQTT bridge", "Dropping queued message expired before being published on {target_topic}");
+                                ack.done();
+                                continue;
+                            }
+                            Forwarded::Queued { received_at, .. } => Some(received_at),
+                            Forwarded::Received { .. } => None,
+                        };
+                        let publish = forwarded.publish().clone();
+                        tx.send(Some((target_topic.clone(), forwarded)))
+                            .await
+                            .unwrap();
+                        let (qos, retain, payload) = (publish.qos, publish.retain, publish.payload);
+                        match received_at {
+                            Some(received_at) => target
+                                .publish_queued(target_topic, qos, retain, payload, received_at)
+                                .await
+                                .unwrap(),
+                            None => target
+                                .publish(target_topic, qos, retain, payload)
+                                .await
+                                .unwrap(),
+                        }
+                        published.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    };
                     match message {
                         BridgeMessage::BridgePub {
                             target_topic,
-                            publish,
-                        } => {
-                            let duplicate = (target_topic.clone(), publish.clone());
-                            tx.send(Some(duplicate)).await.unwrap();
-                            target
-                                .publish(target_topic, publish.qos, publish.retain, publish.payload)
-                                .await
-                                .unwrap();
-                            published.fetch_add(1, Ordering::Relaxed);
-                        }
+                            forwarded,
+                            priority,
+                        } => lanes.push(priority, (target_topic, forwarded)),
                         BridgeMessage::Pub { publish } => {
                             tx.send(None).await.unwrap();
                             target
@@ -385,11 +662,39 @@ impl BridgeMessageSender {
             .unwrap()
     }
 
-    fn publish(&mut self, target_topic: String, publish: Publish) {
+    fn publish(
+        &mut self,
+        target_topic: String,
+        publish: Publish,
+        source: Publish,
+        priority: Priority,
+    ) {
         self.unbounded_tx
             .send(BridgeMessage::BridgePub {
                 target_topic,
-                publish,
+                forwarded: Forwarded::Received { publish, source },
+                priority,
+            })
+            .unwrap()
+    }
+
+    /// Publish a message read from a queue, the message topic being the target topic
+    fn queued_publish(
+        &mut self,
+        publish: Publish,
+        ack: QueueAck,
+        priority: Priority,
+        received_at: SystemTime,
+    ) {
+        self.unbounded_tx
+            .send(BridgeMessage::BridgePub {
+                target_topic: publish.topic.clone(),
+                forwarded: Forwarded::Queued {
+                    publish,
+                    ack,
+                    received_at,
+                },
+                priority,
             })
             .unwrap()
     }
@@ -495,6 +800,18 @@ impl BridgeMessageSender {
 /// - The `half_bridge(cloud_event_loop,local_client)` receives cloud messages and publishes these message locally.
 /// - The `half_bridge(local_event_loop,cloud_client)` handles the acknowledgements: waiting for messages be acknowledged locally, before sending acks for the original messages.
 ///
+/// # Persistent queues
+/// When a `queue` is provided, the messages received by this half are persisted and acknowledged
+/// to their source right away, rather than forwarded to the companion. A separate task reads
+/// the queue and publishes the messages on the target, passing to the companion a [QueueAck]
+/// rather than the original message. On acknowledgement by the target, the companion
+/// removes the message from the queue, completing the message flow.
+///
+/// If a message cannot be persisted, because the queue is full or not writable,
+/// the message is forwarded as if there were no queue.
+/// This is also the case while the queue is empty and the target connected,
+/// the live traffic being then neither persisted nor throttled.
+///
 /// # Health topics
 /// The bridge will publish health information to `health_topic` (if supplied) on `target` to enable
 /// other components to establish bridge health. This is intended to be used the half with cloud
@@ -515,6 +832,8 @@ async fn half_bridge(
     reconnect_policy: TEdgeConfigReaderMqttBridgeReconnectPolicy,
     reconnect_message: Option<Publish>,
     mut self_tx: BridgeMessageSender,
+    queue: Option<BridgeQueue>,
+    mut stats: HalfBridgeStats,
 ) {
     let mut backoff = CustomBackoff::new(
         ::backoff::SystemClock {},
@@ -522,10 +841,11 @@ async fn half_bridge(
         reconnect_policy.maximum_interval.duration(),
         reconnect_policy.reset_window.duration(),
     );
-    let mut forward_pkid_to_received_msg = HashMap::<u16, Option<Publish>>::new();
+    let mut forward_pkid_to_received_msg = HashMap::<u16, Option<Forwarded>>::new();
     let mut bridge_health = BridgeHealth::new(name, tx_health);
     let mut loop_breaker =
         MessageLoopBreaker::new(recv_client.clone(), bidirectional_topic_filters);
+    let mut rate_limiter = RateLimiter::default();
 
     let mut received = 0; // Count of messages received by this half-bridge
     let mut published = 0; // Count of messages published (by the companion)
@@ -538,6 +858,10 @@ async fn half_bridge(
     let mut session_present: Option<bool> = None;
     let mut pending = Vec::new();
 
+    // Whether the connection has been acknowledged by the broker, telling apart lost connections
+    // from failed connection attempts, when choosing the broker endpoint to connect to
+    let mut connected = false;
+
     loop {
         let res = recv_event_loop.poll().await;
         bridge_health.update(&res).await;
@@ -548,6 +872,15 @@ async fn half_bridge(
                 notification
             }
             Err(_) => {
+                let outcome = match std::mem::take(&mut connected) {
+                    true => ConnectionOutcome::Lost,
+                    false => ConnectionOutcome::Failed,
+                };
+                if recv_event_loop.on_connection(outcome) {
+                    // A new endpoint is tried with the initial reconnection interval
+                    backoff.reset();
+                    requeue_in_flight(&mut forward_pkid_to_received_msg);
+                }
                 let time = backoff.backoff();
                 if !time.is_zero() {
                     log_event!(
@@ -578,6 +911,8 @@ async fn half_bridge(
 
         match notification {
             Event::Incoming(Incoming::ConnAck(conn_ack)) => {
+                connected = true;
+                recv_event_loop.on_connection(ConnectionOutcome::Connected);
                 log_event!(name, "Bridge connection subscribing to {topics:?}");
 
                 // Publish reconnect message if provided
@@ -616,14 +951,43 @@ async fn half_bridge(
             // Forward messages from event loop to target
             Event::Incoming(Incoming::Publish(publish)) => {
                 if let Some(publish) = loop_breaker.ensure_not_looped(publish).await {
-                    if let Some(topic) = transformer.convert_topic(&publish.topic) {
+                    if let Some((index, topic)) = transformer.convert(&publish.topic) {
+                        let topic = topic.to_string();
+                        let options = transformer.rule(index).options();
+                        if let Some(limit) = &options.rate_limit {
+                            if !rate_limiter.allow(index, limit, Instant::now()) {
+                                log_event!(debug: name, "Rate limit exceeded, dropping message received on {}", publish.topic);
+                                stats.dropped(index);
+                                recv_client.ack(&publish).await.unwrap();
+                                continue;
+                            }
+                        }
+
                         received += 1;
-                        target.publish(topic.to_string(), publish);
+                        let forwarded = options.apply(&publish);
+                        stats.forwarded(index, &forwarded);
+                        let queued = match &queue {
+                            Some(queue) => queue.push(&topic, &forwarded, options.priority).await,
+                            None => false,
+                        };
+                        if queued {
+                            // Once persisted, the message can be acknowledged to its source
+                            recv_client.ack(&publish).await.unwrap()
+                        } else if forwarded.qos == QoS::AtMostOnce && publish.qos != QoS::AtMostOnce
+                        {
+                            // The target will never acknowledge a message downgraded to QoS 0
+                            recv_client.ack(&publish).await.unwrap();
+                            target.publish(topic, forwarded, publish, options.priority)
+                        } else {
+                            target.publish(topic, forwarded, publish, options.priority)
+                        }
                     } else {
                         // Being not forwarded to this bridge target
                         // The message has to be acknowledged
                         recv_client.ack(&publish).await.unwrap()
                     }
+                } else {
+                    stats.duplicated();
                 }
             }
 
@@ -633,9 +997,15 @@ async fn half_bridge(
                 | Incoming::PubRec(PubRec { pkid: ack_pkid }),
             ) => {
                 match forward_pkid_to_received_msg.remove(&ack_pkid) {
-                    Some(Some(msg)) => {
+                    Some(Some(Forwarded::Received { source, .. })) => {
+                        acknowledged += 1;
+                        stats.acknowledged(ack_pkid, Instant::now());
+                        target.ack(source);
+                    }
+                    Some(Some(Forwarded::Queued { ack, .. })) => {
                         acknowledged += 1;
-                        target.ack(msg);
+                        stats.acknowledged(ack_pkid, Instant::now());
+                        ack.done();
                     }
                     Some(None) => {
                         // A health message was acked, nothing to do
@@ -651,13 +1021,17 @@ async fn half_bridge(
                 if let hash_map::Entry::Vacant(e) = forward_pkid_to_received_msg.entry(pkid) {
                     match target.recv().await {
                         // A message was forwarded by the other bridge half, note the packet id
-                        Some(Some((topic, msg))) => {
+                        Some(Some((topic, forwarded))) => {
                             published += 1;
-                            loop_breaker.forward_on_topic(topic, &msg);
+                            loop_breaker.forward_on_topic(topic, forwarded.publish());
                             if pkid != 0 {
                                 // Messages with pkid 0 (meaning QoS=0) should not be added to the hashmap
                                 // as multiple messages with the pkid=0 can be received
-                                e.insert(Some(msg));
+                                stats.published(pkid, Instant::now());
+                                e.insert(Some(forwarded));
+                            } else if let Forwarded::Queued { ack, .. } = forwarded {
+                                // A QoS 0 message will never be acknowledged
+                                ack.done();
                             }
                         }
 
@@ -687,11 +1061,43 @@ async fn half_bridge(
     }
 }
 
+/// Puts back in their queue the queued messages published and not acknowledged yet
+///
+/// These messages will never be acknowledged by the endpoint the bridge is failing over from,
+/// and have to be published again on the new endpoint.
+fn requeue_in_flight(forward_pkid_to_received_msg: &mut HashMap<u16, Option<Forwarded>>) {
+    let in_flight: Vec<u16> = forward_pkid_to_received_msg
+        .iter()
+        .filter(|(_, forwarded)| matches!(forwarded, Some(Forwarded::Queued { .. })))
+        .map(|(pkid, _)| *pkid)
+        .collect();
+    for pkid in in_flight {
+        if let Some(Some(Forwarded::Queued { ack, .. })) =
+            forward_pkid_to_received_msg.remove(&pkid)
+        {
+            ack.requeue();
+        }
+    }
+}
+
 #[async_trait::async_trait]
 trait MqttEvents: Send {
-    async fn poll(&mut self) -> Result<Event, ConnectionError>;
-    fn take_pending(&mut self) -> VecDeque<Request>;
-    fn set_pending(&mut self, requests: Vec<Request>);
+    /// The requests pending on a lost connection, to be published again on reconnect
+    type Request: std::fmt::Debug + Send;
+
+    /// The connection errors
+    type Error: std::fmt::Display + Send + Sync;
+
+    async fn poll(&mut self) -> Result<Event, Self::Error>;
+    fn take_pending(&mut self) -> VecDeque<Self::Request>;
+    fn set_pending(&mut self, requests: Vec<Self::Request>);
+
+    /// Notifies the outcome of a connection attempt, possibly switching to another broker endpoint
+    ///
+    /// Returns `true` if the event loop switched to an endpoint not tried since the last connection.
+    fn on_connection(&mut self, _outcome: ConnectionOutcome) -> bool {
+        false
+    }
 }
 
 #[async_trait::async_trait]
@@ -704,6 +1110,23 @@ trait MqttClient: MqttAck + Clone + Send + Sync {
         retain: bool,
         payload: Bytes,
     ) -> Result<(), ClientError>;
+
+    /// Tells whether a message received at the given time expired before being published on the topic
+    fn has_expired(&self, _topic: &str, _received_at: SystemTime) -> bool {
+        false
+    }
+
+    /// Publishes a message that has been queued since the given time
+    async fn publish_queued(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Bytes,
+        _received_at: SystemTime,
+    ) -> Result<(), ClientError> {
+        self.publish(topic, qos, retain, payload).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -1170,12 +1593,79 @@ mod tests {
         }
     }
 
+    mod requeue_in_flight {
+        use crate::config_toml::Priority;
+        use crate::queue::BridgeQueue;
+        use crate::requeue_in_flight;
+        use crate::Forwarded;
+        use crate::QueueConfig;
+        use rumqttc::Publish;
+        use rumqttc::QoS;
+        use std::collections::HashMap;
+        use std::time::Duration;
+        use tedge_test_utils::fs::TempTedgeDir;
+
+        #[tokio::test]
+        async fn queued_messages_in_flight_are_put_back_in_their_queue() {
+            let ttd = TempTedgeDir::new();
+            let queue = BridgeQueue::open(
+                "outbound",
+                "cloud",
+                QueueConfig {
+                    path: ttd.utf8_path().join("bridge/outbound.queue"),
+                    max_size: 1024,
+                    max_age: Duration::from_secs(3600),
+                    drop_oldest: vec![],
+                    drain_rate: 0,
+                    priority_quota: 0,
+                },
+            )
+            .unwrap();
+            let message = Publish::new("c8y/s/us", QoS::AtLeastOnce, "queued");
+            queue.push("s/us", &message, Priority::Normal).await;
+
+            let (queued, ack) = queue.next_in_flight().await.unwrap();
+            let received = Publish::new("c8y/s/us", QoS::AtLeastOnce, "received");
+            let mut in_flight = HashMap::from([
+                (
+                    1,
+                    Some(Forwarded::Queued {
+                        publish: queued.publish,
+                        ack,
+                        received_at: queued.received_at,
+                    }),
+                ),
+                (
+                    2,
+                    Some(Forwarded::Received {
+                        publish: received.clone(),
+                        source: received,
+                    }),
+                ),
+                (3, None),
+            ]);
+
+            requeue_in_flight(&mut in_flight);
+
+            let mut pkids: Vec<_> = in_flight.keys().copied().collect();
+            pkids.sort();
+            assert_eq!(pkids, vec![2, 3]);
+            let (again, _) = tokio::time::timeout(Duration::from_secs(5), queue.next_in_flight())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(again.seq, queued.seq);
+            assert_eq!(again.publish.payload, "queued");
+        }
+    }
+
     mod bridge {
         use std::time::Duration;
 
         use crate::test_helpers::*;
         use crate::*;
         use rumqttc::mqttbytes::v4::*;
+        use rumqttc::ConnectionError;
         use rumqttc::Event;
         use rumqttc::QoS;
         use tedge_config::tedge_toml::TEdgeConfigReaderMqttBridgeReconnectPolicy;
@@ -1686,10 +2176,12 @@ mod tests {
                 let (tx1, rx1) = mpsc::channel(10);
 
                 let (tx_health, rx_health) = mpsc::channel(10);
-                let cloud_target = BridgeAsyncClient::new(self.cloud_client.clone(), tx0, rx1);
-                let local_target = BridgeAsyncClient::new(self.local_client.clone(), tx1, rx0);
+                let cloud_target = BridgeAsyncClient::new(self.cloud_client.clone(), tx0, rx1, 0);
+                let local_target = BridgeAsyncClient::new(self.local_client.clone(), tx1, rx0, 0);
                 let cloud_sender = cloud_target.clone_sender();
                 let local_sender = local_target.clone_sender();
+                let stats =
+                    StatsRecorder::new(&self.local_topic_converter, &self.cloud_topic_converter);
 
                 let local_task = tokio::spawn(half_bridge(
                     self.local_events.clone(),
@@ -1703,6 +2195,8 @@ mod tests {
                     TEdgeConfigReaderMqttBridgeReconnectPolicy::test_value(),
                     None,
                     local_sender,
+                    None,
+                    stats.local_half(),
                 ));
                 let cloud_task = tokio::spawn(half_bridge(
                     self.cloud_events.clone(),
@@ -1716,6 +2210,8 @@ mod tests {
                     TEdgeConfigReaderMqttBridgeReconnectPolicy::test_value(),
                     self.cloud_reconnect_message,
                     cloud_sender,
+                    None,
+                    stats.cloud_half(),
                 ));
 
                 tokio::time::timeout(Duration::from_secs(5), self.local_events.all_processed())
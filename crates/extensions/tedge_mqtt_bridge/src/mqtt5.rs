@@ -0,0 +1,689 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use rumqttc::v5;
+use rumqttc::v5::mqttbytes::v5::ConnAck as ConnAck5;
+use rumqttc::v5::mqttbytes::v5::LastWill as LastWill5;
+use rumqttc::v5::mqttbytes::v5::Packet as Packet5;
+use rumqttc::v5::mqttbytes::v5::PubAckReason;
+use rumqttc::v5::mqttbytes::v5::PubRecReason;
+use rumqttc::v5::mqttbytes::v5::Publish as Publish5;
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use rumqttc::v5::mqttbytes::QoS as QoS5;
+use rumqttc::ClientError;
+use rumqttc::ConnAck;
+use rumqttc::ConnectReturnCode;
+use rumqttc::Disconnect;
+use rumqttc::Event;
+use rumqttc::Incoming;
+use rumqttc::LastWill;
+use rumqttc::MqttOptions;
+use rumqttc::PubAck;
+use rumqttc::PubRec;
+use rumqttc::Publish;
+use rumqttc::QoS;
+use rumqttc::Request;
+use rumqttc::SubscribeFilter;
+
+use crate::endpoints::endpoint_name;
+use crate::endpoints::ConnectionOutcome;
+use crate::endpoints::Failover;
+use crate::endpoints::LastWillProvider;
+use crate::topics::matches_ignore_dollar_prefix;
+use crate::BridgeConfig;
+use crate::MqttAck;
+use crate::MqttClient;
+use crate::MqttEvents;
+
+/// The MQTT 5 specific settings of the cloud connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mqtt5Options {
+    /// The maximum number of topic aliases used to shorten the topics of the published messages
+    ///
+    /// The number of aliases actually used is also bounded by the maximum accepted by the broker,
+    /// and no aliases are used when this is set to 0.
+    pub topic_alias_max: u16,
+}
+
+/// Converts the options of an MQTT 3.1.1 connection into the options of the equivalent MQTT 5 connection
+pub(crate) fn mqtt5_options(options: &MqttOptions) -> v5::MqttOptions {
+    let (host, port) = options.broker_address();
+    let mut mqtt5_options = v5::MqttOptions::new(options.client_id(), host, port);
+    mqtt5_options.set_keep_alive(options.keep_alive());
+    mqtt5_options.set_clean_start(options.clean_session());
+    mqtt5_options.set_manual_acks(options.manual_acks());
+    mqtt5_options.set_max_packet_size(u32::try_from(options.max_packet_size()).ok());
+    mqtt5_options.set_outgoing_inflight_upper_limit(options.inflight());
+    mqtt5_options.set_transport(options.transport());
+    if let Some((username, password)) = options.credentials() {
+        mqtt5_options.set_credentials(username, password);
+    }
+    if let Some(will) = options.last_will() {
+        mqtt5_options.set_last_will(last_will5(will));
+    }
+    if let Some(proxy) = options.proxy() {
+        mqtt5_options.set_proxy(proxy);
+    }
+    if let Some(request_modifier) = options.request_modifier() {
+        mqtt5_options.set_request_modifier(move |request| request_modifier(request));
+    }
+    mqtt5_options
+}
+
+fn last_will5(will: LastWill) -> LastWill5 {
+    LastWill5::new(will.topic, will.message, qos5(will.qos), will.retain, None)
+}
+
+/// An MQTT 5 client publishing messages with the properties set by the bridge rules
+///
+/// The messages are published with the message expiry interval and user properties
+/// of the first outbound rule whose target filter matches the message topic.
+/// Topic aliases are assigned to the first published topics, up to the maximum accepted by the broker.
+#[derive(Clone)]
+pub struct Mqtt5Client {
+    inner: v5::AsyncClient,
+    properties: Arc<Vec<(String, PublishProperties)>>,
+    aliases: Arc<Mutex<TopicAliases>>,
+}
+
+impl Mqtt5Client {
+    pub fn new(
+        options: &MqttOptions,
+        mqtt5: Mqtt5Options,
+        rules: &BridgeConfig,
+        cap: usize,
+        log_prefix: String,
+    ) -> (Self, Mqtt5EventLoop) {
+        let (client, eventloop) = v5::AsyncClient::new(mqtt5_options(options), cap);
+        let properties = rules
+            .local_to_remote()
+            .iter()
+            .map(|rule| {
+                let options = rule.options();
+                let properties = PublishProperties {
+                    message_expiry_interval: options
+                        .message_expiry
+                        .map(|expiry| expiry.as_secs() as u32),
+                    user_properties: options.user_properties.clone(),
+                    ..PublishProperties::default()
+                };
+                (rule.target_filter().into_owned(), properties)
+            })
+            .collect();
+        let aliases = Arc::new(Mutex::new(TopicAliases::new(mqtt5.topic_alias_max)));
+
+        (
+            Self {
+                inner: client,
+                properties: Arc::new(properties),
+                aliases: aliases.clone(),
+            },
+            Mqtt5EventLoop {
+                inner: eventloop,
+                log_prefix,
+                has_logged_connect: false,
+                failover: None,
+                last_will_provider: None,
+                aliases,
+            },
+        )
+    }
+
+    fn publish_properties(&self, topic: &str) -> PublishProperties {
+        self.properties
+            .iter()
+            .find(|(filter, _)| matches_ignore_dollar_prefix(topic, filter))
+            .map(|(_, properties)| properties.clone())
+            .unwrap_or_default()
+    }
+
+    /// The properties of a message received at the given time, or `None` if the message has expired
+    ///
+    /// The message expiry interval is reduced by the time elapsed since the message has been received,
+    /// e.g. while queued during an outage.
+    fn publish_properties_at(
+        &self,
+        topic: &str,
+        received_at: SystemTime,
+    ) -> Option<PublishProperties> {
+        let mut properties = self.publish_properties(topic);
+        if let Some(interval) = properties.message_expiry_interval {
+            let elapsed = received_at.elapsed().unwrap_or_default();
+            let remaining = Duration::from_secs(interval.into())
+                .checked_sub(elapsed)
+                .filter(|remaining| !remaining.is_zero())?;
+            properties.message_expiry_interval = Some(remaining.as_secs_f64().ceil() as u32);
+        }
+        Some(properties)
+    }
+
+    async fn publish_with_properties(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Bytes,
+        properties: PublishProperties,
+    ) -> Result<(), ClientError> {
+        if self.try_publish_with_alias(&topic, qos, retain, &payload, &properties) {
+            return Ok(());
+        }
+        self.inner
+            .publish_with_properties(topic, qos5(qos), retain, payload, properties)
+            .await
+            .map_err(client_error)
+    }
+
+    /// Queues a message using a topic alias, returning `false` if no alias can be used
+    ///
+    /// The alias is assigned and the message queued while holding the lock on the aliases.
+    /// So, when the connection is lost, the event loop can give back their topic
+    /// to all the messages queued with an alias, before any alias is reused.
+    /// If the client channel is full, the alias is not used and the caller has to publish the message with its topic.
+    fn try_publish_with_alias(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &Bytes,
+        properties: &PublishProperties,
+    ) -> bool {
+        let mut aliases = self.aliases.lock().unwrap();
+        let Some(alias) = aliases.alias(topic) else {
+            return false;
+        };
+        let (alias_topic, alias_id) = match alias {
+            TopicAlias::New(alias) => (topic.to_owned(), alias),
+            TopicAlias::Known(alias) => (String::new(), alias),
+        };
+        let properties = PublishProperties {
+            topic_alias: Some(alias_id),
+            ..properties.clone()
+        };
+        let queued = self
+            .inner
+            .try_publish_with_properties(
+                alias_topic,
+                qos5(qos),
+                retain,
+                payload.clone(),
+                properties,
+            )
+            .is_ok();
+        if !queued && alias == TopicAlias::New(alias_id) {
+            aliases.release(topic);
+        }
+        queued
+    }
+}
+
+#[async_trait::async_trait]
+impl MqttClient for Mqtt5Client {
+    async fn subscribe(&self, topic: SubscribeFilter) -> Result<(), ClientError> {
+        self.inner
+            .subscribe(topic.path, qos5(topic.qos))
+            .await
+            .map_err(client_error)
+    }
+
+    async fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Bytes,
+    ) -> Result<(), ClientError> {
+        let properties = self.publish_properties(&topic);
+        self.publish_with_properties(topic, qos, retain, payload, properties)
+            .await
+    }
+
+    fn has_expired(&self, topic: &str, received_at: SystemTime) -> bool {
+        self.publish_properties_at(topic, received_at).is_none()
+    }
+
+    async fn publish_queued(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Bytes,
+        received_at: SystemTime,
+    ) -> Result<(), ClientError> {
+        // A message expiring right after having been checked is published with the shortest interval
+        let properties = self
+            .publish_properties_at(&topic, received_at)
+            .unwrap_or_else(|| PublishProperties {
+                message_expiry_interval: Some(1),
+                ..self.publish_properties(&topic)
+            });
+        self.publish_with_properties(topic, qos, retain, payload, properties)
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+#[mutants::skip]
+impl MqttAck for Mqtt5Client {
+    async fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
+        // Only the packet id and QoS of a message are used to acknowledge it
+        let publish = Publish5 {
+            dup: publish.dup,
+            qos: qos5(publish.qos),
+            retain: publish.retain,
+            topic: Bytes::copy_from_slice(publish.topic.as_bytes()),
+            pkid: publish.pkid,
+            payload: publish.payload.clone(),
+            properties: None,
+        };
+        self.inner.ack(&publish).await.map_err(client_error)
+    }
+}
+
+/// A wrapper around [v5::EventLoop] translating the MQTT 5 events into the events handled by the bridge
+///
+/// Along the translation, the MQTT 5 reason codes sent by the broker are logged.
+pub struct Mqtt5EventLoop {
+    inner: v5::EventLoop,
+    log_prefix: String,
+    has_logged_connect: bool,
+    failover: Option<Failover>,
+    last_will_provider: Option<LastWillProvider>,
+    aliases: Arc<Mutex<TopicAliases>>,
+}
+
+impl Mqtt5EventLoop {
+    /// Switches the broker endpoint on repeated connection failures
+    pub(crate) fn with_failover(self, failover: Failover) -> Self {
+        Self {
+            failover: Some(failover),
+            ..self
+        }
+    }
+
+    /// Registers a new last will before each reconnection attempt
+    pub(crate) fn with_last_will_provider(self, provider: LastWillProvider) -> Self {
+        Self {
+            last_will_provider: Some(provider),
+            ..self
+        }
+    }
+
+    /// Translates an MQTT 5 event, returning `None` for the events ignored by the bridge
+    fn translate(&mut self, event: v5::Event) -> Option<Event> {
+        let prefix = &self.log_prefix;
+        let packet = match event {
+            v5::Event::Outgoing(outgoing) => return Some(Event::Outgoing(outgoing)),
+            v5::Event::Incoming(packet) => packet,
+        };
+
+        match packet {
+            Packet5::ConnAck(connack) => {
+                log_event!(
+                    prefix,
+                    "Received CONNACK (Connection Acknowledged): {:?}",
+                    connack
+                );
+                let broker_alias_max = connack
+                    .properties
+                    .as_ref()
+                    .and_then(|properties| properties.topic_alias_max);
+                self.aliases.lock().unwrap().reset(broker_alias_max);
+                Some(Event::Incoming(Incoming::ConnAck(connack3(&connack))))
+            }
+            Packet5::Publish(publish) => {
+                Some(Event::Incoming(Incoming::Publish(publish3(publish))))
+            }
+            Packet5::PubAck(puback) => {
+                if !matches!(
+                    puback.reason,
+                    PubAckReason::Success | PubAckReason::NoMatchingSubscribers
+                ) {
+                    log_event!(warn: prefix,
+                        "Message pkid={} rejected by the broker: {:?} {:?}",
+                        puback.pkid,
+                        puback.reason,
+                        puback.properties.as_ref().and_then(|p| p.reason_string.as_ref()),
+                    );
+                }
+                Some(Event::Incoming(Incoming::PubAck(PubAck {
+                    pkid: puback.pkid,
+                })))
+            }
+            Packet5::PubRec(pubrec) => {
+                if !matches!(
+                    pubrec.reason,
+                    PubRecReason::Success | PubRecReason::NoMatchingSubscribers
+                ) {
+                    log_event!(warn: prefix,
+                        "Message pkid={} rejected by the broker: {:?} {:?}",
+                        pubrec.pkid,
+                        pubrec.reason,
+                        pubrec.properties.as_ref().and_then(|p| p.reason_string.as_ref()),
+                    );
+                }
+                Some(Event::Incoming(Incoming::PubRec(PubRec {
+                    pkid: pubrec.pkid,
+                })))
+            }
+            Packet5::SubAck(suback) => {
+                log_event!(
+                    prefix,
+                    "Received SUBACK (Subscription Acknowledged): {:?}",
+                    suback
+                );
+                None
+            }
+            Packet5::Disconnect(disconnect) => {
+                log_event!(warn: prefix,
+                    "Received DISCONNECT: {:?} {:?}",
+                    disconnect.reason_code,
+                    disconnect.properties.as_ref().and_then(|p| p.reason_string.as_ref()),
+                );
+                Some(Event::Incoming(Incoming::Disconnect))
+            }
+            _ => None,
+        }
+    }
+
+    /// Makes the pending messages independent of the topic aliases of the lost connection
+    ///
+    /// The topic aliases are only valid for the connection on which they have been established.
+    /// The messages to be published again on the next connection are given back their topic.
+    fn forget_topic_aliases(&mut self) {
+        // Holding the lock prevents the client from queuing messages with aliases,
+        // while the messages still in the client channel are moved to the pending messages
+        let mut aliases = self.aliases.lock().unwrap();
+        self.inner.clean();
+        let topics = aliases.reset(None);
+        for request in self.inner.pending.iter_mut() {
+            if let v5::Request::Publish(publish) = request {
+                let Some(properties) = publish.properties.as_mut() else {
+                    continue;
+                };
+                if let Some(alias) = properties.topic_alias.take() {
+                    if publish.topic.is_empty() {
+                        if let Some(topic) = topics.get(&alias) {
+                            publish.topic = Bytes::copy_from_slice(topic.as_bytes());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MqttEvents for Mqtt5EventLoop {
+    type Request = v5::Request;
+    type Error = v5::ConnectionError;
+
+    async fn poll(&mut self) -> Result<Event, v5::ConnectionError> {
+        loop {
+            if !self.has_logged_connect {
+                let prefix = &self.log_prefix;
+                log_event!(prefix, "Attempting to connect to broker using MQTT 5");
+                self.has_logged_connect = true;
+            }
+
+            match self.inner.poll().await {
+                Ok(event) => {
+                    if let Some(event) = self.translate(event) {
+                        return Ok(event);
+                    }
+                }
+                Err(err) => {
+                    let prefix = &self.log_prefix;
+                    log_event!(warn: prefix, "Connection error: {:?}", err);
+                    self.has_logged_connect = false;
+                    self.forget_topic_aliases();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    fn take_pending(&mut self) -> VecDeque<v5::Request> {
+        std::mem::take(&mut self.inner.pending)
+    }
+
+    fn set_pending(&mut self, requests: Vec<v5::Request>) {
+        self.inner.pending = requests.into_iter().collect();
+    }
+
+    fn on_connection(&mut self, outcome: ConnectionOutcome) -> bool {
+        let prefix = &self.log_prefix;
+        let mut untried = false;
+        if let Some((endpoint, is_untried)) = self.failover.as_mut().and_then(|f| f.update(outcome))
+        {
+            log_event!(
+                prefix,
+                "Switching to broker endpoint {}",
+                endpoint_name(endpoint)
+            );
+            self.inner.options = mqtt5_options(endpoint);
+            untried = is_untried;
+        }
+        if outcome != ConnectionOutcome::Connected {
+            if let Some(provider) = &self.last_will_provider {
+                self.inner
+                    .options
+                    .set_last_will(last_will5(provider.last_will()));
+            }
+        }
+        untried
+    }
+}
+
+/// The topic aliases assigned on the current connection
+struct TopicAliases {
+    /// The maximum number of aliases, as configured
+    max: u16,
+
+    /// The maximum number of aliases accepted by the broker, 0 until connected
+    broker_max: u16,
+
+    aliases: HashMap<String, u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopicAlias {
+    /// An alias to be sent along the topic, for the broker to record it
+    New(u16),
+
+    /// An alias already known by the broker, to be sent in place of the topic
+    Known(u16),
+}
+
+impl TopicAliases {
+    fn new(max: u16) -> Self {
+        TopicAliases {
+            max,
+            broker_max: 0,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// Returns the alias to be used for a topic, if any is left
+    fn alias(&mut self, topic: &str) -> Option<TopicAlias> {
+        if let Some(alias) = self.aliases.get(topic) {
+            return Some(TopicAlias::Known(*alias));
+        }
+        let count = self.aliases.len() as u16;
+        if count >= self.max.min(self.broker_max) {
+            return None;
+        }
+        let alias = count + 1;
+        self.aliases.insert(topic.to_owned(), alias);
+        Some(TopicAlias::New(alias))
+    }
+
+    /// Forgets an alias just assigned to a topic, because the message using it could not be queued
+    fn release(&mut self, topic: &str) {
+        self.aliases.remove(topic);
+    }
+
+    /// Forgets the aliases of the previous connection, returning the topics they were standing for
+    fn reset(&mut self, broker_max: Option<u16>) -> HashMap<u16, String> {
+        self.broker_max = broker_max.unwrap_or(0);
+        self.aliases
+            .drain()
+            .map(|(topic, alias)| (alias, topic))
+            .collect()
+    }
+}
+
+fn qos5(qos: QoS) -> QoS5 {
+    match qos {
+        QoS::AtMostOnce => QoS5::AtMostOnce,
+        QoS::AtLeastOnce => QoS5::AtLeastOnce,
+        QoS::ExactlyOnce => QoS5::ExactlyOnce,
+    }
+}
+
+fn qos3(qos: QoS5) -> QoS {
+    match qos {
+        QoS5::AtMostOnce => QoS::AtMostOnce,
+        QoS5::AtLeastOnce => QoS::AtLeastOnce,
+        QoS5::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
+
+fn connack3(connack: &ConnAck5) -> ConnAck {
+    ConnAck {
+        session_present: connack.session_present,
+        code: ConnectReturnCode::Success,
+    }
+}
+
+fn publish3(publish: Publish5) -> Publish {
+    Publish {
+        dup: publish.dup,
+        qos: qos3(publish.qos),
+        retain: publish.retain,
+        topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+        pkid: publish.pkid,
+        payload: publish.payload,
+    }
+}
+
+/// The client errors are only raised when the event loop is gone,
+/// hence the MQTT 5 request is not worth being translated
+fn client_error(_err: v5::ClientError) -> ClientError {
+    ClientError::Request(Request::Disconnect(Disconnect))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_toml::Direction;
+    use crate::config_toml::ExpandedBridgeRule;
+    use crate::RuleOptions;
+
+    #[test]
+    fn topic_aliases_are_bounded_by_the_broker_maximum() {
+        let mut aliases = TopicAliases::new(16);
+        assert_eq!(aliases.alias("a"), None, "no aliases until connected");
+
+        aliases.reset(Some(2));
+        assert_eq!(aliases.alias("a"), Some(TopicAlias::New(1)));
+        assert_eq!(aliases.alias("b"), Some(TopicAlias::New(2)));
+        assert_eq!(aliases.alias("c"), None);
+        assert_eq!(aliases.alias("a"), Some(TopicAlias::Known(1)));
+    }
+
+    #[test]
+    fn topic_aliases_are_forgotten_on_reconnect() {
+        let mut aliases = TopicAliases::new(1);
+        aliases.reset(Some(10));
+        assert_eq!(aliases.alias("a"), Some(TopicAlias::New(1)));
+        assert_eq!(aliases.alias("b"), None);
+
+        let topics = aliases.reset(Some(10));
+        assert_eq!(topics.get(&1).map(String::as_str), Some("a"));
+        assert_eq!(aliases.alias("b"), Some(TopicAlias::New(1)));
+    }
+
+    #[test]
+    fn released_topic_aliases_are_assigned_again() {
+        let mut aliases = TopicAliases::new(2);
+        aliases.reset(Some(10));
+        assert_eq!(aliases.alias("a"), Some(TopicAlias::New(1)));
+        assert_eq!(aliases.alias("b"), Some(TopicAlias::New(2)));
+
+        aliases.release("b");
+        assert_eq!(aliases.alias("c"), Some(TopicAlias::New(2)));
+        assert_eq!(aliases.alias("a"), Some(TopicAlias::Known(1)));
+    }
+
+    #[tokio::test]
+    async fn messages_are_published_with_the_properties_of_the_matching_rule() {
+        let options = RuleOptions {
+            message_expiry: Some(Duration::from_secs(600)),
+            user_properties: vec![("site".into(), "plant-1".into())],
+            ..RuleOptions::default()
+        };
+        let mut rules = BridgeConfig::new();
+        rules
+            .add_expanded_rules(vec![
+                ExpandedBridgeRule {
+                    local_prefix: "c8y/".into(),
+                    remote_prefix: "".into(),
+                    direction: Direction::Outbound,
+                    topic: "s/us".into(),
+                    options: RuleOptions::default(),
+                },
+                ExpandedBridgeRule {
+                    local_prefix: "c8y/".into(),
+                    remote_prefix: "other/".into(),
+                    direction: Direction::Outbound,
+                    topic: "#".into(),
+                    options,
+                },
+            ])
+            .unwrap();
+
+        let (client, _) = Mqtt5Client::new(
+            &MqttOptions::new("device", "localhost", 1883),
+            Mqtt5Options::default(),
+            &rules,
+            10,
+            "cloud".into(),
+        );
+
+        assert_eq!(
+            client.publish_properties("s/us"),
+            PublishProperties::default()
+        );
+        let properties = client.publish_properties("other/measurements");
+        assert_eq!(properties.message_expiry_interval, Some(600));
+        assert_eq!(
+            properties.user_properties,
+            vec![("site".to_owned(), "plant-1".to_owned())]
+        );
+
+        // The time spent in a queue is deducted from the expiry interval
+        let received_at = SystemTime::now() - Duration::from_secs(100);
+        let properties = client
+            .publish_properties_at("other/measurements", received_at)
+            .unwrap();
+        assert!(
+            matches!(properties.message_expiry_interval, Some(500 | 501)),
+            "{properties:?}"
+        );
+        assert!(!client.has_expired("other/measurements", received_at));
+
+        // A message that has been queued for longer than its expiry interval is dropped
+        let received_at = SystemTime::now() - Duration::from_secs(600);
+        assert!(client
+            .publish_properties_at("other/measurements", received_at)
+            .is_none());
+        assert!(client.has_expired("other/measurements", received_at));
+        assert!(!client.has_expired("s/us", received_at));
+    }
+}
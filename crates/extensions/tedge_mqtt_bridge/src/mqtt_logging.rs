@@ -18,6 +18,10 @@ use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::endpoints::endpoint_name;
+use crate::endpoints::ConnectionOutcome;
+use crate::endpoints::Failover;
+use crate::endpoints::LastWillProvider;
 use crate::MqttEvents;
 
 /// A wrapper around [AsyncClient] for logging packets with [LoggingEventLoop]
@@ -44,6 +48,8 @@ impl LoggingAsyncClient {
                 log_rx,
                 log_prefix,
                 has_logged_connect: false,
+                failover: None,
+                last_will_provider: None,
             },
         )
     }
@@ -78,9 +84,27 @@ pub struct LoggingEventLoop {
     log_rx: UnboundedReceiver<Vec<SubscribeFilter>>,
     log_prefix: String,
     has_logged_connect: bool,
+    failover: Option<Failover>,
+    last_will_provider: Option<LastWillProvider>,
 }
 
 impl LoggingEventLoop {
+    /// Switches the broker endpoint on repeated connection failures
+    pub(crate) fn with_failover(self, failover: Failover) -> Self {
+        Self {
+            failover: Some(failover),
+            ..self
+        }
+    }
+
+    /// Registers a new last will before each reconnection attempt
+    pub(crate) fn with_last_will_provider(self, provider: LastWillProvider) -> Self {
+        Self {
+            last_will_provider: Some(provider),
+            ..self
+        }
+    }
+
     pub async fn poll(&mut self) -> Result<Event, rumqttc::ConnectionError> {
         let prefix = &self.log_prefix;
 
@@ -159,6 +183,9 @@ impl LoggingEventLoop {
 
 #[async_trait::async_trait]
 impl MqttEvents for LoggingEventLoop {
+    type Request = Request;
+    type Error = ConnectionError;
+
     async fn poll(&mut self) -> Result<Event, ConnectionError> {
         LoggingEventLoop::poll(self).await
     }
@@ -170,4 +197,25 @@ impl MqttEvents for LoggingEventLoop {
     fn set_pending(&mut self, requests: Vec<Request>) {
         self.inner.pending = requests.into_iter().collect();
     }
+
+    fn on_connection(&mut self, outcome: ConnectionOutcome) -> bool {
+        let prefix = &self.log_prefix;
+        let mut untried = false;
+        if let Some((endpoint, is_untried)) = self.failover.as_mut().and_then(|f| f.update(outcome))
+        {
+            log_event!(
+                prefix,
+                "Switching to broker endpoint {}",
+                endpoint_name(endpoint)
+            );
+            self.inner.mqtt_options = endpoint.clone();
+            untried = is_untried;
+        }
+        if outcome != ConnectionOutcome::Connected {
+            if let Some(provider) = &self.last_will_provider {
+                self.inner.mqtt_options.set_last_will(provider.last_will());
+            }
+        }
+        untried
+    }
 }
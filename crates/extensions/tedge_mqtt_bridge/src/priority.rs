@@ -0,0 +1,178 @@
+//! Priority lanes, ordering the messages waiting to be published by a bridge half.
+//!
+//! The messages are published by strict priority, from the highest class to the lowest,
+//! with an anti-starvation quota: after `quota` messages of a class published in a row
+//! while messages of lower classes are waiting, one message of the next waiting class is published.
+use crate::config_toml::Priority;
+use std::collections::VecDeque;
+
+const CLASSES: usize = 3;
+
+impl Priority {
+    const ALL: [Priority; CLASSES] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+
+    /// The code used to persist the priority, `0` being the normal priority
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            Priority::Normal => 0,
+            Priority::High => 1,
+            Priority::Low => 2,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Priority {
+        match code {
+            1 => Priority::High,
+            2 => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Chooses the priority class of the next message to be published
+#[derive(Debug, Clone)]
+pub(crate) struct PriorityScheduler {
+    /// Number of messages of a class published in a row while lower classes are waiting, 0 for no limit
+    quota: u32,
+    in_a_row: [u32; CLASSES],
+}
+
+impl PriorityScheduler {
+    pub(crate) fn new(quota: u32) -> Self {
+        PriorityScheduler {
+            quota,
+            in_a_row: [0; CLASSES],
+        }
+    }
+
+    /// Returns the class of the next message, given the classes with waiting messages
+    pub(crate) fn next(&mut self, waiting: impl Fn(Priority) -> bool) -> Option<Priority> {
+        let mut chosen = Priority::ALL.into_iter().position(&waiting)?;
+        loop {
+            let lower = (chosen + 1..CLASSES).find(|&lower| waiting(Priority::ALL[lower]));
+            match lower {
+                Some(lower) if self.quota > 0 && self.in_a_row[chosen] >= self.quota => {
+                    // Give way to the lower class, unless its own quota is exhausted
+                    self.in_a_row[chosen] = 0;
+                    chosen = lower;
+                }
+                Some(_) => {
+                    self.in_a_row[chosen] += 1;
+                    break;
+                }
+                None => {
+                    self.in_a_row[chosen] = 0;
+                    break;
+                }
+            }
+        }
+        Some(Priority::ALL[chosen])
+    }
+}
+
+/// In-memory queues of the messages waiting to be published, one per priority class
+pub(crate) struct PriorityLanes<T> {
+    lanes: [VecDeque<T>; CLASSES],
+    scheduler: PriorityScheduler,
+}
+
+impl<T> PriorityLanes<T> {
+    pub(crate) fn new(quota: u32) -> Self {
+        PriorityLanes {
+            lanes: Default::default(),
+            scheduler: PriorityScheduler::new(quota),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+
+    pub(crate) fn push(&mut self, priority: Priority, item: T) {
+        self.lanes[priority.index()].push_back(item)
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let lanes = &self.lanes;
+        let priority = self
+            .scheduler
+            .next(|priority| !lanes[priority.index()].is_empty())?;
+        self.lanes[priority.index()].pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_published_by_strict_priority_without_quota() {
+        let mut lanes = PriorityLanes::new(0);
+        lanes.push(Priority::Low, "low-1");
+        lanes.push(Priority::Normal, "normal-1");
+        lanes.push(Priority::High, "high-1");
+        lanes.push(Priority::Normal, "normal-2");
+        lanes.push(Priority::High, "high-2");
+
+        let order: Vec<_> = std::iter::from_fn(|| lanes.pop()).collect();
+
+        assert_eq!(
+            order,
+            vec!["high-1", "high-2", "normal-1", "normal-2", "low-1"]
+        );
+    }
+
+    #[test]
+    fn lower_classes_are_given_a_share_when_the_quota_is_exhausted() {
+        let mut lanes = PriorityLanes::new(2);
+        for i in 1..=6 {
+            lanes.push(Priority::High, format!("high-{i}"));
+        }
+        for i in 1..=3 {
+            lanes.push(Priority::Normal, format!("normal-{i}"));
+            lanes.push(Priority::Low, format!("low-{i}"));
+        }
+
+        let order: Vec<_> = std::iter::from_fn(|| lanes.pop()).collect();
+
+        assert_eq!(
+            order,
+            vec![
+                "high-1", "high-2", "normal-1", "high-3", "high-4", "normal-2", "high-5", "high-6",
+                // The normal class has exhausted its quota while low messages are waiting
+                "low-1", "normal-3", "low-2", "low-3",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_quota_is_reset_when_no_lower_class_is_waiting() {
+        let mut lanes = PriorityLanes::new(1);
+        lanes.push(Priority::High, "high-1");
+        lanes.push(Priority::High, "high-2");
+        assert_eq!(lanes.pop(), Some("high-1"));
+        assert_eq!(lanes.pop(), Some("high-2"));
+
+        lanes.push(Priority::High, "high-3");
+        lanes.push(Priority::Low, "low-1");
+        assert_eq!(lanes.pop(), Some("high-3"));
+        assert_eq!(lanes.pop(), Some("low-1"));
+        assert!(lanes.is_empty());
+    }
+
+    #[test]
+    fn priority_codes_are_stable() {
+        for priority in Priority::ALL {
+            assert_eq!(Priority::from_code(priority.code()), priority);
+        }
+        assert_eq!(Priority::from_code(0), Priority::Normal);
+    }
+}
@@ -0,0 +1,998 @@
+//! A persistent store-and-forward queue for one direction of the bridge.
+//!
+//! When enabled, the messages received by a half bridge are appended to the queue
+//! and acknowledged to their source right away. They are then published to the target in order
+//! of priority, at a limited rate, and removed from the queue only once acknowledged by the target.
+//! This way, messages are neither lost during long outages of the target, nor when the bridge restarts.
+//!
+//! The queue is persisted as an append-only log of records:
+//! - a message record, with a sequence number, the time the message has been received,
+//!   its drop policy and priority, QoS, retain flag, target topic and payload,
+//! - a removal record, with the sequence number of a message that has been acknowledged or dropped.
+//!
+//! The log is compacted when the removed messages take more room than the queued ones.
+use crate::config_toml::Priority;
+use crate::priority::PriorityScheduler;
+use crate::topics::matches_ignore_dollar_prefix;
+use crate::BridgeMessageSender;
+use camino::Utf8PathBuf;
+use rumqttc::Publish;
+use rumqttc::QoS;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::sync::Notify;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
+
+const MESSAGE_RECORD: u8 = b'M';
+const REMOVAL_RECORD: u8 = b'R';
+
+/// tag, seq, received_at, drop policy and priority, qos, retain, topic length and payload length
+const MESSAGE_HEADER_SIZE: u64 = 1 + 8 + 8 + 1 + 1 + 1 + 2 + 4;
+
+/// tag and seq
+const REMOVAL_RECORD_SIZE: u64 = 1 + 8;
+
+/// The log is never compacted under this size, whatever the proportion of removed messages
+const MIN_COMPACTION_SIZE: u64 = 1024 * 1024;
+
+/// Maximum number of queued messages published to the target and not acknowledged yet
+const MAX_IN_FLIGHT: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// File where the queue is persisted
+    pub path: Utf8PathBuf,
+
+    /// Maximum size of the queued messages, in bytes
+    pub max_size: u64,
+
+    /// Age after which the messages that can be dropped are actually dropped
+    pub max_age: Duration,
+
+    /// Topic filters of the messages dropped oldest first when the queue is full
+    ///
+    /// These filters are matched against the topics on which the messages are received.
+    /// The other messages are never dropped.
+    pub drop_oldest: Vec<String>,
+
+    /// Maximum number of messages published per second, 0 for no limit
+    pub drain_rate: u32,
+
+    /// Number of messages of a priority class published in a row while lower class messages are waiting, 0 for no limit
+    pub priority_quota: u32,
+}
+
+/// What happened to a message pushed to the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// The message has been persisted
+    Queued,
+
+    /// The queue is full and the message has been dropped, as allowed by its drop policy
+    Dropped,
+
+    /// The queue is full and the message has not been persisted, as it must not be dropped
+    Full,
+}
+
+/// A message read from the queue, with its target topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedMessage {
+    pub seq: u64,
+    pub publish: Publish,
+    pub priority: Priority,
+    /// The time the message has been received by the bridge
+    pub received_at: SystemTime,
+}
+
+/// A disk-backed queue of messages
+pub struct PersistentQueue {
+    config: QueueConfig,
+    writer: File,
+    reader: File,
+    log_size: u64,
+    entries: BTreeMap<u64, Entry>,
+    queued_size: u64,
+    next_seq: u64,
+    /// The messages not published yet, per priority class
+    unsent: [BTreeSet<u64>; 3],
+    scheduler: PriorityScheduler,
+}
+
+/// The location in the log of a queued message
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    size: u64,
+    received_at: u64,
+    droppable: bool,
+    priority: Priority,
+}
+
+enum Record {
+    Message { seq: u64, entry: Entry },
+    Removal { seq: u64 },
+}
+
+impl PersistentQueue {
+    /// Opens a queue, restoring the messages persisted by a previous run
+    pub fn open(config: QueueConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let reader = File::open(&config.path)?;
+
+        let mut entries = BTreeMap::new();
+        let mut next_seq = 0;
+        let mut offset = 0;
+        let mut log = BufReader::new(File::open(&config.path)?);
+        // A truncated record at the end of the log is ignored, and removed by the compaction below
+        while let Some(record) = read_record(&mut log, offset)? {
+            match record {
+                Record::Message { seq, entry } => {
+                    offset += entry.size;
+                    next_seq = next_seq.max(seq + 1);
+                    entries.insert(seq, entry);
+                }
+                Record::Removal { seq } => {
+                    offset += REMOVAL_RECORD_SIZE;
+                    entries.remove(&seq);
+                }
+            }
+        }
+
+        let queued_size = entries.values().map(|entry| entry.size).sum();
+        let mut unsent: [BTreeSet<u64>; 3] = Default::default();
+        for (seq, entry) in &entries {
+            unsent[entry.priority.index()].insert(*seq);
+        }
+        let scheduler = PriorityScheduler::new(config.priority_quota);
+        let mut queue = PersistentQueue {
+            config,
+            writer,
+            reader,
+            log_size: offset,
+            entries,
+            queued_size,
+            next_seq,
+            unsent,
+            scheduler,
+        };
+        queue.compact()?;
+        Ok(queue)
+    }
+
+    /// The number of queued messages, including those published and not acknowledged yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Appends a message to the queue, making room for it if the queue is full
+    ///
+    /// The `publish` topic is the topic on which the message has been received,
+    /// while the `target_topic` is the topic on which the message has to be published.
+    /// The message is synced to disk before returning, as it is then acknowledged to its source.
+    pub fn push(
+        &mut self,
+        publish: &Publish,
+        target_topic: &str,
+        priority: Priority,
+        now: SystemTime,
+    ) -> io::Result<PushOutcome> {
+        let droppable = self
+            .config
+            .drop_oldest
+            .iter()
+            .any(|filter| matches_ignore_dollar_prefix(&publish.topic, filter));
+        let size = MESSAGE_HEADER_SIZE + target_topic.len() as u64 + publish.payload.len() as u64;
+        while self.queued_size + size > self.config.max_size {
+            match self.oldest_droppable() {
+                Some(seq) => self.remove(seq)?,
+                None if droppable => return Ok(PushOutcome::Dropped),
+                None => return Ok(PushOutcome::Full),
+            }
+        }
+
+        let seq = self.next_seq;
+        let received_at = unix_millis(now);
+        let record = encode_message(seq, received_at, droppable, priority, target_topic, publish);
+        self.writer.write_all(&record)?;
+        self.writer.sync_data()?;
+        self.entries.insert(
+            seq,
+            Entry {
+                offset: self.log_size,
+                size,
+                received_at,
+                droppable,
+                priority,
+            },
+        );
+        self.unsent[priority.index()].insert(seq);
+        self.next_seq += 1;
+        self.log_size += size;
+        self.queued_size += size;
+        Ok(PushOutcome::Queued)
+    }
+
+    /// Reads the next message to be published, by priority, skipping the expired messages
+    ///
+    /// The message is kept in the queue until removed.
+    pub fn next(&mut self, now: SystemTime) -> io::Result<Option<QueuedMessage>> {
+        let deadline = unix_millis(now).saturating_sub(self.config.max_age.as_millis() as u64);
+        loop {
+            let unsent = &self.unsent;
+            let Some(priority) = self
+                .scheduler
+                .next(|priority| !unsent[priority.index()].is_empty())
+            else {
+                return Ok(None);
+            };
+            let Some(seq) = self.unsent[priority.index()].pop_first() else {
+                return Ok(None);
+            };
+            let entry = self.entries[&seq];
+            if entry.droppable && entry.received_at < deadline {
+                self.remove(seq)?;
+                continue;
+            }
+
+            let record = self.read_raw(entry)?;
+            let publish = decode_message(&record)?;
+            return Ok(Some(QueuedMessage {
+                seq,
+                publish,
+                priority,
+                received_at: UNIX_EPOCH + Duration::from_millis(entry.received_at),
+            }));
+        }
+    }
+
+    /// Puts back a message read but not acknowledged, to be published again
+    ///
+    /// Nothing is done if the message has been removed meanwhile.
+    pub fn requeue(&mut self, seq: u64) {
+        if let Some(entry) = self.entries.get(&seq) {
+            self.unsent[entry.priority.index()].insert(seq);
+        }
+    }
+
+    /// Removes a message from the queue, once acknowledged by the target or dropped
+    pub fn remove(&mut self, seq: u64) -> io::Result<()> {
+        let Some(entry) = self.entries.remove(&seq) else {
+            return Ok(());
+        };
+        self.queued_size -= entry.size;
+        self.unsent[entry.priority.index()].remove(&seq);
+
+        let mut record = vec![REMOVAL_RECORD];
+        record.extend_from_slice(&seq.to_be_bytes());
+        self.writer.write_all(&record)?;
+        self.log_size += REMOVAL_RECORD_SIZE;
+
+        if self.log_size > MIN_COMPACTION_SIZE && self.log_size > 2 * self.queued_size {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn oldest_droppable(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| entry.droppable)
+            .map(|(seq, _)| *seq)
+    }
+
+    fn read_raw(&mut self, entry: Entry) -> io::Result<Vec<u8>> {
+        let mut record = vec![0; entry.size as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut record)?;
+        Ok(record)
+    }
+
+    /// Rewrites the log with only the queued messages
+    fn compact(&mut self) -> io::Result<()> {
+        let tmp_path = self.config.path.with_extension("tmp");
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        let mut offset = 0;
+        let entries: Vec<(u64, Entry)> = self.entries.iter().map(|(k, v)| (*k, *v)).collect();
+        for (seq, entry) in entries {
+            let record = self.read_raw(entry)?;
+            tmp.write_all(&record)?;
+            self.entries.insert(seq, Entry { offset, ..entry });
+            offset += entry.size;
+        }
+        tmp.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, &self.config.path)?;
+
+        self.writer = OpenOptions::new().append(true).open(&self.config.path)?;
+        self.reader = File::open(&self.config.path)?;
+        self.log_size = offset;
+        Ok(())
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn encode_message(
+    seq: u64,
+    received_at: u64,
+    droppable: bool,
+    priority: Priority,
+    target_topic: &str,
+    publish: &Publish,
+) -> Vec<u8> {
+    let mut record = Vec::with_capacity(
+        MESSAGE_HEADER_SIZE as usize + target_topic.len() + publish.payload.len(),
+    );
+    record.push(MESSAGE_RECORD);
+    record.extend_from_slice(&seq.to_be_bytes());
+    record.extend_from_slice(&received_at.to_be_bytes());
+    record.push(droppable as u8 | priority.code() << 1);
+    record.push(publish.qos as u8);
+    record.push(publish.retain as u8);
+    record.extend_from_slice(&(target_topic.len() as u16).to_be_bytes());
+    record.extend_from_slice(&(publish.payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(target_topic.as_bytes());
+    record.extend_from_slice(&publish.payload);
+    record
+}
+
+fn decode_message(record: &[u8]) -> io::Result<Publish> {
+    let header = record
+        .get(..MESSAGE_HEADER_SIZE as usize)
+        .filter(|header| header[0] == MESSAGE_RECORD)
+        .ok_or_else(|| invalid_data("not a message record"))?;
+    let qos = match header[18] {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        _ => return Err(invalid_data("invalid QoS")),
+    };
+    let retain = header[19] != 0;
+    let topic_len = u16::from_be_bytes([header[20], header[21]]) as usize;
+    let body = &record[MESSAGE_HEADER_SIZE as usize..];
+    let topic = body
+        .get(..topic_len)
+        .and_then(|topic| std::str::from_utf8(topic).ok())
+        .ok_or_else(|| invalid_data("invalid topic"))?;
+    let payload = &body[topic_len..];
+
+    let mut publish = Publish::new(topic, qos, payload.to_vec());
+    publish.retain = retain;
+    Ok(publish)
+}
+
+/// Reads the next record of the log, returning `None` at the end of the log or on a truncated record
+fn read_record(log: &mut impl Read, offset: u64) -> io::Result<Option<Record>> {
+    let mut tag = [0; 1];
+    if !read_or_eof(log, &mut tag)? {
+        return Ok(None);
+    }
+    match tag[0] {
+        REMOVAL_RECORD => {
+            let mut seq = [0; 8];
+            if !read_or_eof(log, &mut seq)? {
+                return Ok(None);
+            }
+            Ok(Some(Record::Removal {
+                seq: u64::from_be_bytes(seq),
+            }))
+        }
+        MESSAGE_RECORD => {
+            let mut header = [0; MESSAGE_HEADER_SIZE as usize - 1];
+            if !read_or_eof(log, &mut header)? {
+                return Ok(None);
+            }
+            let seq = u64::from_be_bytes(header[0..8].try_into().unwrap());
+            let received_at = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            let droppable = header[16] & 1 != 0;
+            let priority = Priority::from_code(header[16] >> 1);
+            let topic_len = u16::from_be_bytes([header[19], header[20]]) as u64;
+            let payload_len = u32::from_be_bytes(header[21..25].try_into().unwrap()) as u64;
+            let mut body = vec![0; (topic_len + payload_len) as usize];
+            if !read_or_eof(log, &mut body)? {
+                return Ok(None);
+            }
+            let entry = Entry {
+                offset,
+                size: MESSAGE_HEADER_SIZE + topic_len + payload_len,
+                received_at,
+                droppable,
+                priority,
+            };
+            Ok(Some(Record::Message { seq, entry }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn read_or_eof(log: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match log.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// A persistent queue shared by the half bridge receiving the messages, the task publishing them,
+/// and the companion half bridge receiving the acknowledgements from the target
+///
+/// The queue being persisted on disk, it is only accessed from blocking threads,
+/// so the async tasks of the bridge are never blocked by file I/O.
+#[derive(Clone)]
+pub(crate) struct BridgeQueue {
+    name: &'static str,
+    /// The bridge half publishing the queued messages, i.e. `cloud` for the outbound queue
+    target: &'static str,
+    /// Whether the target broker is connected, as tracked by the bridge health monitor
+    target_connected: Arc<AtomicBool>,
+    queue: Arc<Mutex<PersistentQueue>>,
+    /// The number of queued messages, updated after each operation on the queue
+    depth: Arc<AtomicUsize>,
+    pushed: Arc<Notify>,
+    in_flight: Arc<Semaphore>,
+    drain_rate: u32,
+}
+
+/// Removes a queued message from the queue, once acknowledged by the target
+pub(crate) struct QueueAck {
+    queue: BridgeQueue,
+    seq: u64,
+    _in_flight: OwnedSemaphorePermit,
+}
+
+impl BridgeQueue {
+    pub fn open(name: &'static str, target: &'static str, config: QueueConfig) -> io::Result<Self> {
+        let drain_rate = config.drain_rate;
+        let queue = PersistentQueue::open(config)?;
+        if !queue.is_empty() {
+            log_event!(name, "Restored {} queued messages", queue.len());
+        }
+        Ok(BridgeQueue {
+            name,
+            target,
+            target_connected: Arc::new(AtomicBool::new(false)),
+            depth: Arc::new(AtomicUsize::new(queue.len())),
+            queue: Arc::new(Mutex::new(queue)),
+            pushed: Arc::new(Notify::new()),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            drain_rate,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn target(&self) -> &'static str {
+        self.target
+    }
+
+    pub fn set_target_connected(&self, connected: bool) {
+        self.target_connected.store(connected, Ordering::Relaxed)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Runs an operation on the queue from a blocking thread
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut PersistentQueue) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let queue = self.queue.clone();
+        let depth = self.depth.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut queue = queue.lock().unwrap();
+            let result = operation(&mut queue);
+            depth.store(queue.len(), Ordering::Relaxed);
+            result
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    /// Pushes a message received by the bridge
+    ///
+    /// Returns `true` if the message has been either queued or dropped,
+    /// and has to be acknowledged to its source,
+    /// and `false` if the message has to be forwarded without being queued.
+    ///
+    /// While the target is connected and nothing is queued, the messages are forwarded right away,
+    /// the drain rate only applying to the backlog accumulated while the target was not reachable.
+    pub async fn push(&self, target_topic: &str, publish: &Publish, priority: Priority) -> bool {
+        if self.target_connected.load(Ordering::Relaxed) && self.depth() == 0 {
+            return false;
+        }
+        let name = self.name;
+        let target_topic = target_topic.to_string();
+        let message = publish.clone();
+        let outcome = self
+            .run(move |queue| queue.push(&message, &target_topic, priority, SystemTime::now()))
+            .await;
+        match outcome {
+            Ok(PushOutcome::Queued) => {
+                self.pushed.notify_one();
+                true
+            }
+            Ok(PushOutcome::Dropped) => {
+                log_event!(warn: name, "Queue full, dropping message received on {}", publish.topic);
+                true
+            }
+            Ok(PushOutcome::Full) => {
+                log_event!(warn: name, "Queue full, forwarding message received on {} without queuing it", publish.topic);
+                false
+            }
+            Err(err) => {
+                log_event!(error: name, "Failed to queue message received on {}: {err}", publish.topic);
+                false
+            }
+        }
+    }
+
+    async fn remove(&self, seq: u64) {
+        if let Err(err) = self.run(move |queue| queue.remove(seq)).await {
+            let name = self.name;
+            log_event!(error: name, "Failed to remove acknowledged message from the queue: {err}");
+        }
+    }
+
+    async fn requeue(&self, seq: u64) {
+        if let Err(err) = self
+            .run(move |queue| {
+                queue.requeue(seq);
+                Ok(())
+            })
+            .await
+        {
+            let name = self.name;
+            log_event!(error: name, "Failed to put back an unacknowledged message in the queue: {err}");
+        }
+        self.pushed.notify_one();
+    }
+
+    async fn next(&self) -> QueuedMessage {
+        loop {
+            let next = self.run(|queue| queue.next(SystemTime::now())).await;
+            match next {
+                Ok(Some(message)) => return message,
+                Ok(None) => self.pushed.notified().await,
+                Err(err) => {
+                    let name = self.name;
+                    log_event!(error: name, "Failed to read the queue: {err}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Publishes the queued messages in order of priority, at most `drain_rate` messages per second
+    ///
+    /// The messages are removed from the queue when acknowledged by the target.
+    pub async fn drain(self, mut target: BridgeMessageSender) {
+        let mut ticks = (self.drain_rate > 0).then(|| {
+            let mut interval = tokio::time::interval(Duration::from_secs(1) / self.drain_rate);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        loop {
+            let Some((message, ack)) = self.next_in_flight().await else {
+                return;
+            };
+            if let Some(ticks) = ticks.as_mut() {
+                ticks.tick().await;
+            }
+            target.queued_publish(message.publish, ack, message.priority, message.received_at);
+        }
+    }
+
+    /// Reads the next message to be published, once the number of messages in flight allows it
+    ///
+    /// The message is counted as in flight until its [QueueAck] is either done or requeued.
+    pub(crate) async fn next_in_flight(&self) -> Option<(QueuedMessage, QueueAck)> {
+        let permit = self.in_flight.clone().acquire_owned().await.ok()?;
+        let message = self.next().await;
+        let ack = QueueAck {
+            queue: self.clone(),
+            seq: message.seq,
+            _in_flight: permit,
+        };
+        Some((message, ack))
+    }
+}
+
+impl QueueAck {
+    /// Removes the message from the queue, as acknowledged by the target
+    ///
+    /// The removal is persisted in the background, the message being counted as in flight until then.
+    pub fn done(self) {
+        tokio::spawn(async move { self.queue.remove(self.seq).await });
+    }
+
+    /// Puts the message back in the queue, as it will not be acknowledged by the target
+    ///
+    /// This is the case for the messages in flight when the bridge fails over to another broker endpoint.
+    pub fn requeue(self) {
+        tokio::spawn(async move { self.queue.requeue(self.seq).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    fn config(ttd: &TempTedgeDir, max_size: u64) -> QueueConfig {
+        QueueConfig {
+            path: ttd.utf8_path().join("bridge/outbound.queue"),
+            max_size,
+            max_age: Duration::from_secs(3600),
+            drop_oldest: vec!["c8y/measurement/#".into()],
+            drain_rate: 0,
+            priority_quota: 0,
+        }
+    }
+
+    fn message(topic: &str, payload: &str) -> Publish {
+        Publish::new(topic, QoS::AtLeastOnce, payload)
+    }
+
+    fn next_payload(queue: &mut PersistentQueue, now: SystemTime) -> Option<(u64, String)> {
+        queue.next(now).unwrap().map(|message| {
+            let payload = String::from_utf8(message.publish.payload.to_vec()).unwrap();
+            (message.seq, payload)
+        })
+    }
+
+    #[test]
+    fn messages_are_read_in_order_and_kept_until_removed() {
+        let ttd = TempTedgeDir::new();
+        let now = SystemTime::now();
+        let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+        for payload in ["a", "b", "c"] {
+            let outcome = queue.push(&message("c8y/s/us", payload), "s/us", Priority::Normal, now);
+            assert_eq!(outcome.unwrap(), PushOutcome::Queued);
+        }
+
+        let first = queue.next(now).unwrap().unwrap();
+        assert_eq!(first.publish.topic, "s/us");
+        assert_eq!(first.publish.qos, QoS::AtLeastOnce);
+        assert_eq!(next_payload(&mut queue, now), Some((1, "b".into())));
+        assert_eq!(next_payload(&mut queue, now), Some((2, "c".into())));
+        assert_eq!(next_payload(&mut queue, now), None);
+        assert_eq!(queue.len(), 3);
+
+        queue.remove(first.seq).unwrap();
+        queue.remove(1).unwrap();
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn messages_not_acknowledged_are_restored_on_restart() {
+        let ttd = TempTedgeDir::new();
+        let now = SystemTime::now();
+        {
+            let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+            for payload in ["a", "b", "c"] {
+                queue
+                    .push(&message("c8y/s/us", payload), "s/us", Priority::Normal, now)
+                    .unwrap();
+            }
+            queue.next(now).unwrap();
+            queue.remove(0).unwrap();
+        }
+
+        let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(next_payload(&mut queue, now), Some((1, "b".into())));
+        queue
+            .push(&message("c8y/s/us", "d"), "s/us", Priority::Normal, now)
+            .unwrap();
+        assert_eq!(next_payload(&mut queue, now), Some((2, "c".into())));
+        assert_eq!(next_payload(&mut queue, now), Some((3, "d".into())));
+    }
+
+    #[test]
+    fn messages_are_read_by_priority_even_after_restart() {
+        let ttd = TempTedgeDir::new();
+        let now = SystemTime::now();
+        {
+            let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+            queue
+                .push(&message("c8y/s/us", "m1"), "s/us", Priority::Low, now)
+                .unwrap();
+            queue
+                .push(&message("c8y/s/us", "m2"), "s/us", Priority::Normal, now)
+                .unwrap();
+            queue
+                .push(&message("c8y/s/us", "op"), "s/us", Priority::High, now)
+                .unwrap();
+            assert_eq!(next_payload(&mut queue, now), Some((2, "op".into())));
+        }
+
+        let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+        queue
+            .push(&message("c8y/s/us", "alarm"), "s/us", Priority::High, now)
+            .unwrap();
+        let payloads: Vec<_> = std::iter::from_fn(|| next_payload(&mut queue, now))
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(payloads, vec!["op", "alarm", "m2", "m1"]);
+    }
+
+    #[test]
+    fn truncated_records_are_ignored_on_restart() {
+        let ttd = TempTedgeDir::new();
+        let config = config(&ttd, 1024);
+        let now = SystemTime::now();
+        {
+            let mut queue = PersistentQueue::open(config.clone()).unwrap();
+            queue
+                .push(&message("c8y/s/us", "a"), "s/us", Priority::Normal, now)
+                .unwrap();
+        }
+        let mut log = OpenOptions::new().append(true).open(&config.path).unwrap();
+        log.write_all(&[MESSAGE_RECORD, 0, 0]).unwrap();
+
+        let mut queue = PersistentQueue::open(config.clone()).unwrap();
+        assert_eq!(queue.len(), 1);
+        queue
+            .push(&message("c8y/s/us", "b"), "s/us", Priority::Normal, now)
+            .unwrap();
+        drop(queue);
+
+        let mut queue = PersistentQueue::open(config).unwrap();
+        assert_eq!(next_payload(&mut queue, now), Some((0, "a".into())));
+        assert_eq!(next_payload(&mut queue, now), Some((1, "b".into())));
+    }
+
+    #[test]
+    fn oldest_droppable_messages_are_dropped_when_full() {
+        let ttd = TempTedgeDir::new();
+        let now = SystemTime::now();
+        let record_size = MESSAGE_HEADER_SIZE + "s/us".len() as u64 + 1;
+        let mut queue = PersistentQueue::open(config(&ttd, 3 * record_size)).unwrap();
+
+        let measurement = |payload| message("c8y/measurement/measurements/create", payload);
+        let alarm = |payload| message("c8y/alarm/alarms/create", payload);
+        assert_eq!(
+            queue
+                .push(&measurement("1"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue
+                .push(&alarm("2"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue
+                .push(&measurement("3"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+
+        // Room is made by dropping the oldest measurement
+        assert_eq!(
+            queue
+                .push(&alarm("4"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue
+                .push(&measurement("5"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+
+        // There are no more measurements to drop but the one just pushed
+        assert_eq!(
+            queue
+                .push(&alarm("6"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue
+                .push(&measurement("7"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Dropped
+        );
+        assert_eq!(
+            queue
+                .push(&alarm("8"), "s/us", Priority::Normal, now)
+                .unwrap(),
+            PushOutcome::Full
+        );
+
+        let payloads: Vec<_> = std::iter::from_fn(|| next_payload(&mut queue, now))
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(payloads, vec!["2", "4", "6"]);
+    }
+
+    #[test]
+    fn expired_droppable_messages_are_skipped() {
+        let ttd = TempTedgeDir::new();
+        let received = SystemTime::now();
+        let later = received + Duration::from_secs(7200);
+        let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+        let measurement = message("c8y/measurement/measurements/create", "1");
+        queue
+            .push(&measurement, "s/us", Priority::Normal, received)
+            .unwrap();
+        queue
+            .push(
+                &message("c8y/alarm/alarms/create", "2"),
+                "s/us",
+                Priority::Normal,
+                received,
+            )
+            .unwrap();
+
+        assert_eq!(next_payload(&mut queue, later), Some((1, "2".into())));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn the_log_is_compacted_when_most_messages_are_removed() {
+        let ttd = TempTedgeDir::new();
+        let config = config(&ttd, 10 * MIN_COMPACTION_SIZE);
+        let now = SystemTime::now();
+        let payload = "x".repeat(1024);
+        let mut queue = PersistentQueue::open(config.clone()).unwrap();
+        for _ in 0..2048 {
+            queue
+                .push(
+                    &message("c8y/s/us", &payload),
+                    "s/us",
+                    Priority::Normal,
+                    now,
+                )
+                .unwrap();
+        }
+        for seq in 0..2047 {
+            queue.remove(seq).unwrap();
+        }
+
+        let log_size = std::fs::metadata(&config.path).unwrap().len();
+        assert!(
+            log_size < MIN_COMPACTION_SIZE,
+            "log not compacted: {log_size}"
+        );
+        assert_eq!(next_payload(&mut queue, now), Some((2047, payload)));
+    }
+
+    #[tokio::test]
+    async fn bridge_queue_tracks_the_depth_of_the_persisted_queue() {
+        let ttd = TempTedgeDir::new();
+        let queue = BridgeQueue::open("outbound", "cloud", config(&ttd, 1024)).unwrap();
+
+        assert!(
+            queue
+                .push("s/us", &message("c8y/s/us", "a"), Priority::Normal)
+                .await
+        );
+        assert!(
+            queue
+                .push("s/us", &message("c8y/s/us", "b"), Priority::Normal)
+                .await
+        );
+        assert_eq!(queue.depth(), 2);
+
+        let next = queue.next().await;
+        assert_eq!(next.publish.payload, "a");
+        queue.remove(next.seq).await;
+        assert_eq!(queue.depth(), 1);
+    }
+
+    #[test]
+    fn requeued_messages_are_read_again() {
+        let ttd = TempTedgeDir::new();
+        let now = SystemTime::now();
+        let mut queue = PersistentQueue::open(config(&ttd, 1024)).unwrap();
+        for payload in ["a", "b"] {
+            queue
+                .push(&message("c8y/s/us", payload), "s/us", Priority::Normal, now)
+                .unwrap();
+        }
+        assert_eq!(next_payload(&mut queue, now), Some((0, "a".into())));
+        assert_eq!(next_payload(&mut queue, now), Some((1, "b".into())));
+        assert_eq!(next_payload(&mut queue, now), None);
+
+        queue.requeue(0);
+        queue.remove(1).unwrap();
+        queue.requeue(1);
+        assert_eq!(next_payload(&mut queue, now), Some((0, "a".into())));
+        assert_eq!(next_payload(&mut queue, now), None);
+    }
+
+    #[tokio::test]
+    async fn messages_in_flight_are_published_again_when_requeued() {
+        let ttd = TempTedgeDir::new();
+        let queue = BridgeQueue::open("outbound", "cloud", config(&ttd, 1024)).unwrap();
+        queue
+            .push("s/us", &message("c8y/s/us", "a"), Priority::Normal)
+            .await;
+
+        let (in_flight, ack) = queue.next_in_flight().await.unwrap();
+        ack.requeue();
+
+        let again = tokio::time::timeout(Duration::from_secs(5), queue.next())
+            .await
+            .unwrap();
+        assert_eq!(again.seq, in_flight.seq);
+        assert_eq!(again.publish.payload, "a");
+        assert_eq!(queue.depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn messages_bypass_the_queue_while_empty_and_the_target_connected() {
+        let ttd = TempTedgeDir::new();
+        let queue = BridgeQueue::open("outbound", "cloud", config(&ttd, 1024)).unwrap();
+        queue.set_target_connected(true);
+        assert!(
+            !queue
+                .push("s/us", &message("c8y/s/us", "a"), Priority::Normal)
+                .await
+        );
+        assert_eq!(queue.depth(), 0);
+
+        queue.set_target_connected(false);
+        assert!(
+            queue
+                .push("s/us", &message("c8y/s/us", "b"), Priority::Normal)
+                .await
+        );
+
+        // Not to overtake the backlog, the messages are queued until the queue is drained
+        queue.set_target_connected(true);
+        assert!(
+            queue
+                .push("s/us", &message("c8y/s/us", "c"), Priority::Normal)
+                .await
+        );
+        assert_eq!(queue.depth(), 2);
+    }
+}
@@ -0,0 +1,477 @@
+use crate::topics::TopicConverter;
+use crate::BridgeMessageSender;
+use camino::Utf8PathBuf;
+use rumqttc::Publish;
+use rumqttc::QoS;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The traffic counters of a bridge, as published periodically and saved in the bridge stats file
+///
+/// The counters are cumulated since the bridge started,
+/// except the acknowledgement latencies which are computed over the last period.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BridgeStats {
+    /// Messages forwarded from the local broker to the cloud
+    pub outbound: DirectionStats,
+
+    /// Messages forwarded from the cloud to the local broker
+    pub inbound: DirectionStats,
+}
+
+/// The traffic counters of a bridge direction
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirectionStats {
+    /// Number of messages forwarded to the target broker
+    pub forwarded: u64,
+
+    /// Number of payload bytes forwarded to the target broker
+    pub forwarded_bytes: u64,
+
+    /// Number of messages dropped, as exceeding the rate limit of their rule
+    pub dropped: u64,
+
+    /// Number of messages not forwarded, as duplicates of messages just received from the target on a bidirectional topic
+    pub duplicated: u64,
+
+    /// Number of forwarded messages acknowledged by the target broker
+    pub acknowledged: u64,
+
+    /// Number of forwarded messages waiting for an acknowledgement from the target broker
+    pub in_flight: u64,
+
+    /// Average time in milliseconds taken by the target broker to acknowledge a message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack_latency_avg_ms: Option<f64>,
+
+    /// Maximum time in milliseconds taken by the target broker to acknowledge a message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack_latency_max_ms: Option<f64>,
+
+    /// The counters of each bridge rule
+    #[serde(default)]
+    pub rules: Vec<RuleStats>,
+}
+
+/// The traffic counters of a bridge rule
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleStats {
+    /// The filter matching the topics of the messages received by this rule
+    pub topic: String,
+
+    /// Number of messages forwarded by this rule
+    pub forwarded: u64,
+
+    /// Number of payload bytes forwarded by this rule
+    pub forwarded_bytes: u64,
+
+    /// Number of messages dropped, as exceeding the rate limit of this rule
+    pub dropped: u64,
+}
+
+impl BridgeStats {
+    /// Formats these counters as a thin-edge measurement
+    ///
+    /// Each direction is a measurement group, as well as each rule with some traffic,
+    /// e.g. `{"outbound":{"forwarded":12,...},"outbound_c8y_s_us":{"forwarded":4,...},"inbound":{...}}`.
+    ///
+    /// The group of a rule is named after its topic filter, with the characters
+    /// that cannot be used in a measurement name replaced.
+    pub fn measurement(&self) -> serde_json::Value {
+        let mut groups = serde_json::Map::new();
+        for (name, direction) in [("outbound", &self.outbound), ("inbound", &self.inbound)] {
+            let mut series = serde_json::json!({
+                "forwarded": direction.forwarded,
+                "forwarded_bytes": direction.forwarded_bytes,
+                "dropped": direction.dropped,
+                "duplicated": direction.duplicated,
+                "acknowledged": direction.acknowledged,
+                "in_flight": direction.in_flight,
+            });
+            if let Some(latency) = direction.ack_latency_avg_ms {
+                series["ack_latency_avg_ms"] = latency.into();
+            }
+            if let Some(latency) = direction.ack_latency_max_ms {
+                series["ack_latency_max_ms"] = latency.into();
+            }
+            groups.insert(name.to_string(), series);
+
+            for (index, rule) in direction.rules.iter().enumerate() {
+                if rule.forwarded == 0 && rule.dropped == 0 {
+                    continue;
+                }
+                let mut group = rule_group_name(name, &rule.topic);
+                if groups.contains_key(&group) {
+                    // Two topic filters that only differ by replaced characters
+                    group = format!("{group}_{index}");
+                }
+                groups.insert(
+                    group,
+                    serde_json::json!({
+                        "forwarded": rule.forwarded,
+                        "forwarded_bytes": rule.forwarded_bytes,
+                        "dropped": rule.dropped,
+                    }),
+                );
+            }
+        }
+        serde_json::Value::Object(groups)
+    }
+}
+
+/// The measurement group name of a rule, e.g. `outbound_te_plus_plus_plus_plus_m_all` for `te/+/+/+/+/m/#`
+///
+/// Only ASCII letters, digits, `-` and `_` are kept, as the other characters
+/// (notably `/`, `+`, `#` and `.`) have special meanings in the measurement names of the clouds.
+fn rule_group_name(direction: &str, topic: &str) -> String {
+    let mut name = format!("{direction}_");
+    for c in topic.chars() {
+        match c {
+            '+' => name.push_str("plus"),
+            '#' => name.push_str("all"),
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => name.push(c),
+            _ => name.push('_'),
+        }
+    }
+    name
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Outbound,
+    Inbound,
+}
+
+/// The counters shared by the two halves of a bridge and its stats publisher
+#[derive(Clone)]
+pub(crate) struct StatsRecorder {
+    inner: Arc<Mutex<SharedStats>>,
+}
+
+struct SharedStats {
+    stats: BridgeStats,
+    latency: [LatencyWindow; 2],
+}
+
+/// The acknowledgement latencies observed since the last published stats
+#[derive(Default)]
+struct LatencyWindow {
+    total: Duration,
+    max: Duration,
+    count: u32,
+}
+
+impl StatsRecorder {
+    pub(crate) fn new(outbound: &TopicConverter, inbound: &TopicConverter) -> Self {
+        let rules = |converter: &TopicConverter| {
+            converter
+                .0
+                .iter()
+                .map(|rule| RuleStats {
+                    topic: rule.topic_filter().to_owned(),
+                    ..RuleStats::default()
+                })
+                .collect()
+        };
+        let stats = BridgeStats {
+            outbound: DirectionStats {
+                rules: rules(outbound),
+                ..DirectionStats::default()
+            },
+            inbound: DirectionStats {
+                rules: rules(inbound),
+                ..DirectionStats::default()
+            },
+        };
+        StatsRecorder {
+            inner: Arc::new(Mutex::new(SharedStats {
+                stats,
+                latency: Default::default(),
+            })),
+        }
+    }
+
+    /// The recorder used by the half bridge receiving local messages
+    pub(crate) fn local_half(&self) -> HalfBridgeStats {
+        self.half(Side::Outbound, Side::Inbound)
+    }
+
+    /// The recorder used by the half bridge receiving cloud messages
+    pub(crate) fn cloud_half(&self) -> HalfBridgeStats {
+        self.half(Side::Inbound, Side::Outbound)
+    }
+
+    fn half(&self, received: Side, acknowledged: Side) -> HalfBridgeStats {
+        HalfBridgeStats {
+            recorder: self.clone(),
+            received,
+            acknowledged,
+            sent_at: HashMap::new(),
+        }
+    }
+
+    /// Returns the current counters, resetting the acknowledgement latency window
+    pub(crate) fn snapshot(&self) -> BridgeStats {
+        let mut shared = self.inner.lock().unwrap();
+        let SharedStats { stats, latency } = &mut *shared;
+        for (direction, window) in [&mut stats.outbound, &mut stats.inbound]
+            .into_iter()
+            .zip(latency.iter_mut())
+        {
+            let window = std::mem::take(window);
+            direction.ack_latency_avg_ms =
+                (window.count > 0).then(|| millis(window.total) / f64::from(window.count));
+            direction.ack_latency_max_ms = (window.count > 0).then(|| millis(window.max));
+        }
+        stats.clone()
+    }
+
+    fn update(&self, side: Side, f: impl FnOnce(&mut DirectionStats, &mut LatencyWindow)) {
+        let mut shared = self.inner.lock().unwrap();
+        let SharedStats { stats, latency } = &mut *shared;
+        match side {
+            Side::Outbound => f(&mut stats.outbound, &mut latency[0]),
+            Side::Inbound => f(&mut stats.inbound, &mut latency[1]),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Records the traffic observed by a half bridge
+///
+/// A half bridge receives the messages of one direction,
+/// and the acknowledgements of the messages forwarded by its companion in the other direction.
+pub(crate) struct HalfBridgeStats {
+    recorder: StatsRecorder,
+    received: Side,
+    acknowledged: Side,
+    sent_at: HashMap<u16, Instant>,
+}
+
+impl HalfBridgeStats {
+    /// A message received by this half has been forwarded using the given rule
+    pub(crate) fn forwarded(&self, rule: usize, publish: &Publish) {
+        let bytes = publish.payload.len() as u64;
+        self.recorder.update(self.received, |stats, _| {
+            stats.forwarded += 1;
+            stats.forwarded_bytes += bytes;
+            if let Some(rule) = stats.rules.get_mut(rule) {
+                rule.forwarded += 1;
+                rule.forwarded_bytes += bytes;
+            }
+        })
+    }
+
+    /// A message received by this half has been dropped by the rate limit of the given rule
+    pub(crate) fn dropped(&self, rule: usize) {
+        self.recorder.update(self.received, |stats, _| {
+            stats.dropped += 1;
+            if let Some(rule) = stats.rules.get_mut(rule) {
+                rule.dropped += 1;
+            }
+        })
+    }
+
+    /// A message received by this half has not been forwarded back to where it came from
+    pub(crate) fn duplicated(&self) {
+        self.recorder
+            .update(self.received, |stats, _| stats.duplicated += 1)
+    }
+
+    /// A message forwarded by the companion has been published with the given packet id
+    pub(crate) fn published(&mut self, pkid: u16, now: Instant) {
+        self.sent_at.insert(pkid, now);
+        let in_flight = self.sent_at.len() as u64;
+        self.recorder
+            .update(self.acknowledged, |stats, _| stats.in_flight = in_flight)
+    }
+
+    /// A message forwarded by the companion has been acknowledged by its target
+    pub(crate) fn acknowledged(&mut self, pkid: u16, now: Instant) {
+        let latency = self
+            .sent_at
+            .remove(&pkid)
+            .map(|sent_at| now.saturating_duration_since(sent_at));
+        let in_flight = self.sent_at.len() as u64;
+        self.recorder.update(self.acknowledged, |stats, window| {
+            stats.acknowledged += 1;
+            stats.in_flight = in_flight;
+            if let Some(latency) = latency {
+                window.total += latency;
+                window.max = window.max.max(latency);
+                window.count += 1;
+            }
+        })
+    }
+}
+
+/// Periodically publishes the bridge stats as a measurement of the bridge service,
+/// and saves them in a file read by `tedge bridge inspect --stats`
+pub(crate) struct StatsPublisher {
+    pub(crate) recorder: StatsRecorder,
+    pub(crate) interval: Duration,
+    pub(crate) measurement_topic: Option<String>,
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) local: BridgeMessageSender,
+}
+
+impl StatsPublisher {
+    pub(crate) async fn run(mut self) {
+        let name = "stats";
+        if let Some(dir) = self.path.parent() {
+            if let Err(err) = tokio::fs::create_dir_all(dir).await {
+                log_event!(error: name, "Failed to create the bridge stats directory {dir}: {err}");
+            }
+        }
+
+        let mut ticks = tokio::time::interval(self.interval);
+        // The first tick completes immediately, while no message has been forwarded yet
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let stats = self.recorder.snapshot();
+
+            if let Some(topic) = &self.measurement_topic {
+                let payload = stats.measurement().to_string();
+                // The acknowledgement by the local broker is ignored by the companion
+                let msg = Publish::new(topic, QoS::AtLeastOnce, payload);
+                self.local.internal_publish(msg);
+            }
+
+            let content = serde_json::to_vec_pretty(&stats).unwrap();
+            if let Err(err) =
+                tedge_utils::fs::atomically_write_file_async(&self.path, &content).await
+            {
+                log_event!(warn: name, "Failed to save the bridge stats: {err}");
+            }
+        }
+    }
+}
+
+/// The topic of the bridge service measurements, derived from its health topic
+pub(crate) fn measurement_topic(health_topic: &str) -> Option<String> {
+    let service_topic = health_topic.strip_suffix("/status/health")?;
+    Some(format!("{service_topic}/m/bridge"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BridgeRule;
+    use serde_json::json;
+
+    #[test]
+    fn counts_forwarded_dropped_and_duplicated_messages_per_rule() {
+        let recorder = recorder();
+        let local = recorder.local_half();
+        let cloud = recorder.cloud_half();
+
+        local.forwarded(0, &Publish::new("te/m", QoS::AtLeastOnce, "12345"));
+        local.forwarded(1, &Publish::new("c8y/s/us", QoS::AtLeastOnce, "123"));
+        local.dropped(1);
+        cloud.duplicated();
+
+        let stats = recorder.snapshot();
+        assert_eq!(stats.outbound.forwarded, 2);
+        assert_eq!(stats.outbound.forwarded_bytes, 8);
+        assert_eq!(stats.outbound.dropped, 1);
+        assert_eq!(stats.outbound.duplicated, 0);
+        assert_eq!(stats.inbound.duplicated, 1);
+        assert_eq!(
+            stats.outbound.rules[1],
+            RuleStats {
+                topic: "c8y/s/us".into(),
+                forwarded: 1,
+                forwarded_bytes: 3,
+                dropped: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn acknowledgement_latency_is_computed_over_the_last_period() {
+        let recorder = recorder();
+        // The cloud half receives the acknowledgements of the outbound messages
+        let mut cloud = recorder.cloud_half();
+        let start = Instant::now();
+
+        cloud.published(1, start);
+        cloud.published(2, start);
+        cloud.acknowledged(1, start + Duration::from_millis(10));
+        cloud.acknowledged(2, start + Duration::from_millis(30));
+        cloud.published(3, start);
+
+        let stats = recorder.snapshot();
+        assert_eq!(stats.outbound.acknowledged, 2);
+        assert_eq!(stats.outbound.in_flight, 1);
+        assert_eq!(stats.outbound.ack_latency_avg_ms, Some(20.0));
+        assert_eq!(stats.outbound.ack_latency_max_ms, Some(30.0));
+
+        let stats = recorder.snapshot();
+        assert_eq!(stats.outbound.acknowledged, 2);
+        assert_eq!(stats.outbound.ack_latency_avg_ms, None);
+    }
+
+    #[test]
+    fn measurement_groups_counters_per_direction_and_active_rule() {
+        let recorder = recorder();
+        recorder
+            .local_half()
+            .forwarded(1, &Publish::new("c8y/s/us", QoS::AtLeastOnce, "123"));
+
+        let measurement = recorder.snapshot().measurement();
+
+        assert_eq!(
+            measurement,
+            json!({
+                "outbound": {"forwarded": 1, "forwarded_bytes": 3, "dropped": 0, "duplicated": 0, "acknowledged": 0, "in_flight": 0},
+                "outbound_c8y_s_us": {"forwarded": 1, "forwarded_bytes": 3, "dropped": 0},
+                "inbound": {"forwarded": 0, "forwarded_bytes": 0, "dropped": 0, "duplicated": 0, "acknowledged": 0, "in_flight": 0},
+            })
+        );
+    }
+
+    #[test]
+    fn rule_group_names_only_use_safe_characters() {
+        assert_eq!(
+            rule_group_name("outbound", "te/+/+/+/+/m/#"),
+            "outbound_te_plus_plus_plus_plus_m_all"
+        );
+        assert_eq!(
+            rule_group_name("inbound", "c8y/devicecontrol/notifications"),
+            "inbound_c8y_devicecontrol_notifications"
+        );
+        assert_eq!(rule_group_name("outbound", "a.b c"), "outbound_a_b_c");
+    }
+
+    #[test]
+    fn measurement_topic_is_derived_from_the_health_topic() {
+        assert_eq!(
+            measurement_topic("te/device/main/service/tedge-mapper-bridge-c8y/status/health")
+                .as_deref(),
+            Some("te/device/main/service/tedge-mapper-bridge-c8y/m/bridge")
+        );
+    }
+
+    fn recorder() -> StatsRecorder {
+        let outbound = TopicConverter(vec![
+            BridgeRule::try_new("te/#".into(), "".into(), "".into()).unwrap(),
+            BridgeRule::try_new("s/us".into(), "c8y/".into(), "".into()).unwrap(),
+        ]);
+        let inbound = TopicConverter(vec![BridgeRule::try_new(
+            "s/ds".into(),
+            "".into(),
+            "c8y/".into(),
+        )
+        .unwrap()]);
+        StatsRecorder::new(&outbound, &inbound)
+    }
+}
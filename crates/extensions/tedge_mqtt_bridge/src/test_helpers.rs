@@ -90,6 +90,9 @@ impl<I: Into<VecDeque<EventRes>>> From<I> for FixedEventStream {
 
 #[async_trait::async_trait]
 impl MqttEvents for FixedEventStream {
+    type Request = Request;
+    type Error = ConnectionError;
+
     async fn poll(&mut self) -> Result<Event, ConnectionError> {
         if let Some(event) = self.next_event() {
             event
@@ -251,6 +254,9 @@ impl AllProcessed for ChannelEvents {
 
 #[async_trait::async_trait]
 impl MqttEvents for ChannelEvents {
+    type Request = Request;
+    type Error = ConnectionError;
+
     async fn poll(&mut self) -> Result<Event, ConnectionError> {
         let mut inner = self.0.lock().await;
         if !inner.connected {
@@ -1,6 +1,9 @@
 use crate::BridgeRule;
+use crate::RateLimit;
 use rumqttc::matches;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Instant;
 use tracing::log::warn;
 
 pub fn matches_ignore_dollar_prefix(topic: &str, filter: &str) -> bool {
@@ -16,12 +19,79 @@ pub struct TopicConverter(pub Vec<BridgeRule>);
 
 impl TopicConverter {
     pub fn convert_topic<'a>(&'a self, topic: &'a str) -> Option<Cow<'a, str>> {
+        self.convert(topic).map(|(_, target_topic)| target_topic)
+    }
+
+    /// Returns the target topic along with the index of the rule applied
+    pub fn convert<'a>(&'a self, topic: &'a str) -> Option<(usize, Cow<'a, str>)> {
         self.0
             .iter()
-            .find_map(|rule| rule.apply(topic))
+            .enumerate()
+            .find_map(|(index, rule)| Some((index, rule.apply(topic)?)))
             .or_else(|| {
                 warn!("Failed to convert {topic:?}");
                 None
             })
     }
+
+    pub fn rule(&self, index: usize) -> &BridgeRule {
+        &self.0[index]
+    }
+}
+
+/// Token buckets limiting the rate of the messages forwarded by the rules of a half bridge
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<usize, TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Returns `true` if a message can be forwarded by the given rule without exceeding its limit
+    pub fn allow(&mut self, rule: usize, limit: &RateLimit, now: Instant) -> bool {
+        let burst = f64::from(limit.burst);
+        let bucket = self.buckets.entry(rule).or_insert(TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * limit.max_rate).min(burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rate_limiter_allows_bursts_then_the_max_rate() {
+        let limit = RateLimit {
+            max_rate: 2.0,
+            burst: 3,
+        };
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        let allowed = (0..5).filter(|_| limiter.allow(0, &limit, start)).count();
+        assert_eq!(allowed, 3);
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.allow(0, &limit, later));
+        assert!(!limiter.allow(0, &limit, later));
+
+        // Each rule has its own budget
+        assert!(limiter.allow(1, &limit, later));
+    }
 }
@@ -17,7 +17,9 @@ use std::str::from_utf8;
 use std::time::Duration;
 use tedge_config::TEdgeConfig;
 use tedge_mqtt_bridge::BridgeConfig;
+use tedge_mqtt_bridge::CloudEndpoints;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
+use tedge_test_utils::fs::TempTedgeDir;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 use tokio::sync::oneshot;
@@ -48,6 +50,16 @@ async fn start_mqtt_bridge_with_reconnect_message(
     cloud_port: u16,
     rules: BridgeConfig,
     reconnect_message: Option<Publish>,
+) {
+    let tedge_config = tedge_mqtt_config(local_port);
+    start_mqtt_bridge_with_config(&tedge_config, cloud_port, rules, reconnect_message).await;
+}
+
+async fn start_mqtt_bridge_with_config(
+    tedge_config: &TEdgeConfig,
+    cloud_port: u16,
+    rules: BridgeConfig,
+    reconnect_message: Option<Publish>,
 ) {
     let cloud_config = MqttOptions::new("a-device-id", "127.0.0.1", cloud_port);
     let service_name = "tedge-mapper-test";
@@ -56,7 +68,7 @@ async fn start_mqtt_bridge_with_reconnect_message(
         .try_into()
         .unwrap();
     MqttBridgeActorBuilder::new(
-        &tedge_mqtt_config(local_port),
+        tedge_config,
         service_name,
         &health_topic,
         rules,
@@ -519,6 +531,129 @@ async fn bridge_publishes_reconnect_message_on_cloud_reconnection() {
     EventPoller::run_in_bg(ev_local);
 }
 
+#[tokio::test]
+async fn bridge_forwards_queued_messages_in_order() {
+    std::env::set_var("RUST_LOG", "tedge_mqtt_bridge=info");
+    let _ = env_logger::try_init();
+    let local_broker_port = free_port().await;
+    let cloud_broker_port = free_port().await;
+    let (local, mut ev_local) = new_broker_and_client("local", local_broker_port);
+    let (cloud, mut ev_cloud) = new_broker_and_client("cloud", cloud_broker_port);
+    let ttd = TempTedgeDir::new();
+
+    let mut rules = BridgeConfig::new();
+    rules.forward_from_local("s/us", "c8y/", "").unwrap();
+    rules.forward_from_remote("s/ds", "c8y/", "").unwrap();
+
+    cloud.subscribe("s/us", QoS::AtLeastOnce).await.unwrap();
+    await_subscription(&mut ev_cloud).await;
+    local.subscribe(HEALTH, QoS::AtLeastOnce).await.unwrap();
+
+    let tedge_config = TEdgeConfig::load_toml_str(&format!(
+        "
+    mqtt.client.port = {local_broker_port}
+    mqtt.bridge.reconnect_policy.initial_interval = \"0s\"
+    mqtt.bridge.queue.outbound = true
+    mqtt.bridge.queue.drain_rate = 0
+    data.path = \"{}\"
+    ",
+        ttd.utf8_path()
+    ));
+    start_mqtt_bridge_with_config(&tedge_config, cloud_broker_port, rules, None).await;
+
+    let health = next_received_message(&mut ev_local).await.unwrap();
+    let health: serde_json::Value = serde_json::from_slice(&health.payload).unwrap();
+    assert_eq!(health["status"], "up");
+    assert!(health["queued"]["outbound"].is_u64(), "{health}");
+
+    let _poll_local = EventPoller::run_in_bg(ev_local);
+    for i in 0..100 {
+        local
+            .publish("c8y/s/us", QoS::AtLeastOnce, false, i.to_string())
+            .await
+            .unwrap();
+    }
+
+    for i in 0..100 {
+        let msg = next_received_message(&mut ev_cloud).await.unwrap();
+        assert_eq!(msg.topic, "s/us");
+        assert_eq!(from_utf8(&msg.payload).unwrap(), i.to_string());
+    }
+    assert!(ttd
+        .utf8_path()
+        .join("bridge/tedge-mapper-test/outbound.queue")
+        .exists());
+}
+
+#[tokio::test]
+async fn bridge_fails_over_to_the_next_endpoint_when_the_primary_is_unreachable() {
+    std::env::set_var("RUST_LOG", "tedge_mqtt_bridge=info");
+    let _ = env_logger::try_init();
+    let local_broker_port = free_port().await;
+    let unreachable_port = free_port().await;
+    let cloud_broker_port = free_port().await;
+    let (local, mut ev_local) = new_broker_and_client("local", local_broker_port);
+    let (cloud, mut ev_cloud) = new_broker_and_client("cloud", cloud_broker_port);
+
+    let mut rules = BridgeConfig::new();
+    rules.forward_from_local("s/us", "c8y/", "").unwrap();
+
+    cloud.subscribe("s/us", QoS::AtLeastOnce).await.unwrap();
+    await_subscription(&mut ev_cloud).await;
+    local.subscribe(HEALTH, QoS::AtLeastOnce).await.unwrap();
+
+    let endpoints = CloudEndpoints::new(MqttOptions::new(
+        "a-device-id",
+        "127.0.0.1",
+        unreachable_port,
+    ))
+    .with_endpoint(MqttOptions::new(
+        "a-device-id",
+        "127.0.0.1",
+        cloud_broker_port,
+    ))
+    .with_max_failures(1);
+    let health_topic = "te/device/main/service/tedge-mapper-test/status/health"
+        .try_into()
+        .unwrap();
+    MqttBridgeActorBuilder::new(
+        &tedge_mqtt_config(local_broker_port),
+        "tedge-mapper-test",
+        &health_topic,
+        rules,
+        endpoints,
+        None,
+    )
+    .await;
+
+    let expected_endpoint = format!("127.0.0.1:{cloud_broker_port}");
+    timeout(DEFAULT_TIMEOUT, async {
+        loop {
+            let health = next_received_message(&mut ev_local).await.unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&health.payload).unwrap();
+            if json["status"] == "up" {
+                assert_eq!(json["endpoint"], expected_endpoint.as_str());
+                break;
+            }
+        }
+    })
+    .await
+    .expect("the bridge to connect to the failover endpoint");
+
+    local
+        .publish(
+            "c8y/s/us",
+            QoS::AtLeastOnce,
+            false,
+            "a,fake,smartrest,message",
+        )
+        .await
+        .unwrap();
+    let _ev_local = EventPoller::run_in_bg(ev_local);
+    let msg = next_received_message(&mut ev_cloud).await.unwrap();
+    assert_eq!(msg.topic, "s/us");
+}
+
 async fn wait_until_health_status_is(
     status: &str,
     event_loop: &mut EventLoop,
@@ -1,3 +1,6 @@
+use crate::socket::watch_udp;
+use crate::socket::watch_unix_socket;
+use crate::Framing;
 use crate::WatchError;
 use crate::WatchEvent;
 use crate::WatchRequest;
@@ -21,6 +24,7 @@ use tokio::process::Child;
 use tokio::process::ChildStderr;
 use tokio::process::ChildStdout;
 use tokio::process::Command;
+use tokio::task::JoinHandle;
 
 type ClientId = u32;
 type Topic = String;
@@ -29,6 +33,8 @@ type CommandLine = String;
 pub struct Watcher {
     /// The collection of commands watched by each client
     processes: HashMap<(ClientId, Topic), (CommandLine, Child)>,
+    /// The collection of sockets watched by each client, with the path of any Unix socket
+    sockets: HashMap<(ClientId, Topic), (JoinHandle<()>, Option<Utf8PathBuf>)>,
     /// The channels to send events to clients identified by their slot
     event_senders: Vec<DynSender<WatchEvent>>,
     /// Channel used to send requests on behalf of a client
@@ -48,6 +54,8 @@ impl Actor for Watcher {
             let topic = match &request {
                 WatchRequest::WatchFile { topic, .. }
                 | WatchRequest::WatchCommand { topic, .. }
+                | WatchRequest::WatchUdp { topic, .. }
+                | WatchRequest::WatchUnixSocket { topic, .. }
                 | WatchRequest::UnWatch { topic } => topic.clone(),
             };
             let result = match request {
@@ -59,6 +67,16 @@ impl Actor for Watcher {
                     command,
                     cwd,
                 } => self.watch_command(client, topic, command, cwd).await,
+                WatchRequest::WatchUdp {
+                    topic,
+                    address,
+                    framing,
+                } => self.watch_udp(client, topic, address, framing).await,
+                WatchRequest::WatchUnixSocket {
+                    topic,
+                    path,
+                    framing,
+                } => self.watch_unix_socket(client, topic, path, framing).await,
                 WatchRequest::UnWatch { topic } => self.unwatch(client, topic).await,
             };
             if let Err(error) = result {
@@ -79,6 +97,7 @@ impl Watcher {
     ) -> Self {
         Watcher {
             processes: HashMap::new(),
+            sockets: HashMap::new(),
             event_senders,
             request_sender,
             request_receiver,
@@ -117,6 +136,48 @@ impl Watcher {
         Ok(())
     }
 
+    pub async fn watch_udp(
+        &mut self,
+        client: u32,
+        topic: Topic,
+        address: String,
+        framing: Framing,
+    ) -> Result<(), WatchError> {
+        self.close_socket(client, &topic).await;
+        let event_sender = self.client_sender(client);
+        let reader = watch_udp(topic.clone(), address, framing, event_sender).await?;
+        self.sockets.insert((client, topic), (reader, None));
+        Ok(())
+    }
+
+    pub async fn watch_unix_socket(
+        &mut self,
+        client: u32,
+        topic: Topic,
+        path: Utf8PathBuf,
+        framing: Framing,
+    ) -> Result<(), WatchError> {
+        self.close_socket(client, &topic).await;
+        let event_sender = self.client_sender(client);
+        let reader = watch_unix_socket(topic.clone(), &path, framing, event_sender).await?;
+        self.sockets.insert((client, topic), (reader, Some(path)));
+        Ok(())
+    }
+
+    /// Stop reading the socket watched by a client on a topic, if any
+    ///
+    /// The reader task is awaited after being aborted, so the socket is released
+    /// before the same address is bound again.
+    async fn close_socket(&mut self, client: ClientId, topic: &Topic) {
+        if let Some((reader, path)) = self.sockets.remove(&(client, topic.clone())) {
+            reader.abort();
+            let _ = reader.await;
+            if let Some(path) = path {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
     fn client_sender(&self, client: u32) -> DynSender<WatchEvent> {
         self.event_senders
             .get(client as usize)
@@ -168,6 +229,7 @@ impl Watcher {
     }
 
     pub async fn unwatch(&mut self, client: u32, topic: Topic) -> Result<(), WatchError> {
+        self.close_socket(client, &topic).await;
         if let Some((command, mut child)) = self.processes.remove(&(client, topic)) {
             if let Ok(Some(status)) = child.try_wait() {
                 return check_status(&command, status);
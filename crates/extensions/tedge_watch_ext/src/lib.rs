@@ -12,9 +12,12 @@ use tedge_actors::RuntimeRequestSink;
 use tedge_actors::SimpleMessageBoxBuilder;
 
 mod actor;
+mod socket;
 #[cfg(test)]
 mod tests;
 
+pub use socket::Framing;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum WatchRequest {
     WatchFile {
@@ -26,11 +29,24 @@ pub enum WatchRequest {
         command: String,
         cwd: Utf8PathBuf,
     },
+    WatchUdp {
+        topic: String,
+        address: String,
+        framing: Framing,
+    },
+    WatchUnixSocket {
+        topic: String,
+        path: Utf8PathBuf,
+        framing: Framing,
+    },
     UnWatch {
         topic: String,
     },
 }
 
+/// An event on a watched resource
+///
+/// A `StdoutLine` is a line output by a watched command or file, or a message received on a watched socket.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum WatchEvent {
     StdoutLine { topic: String, line: String },
@@ -55,6 +71,12 @@ pub enum WatchError {
 
     #[error("Failed to kill `{command}`: {error}")]
     TerminationFailed { command: String, error: String },
+
+    #[error("Failed to bind `{address}`: {error}")]
+    CannotBind { address: String, error: String },
+
+    #[error("Failed to receive from `{address}`: {error}")]
+    SocketFailed { address: String, error: String },
 }
 
 pub use actor::command_output;
@@ -0,0 +1,266 @@
+use crate::WatchError;
+use crate::WatchEvent;
+use camino::Utf8Path;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use tedge_actors::CloneSender;
+use tedge_actors::DynSender;
+use tedge_actors::Sender;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::BufReader;
+use tokio::net::UdpSocket;
+use tokio::net::UnixDatagram;
+use tokio::net::UnixListener;
+use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
+
+/// Maximum size of a datagram or a length-prefixed frame
+const MAX_FRAME_SIZE: usize = 65536;
+
+/// How the bytes received on a socket are split into messages
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Framing {
+    /// Each datagram is a message
+    #[default]
+    Datagram,
+
+    /// Each line is a message
+    Newline,
+
+    /// Each message is prefixed by its length, as a 4-byte big-endian integer
+    LengthPrefixed,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "datagram" => Ok(Framing::Datagram),
+            "newline" => Ok(Framing::Newline),
+            "length-prefixed" => Ok(Framing::LengthPrefixed),
+            _ => Err(format!(
+                "Unknown framing {s:?}: expecting datagram, newline or length-prefixed"
+            )),
+        }
+    }
+}
+
+impl Display for Framing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Framing::Datagram => write!(f, "datagram"),
+            Framing::Newline => write!(f, "newline"),
+            Framing::LengthPrefixed => write!(f, "length-prefixed"),
+        }
+    }
+}
+
+impl Framing {
+    /// Split a datagram into messages
+    fn split(self, datagram: &[u8]) -> Vec<String> {
+        match self {
+            Framing::Datagram => {
+                let text = String::from_utf8_lossy(datagram);
+                vec![text.trim_end_matches(['\r', '\n']).to_string()]
+            }
+            Framing::Newline => String::from_utf8_lossy(datagram)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Framing::LengthPrefixed => {
+                let mut frames = vec![];
+                let mut bytes = datagram;
+                while let Some((prefix, rest)) = bytes.split_first_chunk::<4>() {
+                    let len = u32::from_be_bytes(*prefix) as usize;
+                    let Some(frame) = rest.get(..len) else {
+                        break;
+                    };
+                    frames.push(String::from_utf8_lossy(frame).to_string());
+                    bytes = &rest[len..];
+                }
+                frames
+            }
+        }
+    }
+}
+
+/// Bind a UDP socket and spawn a task forwarding the received messages
+pub(crate) async fn watch_udp(
+    topic: String,
+    address: String,
+    framing: Framing,
+    mut event_sender: DynSender<WatchEvent>,
+) -> Result<JoinHandle<()>, WatchError> {
+    let socket = UdpSocket::bind(&address)
+        .await
+        .map_err(|err| WatchError::CannotBind {
+            address: address.clone(),
+            error: err.to_string(),
+        })?;
+    Ok(tokio::spawn(async move {
+        let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+        loop {
+            match socket.recv(&mut buffer).await {
+                Ok(len) => forward(&topic, framing.split(&buffer[..len]), &mut event_sender).await,
+                Err(err) => {
+                    return end_of_stream(topic, address, err, event_sender).await;
+                }
+            }
+        }
+    }))
+}
+
+/// Bind a Unix socket and spawn a task forwarding the received messages
+///
+/// A datagram socket is bound for the `Datagram` framing, and a stream socket otherwise.
+/// Any file left at the socket path by a previous run is removed.
+pub(crate) async fn watch_unix_socket(
+    topic: String,
+    path: &Utf8Path,
+    framing: Framing,
+    mut event_sender: DynSender<WatchEvent>,
+) -> Result<JoinHandle<()>, WatchError> {
+    let cannot_bind = |err: std::io::Error| WatchError::CannotBind {
+        address: path.to_string(),
+        error: err.to_string(),
+    };
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(cannot_bind(err)),
+        _ => {}
+    }
+    let address = path.to_string();
+
+    if framing == Framing::Datagram {
+        let socket = UnixDatagram::bind(path).map_err(cannot_bind)?;
+        return Ok(tokio::spawn(async move {
+            let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+            loop {
+                match socket.recv(&mut buffer).await {
+                    Ok(len) => {
+                        forward(&topic, framing.split(&buffer[..len]), &mut event_sender).await
+                    }
+                    Err(err) => {
+                        return end_of_stream(topic, address, err, event_sender).await;
+                    }
+                }
+            }
+        }));
+    }
+
+    let listener = UnixListener::bind(path).map_err(cannot_bind)?;
+    Ok(tokio::spawn(async move {
+        // The connection readers are aborted along the listener, when dropped
+        let mut connections = JoinSet::new();
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let topic = topic.clone();
+                    let event_sender = event_sender.sender_clone();
+                    connections.spawn(read_stream(topic, stream, framing, event_sender));
+                }
+                Err(err) => {
+                    return end_of_stream(topic, address, err, event_sender).await;
+                }
+            }
+            while connections.try_join_next().is_some() {}
+        }
+    }))
+}
+
+/// Forward the messages received on a stream connection, till the connection is closed
+async fn read_stream(
+    topic: String,
+    stream: impl AsyncRead + Unpin,
+    framing: Framing,
+    mut event_sender: DynSender<WatchEvent>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let line = match framing {
+            Framing::LengthPrefixed => {
+                let Ok(len) = reader.read_u32().await else {
+                    return;
+                };
+                if len as usize > MAX_FRAME_SIZE {
+                    return;
+                }
+                let mut frame = vec![0u8; len as usize];
+                if reader.read_exact(&mut frame).await.is_err() {
+                    return;
+                }
+                String::from_utf8_lossy(&frame).to_string()
+            }
+            Framing::Newline | Framing::Datagram => {
+                let mut line = String::new();
+                match reader.read_line(&mut line).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => line.trim_end_matches(['\r', '\n']).to_string(),
+                }
+            }
+        };
+        if !line.is_empty() {
+            let _ = event_sender
+                .send(WatchEvent::StdoutLine {
+                    topic: topic.clone(),
+                    line,
+                })
+                .await;
+        }
+    }
+}
+
+async fn forward(topic: &str, lines: Vec<String>, event_sender: &mut DynSender<WatchEvent>) {
+    for line in lines {
+        let _ = event_sender
+            .send(WatchEvent::StdoutLine {
+                topic: topic.to_string(),
+                line,
+            })
+            .await;
+    }
+}
+
+async fn end_of_stream(
+    topic: String,
+    address: String,
+    err: std::io::Error,
+    mut event_sender: DynSender<WatchEvent>,
+) {
+    let error = WatchError::SocketFailed {
+        address,
+        error: err.to_string(),
+    };
+    let _ = event_sender
+        .send(WatchEvent::Error {
+            topic: topic.clone(),
+            error,
+        })
+        .await;
+    let _ = event_sender.send(WatchEvent::EndOfStream { topic }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitting_datagrams() {
+        assert_eq!(
+            Framing::Datagram.split(b"<13>Oct 11 22:14:15 host app: hello\n"),
+            vec!["<13>Oct 11 22:14:15 host app: hello"]
+        );
+        assert_eq!(
+            Framing::Newline.split(b"requests:1|c\nlatency:320|ms\n"),
+            vec!["requests:1|c", "latency:320|ms"]
+        );
+        assert_eq!(
+            Framing::LengthPrefixed.split(b"\0\0\0\x05hello\0\0\0\x05world\0\0\0\x09truncated"),
+            vec!["hello", "world"]
+        );
+    }
+}
@@ -1,13 +1,16 @@
+use crate::Framing;
 use crate::WatchActorBuilder;
 use crate::WatchEvent;
 use crate::WatchRequest;
 use camino::Utf8PathBuf;
+use std::time::Duration;
 use tedge_actors::Actor;
 use tedge_actors::Builder;
 use tedge_actors::MessageReceiver;
 use tedge_actors::Sender;
 use tedge_actors::SimpleMessageBox;
 use tedge_actors::SimpleMessageBoxBuilder;
+use tokio::io::AsyncWriteExt;
 
 #[tokio::test]
 async fn reading_process_stdout() {
@@ -89,6 +92,135 @@ async fn serving_independent_clients() {
     assert_eq!(&topic, "seq");
 }
 
+#[tokio::test]
+async fn reading_udp_datagrams() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    actor
+        .send(WatchRequest::WatchUdp {
+            topic: "statsd".to_string(),
+            address: format!("127.0.0.1:{port}"),
+            framing: Framing::Newline,
+        })
+        .await
+        .unwrap();
+
+    // Datagrams sent before the socket is bound are lost
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let msg = loop {
+        client
+            .send_to(b"requests:1|c\nlatency:320|ms", ("127.0.0.1", port))
+            .await
+            .unwrap();
+        if let Ok(msg) = tokio::time::timeout(Duration::from_millis(100), actor.recv()).await {
+            break msg;
+        }
+    };
+    assert_eq!(
+        msg,
+        Some(WatchEvent::StdoutLine {
+            topic: "statsd".to_string(),
+            line: "requests:1|c".to_string()
+        })
+    );
+    assert_eq!(
+        actor.recv().await,
+        Some(WatchEvent::StdoutLine {
+            topic: "statsd".to_string(),
+            line: "latency:320|ms".to_string()
+        })
+    );
+}
+
+#[tokio::test]
+async fn watching_the_same_udp_topic_again_replaces_the_socket() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    // The second request is served only once the previous socket has been released
+    for _ in 0..2 {
+        actor
+            .send(WatchRequest::WatchUdp {
+                topic: "statsd".to_string(),
+                address: format!("127.0.0.1:{port}"),
+                framing: Framing::Newline,
+            })
+            .await
+            .unwrap();
+    }
+
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let msg = loop {
+        client
+            .send_to(b"requests:1|c", ("127.0.0.1", port))
+            .await
+            .unwrap();
+        if let Ok(msg) = tokio::time::timeout(Duration::from_millis(100), actor.recv()).await {
+            break msg;
+        }
+    };
+    assert_eq!(
+        msg,
+        Some(WatchEvent::StdoutLine {
+            topic: "statsd".to_string(),
+            line: "requests:1|c".to_string()
+        })
+    );
+}
+
+#[tokio::test]
+async fn reading_unix_socket_streams() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = Utf8PathBuf::from_path_buf(dir.path().join("app.sock")).unwrap();
+
+    actor
+        .send(WatchRequest::WatchUnixSocket {
+            topic: "app".to_string(),
+            path: path.clone(),
+            framing: Framing::LengthPrefixed,
+        })
+        .await
+        .unwrap();
+
+    let mut stream = loop {
+        match tokio::net::UnixStream::connect(&path).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream
+        .write_all(b"\0\0\0\x05hello\0\0\0\x05world")
+        .await
+        .unwrap();
+
+    for expected in ["hello", "world"] {
+        assert_eq!(
+            actor.recv().await,
+            Some(WatchEvent::StdoutLine {
+                topic: "app".to_string(),
+                line: expected.to_string()
+            })
+        );
+    }
+
+    actor
+        .send(WatchRequest::UnWatch {
+            topic: "app".to_string(),
+        })
+        .await
+        .unwrap();
+}
+
 fn launch_watcher(client_count: u32) -> Vec<SimpleMessageBox<WatchEvent, WatchRequest>> {
     let mut watcher = WatchActorBuilder::new();
     let clients = (0..=client_count)